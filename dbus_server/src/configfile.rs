@@ -0,0 +1,87 @@
+use std::fs;
+use serde::Deserialize;
+
+/******************************************************************************
+ * PUBLIC TYPES
+ ******************************************************************************/
+
+/// The `econfmanager` section of the configuration file. The fields of the JSON-RPC server are ignored,
+/// so both servers can read the same file
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    #[serde(default = "default_saved_database_path")]
+    pub saved_database_path: String,
+    #[serde(default = "default_default_data_folder")]
+    pub default_data_folder: String,
+    /// File with the hex 32-byte device key of the secret parameters
+    #[serde(default)]
+    pub secret_key_file: Option<String>,
+    /// Bus to connect to: system or session
+    #[serde(default = "default_dbus_bus")]
+    pub dbus_bus: String,
+    /// Well-known name of the service on the bus
+    #[serde(default = "default_dbus_service_name")]
+    pub dbus_service_name: String,
+    /// Path of the manager object, the groups of the parameters are its children
+    #[serde(default = "default_dbus_object_path")]
+    pub dbus_object_path: String,
+    /// Role of the D-Bus clients: user, service or factory. The bus policy decides who can connect
+    #[serde(default = "default_role")]
+    pub default_role: String,
+}
+
+#[derive(Deserialize)]
+struct YamlConfig {
+    econfmanager: Config,
+}
+
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn default_database_path() -> String {
+    "configuration.db".to_string()
+}
+
+fn default_saved_database_path() -> String {
+    "configuration_saved.db".to_string()
+}
+
+fn default_default_data_folder() -> String {
+    ".".to_string()
+}
+
+fn default_dbus_bus() -> String {
+    "system".to_string()
+}
+
+fn default_dbus_service_name() -> String {
+    "org.econfmanager".to_string()
+}
+
+fn default_dbus_object_path() -> String {
+    "/org/econfmanager".to_string()
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl Config {
+    pub fn from_file(config_file: String) -> Config {
+        let file_content = fs::read_to_string(std::path::Path::new(&config_file))
+            .unwrap_or_else(|_| panic!("Failed to read configuration file {}", config_file));
+
+        let config: YamlConfig = serde_yaml::from_str(&file_content)
+            .expect("Failed to parse configuration");
+
+        config.econfmanager
+    }
+}
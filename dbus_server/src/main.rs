@@ -0,0 +1,76 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use arguments::Args;
+use clap::Parser;
+use configfile::Config;
+use econfmanager::async_interface::AsyncInterfaceInstance;
+use econfmanager::interface::InterfaceInstance;
+use econfmanager::schema::AccessLevel;
+use env_logger::Env;
+use log::{error, info};
+use service::Service;
+
+pub mod arguments;
+pub mod configfile;
+pub mod properties;
+pub mod service;
+
+const PERIODIC_UPDATE_INTERVAL: Duration = Duration::from_millis(5000);
+
+/// Read the hex 32-byte device key of the secret parameters, the server can't start without it
+fn read_secret_key(path: &str) -> [u8; 32] {
+    let hex = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read the secret key {}: {}", path, e));
+    let hex = hex.trim();
+    if hex.len() != 64 {
+        panic!("Secret key {} must have 64 hex characters, got {}", path, hex.len());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .unwrap_or_else(|e| panic!("Invalid secret key {}: {}", path, e));
+    }
+    key
+}
+
+async fn connect(config: &Config) -> zbus::Result<zbus::Connection> {
+    let builder = match config.dbus_bus.as_str() {
+        "system" => zbus::connection::Builder::system()?,
+        "session" => zbus::connection::Builder::session()?,
+        bus => panic!("Unknown dbus_bus {} in the configuration, expected system or session", bus),
+    };
+    builder.build().await
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+
+    let args = Args::parse();
+    info!("Starting D-Bus server with configuration: {}", args.config);
+    let config = Config::from_file(args.config);
+
+    let mut builder = InterfaceInstance::builder()
+        .database_path(&config.database_path)
+        .saved_database_path(&config.saved_database_path)
+        .default_data_folder(&config.default_data_folder);
+    if let Some(path) = &config.secret_key_file {
+        builder = builder.secret_key(read_secret_key(path));
+    }
+    let mut interface_instance = builder.build().unwrap();
+    interface_instance.start_periodic_update(PERIODIC_UPDATE_INTERVAL);
+    let interface = AsyncInterfaceInstance::new(interface_instance).unwrap();
+
+    let role = AccessLevel::from_name(&config.default_role)
+        .unwrap_or_else(|| panic!("Unknown default_role {} in the configuration", config.default_role));
+
+    let connection = connect(&config)
+        .await
+        .unwrap_or_else(|e| panic!("Failed to connect to the {} bus: {}", config.dbus_bus, e));
+
+    let service = Arc::new(Service::new(connection, interface, config.dbus_object_path.clone(), role));
+    if let Err(e) = service.run(&config.dbus_service_name).await {
+        error!("D-Bus connection failed: {}", e);
+    }
+}
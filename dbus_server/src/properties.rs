@@ -0,0 +1,203 @@
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use econfmanager::generated::{GROUPS_DATA, PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
+use econfmanager::schema::{Parameter, ParameterValue, ParameterValueType};
+use zbus::zvariant::Value;
+
+/// Interface of the group objects, a property for every parameter of the group
+pub const PARAMETERS_INTERFACE: &str = "org.econfmanager.Parameters";
+/// Interface of the manager object with the save and load methods
+pub const MANAGER_INTERFACE: &str = "org.econfmanager.Manager";
+pub const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+pub const INTROSPECTABLE_INTERFACE: &str = "org.freedesktop.DBus.Introspectable";
+pub const PEER_INTERFACE: &str = "org.freedesktop.DBus.Peer";
+
+/// Blobs over this size are announced in PropertiesChanged as invalidated, the clients read them if needed
+pub const MAX_SIGNAL_BLOB_SIZE: usize = 1024;
+
+const STANDARD_INTERFACES_XML: &str = r#"  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+    <method name="Set">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="in"/>
+    </method>
+    <signal name="PropertiesChanged">
+      <arg name="interface_name" type="s"/>
+      <arg name="changed_properties" type="a{sv}"/>
+      <arg name="invalidated_properties" type="as"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+    <method name="GetMachineId">
+      <arg name="machine_uuid" type="s" direction="out"/>
+    </method>
+  </interface>
+"#;
+
+const MANAGER_INTERFACE_XML: &str = r#"  <interface name="org.econfmanager.Manager">
+    <method name="Save"/>
+    <method name="Load"/>
+    <method name="FactoryReset"/>
+  </interface>
+"#;
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+/// The parameters available over D-Bus, the internal ones are not exposed
+pub fn is_exposed(id: ParameterId) -> bool {
+    let parameter = &PARAMETER_DATA[id.index()];
+    !parameter.internal && signature(&parameter.value_type).is_some()
+}
+
+/// The group and the property name of the parameter ("group@name")
+pub fn split_name(parameter: &Parameter) -> (&'static str, &'static str) {
+    parameter.name_id.split_once('@').unwrap_or(("", parameter.name_id))
+}
+
+/// The exposed parameters of the group, in the order of the definitions
+pub fn group_parameters(group: &str) -> Vec<ParameterId> {
+    (0..PARAMETERS_NUM)
+        .filter_map(ParameterId::from_index)
+        .filter(|id| is_exposed(*id) && split_name(&PARAMETER_DATA[id.index()]).0 == group)
+        .collect()
+}
+
+/// The parameter of the property in the group object
+pub fn find_property(group: &str, property: &str) -> Option<ParameterId> {
+    group_parameters(group)
+        .into_iter()
+        .find(|id| split_name(&PARAMETER_DATA[id.index()]).1 == property)
+}
+
+pub fn signature(value_type: &ParameterValueType) -> Option<&'static str> {
+    Some(match value_type {
+        ParameterValueType::TypeBool => "b",
+        ParameterValueType::TypeI32 | ParameterValueType::TypeEnum(_) => "i",
+        ParameterValueType::TypeU32 => "u",
+        ParameterValueType::TypeI64 => "x",
+        ParameterValueType::TypeU64 => "t",
+        // D-Bus has no single precision type
+        ParameterValueType::TypeF32 | ParameterValueType::TypeF64 => "d",
+        ParameterValueType::TypeString => "s",
+        ParameterValueType::TypeBlob => "ay",
+        ParameterValueType::TypeNone => return None,
+    })
+}
+
+/// The property can be written: not const, read-only or computed
+pub fn is_writable(parameter: &Parameter) -> bool {
+    !parameter.is_const && !parameter.readonly && !parameter.computed
+}
+
+pub fn to_dbus_value(value: &ParameterValue) -> Option<Value<'static>> {
+    Some(match value {
+        ParameterValue::ValBool(value) => Value::from(*value),
+        ParameterValue::ValI32(value) | ParameterValue::ValEnum(value) => Value::from(*value),
+        ParameterValue::ValU32(value) => Value::from(*value),
+        ParameterValue::ValI64(value) => Value::from(*value),
+        ParameterValue::ValU64(value) => Value::from(*value),
+        ParameterValue::ValF32(value) => Value::from(*value as f64),
+        ParameterValue::ValF64(value) => Value::from(*value),
+        ParameterValue::ValString(value) => Value::from(value.to_string()),
+        ParameterValue::ValBlob(value) => Value::from(value.clone()),
+        ParameterValue::ValNone | ParameterValue::ValPath(_) => return None,
+    })
+}
+
+/// The value of the parameter type from the D-Bus value, which must have the signature of the property
+pub fn from_dbus_value(parameter: &Parameter, value: &Value<'_>) -> Result<ParameterValue, String> {
+    let mismatch = || {
+        format!(
+            "Property {} has the signature {}, got {}",
+            parameter.name_id, signature(&parameter.value_type).unwrap_or(""), value.value_signature()
+        )
+    };
+    Ok(match (&parameter.value_type, value) {
+        (ParameterValueType::TypeBool, Value::Bool(value)) => ParameterValue::ValBool(*value),
+        (ParameterValueType::TypeI32, Value::I32(value)) => ParameterValue::ValI32(*value),
+        (ParameterValueType::TypeEnum(_), Value::I32(value)) => ParameterValue::ValEnum(*value),
+        (ParameterValueType::TypeU32, Value::U32(value)) => ParameterValue::ValU32(*value),
+        (ParameterValueType::TypeI64, Value::I64(value)) => ParameterValue::ValI64(*value),
+        (ParameterValueType::TypeU64, Value::U64(value)) => ParameterValue::ValU64(*value),
+        (ParameterValueType::TypeF32, Value::F64(value)) => ParameterValue::ValF32(*value as f32),
+        (ParameterValueType::TypeF64, Value::F64(value)) => ParameterValue::ValF64(*value),
+        (ParameterValueType::TypeString, Value::Str(value)) => ParameterValue::ValString(Cow::Owned(value.to_string())),
+        (ParameterValueType::TypeBlob, Value::Array(array)) => {
+            let bytes: Vec<u8> = array.try_clone()
+                .ok()
+                .and_then(|array| Vec::<u8>::try_from(array).ok())
+                .ok_or_else(mismatch)?;
+            ParameterValue::ValBlob(bytes)
+        }
+        _ => return Err(mismatch()),
+    })
+}
+
+/// Introspection of the object at the path: the manager object, a group object, or one of the parents
+/// of the manager object. None if there is no object at the path
+pub fn introspect(root_path: &str, path: &str) -> Option<String> {
+    let mut xml = String::from(
+        "<!DOCTYPE node PUBLIC \"-//freedesktop//DTD D-BUS Object Introspection 1.0//EN\"\n \
+         \"http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd\">\n<node>\n",
+    );
+    if path == root_path {
+        xml.push_str(STANDARD_INTERFACES_XML);
+        xml.push_str(MANAGER_INTERFACE_XML);
+        for group in GROUPS_DATA.iter().filter(|group| !group_parameters(group.name).is_empty()) {
+            let _ = writeln!(xml, "  <node name=\"{}\"/>", group.name);
+        }
+    } else if let Some(group) = group_of_path(root_path, path) {
+        xml.push_str(STANDARD_INTERFACES_XML);
+        let _ = writeln!(xml, "  <interface name=\"{}\">", PARAMETERS_INTERFACE);
+        for id in group_parameters(group) {
+            let parameter = &PARAMETER_DATA[id.index()];
+            let access = match (parameter.secret, is_writable(parameter)) {
+                (true, _) => "write",
+                (false, true) => "readwrite",
+                (false, false) => "read",
+            };
+            let _ = writeln!(
+                xml,
+                "    <property name=\"{}\" type=\"{}\" access=\"{}\"/>",
+                split_name(parameter).1, signature(&parameter.value_type).unwrap_or("v"), access
+            );
+        }
+        xml.push_str("  </interface>\n");
+    } else {
+        // The parents, so the tools walking the tree from "/" find the objects
+        let prefix = if path == "/" { "/" } else { &format!("{}/", path) };
+        let child = root_path.strip_prefix(prefix)?.split('/').next()?;
+        xml.push_str(STANDARD_INTERFACES_XML);
+        let _ = writeln!(xml, "  <node name=\"{}\"/>", child);
+    }
+    xml.push_str("</node>\n");
+    Some(xml)
+}
+
+/// The group of the object path, "<root>/<group>"
+pub fn group_of_path<'p>(root_path: &str, path: &'p str) -> Option<&'p str> {
+    let group = path.strip_prefix(root_path)?.strip_prefix('/')?;
+    GROUPS_DATA.iter().any(|known| known.name == group).then_some(group)
+}
+
+pub fn group_path(root_path: &str, group: &str) -> String {
+    format!("{}/{}", root_path, group)
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use econfmanager::async_interface::{AsyncError, AsyncInterfaceInstance};
+use econfmanager::error::EconfError;
+use econfmanager::generated::{PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
+use econfmanager::schema::{AccessLevel, ParameterValue};
+use econfmanager::sensitive;
+use futures_util::StreamExt;
+use log::{debug, error, info, warn};
+use tokio::sync::broadcast::error::RecvError;
+use zbus::message::{Header, Type};
+use zbus::zvariant::Value;
+use zbus::{fdo, Connection, Message, MessageStream};
+
+use crate::properties::{
+    from_dbus_value, find_property, group_of_path, group_parameters, group_path, introspect, is_exposed, is_writable,
+    split_name, to_dbus_value, INTROSPECTABLE_INTERFACE, MANAGER_INTERFACE, MAX_SIGNAL_BLOB_SIZE, PARAMETERS_INTERFACE,
+    PEER_INTERFACE, PROPERTIES_INTERFACE,
+};
+
+/// Source of the writes made through D-Bus, passed to the change callbacks
+const WRITE_SOURCE: &str = "dbus";
+const MACHINE_ID_FILE: &str = "/etc/machine-id";
+
+/// Body of a successful reply
+enum Reply {
+    Empty,
+    Value(Value<'static>),
+    Properties(HashMap<String, Value<'static>>),
+    Text(String),
+}
+
+/// Serves the method calls of the manager object and the group objects, and sends PropertiesChanged
+/// for the changes of the parameters made by any process sharing the database
+pub struct Service {
+    connection: Connection,
+    interface: AsyncInterfaceInstance,
+    root_path: String,
+    role: AccessLevel,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn econf_error(e: AsyncError) -> fdo::Error {
+    match e.downcast_ref::<EconfError>() {
+        Some(EconfError::Validation { .. }) | Some(EconfError::TypeMismatch { .. }) => fdo::Error::InvalidArgs(e.to_string()),
+        Some(EconfError::Const(_)) | Some(EconfError::ReadOnly(_)) | Some(EconfError::ReadOnlyInstance) => {
+            fdo::Error::PropertyReadOnly(e.to_string())
+        }
+        _ => fdo::Error::Failed(e.to_string()),
+    }
+}
+
+fn arguments_error(e: zbus::Error) -> fdo::Error {
+    fdo::Error::InvalidArgs(e.to_string())
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl Service {
+    pub fn new(connection: Connection, interface: AsyncInterfaceInstance, root_path: String, role: AccessLevel) -> Self {
+        Self { connection, interface, root_path, role }
+    }
+
+    /// Take the name on the bus and handle the method calls until the connection is closed,
+    /// every call in its own task
+    pub async fn run(self: Arc<Self>, service_name: &str) -> zbus::Result<()> {
+        // Created before the name is taken, so no call sent to the name is missed
+        let mut stream = MessageStream::from(&self.connection);
+        self.connection.request_name(service_name).await?;
+        info!("Serving {} at {}", service_name, self.root_path);
+        let signals = tokio::spawn(self.clone().send_changes());
+        while let Some(message) = stream.next().await {
+            let message = message?;
+            if message.header().message_type() != Type::MethodCall {
+                continue;
+            }
+            let service = self.clone();
+            tokio::spawn(async move { service.dispatch(message).await });
+        }
+        signals.abort();
+        Ok(())
+    }
+
+    async fn dispatch(&self, message: Message) {
+        let header = message.header();
+        let result = self.handle(&message, &header).await;
+        let sent = match result {
+            Ok(Reply::Empty) => self.connection.reply(&header, &()).await,
+            Ok(Reply::Value(value)) => self.connection.reply(&header, &value).await,
+            Ok(Reply::Properties(properties)) => self.connection.reply(&header, &properties).await,
+            Ok(Reply::Text(text)) => self.connection.reply(&header, &text).await,
+            Err(e) => {
+                debug!("D-Bus call {:?} {:?} failed: {}", header.path(), header.member(), e);
+                self.connection.reply_dbus_error(&header, e).await
+            }
+        };
+        if let Err(e) = sent {
+            warn!("Failed to reply to the D-Bus call: {}", e);
+        }
+    }
+
+    async fn handle(&self, message: &Message, header: &Header<'_>) -> fdo::Result<Reply> {
+        let path = header.path().map(|path| path.as_str()).unwrap_or_default();
+        let interface = header.interface().map(|interface| interface.as_str());
+        let member = header.member().map(|member| member.as_str()).unwrap_or_default();
+        let group = group_of_path(&self.root_path, path);
+        let body = message.body();
+        if group.is_none() && path != self.root_path {
+            // Only introspection of the parent objects
+            return match (interface, member) {
+                (Some(INTROSPECTABLE_INTERFACE) | None, "Introspect") => introspect(&self.root_path, path)
+                    .map(Reply::Text)
+                    .ok_or_else(|| fdo::Error::UnknownObject(format!("No object at {}", path))),
+                (Some(PEER_INTERFACE) | None, "Ping") => Ok(Reply::Empty),
+                _ => Err(fdo::Error::UnknownObject(format!("No object at {}", path))),
+            };
+        }
+
+        match (interface, member) {
+            (Some(PEER_INTERFACE) | None, "Ping") => Ok(Reply::Empty),
+            (Some(PEER_INTERFACE) | None, "GetMachineId") => std::fs::read_to_string(MACHINE_ID_FILE)
+                .map(|id| Reply::Text(id.trim().to_owned()))
+                .map_err(|e| fdo::Error::Failed(format!("Failed to read {}: {}", MACHINE_ID_FILE, e))),
+            (Some(INTROSPECTABLE_INTERFACE) | None, "Introspect") => {
+                introspect(&self.root_path, path).map(Reply::Text).ok_or_else(|| fdo::Error::UnknownObject(path.to_owned()))
+            }
+            (Some(PROPERTIES_INTERFACE) | None, "Get") => {
+                let (interface_name, property): (&str, &str) = body.deserialize().map_err(arguments_error)?;
+                let id = self.property(group, interface_name, property)?;
+                self.get(id).await.map(Reply::Value)
+            }
+            (Some(PROPERTIES_INTERFACE) | None, "GetAll") => {
+                let (interface_name,): (&str,) = body.deserialize().map_err(arguments_error)?;
+                let Some(group) = group.filter(|_| interface_name == PARAMETERS_INTERFACE) else {
+                    return Ok(Reply::Properties(HashMap::new()));
+                };
+                let mut properties = HashMap::new();
+                for id in group_parameters(group) {
+                    if !PARAMETER_DATA[id.index()].secret {
+                        properties.insert(split_name(&PARAMETER_DATA[id.index()]).1.to_owned(), self.get(id).await?);
+                    }
+                }
+                Ok(Reply::Properties(properties))
+            }
+            (Some(PROPERTIES_INTERFACE) | None, "Set") => {
+                let (interface_name, property, value): (&str, &str, Value<'_>) = body.deserialize().map_err(arguments_error)?;
+                let id = self.property(group, interface_name, property)?;
+                self.set(id, &value).await.map(|_| Reply::Empty)
+            }
+            (Some(MANAGER_INTERFACE) | None, "Save") if group.is_none() => {
+                self.interface.save().await.map_err(econf_error).map(|_| Reply::Empty)
+            }
+            (Some(MANAGER_INTERFACE) | None, "Load") if group.is_none() => {
+                self.interface.load().await.map_err(econf_error).map(|_| Reply::Empty)
+            }
+            (Some(MANAGER_INTERFACE) | None, "FactoryReset") if group.is_none() => {
+                self.interface.factory_reset().await.map_err(econf_error).map(|_| Reply::Empty)
+            }
+            _ => Err(fdo::Error::UnknownMethod(format!("Unknown method {}.{}", interface.unwrap_or_default(), member))),
+        }
+    }
+
+    /// The parameter of the property of a group object
+    fn property(&self, group: Option<&str>, interface_name: &str, property: &str) -> fdo::Result<ParameterId> {
+        if interface_name != PARAMETERS_INTERFACE {
+            return Err(fdo::Error::UnknownInterface(format!("Unknown interface {}", interface_name)));
+        }
+        group
+            .and_then(|group| find_property(group, property))
+            .ok_or_else(|| fdo::Error::UnknownProperty(format!("Unknown property {}", property)))
+    }
+
+    async fn get(&self, id: ParameterId) -> fdo::Result<Value<'static>> {
+        let parameter = &PARAMETER_DATA[id.index()];
+        if parameter.secret {
+            return Err(fdo::Error::AccessDenied(format!("Property {} is write-only", parameter.name_id)));
+        }
+        let value = self.interface.get(id, false).await.map_err(econf_error)?;
+        to_dbus_value(&value).ok_or_else(|| fdo::Error::Failed(format!("Value of {} can't be sent", parameter.name_id)))
+    }
+
+    async fn set(&self, id: ParameterId, value: &Value<'_>) -> fdo::Result<()> {
+        let parameter = &PARAMETER_DATA[id.index()];
+        if !is_writable(parameter) {
+            return Err(fdo::Error::PropertyReadOnly(format!("Property {} is read-only", parameter.name_id)));
+        }
+        if self.role < parameter.access_level {
+            return Err(fdo::Error::AccessDenied(format!(
+                "Property {} requires the {} role, the D-Bus clients have {}",
+                parameter.name_id, parameter.access_level.name(), self.role.name()
+            )));
+        }
+        let value = from_dbus_value(parameter, value).map_err(fdo::Error::InvalidArgs)?;
+        info!("D-Bus write of {}: {}", parameter.name_id, sensitive::log_value(id, &value));
+        self.interface
+            .set_with_source(id, value, Some(WRITE_SOURCE.to_owned()))
+            .await
+            .map_err(econf_error)?;
+        Ok(())
+    }
+
+    /// Send PropertiesChanged for the parameter changes. The secret parameters and the large blobs
+    /// are sent as invalidated, without the value
+    async fn send_changes(self: Arc<Self>) {
+        let mut events = self.interface.subscribe();
+        loop {
+            let ids = match events.recv().await {
+                Ok(event) => vec![event.id],
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Missed {} change events, announcing all the parameters", missed);
+                    (0..PARAMETERS_NUM).filter_map(ParameterId::from_index).collect()
+                }
+                Err(RecvError::Closed) => break,
+            };
+            for id in ids.into_iter().filter(|id| is_exposed(*id)) {
+                if let Err(e) = self.send_change(id).await {
+                    error!("Failed to send PropertiesChanged for {}: {}", PARAMETER_DATA[id.index()].name_id, e);
+                }
+            }
+        }
+    }
+
+    async fn send_change(&self, id: ParameterId) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let parameter = &PARAMETER_DATA[id.index()];
+        let (group, property) = split_name(parameter);
+        let mut changed: HashMap<&str, Value<'static>> = HashMap::new();
+        let mut invalidated: Vec<&str> = Vec::new();
+        if parameter.secret {
+            invalidated.push(property);
+        } else {
+            match self.interface.get(id, false).await? {
+                ParameterValue::ValBlob(blob) if blob.len() > MAX_SIGNAL_BLOB_SIZE => invalidated.push(property),
+                value => {
+                    if let Some(dbus_value) = to_dbus_value(&value) {
+                        changed.insert(property, dbus_value);
+                    }
+                }
+            }
+        }
+        self.connection
+            .emit_signal(
+                None::<()>,
+                group_path(&self.root_path, group).as_str(),
+                PROPERTIES_INTERFACE,
+                "PropertiesChanged",
+                &(PARAMETERS_INTERFACE, changed, invalidated),
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+//! Compare the lookup of the parameters by name with a linear scan (how `get_parameter_id_from_name`
+//! and `update()` used to work) against the binary search of the generated sorted table, for a
+//! schema of 1000 parameters. The generated schema of the crate is measured too.
+//!
+//! Run with `cargo bench --bench name_lookup`
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use econfmanager::generated::{PARAMETER_DATA, ParameterId};
+
+const PARAMETERS: usize = 1000;
+const ITERATIONS: u32 = 100;
+
+fn report(name: &str, elapsed: Duration, lookups: usize) {
+    println!(
+        "{:<32} {:>10.3} ms total {:>10.3} ns per lookup",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        elapsed.as_secs_f64() * 1_000_000_000.0 / lookups as f64
+    );
+}
+
+fn main() {
+    // The same shape as the generated tables: the names in the schema order and sorted with the index
+    let names: Vec<String> = (0..PARAMETERS)
+        .map(|index| format!("group_{}@parameter_{}", index % 20, index))
+        .collect();
+    let mut sorted: Vec<(&str, usize)> = names.iter().enumerate().map(|(index, name)| (name.as_str(), index)).collect();
+    sorted.sort();
+    let lookups = PARAMETERS * ITERATIONS as usize;
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for name in &names {
+            black_box(names.iter().position(|candidate| candidate == black_box(name)));
+        }
+    }
+    report("Linear scan", start.elapsed(), lookups);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for name in &names {
+            let name = black_box(name.as_str());
+            black_box(sorted.binary_search_by(|(candidate, _)| (*candidate).cmp(name)).ok().map(|position| sorted[position].1));
+        }
+    }
+    report("Binary search", start.elapsed(), lookups);
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        for parameter in PARAMETER_DATA {
+            black_box(ParameterId::from_name(black_box(parameter.name_id)));
+        }
+    }
+    report(
+        &format!("ParameterId::from_name ({})", PARAMETER_DATA.len()),
+        start.elapsed(),
+        PARAMETER_DATA.len() * ITERATIONS as usize,
+    );
+}
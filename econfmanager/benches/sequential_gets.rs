@@ -0,0 +1,54 @@
+//! Compare 1k sequential database reads with a connection opened for every read
+//! (how the database layer used to work) against `get()` using the persistent connection.
+//!
+//! Run with `cargo bench --bench sequential_gets`
+
+use std::time::{Duration, Instant};
+
+use econfmanager::database_utils::DbConnection;
+use econfmanager::generated::{PARAMETER_DATA, ParameterId};
+use econfmanager::interface::{CachePolicy, InterfaceInstance};
+
+const ITERATIONS: u32 = 1000;
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{:<32} {:>10.3} ms total {:>10.3} us per read",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        elapsed.as_secs_f64() * 1_000_000.0 / ITERATIONS as f64
+    );
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let folder = std::env::temp_dir().join(format!("econfmanager_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&folder)?;
+    let database_path = folder.join("parameters.db").to_string_lossy().to_string();
+
+    // Every get goes to the database
+    let interface = InterfaceInstance::builder()
+        .database_path(&database_path)
+        .saved_database_path(folder.join("saved_parameters.db").to_string_lossy())
+        .cache_policy(CachePolicy::None)
+        .build()?;
+    let id = ParameterId::from_index(0).ok_or("No parameters defined")?;
+    let key = PARAMETER_DATA[0].name_id;
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let db = DbConnection::new(&database_path, false, false)?;
+        let mut stmt = db.conn().prepare("SELECT value FROM parameters WHERE key = ?")?;
+        let _ = stmt.query_row([key], |row| row.get::<usize, rusqlite::types::Value>(0));
+    }
+    report("Connection per read", start.elapsed());
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        interface.get(id, false)?;
+    }
+    report("Persistent connection get()", start.elapsed());
+
+    drop(interface);
+    let _ = std::fs::remove_dir_all(&folder);
+    Ok(())
+}
@@ -6,9 +6,33 @@ use std::fs::canonicalize;
 #[path = "build/file_generator.rs"]
 pub mod file_generator;
 
+#[path = "build/cpp_generator.rs"]
+pub mod cpp_generator;
+
+#[path = "build/python_generator.rs"]
+pub mod python_generator;
+
+#[path = "build/ts_generator.rs"]
+pub mod ts_generator;
+
+#[path = "build/compat_check.rs"]
+pub mod compat_check;
+
 #[path = "src/schema.rs"]
 pub mod schema;
-use file_generator::{generate_parameter_enum, generate_parameter_functions, generate_parameter_ids, process_convert_c_file};
+
+#[path = "src/manifest.rs"]
+pub mod manifest;
+
+#[path = "src/nvs.rs"]
+pub mod nvs;
+use file_generator::{append_parameter_macros, generate_parameter_enum, generate_parameter_functions, generate_parameter_ids, process_convert_c_file};
+use cpp_generator::generate_cpp_wrapper;
+use python_generator::generate_python_module;
+use ts_generator::generate_ts_client;
+use compat_check::{check_database_compatibility, write_compatibility_report};
+use manifest::{parameters_manifest, schema_hash, sign_manifest};
+use nvs::nvs_c_header;
 use schema::SchemaManager;
 
 const OPTIONS_PROTO_FILE: &str = "options.proto";
@@ -18,6 +42,16 @@ const SERVICE_PROTO_FILE_RS: &str = "service_events.rs";
 const PARAMETER_IDS_FILE: &str = "parameter_ids.proto";
 const PARAMETER_IDS_PROTO_FILE_RS: &str = "parameter_ids.rs";
 const DESCRIPTORS_FILE: &str = "descriptors.bin";
+const MANIFEST_FILE: &str = "parameters_manifest.json";
+const NVS_HEADER_FILE: &str = "econf_nvs.h";
+const CPP_WRAPPER_FILE: &str = "econfmanager.hpp";
+const PYTHON_MODULE_FILE: &str = "econfmanager.py";
+const TS_CLIENT_FILE: &str = "client.ts";
+const COMPAT_REPORT_FILE: &str = "schema_compatibility.json";
+/// A deployed database to check the parameters against, the check is skipped if not set
+const COMPAT_DATABASE_ENV: &str = "ECONF_COMPAT_DATABASE";
+/// File with the hex Ed25519 secret key used to sign the manifest, the manifest is not signed if not set
+const MANIFEST_SIGNING_KEY_ENV: &str = "ECONF_MANIFEST_SIGNING_KEY";
 
 const PROTO_CONF_FOLDER: &str = "proto_conf";
 
@@ -85,6 +119,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir_all(generated_dir)
         .unwrap_or_else(|op|{panic!("Failed creating generated files dir: {}", op)});
 
+    let descriptors = fs::read(&abs_descriptor_path)
+        .unwrap_or_else(|op|{panic!("Error reading descriptors: {}", op)});
+
     let schema = SchemaManager::new(
         abs_descriptor_path.into_os_string().into_string().unwrap(),
         Vec::new(),
@@ -104,6 +141,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     generate_parameter_functions(&parameters, generated_dir.to_str().unwrap().to_owned())
         .unwrap_or_else(|op|{panic!("Error generating parameters functions: {}", op)});
 
+    let mut manifest = parameters_manifest(&parameters, &groups, &schema_hash(&descriptors), env!("CARGO_PKG_VERSION"));
+    println!("cargo:rerun-if-env-changed={MANIFEST_SIGNING_KEY_ENV}");
+    if let Ok(key_path) = env::var(MANIFEST_SIGNING_KEY_ENV) {
+        println!("cargo:rerun-if-changed={}", key_path);
+        let key = fs::read_to_string(&key_path)
+            .unwrap_or_else(|op|{panic!("Error reading manifest signing key {}: {}", key_path, op)});
+        sign_manifest(&mut manifest, &key)
+            .unwrap_or_else(|op|{panic!("Error signing the manifest: {}", op)});
+    }
+    fs::write(build_dir.join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)
+        .unwrap_or_else(|op|{panic!("Error writing the manifest: {}", op)});
+
+    fs::write(build_dir.join(NVS_HEADER_FILE), nvs_c_header(&parameters))
+        .unwrap_or_else(|op|{panic!("Error writing the NVS header: {}", op)});
+
+    println!("cargo:rerun-if-env-changed={COMPAT_DATABASE_ENV}");
+    if let Ok(database_path) = env::var(COMPAT_DATABASE_ENV) {
+        println!("cargo:rerun-if-changed={}", database_path);
+        let database_path = Path::new(&database_path);
+        let issues = check_database_compatibility(&parameters, database_path)
+            .unwrap_or_else(|op|{panic!("Error checking the compatibility with {}: {}", database_path.display(), op)});
+        for issue in &issues {
+            println!("cargo:warning={} ({}): {}", issue.key, issue.kind, issue.message);
+        }
+        write_compatibility_report(&issues, database_path, &build_dir.join(COMPAT_REPORT_FILE))
+            .unwrap_or_else(|op|{panic!("Error writing the compatibility report: {}", op)});
+    }
+
     let header_path: PathBuf = build_dir.join("econfmanager.h");
     let header_path_copy = header_path.clone();
     // Try to find cbindgen in the system PATH
@@ -123,12 +188,29 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .status()
         .expect("Failed to run cbindgen");
 
-    process_convert_c_file(&header_path_copy, &header_path_copy)?;
-
     if !status.success() {
         panic!("cbindgen failed with status: {}", status);
     }
 
+    process_convert_c_file(&header_path_copy, &header_path_copy)?;
+    append_parameter_macros(&parameters, &header_path_copy)
+        .unwrap_or_else(|op|{panic!("Error adding the parameter macros to the header: {}", op)});
+
+    if env::var("CARGO_FEATURE_CPP").is_ok() {
+        generate_cpp_wrapper(&parameters, &build_dir.join(CPP_WRAPPER_FILE))
+            .unwrap_or_else(|op|{panic!("Error generating the C++ wrapper: {}", op)});
+    }
+
+    if env::var("CARGO_FEATURE_PYTHON").is_ok() {
+        generate_python_module(&parameters, &build_dir.join(PYTHON_MODULE_FILE))
+            .unwrap_or_else(|op|{panic!("Error generating the Python module: {}", op)});
+    }
+
+    if env::var("CARGO_FEATURE_TYPESCRIPT").is_ok() {
+        generate_ts_client(&parameters, &build_dir.join(TS_CLIENT_FILE))
+            .unwrap_or_else(|op|{panic!("Error generating the TypeScript client: {}", op)});
+    }
+
     let mut proto_files: Vec<_> = fs::read_dir(parameters_proto_path)
         .unwrap()
         .filter_map(|entry| {
@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags};
+use serde_json::json;
+
+use crate::schema::{Parameter, ParameterValue, ParameterValueType, ValidationMethod};
+
+/// The table of the parameters in the database, see database_utils.rs
+const TABLE_NAME: &str = "parameters";
+/// Prefix of the blobs stored as files, see database_utils.rs
+const BLOB_FILE_PREFIX: &str = "sha256:";
+
+/// A stored value that the new parameters don't read back the same way
+pub(crate) struct CompatibilityIssue {
+    pub(crate) key: String,
+    pub(crate) kind: &'static str,
+    pub(crate) message: String,
+}
+
+fn issue(key: &str, kind: &'static str, message: String) -> CompatibilityIssue {
+    CompatibilityIssue { key: key.to_owned(), kind, message }
+}
+
+fn numeric(value: &ParameterValue) -> Option<f64> {
+    match value {
+        ParameterValue::ValBool(v) => Some(if *v { 1.0 } else { 0.0 }),
+        ParameterValue::ValI32(v) | ParameterValue::ValEnum(v) => Some(*v as f64),
+        ParameterValue::ValU32(v) => Some(*v as f64),
+        ParameterValue::ValI64(v) => Some(*v as f64),
+        ParameterValue::ValU64(v) => Some(*v as f64),
+        ParameterValue::ValF32(v) => Some(*v as f64),
+        ParameterValue::ValF64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+fn integer_limits(value_type: &ParameterValueType) -> Option<(i128, i128)> {
+    match value_type {
+        ParameterValueType::TypeI32 | ParameterValueType::TypeEnum(_) => Some((i32::MIN as i128, i32::MAX as i128)),
+        ParameterValueType::TypeU32 => Some((0, u32::MAX as i128)),
+        ParameterValueType::TypeI64 => Some((i64::MIN as i128, i64::MAX as i128)),
+        // SQLite integers are signed 64 bit, the u64 values above i64::MAX are stored wrapped around
+        ParameterValueType::TypeU64 => Some((i64::MIN as i128, i64::MAX as i128)),
+        _ => None,
+    }
+}
+
+/// Check how the stored value is read with the new type, returns the number for the validation check
+fn check_type(parameter: &Parameter, stored: &Value) -> Result<Option<f64>, String> {
+    let value_type = &parameter.value_type;
+    let mismatch = || format!("stored as {}, the parameter is now {}, the default is used instead", stored.data_type(), value_type);
+    if let Some((min, max)) = integer_limits(value_type) {
+        return match stored {
+            Value::Integer(i) if (min..=max).contains(&(*i as i128)) => Ok(Some(*i as f64)),
+            Value::Integer(i) => Err(format!("stored value {} doesn't fit {}", i, value_type)),
+            Value::Real(f) => Err(format!("stored REAL {} is truncated to {}", f, value_type)),
+            Value::Null => Ok(None),
+            _ => Err(mismatch()),
+        };
+    }
+    match (value_type, stored) {
+        (ParameterValueType::TypeString, Value::Text(_)) | (ParameterValueType::TypeBlob, Value::Blob(_)) => Ok(None),
+        (ParameterValueType::TypeBlob, Value::Text(reference)) if reference.starts_with(BLOB_FILE_PREFIX) => Ok(None),
+        // Encrypted, see the secret option
+        (_, Value::Blob(_)) if parameter.secret => Ok(None),
+        (ParameterValueType::TypeBool | ParameterValueType::TypeF32 | ParameterValueType::TypeF64, Value::Integer(i)) => Ok(Some(*i as f64)),
+        (ParameterValueType::TypeBool | ParameterValueType::TypeF32 | ParameterValueType::TypeF64, Value::Real(f)) => Ok(Some(*f)),
+        (_, Value::Null) => Ok(None),
+        _ => Err(mismatch()),
+    }
+}
+
+fn check_validation(parameter: &Parameter, stored: f64) -> Option<String> {
+    match &parameter.validation {
+        ValidationMethod::Range { min, max } => {
+            let (min, max) = (numeric(min)?, numeric(max)?);
+            if stored < min || stored > max {
+                return Some(format!("stored value {} is outside the range {}..{} and will be clamped", stored, min, max));
+            }
+            None
+        }
+        ValidationMethod::AllowedValues { values, .. } => {
+            if values.iter().filter_map(numeric).any(|value| value == stored) {
+                return None;
+            }
+            Some(format!("stored value {} is not one of the allowed values, the closest one will be used", stored))
+        }
+        ValidationMethod::None | ValidationMethod::Text { .. } | ValidationMethod::CustomCallback => None,
+    }
+}
+
+fn check_text(parameter: &Parameter, stored: &str) -> Option<String> {
+    let ValidationMethod::Text { max_length, pattern } = &parameter.validation else {
+        return None;
+    };
+    if let Some(max_length) = max_length {
+        let length = stored.chars().count();
+        if length > *max_length as usize {
+            return Some(format!("stored string of {} characters is longer than {} and will be rejected on the next write", length, max_length));
+        }
+    }
+    let pattern = (*pattern)?;
+    let regex = Regex::new(&format!("^(?:{})$", pattern)).ok()?;
+    if !regex.is_match(stored) {
+        return Some(format!("stored string doesn't match the pattern {}", pattern));
+    }
+    None
+}
+
+/// Compare the parameters with the values stored in a deployed database: removed keys,
+/// type changes and values that the new validation rules reject
+pub(crate) fn check_database_compatibility(
+    parameters: &Vec<Parameter>,
+    database_path: &Path,
+) -> Result<Vec<CompatibilityIssue>, Box<dyn std::error::Error>> {
+    let conn = Connection::open_with_flags(database_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(&format!("SELECT key, value FROM {}", TABLE_NAME))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<usize, String>(0)?, row.get::<usize, Value>(1)?)))?;
+
+    let mut issues = Vec::new();
+    for row in rows {
+        let (key, stored) = row?;
+        let Some(parameter) = parameters.iter().find(|parameter| parameter.name_id == key) else {
+            issues.push(issue(&key, "removed", "the parameter is not defined anymore, the stored value is ignored".to_owned()));
+            continue;
+        };
+        match check_type(parameter, &stored) {
+            Ok(Some(number)) => {
+                if let Some(message) = check_validation(parameter, number) {
+                    issues.push(issue(&key, "validation", message));
+                }
+            }
+            Ok(None) => {
+                if let Value::Text(text) = &stored {
+                    if let Some(message) = check_text(parameter, text) {
+                        issues.push(issue(&key, "validation", message));
+                    }
+                }
+            }
+            Err(message) => issues.push(issue(&key, "type", message)),
+        }
+    }
+    Ok(issues)
+}
+
+pub(crate) fn write_compatibility_report(issues: &[CompatibilityIssue], database_path: &Path, report_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let report = json!({
+        "database": database_path.to_string_lossy(),
+        "issues": issues.iter().map(|issue| json!({
+            "key": issue.key,
+            "kind": issue.kind,
+            "message": issue.message,
+        })).collect::<Vec<_>>(),
+    });
+    fs::write(report_path, serde_json::to_string_pretty(&report)?)?;
+    Ok(())
+}
@@ -0,0 +1,326 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::file_generator::{get_parameter_name_for_enum, get_parameter_name_for_function};
+use crate::schema::{Parameter, ParameterValueType};
+
+/// The part of the wrapper that doesn't depend on the parameters
+const CPP_WRAPPER_HEAD: &str = r#"/*
+* Auto-generated - DO NOT EDIT
+* C++ wrapper of the econfmanager C API, requires C++17
+*/
+
+#pragma once
+
+#include "econfmanager.h"
+
+#include <array>
+#include <cstdint>
+#include <functional>
+#include <map>
+#include <memory>
+#include <mutex>
+#include <stdexcept>
+#include <string>
+#include <type_traits>
+#include <utility>
+#include <vector>
+
+namespace Econf {
+
+/// Failure of a library call, with the status and the message from econf_get_last_error
+class Error : public std::runtime_error {
+public:
+    Error(EconfStatus status, const std::string &message) : std::runtime_error(message), status_(status) {}
+
+    EconfStatus status() const { return status_; }
+
+private:
+    EconfStatus status_;
+};
+
+/// Parameter change event, the source is empty if unknown
+struct ChangeEvent {
+    ParameterId id;
+    std::string source;
+    double timestamp;
+    uint64_t sequence;
+};
+
+using ChangeCallback = std::function<void(const ChangeEvent &)>;
+
+/// Library instance, initialized in the constructor and released in the destructor
+class Manager {
+public:
+    Manager(const std::string &database_path, const std::string &saved_database_path, const std::string &default_data_folder) {
+        check(econf_init(database_path.c_str(), saved_database_path.c_str(), default_data_folder.c_str(), &interface_), nullptr);
+    }
+
+    /// With the device key of the secret parameters
+    Manager(const std::string &database_path, const std::string &saved_database_path, const std::string &default_data_folder,
+            const std::array<uint8_t, 32> &secret_key) {
+        check(econf_init_with_secret_key(database_path.c_str(), saved_database_path.c_str(), default_data_folder.c_str(),
+                                         secret_key.data(), secret_key.size(), &interface_), nullptr);
+    }
+
+    ~Manager() {
+        if (interface_ != nullptr) {
+            // Stops the library threads, so no callback runs after this
+            econf_deinit(interface_);
+        }
+    }
+
+    Manager(const Manager &) = delete;
+    Manager &operator=(const Manager &) = delete;
+
+    Manager(Manager &&other) noexcept
+        : interface_(std::exchange(other.interface_, nullptr)), callbacks_(std::move(other.callbacks_)) {}
+
+    Manager &operator=(Manager &&other) noexcept {
+        if (this != &other) {
+            if (interface_ != nullptr) {
+                econf_deinit(interface_);
+            }
+            interface_ = std::exchange(other.interface_, nullptr);
+            callbacks_ = std::move(other.callbacks_);
+        }
+        return *this;
+    }
+
+    /// The instance for the C functions
+    CInterfaceInstance *handle() const { return interface_; }
+
+    /// Read a parameter. T is bool, the integer and floating point types of the parameters,
+    /// std::string, std::vector<uint8_t> or an enum type
+    template <typename T>
+    T get(ParameterId id) const { return read_value<T>(&econf_get_value, id); }
+
+    /// The staged value of the parameter, the current value if no change is staged
+    template <typename T>
+    T get_staged(ParameterId id) const { return read_value<T>(&econf_get_staged, id); }
+
+    /// Write a parameter, the type must match the parameter. Returns whether the value was changed or clamped
+    template <typename T>
+    EconfSetResult set(ParameterId id, const T &parameter) {
+        EconfSetResult result = SetChanged;
+        check(econf_set_value(interface_, id, to_value(parameter), &result), interface_);
+        return result;
+    }
+
+    EconfSetResult set(ParameterId id, const char *parameter) { return set(id, std::string(parameter)); }
+
+    /// Write a parameter only if it was not written since `expected_sequence`,
+    /// throws `Econf::Error` with `StatusConflict` otherwise
+    template <typename T>
+    EconfSetResult set_if(ParameterId id, const T &parameter, uint64_t expected_sequence) {
+        EconfSetResult result = SetChanged;
+        check(econf_set_value_if(interface_, id, to_value(parameter), expected_sequence, &result), interface_);
+        return result;
+    }
+
+    /// Stage a change, written with the other staged changes by `apply_staged`
+    template <typename T>
+    void set_staged(ParameterId id, const T &parameter) {
+        check(econf_set_staged(interface_, id, to_value(parameter)), interface_);
+    }
+
+    void set_staged(ParameterId id, const char *parameter) { set_staged(id, std::string(parameter)); }
+
+    /// Write the staged changes together, none of them if any is not accepted
+    void apply_staged() { check(econf_apply_staged(interface_), interface_); }
+    void discard_staged() { check(econf_discard_staged(interface_), interface_); }
+
+    /// Sequence number of the last write of the parameter for `set_if`, 0 if it was never written
+    uint64_t get_sequence(ParameterId id) {
+        uint64_t sequence = 0;
+        check(econf_get_sequence(interface_, id, &sequence), interface_);
+        return sequence;
+    }
+
+    /// The value refers to the data of the parameter, which must outlive it
+    template <typename T>
+    static EconfValue to_value(const T &parameter) {
+        EconfValue value{};
+        if constexpr (std::is_same_v<T, std::string>) {
+            value.value_type = ValueString;
+            value.data.val_buffer = EconfBuffer{reinterpret_cast<uint8_t *>(const_cast<char *>(parameter.c_str())), parameter.size() + 1, parameter.size() + 1};
+        } else if constexpr (std::is_same_v<T, std::vector<uint8_t>>) {
+            value.value_type = ValueBlob;
+            value.data.val_buffer = EconfBuffer{const_cast<uint8_t *>(parameter.data()), parameter.size(), parameter.size()};
+        } else if constexpr (std::is_enum_v<T>) {
+            value.value_type = ValueEnum;
+            value.data.val_enum = static_cast<int32_t>(parameter);
+        } else {
+            value.value_type = value_type<T>();
+            write<T>(value.data, parameter);
+        }
+        return value;
+    }
+
+    /// Add a change callback of the parameter, it is called from the library threads.
+    /// The function is kept until the manager is destroyed, a call can still run in another thread after the removal
+    CallbackHandle add_callback(ParameterId id, ChangeCallback callback) {
+        auto function = std::make_unique<ChangeCallback>(std::move(callback));
+        CallbackHandle callback_handle = 0;
+        check(econf_add_callback_ex(interface_, id, &Manager::call, function.get(), &callback_handle), interface_);
+        std::lock_guard<std::mutex> lock(*callbacks_mutex_);
+        callbacks_[callback_handle] = std::move(function);
+        return callback_handle;
+    }
+
+    void delete_callback(CallbackHandle callback_handle) {
+        check(econf_delete_callback_handle(interface_, callback_handle), interface_);
+    }
+
+    void save() { check(econf_save(interface_), interface_); }
+    void load() { check(econf_load(interface_), interface_); }
+    void factory_reset() { check(econf_factory_reset(interface_), interface_); }
+    void refresh_defaults() { check(econf_refresh_defaults(interface_), interface_); }
+    void maintain() { check(econf_maintain(interface_), interface_); }
+    void update_poll() { check(econf_update_poll(interface_), interface_); }
+    void set_up_timer_poll(int64_t period_ms) { check(econf_set_up_timer_poll(interface_, period_ms), interface_); }
+    void stop_timer_poll() { check(econf_stop_timer_poll(interface_), interface_); }
+    void start_autosave(int64_t period_ms, bool only_if_dirty = true) { check(econf_start_autosave(interface_, period_ms, only_if_dirty), interface_); }
+    void stop_autosave() { check(econf_stop_autosave(interface_), interface_); }
+"#;
+
+const CPP_WRAPPER_TAIL: &str = r#"
+private:
+    using Reader = decltype(&econf_get_value);
+
+    template <typename T>
+    T read_value(Reader reader, ParameterId id) const {
+        EconfValue value{};
+        if constexpr (std::is_same_v<T, std::string> || std::is_same_v<T, std::vector<uint8_t>>) {
+            // Ask for the length first, repeat if the value grew in between
+            std::vector<uint8_t> buffer;
+            for (;;) {
+                value.data.val_buffer = EconfBuffer{buffer.data(), buffer.size(), 0};
+                check(reader(interface_, id, &value), interface_);
+                expect_type(value, std::is_same_v<T, std::string> ? ValueString : ValueBlob);
+                if (value.data.val_buffer.length <= buffer.size() && (buffer.size() > 0 || value.data.val_buffer.length == 0)) {
+                    break;
+                }
+                buffer.resize(value.data.val_buffer.length);
+            }
+            buffer.resize(value.data.val_buffer.length);
+            if constexpr (std::is_same_v<T, std::string>) {
+                return buffer.empty() ? std::string() : std::string(buffer.begin(), buffer.end() - 1);
+            } else {
+                return buffer;
+            }
+        } else {
+            check(reader(interface_, id, &value), interface_);
+            if constexpr (std::is_enum_v<T>) {
+                expect_type(value, ValueEnum);
+                return static_cast<T>(value.data.val_enum);
+            } else {
+                expect_type(value, value_type<T>());
+                return read<T>(value.data);
+            }
+        }
+    }
+
+    static void check(EconfStatus status, CInterfaceInstance *interface) {
+        if (status == StatusOk) {
+            return;
+        }
+        char message[512] = {0};
+        econf_get_last_error(interface, message, sizeof(message));
+        throw Error(status, message);
+    }
+
+    static void expect_type(const EconfValue &value, EconfValueType type) {
+        if (value.value_type != type) {
+            throw Error(StatusTypeMismatch, "Parameter type doesn't match the requested type");
+        }
+    }
+
+    template <typename T>
+    static constexpr EconfValueType value_type() {
+        if constexpr (std::is_same_v<T, bool>) return ValueBool;
+        else if constexpr (std::is_same_v<T, int32_t>) return ValueI32;
+        else if constexpr (std::is_same_v<T, uint32_t>) return ValueU32;
+        else if constexpr (std::is_same_v<T, int64_t>) return ValueI64;
+        else if constexpr (std::is_same_v<T, uint64_t>) return ValueU64;
+        else if constexpr (std::is_same_v<T, float>) return ValueF32;
+        else if constexpr (std::is_same_v<T, double>) return ValueF64;
+        else static_assert(!sizeof(T), "Unsupported parameter type");
+    }
+
+    template <typename T>
+    static T read(const EconfValueData &data) {
+        if constexpr (std::is_same_v<T, bool>) return data.val_bool;
+        else if constexpr (std::is_same_v<T, int32_t>) return data.val_i32;
+        else if constexpr (std::is_same_v<T, uint32_t>) return data.val_u32;
+        else if constexpr (std::is_same_v<T, int64_t>) return data.val_i64;
+        else if constexpr (std::is_same_v<T, uint64_t>) return data.val_u64;
+        else if constexpr (std::is_same_v<T, float>) return data.val_f32;
+        else return data.val_f64;
+    }
+
+    template <typename T>
+    static void write(EconfValueData &data, T value) {
+        if constexpr (std::is_same_v<T, bool>) data.val_bool = value;
+        else if constexpr (std::is_same_v<T, int32_t>) data.val_i32 = value;
+        else if constexpr (std::is_same_v<T, uint32_t>) data.val_u32 = value;
+        else if constexpr (std::is_same_v<T, int64_t>) data.val_i64 = value;
+        else if constexpr (std::is_same_v<T, uint64_t>) data.val_u64 = value;
+        else if constexpr (std::is_same_v<T, float>) data.val_f32 = value;
+        else data.val_f64 = value;
+    }
+
+    static void call(const EconfParameterChangeEvent *event, void *user_data) {
+        ChangeEvent change{event->id, event->source != nullptr ? event->source : "", event->timestamp, event->sequence};
+        (*static_cast<ChangeCallback *>(user_data))(change);
+    }
+
+    CInterfaceInstance *interface_ = nullptr;
+    std::unique_ptr<std::mutex> callbacks_mutex_ = std::make_unique<std::mutex>();
+    std::map<CallbackHandle, std::unique_ptr<ChangeCallback>> callbacks_;
+};
+
+} // namespace Econf
+"#;
+
+fn cpp_type(value_type: &ParameterValueType) -> Option<String> {
+    Some(match value_type {
+        ParameterValueType::TypeBool => "bool".to_owned(),
+        ParameterValueType::TypeI32 => "int32_t".to_owned(),
+        ParameterValueType::TypeU32 => "uint32_t".to_owned(),
+        ParameterValueType::TypeI64 => "int64_t".to_owned(),
+        ParameterValueType::TypeU64 => "uint64_t".to_owned(),
+        ParameterValueType::TypeF32 => "float".to_owned(),
+        ParameterValueType::TypeF64 => "double".to_owned(),
+        ParameterValueType::TypeString => "std::string".to_owned(),
+        ParameterValueType::TypeBlob => "std::vector<uint8_t>".to_owned(),
+        ParameterValueType::TypeEnum(name) => format!("{}_t", name),
+        ParameterValueType::TypeNone => return None,
+    })
+}
+
+/// Generates `econfmanager.hpp` with the `Econf::Manager` class and a typed getter and setter for every parameter
+pub(crate) fn generate_cpp_wrapper(parameters: &Vec<Parameter>, header_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = File::create(header_path)?;
+    write!(f, "{}", CPP_WRAPPER_HEAD)?;
+
+    for p in parameters {
+        let Some(value_type) = cpp_type(&p.value_type) else {
+            continue;
+        };
+        let pm_name = get_parameter_name_for_function(&p.name_id.to_string());
+        let pm_id_name = get_parameter_name_for_enum(&p.name_id.to_string());
+        let title = if p.title.is_empty() { p.name_id.to_string() } else { p.title.to_string() };
+
+        writeln!(f)?;
+        writeln!(f, "    /// {}", title)?;
+        writeln!(f, "    {value_type} {pm_name}() const {{ return get<{value_type}>({pm_id_name}); }}")?;
+        if !p.is_const && !p.computed {
+            writeln!(f, "    void set_{pm_name}(const {value_type} &value) {{ set<{value_type}>({pm_id_name}, value); }}")?;
+        }
+    }
+
+    write!(f, "{}", CPP_WRAPPER_TAIL)?;
+    Ok(())
+}
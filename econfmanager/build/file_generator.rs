@@ -1,6 +1,6 @@
 use std::fs;
 use std::process::Command;
-use std::{collections::HashSet, fs::File};
+use std::{collections::{HashMap, HashSet}, fs::File};
 use std::io::Write;
 use std::path::Path;
 
@@ -9,7 +9,7 @@ use regex::Regex;
 use schema::{Parameter, ParameterValue, ValidationMethod};
 
 
-fn get_parameter_name_for_enum(name_id: &String) -> String {
+pub(crate) fn get_parameter_name_for_enum(name_id: &String) -> String {
     name_id
         .split('@')
         .map(|part| part.to_uppercase())
@@ -17,7 +17,7 @@ fn get_parameter_name_for_enum(name_id: &String) -> String {
         .join("_")
 }
 
-fn get_parameter_name_for_function(name_id: &String) -> String {
+pub(crate) fn get_parameter_name_for_function(name_id: &String) -> String {
     name_id.split('@').collect::<Vec<_>>().join("_")
 }
 
@@ -80,20 +80,40 @@ pub(crate) fn generate_parameter_ids(
         })
         .collect();
 
+    check_parameter_ids(parameters)?;
+
     let dest_path = Path::new(&build_dir).join("parameter_ids.proto");
     let mut f = File::create(dest_path)?;
 
+    // The first value of a proto3 enum must be zero
+    let mut ids: Vec<(usize, &String)> = parameters.iter().map(|parameter| parameter.id).zip(enum_variants.iter()).collect();
+    ids.sort_by_key(|(id, _)| *id);
+
     writeln!(f, "// Auto-generated. See build.rs")?;
     writeln!(f, "syntax = \"proto3\";")?;
     writeln!(f, "package parameter_ids;")?;
     writeln!(f, "enum ParameterIdApi {{")?;
-    for (index, variant) in enum_variants.iter().enumerate() {
-        writeln!(f, "{} = {};", variant, index)?;
+    if ids.first().is_some_and(|(id, _)| *id != 0) {
+        writeln!(f, "    PARAMETER_ID_UNSPECIFIED = 0;")?;
+    }
+    for (id, variant) in ids {
+        writeln!(f, "{} = {};", variant, id)?;
     }
     writeln!(f, "}}")?;
     Ok(())
 }
 
+/// The IDs are sent in the notifications, two parameters with the same ID can't be told apart
+fn check_parameter_ids(parameters: &Vec<Parameter>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut used: HashMap<usize, &str> = HashMap::new();
+    for parameter in parameters {
+        if let Some(other) = used.insert(parameter.id, parameter.name_id) {
+            return Err(format!("Parameter {} ID {} is already used by {}", parameter.name_id, parameter.id, other).into());
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn generate_parameter_enum(
     parameters: &Vec<Parameter>,
     groups: &Vec<Group>,
@@ -109,6 +129,8 @@ pub(crate) fn generate_parameter_enum(
         })
         .collect();
 
+    check_parameter_ids(parameters)?;
+
     let dest_path = Path::new(&build_dir).join("generated.rs");
     let mut f = File::create(dest_path)?;
 
@@ -116,25 +138,68 @@ pub(crate) fn generate_parameter_enum(
     writeln!(f, "use std::borrow::Cow;")?;
     writeln!(
         f,
-        "use crate::schema::{{Parameter, ParameterValue, ParameterValueType, ValidationMethod, Group}};"
+        "use crate::schema::{{AccessLevel, Parameter, ParameterValue, ParameterValueType, PersistPolicy, ValidationMethod, Group}};"
     )?;
     writeln!(f, "/// Auto‐generated. See build.rs")?;
 
     writeln!(f, "#[repr(usize)]")?;
     writeln!(
         f,
-        "#[derive(TryFromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]"
+        "#[derive(TryFromPrimitive, Debug, Clone, Copy, PartialEq, Eq, Hash)]"
     )?;
     writeln!(f, "#[allow(non_camel_case_types)]")?;
     writeln!(f, "pub enum ParameterId {{")?;
-    for (index, variant) in enum_variants.iter().enumerate() {
-        writeln!(f, "    {} = {},", variant, index)?;
+    for (parameter, variant) in parameters.iter().zip(enum_variants.iter()) {
+        writeln!(f, "    {} = {},", variant, parameter.id)?;
     }
-    writeln!(f, "    INVALID_PARAMETER")?;
+    writeln!(f, "    INVALID_PARAMETER = {}", parameters.iter().map(|parameter| parameter.id + 1).max().unwrap_or(0))?;
     writeln!(f, "}}\n")?;
 
     writeln!(f, "pub const PARAMETERS_NUM:usize = {};\n", enum_variants.len())?;
 
+    // The IDs can have gaps, PARAMETER_DATA is indexed by the position of the parameter
+    writeln!(f, "impl ParameterId {{")?;
+    writeln!(f, "    /// Position of the parameter in PARAMETER_DATA, PARAMETERS_NUM for INVALID_PARAMETER")?;
+    writeln!(f, "    pub const fn index(self) -> usize {{")?;
+    writeln!(f, "        match self {{")?;
+    for (index, variant) in enum_variants.iter().enumerate() {
+        writeln!(f, "            ParameterId::{} => {},", variant, index)?;
+    }
+    writeln!(f, "            ParameterId::INVALID_PARAMETER => PARAMETERS_NUM,")?;
+    writeln!(f, "        }}")?;
+    writeln!(f, "    }}\n")?;
+    writeln!(f, "    /// The parameter at the position in PARAMETER_DATA")?;
+    writeln!(f, "    pub const fn from_index(index: usize) -> Option<ParameterId> {{")?;
+    writeln!(f, "        match index {{")?;
+    for (index, variant) in enum_variants.iter().enumerate() {
+        writeln!(f, "            {} => Some(ParameterId::{}),", index, variant)?;
+    }
+    writeln!(f, "            _ => None,")?;
+    writeln!(f, "        }}")?;
+    writeln!(f, "    }}\n")?;
+    writeln!(f, "    /// The parameter with the name (\"group@parameter\"), a binary search of PARAMETER_NAMES_SORTED")?;
+    writeln!(f, "    pub fn from_name(name: &str) -> Option<ParameterId> {{")?;
+    writeln!(f, "        PARAMETER_NAMES_SORTED")?;
+    writeln!(f, "            .binary_search_by(|(parameter_name, _)| (*parameter_name).cmp(name))")?;
+    writeln!(f, "            .ok()")?;
+    writeln!(f, "            .map(|position| PARAMETER_NAMES_SORTED[position].1)")?;
+    writeln!(f, "    }}")?;
+    writeln!(f, "}}\n")?;
+
+    // Sorted by the bytes of the names, the same order as str::cmp used by the binary search
+    let mut sorted_names: Vec<(&str, &String)> = parameters
+        .iter()
+        .zip(enum_variants.iter())
+        .map(|(parameter, variant)| (parameter.name_id, variant))
+        .collect();
+    sorted_names.sort();
+    writeln!(f, "/// The names of the parameters in sorted order, for the lookups by name")?;
+    writeln!(f, "pub const PARAMETER_NAMES_SORTED: &[(&str, ParameterId)] = &[")?;
+    for (name, variant) in sorted_names {
+        writeln!(f, "    ({:?}, ParameterId::{}),", name, variant)?;
+    }
+    writeln!(f, "];\n")?;
+
     writeln!(f, "pub const PARAMETER_DATA: &'static [Parameter] = &[")?;
     for p in parameters{
         let value_type = format_anyvalue_type(&p.value_type);
@@ -163,6 +228,11 @@ pub(crate) fn generate_parameter_enum(
                     str_names
                 )
             }
+            ValidationMethod::Text { max_length, pattern } => format!(
+                "ValidationMethod::Text {{ max_length: {:?}, pattern: {:?} }}",
+                max_length,
+                pattern,
+            ),
             ValidationMethod::CustomCallback => todo!(),
         };
         let tags_code = p
@@ -175,7 +245,7 @@ pub(crate) fn generate_parameter_enum(
         writeln!(f, "        Parameter {{")?;
         writeln!(f, "            value_type: {},", value_type)?;
         writeln!(f, "            value_default: {},", value_default)?;
-        // writeln!(f, "            id: {:?},", enum_variants[idx])?;
+        writeln!(f, "            id: {},", p.id)?;
         writeln!(f, "            name_id: {:?},", p.name_id)?;
         writeln!(f, "            validation: {},", validation_code)?;
         writeln!(f, "            comment: {:?},", p.comment)?;
@@ -185,6 +255,20 @@ pub(crate) fn generate_parameter_enum(
         writeln!(f, "            runtime: {},", p.runtime)?;
         writeln!(f, "            readonly: {},", p.readonly)?;
         writeln!(f, "            internal: {},", p.internal)?;
+        writeln!(f, "            blob_schema: {:?},", p.blob_schema)?;
+        writeln!(f, "            unit: {:?},", p.unit)?;
+        writeln!(f, "            step: {:?},", p.step)?;
+        writeln!(f, "            precision: {:?},", p.precision)?;
+        writeln!(f, "            widget: {:?},", p.widget)?;
+        writeln!(f, "            deprecated: {},", p.deprecated)?;
+        writeln!(f, "            hidden: {},", p.hidden)?;
+        writeln!(f, "            access_level: AccessLevel::{:?},", p.access_level)?;
+        writeln!(f, "            allow_non_finite: {},", p.allow_non_finite)?;
+        writeln!(f, "            max_size: {:?},", p.max_size)?;
+        writeln!(f, "            content_type: {:?},", p.content_type)?;
+        writeln!(f, "            persist_policy: PersistPolicy::{:?},", p.persist_policy)?;
+        writeln!(f, "            secret: {},", p.secret)?;
+        writeln!(f, "            computed: {},", p.computed)?;
         writeln!(f, "        }},")?;
     }
     writeln!(f, "];\n\n")?;
@@ -203,7 +287,7 @@ pub(crate) fn generate_parameter_enum(
     Ok(())
 }
 
-fn value_to_string(value: &ParameterValue) -> String {
+pub(crate) fn value_to_string(value: &ParameterValue) -> String {
     match value {
         ParameterValue::ValBool(b) => b.to_string(),
         ParameterValue::ValI32(i) => i.to_string(),
@@ -233,7 +317,7 @@ pub(crate) fn generate_parameter_functions(
         writeln!(f, "use std::ffi::c_char;")?;
         writeln!(f, "#[allow(unused_imports)]")?;
         writeln!(f, "use crate::{{")?;
-        writeln!(f, "lib_helper_functions::{{get_parameter, get_parameter_quick, set_parameter, get_string, set_string, get_blob, set_blob}}, generated::ParameterId, CInterfaceInstance, EconfStatus}};\n")?;
+        writeln!(f, "lib_helper_functions::{{get_parameter, get_parameter_default, get_parameter_quick, set_parameter, get_string, get_string_default, set_string, get_blob, get_blob_default, set_blob}}, generated::ParameterId, CInterfaceInstance, EconfStatus}};\n")?;
         writeln!(f, "use num_derive::FromPrimitive;")?;
         writeln!(f, "use num_traits::FromPrimitive;")?;
 
@@ -243,19 +327,27 @@ pub(crate) fn generate_parameter_functions(
             let pm_name = get_parameter_name_for_function(&p.name_id.to_string());
             let pm_id_name = get_parameter_name_for_enum(&p.name_id.to_string());
             let short_name = get_parameter_name_short(&p.name_id.to_string());
+            let doc = ParameterDoc::new(p);
+            // Only the getters for the computed parameters
+            let is_const = p.is_const || p.computed;
 
             match &p.value_type {
                 ParameterValueType::TypeNone => todo!(),
-                ParameterValueType::TypeBool => write_general_setter_and_getter(&mut f, "bool".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeI32 => write_general_setter_and_getter(&mut f, "i32".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeU32 => write_general_setter_and_getter(&mut f, "u32".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeI64 => write_general_setter_and_getter(&mut f, "i64".to_owned(), pm_name,short_name, pm_id_name,  p.is_const)?,
-                ParameterValueType::TypeU64 => write_general_setter_and_getter(&mut f, "u64".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeF32 => write_general_setter_and_getter(&mut f, "f32".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeF64 => write_general_setter_and_getter(&mut f, "f64".to_owned(), pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeString => write_string_setter_and_getter(&mut f, pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeBlob => write_blob_setter_and_getter(&mut f, pm_name, short_name, pm_id_name, p.is_const)?,
-                ParameterValueType::TypeEnum(p_enum_name) => write_enum_setter_and_getter(&mut f, p_enum_name.to_string(), pm_name, short_name, pm_id_name, p.is_const, &p.validation, &mut enums)?,
+                ParameterValueType::TypeBool => write_general_setter_and_getter(&mut f, "bool".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeI32 => write_general_setter_and_getter(&mut f, "i32".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeU32 => write_general_setter_and_getter(&mut f, "u32".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeI64 => write_general_setter_and_getter(&mut f, "i64".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeU64 => write_general_setter_and_getter(&mut f, "u64".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeF32 => write_general_setter_and_getter(&mut f, "f32".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeF64 => write_general_setter_and_getter(&mut f, "f64".to_owned(), pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeString => write_string_setter_and_getter(&mut f, pm_name, short_name, pm_id_name, is_const, &doc)?,
+                ParameterValueType::TypeBlob => {
+                    if let Some(blob_schema) = p.blob_schema {
+                        write_blob_decoded_helpers(&mut f, blob_schema, pm_name.clone(), pm_id_name.clone(), is_const)?;
+                    }
+                    write_blob_setter_and_getter(&mut f, pm_name, short_name, pm_id_name, is_const, &doc)?
+                },
+                ParameterValueType::TypeEnum(p_enum_name) => write_enum_setter_and_getter(&mut f, p_enum_name.to_string(), pm_name, short_name, pm_id_name, is_const, &doc, &p.validation, &mut enums)?,
             }
         }
     }
@@ -267,8 +359,45 @@ pub(crate) fn generate_parameter_functions(
     Ok(())
 }
 
-fn write_string_setter_and_getter(f: &mut File, pm_name: String, short_name: String, pm_id_name: String, is_const: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Doxygen comments of the generated functions, cbindgen copies them to the header
+struct ParameterDoc {
+    brief: String,
+    details: Vec<String>,
+}
+
+impl ParameterDoc {
+    fn new(p: &Parameter) -> Self {
+        let brief = if p.title.is_empty() { p.name_id.to_string() } else { p.title.to_string() };
+        let details = p.comment.lines().map(|line| line.trim().to_owned()).filter(|line| !line.is_empty()).collect();
+        ParameterDoc { brief, details }
+    }
+
+    /// The comment lines, the first one is indented by the template
+    fn format(&self, indent: &str, action: &str, params: &[(&str, &str)], ret: &str) -> String {
+        let mut lines = vec![format!("/// @brief {} {}", action, self.brief)];
+        if !self.details.is_empty() {
+            lines.push("///".to_owned());
+            lines.extend(self.details.iter().map(|line| format!("/// {}", line)));
+        }
+        lines.push("///".to_owned());
+        lines.extend(params.iter().map(|(name, description)| format!("/// @param {} {}", name, description)));
+        lines.push(format!("/// @return {}", ret));
+        lines.join(&format!("\n{}", indent))
+    }
+}
+
+fn write_string_setter_and_getter(f: &mut File, pm_name: String, short_name: String, pm_id_name: String, is_const: bool, doc: &ParameterDoc) -> Result<(), Box<dyn std::error::Error>> {
+    let buffer_params = [
+        ("interface", "Library interface"),
+        (short_name.as_str(), "Buffer for the null-terminated string"),
+        ("max_len", "Size of the buffer"),
+        ("out_len", "Length of the string, can be NULL"),
+    ];
+    let get_doc = doc.format("        ", "Get", &buffer_params, "Status of the operation");
+    let default_doc = doc.format("        ", "Get the default of", &buffer_params, "Status of the operation");
+    let set_doc = doc.format("            ", "Set", &[("interface", "Library interface"), (short_name.as_str(), "Null-terminated string")], "Status of the operation");
     writeln!(f, r#"
+        {get_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}(
             interface: *const CInterfaceInstance,
@@ -278,10 +407,22 @@ fn write_string_setter_and_getter(f: &mut File, pm_name: String, short_name: Str
         ) -> EconfStatus {{
             get_string(interface, ParameterId::{pm_id_name}, {short_name}, max_len, out_len)
         }}
+
+        {default_doc}
+        #[unsafe(no_mangle)]
+        pub extern "C" fn get_{pm_name}_default(
+            interface: *const CInterfaceInstance,
+            {short_name}: *mut c_char,
+            max_len: usize,
+            out_len: *mut usize
+        ) -> EconfStatus {{
+            get_string_default(interface, ParameterId::{pm_id_name}, {short_name}, max_len, out_len)
+        }}
     "#)?;
             
     if !is_const {
         writeln!(f, r#"
+            {set_doc}
             #[unsafe(no_mangle)]
             pub extern "C" fn set_{pm_name}(
                 interface: *const CInterfaceInstance,
@@ -295,8 +436,18 @@ fn write_string_setter_and_getter(f: &mut File, pm_name: String, short_name: Str
     Ok(())
 }
 
-fn write_blob_setter_and_getter(f: &mut File, pm_name: String, short_name: String, pm_id_name: String, is_const: bool) -> Result<(), Box<dyn std::error::Error>> {
+fn write_blob_setter_and_getter(f: &mut File, pm_name: String, short_name: String, pm_id_name: String, is_const: bool, doc: &ParameterDoc) -> Result<(), Box<dyn std::error::Error>> {
+    let buffer_params = [
+        ("interface", "Library interface"),
+        (short_name.as_str(), "Buffer for the data, NULL to get only the length"),
+        ("max_len", "Size of the buffer"),
+        ("out_len", "Length of the data, can be NULL"),
+    ];
+    let get_doc = doc.format("        ", "Get", &buffer_params, "Status of the operation");
+    let default_doc = doc.format("        ", "Get the default of", &buffer_params, "Status of the operation");
+    let set_doc = doc.format("            ", "Set", &[("interface", "Library interface"), (short_name.as_str(), "Data"), ("len", "Length of the data")], "Status of the operation");
     writeln!(f, r#"
+        {get_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}(
             interface: *const CInterfaceInstance,
@@ -306,10 +457,22 @@ fn write_blob_setter_and_getter(f: &mut File, pm_name: String, short_name: Strin
         ) -> EconfStatus {{
             get_blob(interface, ParameterId::{pm_id_name}, {short_name}, max_len, out_len)
         }}
+
+        {default_doc}
+        #[unsafe(no_mangle)]
+        pub extern "C" fn get_{pm_name}_default(
+            interface: *const CInterfaceInstance,
+            {short_name}: *mut u8,
+            max_len: usize,
+            out_len: *mut usize,
+        ) -> EconfStatus {{
+            get_blob_default(interface, ParameterId::{pm_id_name}, {short_name}, max_len, out_len)
+        }}
     "#)?;
             
     if !is_const {
         writeln!(f, r#"
+            {set_doc}
             #[unsafe(no_mangle)]
             pub extern "C" fn set_{pm_name}(
                 interface: *const CInterfaceInstance,
@@ -324,11 +487,58 @@ fn write_blob_setter_and_getter(f: &mut File, pm_name: String, short_name: Strin
     Ok(())
 }
 
-fn write_general_setter_and_getter(f: &mut File, pm_type: String, pm_name: String, short_name: String, pm_id_name: String, is_const: bool) -> Result<(), Box<dyn std::error::Error>> {
+/// Converts a fully qualified proto message name (package.Message) to the path of the prost generated type
+fn get_message_rust_path(full_name: &str) -> String {
+    let mut parts: Vec<&str> = full_name.split('.').collect();
+    let message = parts.pop().unwrap_or(full_name);
+    let mut path = vec!["crate"];
+    path.extend(parts);
+    path.push(message);
+    path.join("::")
+}
+
+fn write_blob_decoded_helpers(f: &mut File, blob_schema: &str, pm_name: String, pm_id_name: String, is_const: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let message_path = get_message_rust_path(blob_schema);
+    writeln!(f, r#"
+        /// Read the blob and decode it as `{blob_schema}`
+        pub fn get_{pm_name}_decoded(
+            interface: &crate::interface::InterfaceInstance
+        ) -> Result<{message_path}, Box<dyn std::error::Error>> {{
+            match interface.get(ParameterId::{pm_id_name}, false)? {{
+                crate::schema::ParameterValue::ValBlob(blob) => Ok(<{message_path} as prost::Message>::decode(blob.as_slice())?),
+                other => Err(format!("Unexpected value {{}} for blob {pm_name}", other).into()),
+            }}
+        }}
+    "#)?;
+
+    if !is_const {
+        writeln!(f, r#"
+            /// Encode `{blob_schema}` and write it as the blob value
+            pub fn set_{pm_name}_encoded(
+                interface: &crate::interface::InterfaceInstance,
+                message: &{message_path}
+            ) -> Result<(), Box<dyn std::error::Error>> {{
+                let blob = <{message_path} as prost::Message>::encode_to_vec(message);
+                interface.set(ParameterId::{pm_id_name}, crate::schema::ParameterValue::ValBlob(blob))?;
+                Ok(())
+            }}
+        "#)?;
+    }
+
+    Ok(())
+}
+
+fn write_general_setter_and_getter(f: &mut File, pm_type: String, pm_name: String, short_name: String, pm_id_name: String, is_const: bool, doc: &ParameterDoc) -> Result<(), Box<dyn std::error::Error>> {
+    let get_doc = doc.format("        ", "Get", &[("interface", "Library interface"), (short_name.as_str(), "Output value")], "Status of the operation");
+    let default_doc = doc.format("        ", "Get the default of", &[("interface", "Library interface"), (short_name.as_str(), "Output value")], "Status of the operation");
+    let quick_doc = doc.format("        ", "Get", &[("interface", "Library interface"), ("ok", "Set to false when the default is returned after an error, can be NULL")], "The value, or the default on error");
+    let result_name = format!("{short_name}_result");
+    let set_doc = doc.format("            ", "Set", &[("interface", "Library interface"), (short_name.as_str(), "New value"), (result_name.as_str(), "Value after the validation, can be NULL")], "Status of the operation");
     writeln!(f, r#"
         #[allow(non_camel_case_types)]
         pub type {pm_name}_t = {pm_type};
 
+        {get_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}(
             interface: *const CInterfaceInstance,
@@ -337,16 +547,28 @@ fn write_general_setter_and_getter(f: &mut File, pm_type: String, pm_name: Strin
             get_parameter::<{pm_type}>(interface, ParameterId::{pm_id_name}, {short_name})
         }}
 
+        {default_doc}
+        #[unsafe(no_mangle)]
+        pub extern "C" fn get_{pm_name}_default(
+            interface: *const CInterfaceInstance,
+            {short_name}: *mut {pm_name}_t
+        ) -> EconfStatus {{
+            get_parameter_default::<{pm_type}>(interface, ParameterId::{pm_id_name}, {short_name})
+        }}
+
+        {quick_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}_quick(
-            interface: *const CInterfaceInstance
+            interface: *const CInterfaceInstance,
+            ok: *mut bool
         ) -> {pm_name}_t {{
-            get_parameter_quick::<{pm_type}>(interface, ParameterId::{pm_id_name})
+            get_parameter_quick::<{pm_type}>(interface, ParameterId::{pm_id_name}, ok)
         }}
     "#)?;
             
     if !is_const {
         writeln!(f, r#"
+            {set_doc}
             #[unsafe(no_mangle)]
             pub extern "C" fn set_{pm_name}(
                 interface: *const CInterfaceInstance,
@@ -361,7 +583,12 @@ fn write_general_setter_and_getter(f: &mut File, pm_type: String, pm_name: Strin
     Ok(())
 }
 
-fn write_enum_setter_and_getter(f: &mut File, p_enum_name: String, pm_name: String, short_name: String, pm_id_name: String, is_const: bool, validation: &ValidationMethod, enums: &mut HashSet<String>) -> Result<(), Box<dyn std::error::Error>> {
+fn write_enum_setter_and_getter(f: &mut File, p_enum_name: String, pm_name: String, short_name: String, pm_id_name: String, is_const: bool, doc: &ParameterDoc, validation: &ValidationMethod, enums: &mut HashSet<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let get_doc = doc.format("        ", "Get", &[("interface", "Library interface"), (short_name.as_str(), "Output value")], "Status of the operation");
+    let default_doc = doc.format("        ", "Get the default of", &[("interface", "Library interface"), (short_name.as_str(), "Output value")], "Status of the operation");
+    let quick_doc = doc.format("        ", "Get", &[("interface", "Library interface"), ("ok", "Set to false when the default is returned after an error, can be NULL")], "The value, or the default on error");
+    let result_name = format!("{short_name}_result");
+    let set_doc = doc.format("            ", "Set", &[("interface", "Library interface"), (short_name.as_str(), "New value"), (result_name.as_str(), "Value after the validation, can be NULL")], "Status of the operation");
     match &validation {
         ValidationMethod::AllowedValues { values, names } => {
             let vals = values
@@ -392,6 +619,7 @@ fn write_enum_setter_and_getter(f: &mut File, p_enum_name: String, pm_name: Stri
     };  
 
     writeln!(f, r#"
+        {get_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}(
             interface: *const CInterfaceInstance,
@@ -401,11 +629,23 @@ fn write_enum_setter_and_getter(f: &mut File, p_enum_name: String, pm_name: Stri
             get_parameter::<i32>(interface, ParameterId::{pm_id_name}, parameter_i32)
         }}
 
+        {default_doc}
+        #[unsafe(no_mangle)]
+        pub extern "C" fn get_{pm_name}_default(
+            interface: *const CInterfaceInstance,
+            {short_name}: *mut {p_enum_name}_t
+        ) -> EconfStatus {{
+            let parameter_i32 = {short_name} as *mut i32;
+            get_parameter_default::<i32>(interface, ParameterId::{pm_id_name}, parameter_i32)
+        }}
+
+        {quick_doc}
         #[unsafe(no_mangle)]
         pub extern "C" fn get_{pm_name}_quick(
-            interface: *const CInterfaceInstance
+            interface: *const CInterfaceInstance,
+            ok: *mut bool
         ) -> {p_enum_name}_t {{
-            let parameter_i32 = get_parameter_quick::<i32>(interface, ParameterId::{pm_id_name});
+            let parameter_i32 = get_parameter_quick::<i32>(interface, ParameterId::{pm_id_name}, ok);
             FromPrimitive::from_i32(parameter_i32).unwrap_or_else(|| {{
                 {p_enum_name}_t::default()
             }})
@@ -414,6 +654,7 @@ fn write_enum_setter_and_getter(f: &mut File, p_enum_name: String, pm_name: Stri
             
     if !is_const {
         writeln!(f, r#"
+            {set_doc}
             #[unsafe(no_mangle)]
             pub extern "C" fn set_{pm_name}(
                 interface: *const CInterfaceInstance,
@@ -475,3 +716,118 @@ pub(crate) fn process_convert_c_file(input_path: &Path, output_path: &Path) -> s
     
     Ok(())
 }
+
+/// Escapes the string as a C string literal, the non-ASCII bytes are written as octal escapes
+fn format_c_string(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'\n' => literal.push_str("\\n"),
+            b'\r' => literal.push_str("\\r"),
+            b'\t' => literal.push_str("\\t"),
+            0x20..=0x7E => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Formats a signed integer for a macro, the negative values are parenthesized.
+/// The minimum can't be written as a literal, the positive part of it would overflow
+fn format_c_signed(value: i64, wrapper: &str, min: i64) -> String {
+    let literal = |v: i64| if wrapper.is_empty() { v.to_string() } else { format!("{}({})", wrapper, v) };
+    if value == min {
+        format!("(-{} - 1)", literal(-(value + 1)))
+    } else if value < 0 {
+        format!("(-{})", literal(-value))
+    } else {
+        literal(value)
+    }
+}
+
+/// Formats the value as a C constant expression, None for the values without a C literal (blobs, files)
+fn format_c_value(value: &ParameterValue, validation: &ValidationMethod) -> Option<String> {
+    match value {
+        ParameterValue::ValBool(b) => Some(b.to_string()),
+        ParameterValue::ValI32(i) => Some(format_c_signed(*i as i64, "", i32::MIN as i64)),
+        ParameterValue::ValU32(u) => Some(format!("{}u", u)),
+        ParameterValue::ValI64(i) => Some(format_c_signed(*i, "INT64_C", i64::MIN)),
+        ParameterValue::ValU64(u) => Some(format!("UINT64_C({})", u)),
+        ParameterValue::ValF32(f) if f.is_finite() => Some(format!("{:?}f", f)),
+        ParameterValue::ValF64(f) if f.is_finite() => Some(format!("{:?}", f)),
+        ParameterValue::ValString(s) => Some(format_c_string(s)),
+        // The enum values are written with the names from the enum declaration
+        ParameterValue::ValEnum(v) => match validation {
+            ValidationMethod::AllowedValues { values, names } => values
+                .iter()
+                .position(|value| matches!(value, ParameterValue::ValEnum(allowed) if allowed == v))
+                .and_then(|index| names.get(index))
+                .map(|name| name.to_string())
+                .or_else(|| Some(format_c_signed(*v as i64, "", i32::MIN as i64))),
+            _ => Some(format_c_signed(*v as i64, "", i32::MIN as i64)),
+        },
+        _ => None,
+    }
+}
+
+/// Macros with the defaults, the range limits and the enum value names of the parameters
+fn format_parameter_macros(parameters: &Vec<Parameter>) -> String {
+    let mut macros = String::from("\n/* Parameter defaults, limits and enum names. Auto-generated, see build.rs */\n\n");
+    let mut enums = HashSet::new();
+    for p in parameters {
+        let pm_id_name = get_parameter_name_for_enum(&p.name_id.to_string());
+        if let Some(default) = format_c_value(&p.value_default, &p.validation) {
+            macros.push_str(&format!("#define {}_DEFAULT {}\n", pm_id_name, default));
+        }
+        if let Some(max_size) = p.max_size {
+            macros.push_str(&format!("#define {}_MAX_SIZE {}u\n", pm_id_name, max_size));
+        }
+        match &p.validation {
+            ValidationMethod::Range { min, max } => {
+                if let Some(min) = format_c_value(min, &p.validation) {
+                    macros.push_str(&format!("#define {}_MIN {}\n", pm_id_name, min));
+                }
+                if let Some(max) = format_c_value(max, &p.validation) {
+                    macros.push_str(&format!("#define {}_MAX {}\n", pm_id_name, max));
+                }
+            }
+            ValidationMethod::Text { max_length, pattern } => {
+                if let Some(max_length) = max_length {
+                    macros.push_str(&format!("#define {}_MAX_LENGTH {}u\n", pm_id_name, max_length));
+                }
+                if let Some(pattern) = pattern {
+                    macros.push_str(&format!("#define {}_PATTERN {}\n", pm_id_name, format_c_string(pattern)));
+                }
+            }
+            ValidationMethod::AllowedValues { values, names } => {
+                if let ParameterValueType::TypeEnum(p_enum_name) = &p.value_type {
+                    if enums.insert(p_enum_name.to_string()) {
+                        // Maps a value of the enum to its name, an empty string for unknown values
+                        let mut name_of = String::new();
+                        for (value, name) in values.iter().zip(names.iter()) {
+                            macros.push_str(&format!("#define {}_NAME {}\n", name, format_c_string(name)));
+                            name_of.push_str(&format!("(value) == {} ? {} : ", value_to_string(value), format_c_string(name)));
+                        }
+                        macros.push_str(&format!("#define {}_t_NAME(value) ({}\"\")\n", p_enum_name, name_of));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    macros
+}
+
+/// Adds the parameter macros to the header generated by cbindgen, before the closing `extern "C"` block
+pub(crate) fn append_parameter_macros(parameters: &Vec<Parameter>, header_path: &Path) -> std::io::Result<()> {
+    let mut content = fs::read_to_string(header_path)?;
+    let macros = format_parameter_macros(parameters);
+    match content.rfind("#ifdef __cplusplus") {
+        Some(position) => content.insert_str(position, &format!("{}\n", macros)),
+        None => content.push_str(&macros),
+    }
+    fs::write(header_path, content)
+}
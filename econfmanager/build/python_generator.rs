@@ -0,0 +1,416 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::file_generator::{get_parameter_name_for_enum, get_parameter_name_for_function, value_to_string};
+use crate::schema::{Parameter, ParameterValueType, ValidationMethod};
+
+/// The part of the module that doesn't depend on the parameters
+const PYTHON_MODULE_HEAD: &str = r#""""
+Python interface of the econfmanager library, a ctypes wrapper over the C API.
+
+Auto-generated - DO NOT EDIT
+"""
+
+import ctypes
+import os
+from enum import IntEnum
+from typing import Any, Callable, Dict, Optional
+
+
+class EconfStatus(IntEnum):
+    StatusOk = 0
+    StatusError = 1
+    StatusNotFound = 2
+    StatusTypeMismatch = 3
+    StatusValidationFailed = 4
+    StatusReadonly = 5
+    StatusLockTimeout = 6
+    StatusDbError = 7
+    StatusConflict = 8
+    StatusDbBusy = 9
+
+
+class EconfSetResult(IntEnum):
+    SetChanged = 0
+    SetNotChanged = 1
+    SetClamped = 2
+
+
+class EconfValueType(IntEnum):
+    ValueNone = 0
+    ValueBool = 1
+    ValueI32 = 2
+    ValueU32 = 3
+    ValueI64 = 4
+    ValueU64 = 5
+    ValueF32 = 6
+    ValueF64 = 7
+    ValueString = 8
+    ValueBlob = 9
+    ValueEnum = 10
+
+
+class EconfError(Exception):
+    """Failure of a library call, with the status and the message from econf_get_last_error"""
+
+    def __init__(self, status: EconfStatus, message: str):
+        super().__init__(f"{status.name}: {message}")
+        self.status = status
+        self.message = message
+
+
+class EconfBuffer(ctypes.Structure):
+    _fields_ = [("data", ctypes.POINTER(ctypes.c_uint8)), ("capacity", ctypes.c_size_t), ("length", ctypes.c_size_t)]
+
+
+class EconfValueData(ctypes.Union):
+    _fields_ = [
+        ("val_bool", ctypes.c_bool),
+        ("val_i32", ctypes.c_int32),
+        ("val_u32", ctypes.c_uint32),
+        ("val_i64", ctypes.c_int64),
+        ("val_u64", ctypes.c_uint64),
+        ("val_f32", ctypes.c_float),
+        ("val_f64", ctypes.c_double),
+        ("val_enum", ctypes.c_int32),
+        ("val_buffer", EconfBuffer),
+    ]
+
+
+class EconfValue(ctypes.Structure):
+    _fields_ = [("value_type", ctypes.c_int), ("status", ctypes.c_int), ("data", EconfValueData)]
+
+
+class EconfParameterChangeEvent(ctypes.Structure):
+    _fields_ = [("id", ctypes.c_size_t), ("source", ctypes.c_char_p), ("timestamp", ctypes.c_double), ("sequence", ctypes.c_uint64)]
+
+
+class ChangeEvent:
+    """Parameter change event, the source is empty if unknown"""
+
+    def __init__(self, event: EconfParameterChangeEvent):
+        self.id = ParameterId(event.id)
+        self.source = event.source.decode() if event.source else ""
+        self.timestamp = event.timestamp
+        self.sequence = event.sequence
+
+    def __repr__(self):
+        return f"ChangeEvent(id={self.id.name}, source={self.source!r}, timestamp={self.timestamp}, sequence={self.sequence})"
+
+
+_ChangeCallback = ctypes.CFUNCTYPE(None, ctypes.POINTER(EconfParameterChangeEvent), ctypes.c_void_p)
+_ProgressCallback = ctypes.CFUNCTYPE(None, ctypes.c_uint64, ctypes.c_uint64, ctypes.c_void_p)
+
+_NUMERIC_FIELDS = {
+    EconfValueType.ValueBool: "val_bool",
+    EconfValueType.ValueI32: "val_i32",
+    EconfValueType.ValueU32: "val_u32",
+    EconfValueType.ValueI64: "val_i64",
+    EconfValueType.ValueU64: "val_u64",
+    EconfValueType.ValueF32: "val_f32",
+    EconfValueType.ValueF64: "val_f64",
+    EconfValueType.ValueEnum: "val_enum",
+}
+"#;
+
+const PYTHON_MANAGER_CLASS: &str = r#"
+
+class EconfManager:
+    """
+    Library instance, released with close() or at the end of a with block.
+    The change callbacks are called from the library threads
+    """
+
+    def __init__(self, database_path: str, saved_database_path: str, default_data_folder: str, lib_path: Optional[str] = None,
+                 secret_key: Optional[bytes] = None):
+        if lib_path is None:
+            lib_path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "libeconfmanager.so")
+        self._lib = ctypes.CDLL(lib_path)
+        self._setup_functions()
+        # The callbacks are kept until close(), a call can still run in another thread after the removal
+        self._callbacks = []
+        self._interface = ctypes.c_void_p()
+        if secret_key is None:
+            status = self._lib.econf_init(
+                database_path.encode(), saved_database_path.encode(), default_data_folder.encode(), ctypes.byref(self._interface)
+            )
+        else:
+            # The device key of the secret parameters, 32 bytes
+            status = self._lib.econf_init_with_secret_key(
+                database_path.encode(), saved_database_path.encode(), default_data_folder.encode(),
+                secret_key, len(secret_key), ctypes.byref(self._interface)
+            )
+        self._check(status, None)
+
+    def _setup_functions(self):
+        lib = self._lib
+        p = ctypes.c_void_p
+        signatures = {
+            "econf_init": [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.POINTER(ctypes.c_void_p)],
+            "econf_init_with_secret_key": [ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_size_t,
+                                           ctypes.POINTER(ctypes.c_void_p)],
+            "econf_deinit": [p],
+            "econf_get_last_error": [p, ctypes.c_char_p, ctypes.c_size_t],
+            "econf_get_value": [p, ctypes.c_size_t, ctypes.POINTER(EconfValue)],
+            "econf_set_value": [p, ctypes.c_size_t, EconfValue, ctypes.POINTER(ctypes.c_int)],
+            "econf_set_value_if": [p, ctypes.c_size_t, EconfValue, ctypes.c_uint64, ctypes.POINTER(ctypes.c_int)],
+            "econf_get_sequence": [p, ctypes.c_size_t, ctypes.POINTER(ctypes.c_uint64)],
+            "econf_set_staged": [p, ctypes.c_size_t, EconfValue],
+            "econf_get_staged": [p, ctypes.c_size_t, ctypes.POINTER(EconfValue)],
+            "econf_apply_staged": [p],
+            "econf_discard_staged": [p],
+            "econf_add_callback_ex": [p, ctypes.c_size_t, _ChangeCallback, p, ctypes.POINTER(ctypes.c_uint64)],
+            "econf_delete_callback_handle": [p, ctypes.c_uint64],
+            "econf_save_with_progress": [p, _ProgressCallback, p],
+            "econf_load_with_progress": [p, _ProgressCallback, p],
+            "econf_factory_reset": [p],
+            "econf_refresh_defaults": [p],
+            "econf_maintain": [p],
+            "econf_update_poll": [p],
+            "econf_set_up_timer_poll": [p, ctypes.c_int64],
+            "econf_stop_timer_poll": [p],
+            "econf_start_autosave": [p, ctypes.c_int64, ctypes.c_bool],
+            "econf_stop_autosave": [p],
+        }
+        for name, argtypes in signatures.items():
+            function = getattr(lib, name)
+            function.argtypes = argtypes
+            function.restype = ctypes.c_int
+
+    def _check(self, status: int, interface) -> None:
+        if status == EconfStatus.StatusOk:
+            return
+        message = ctypes.create_string_buffer(512)
+        self._lib.econf_get_last_error(interface, message, len(message))
+        raise EconfError(EconfStatus(status), message.value.decode(errors="replace"))
+
+    def close(self) -> None:
+        """Stop the library threads and release the instance"""
+        if self._interface:
+            self._lib.econf_deinit(self._interface)
+            self._interface = ctypes.c_void_p()
+            self._callbacks.clear()
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, *args):
+        self.close()
+
+    def __del__(self):
+        if getattr(self, "_interface", None):
+            self.close()
+
+    def get(self, id: "ParameterId") -> Any:
+        """Read a parameter. The strings are returned as str, the blobs as bytes and the enums as their IntEnum"""
+        return self._read(self._lib.econf_get_value, id)
+
+    def get_staged(self, id: "ParameterId") -> Any:
+        """The staged value of the parameter, the current value if no change is staged"""
+        return self._read(self._lib.econf_get_staged, id)
+
+    def _read(self, reader, id: "ParameterId") -> Any:
+        value = EconfValue()
+        self._check(reader(self._interface, id, ctypes.byref(value)), self._interface)
+        value_type = EconfValueType(value.value_type)
+        if value_type in (EconfValueType.ValueString, EconfValueType.ValueBlob):
+            # The first read returns the length, repeat if the value grew in between
+            while True:
+                length = value.data.val_buffer.length
+                buffer = (ctypes.c_uint8 * max(length, 1))()
+                value.data.val_buffer = EconfBuffer(ctypes.cast(buffer, ctypes.POINTER(ctypes.c_uint8)), length, 0)
+                self._check(reader(self._interface, id, ctypes.byref(value)), self._interface)
+                if value.data.val_buffer.length <= length:
+                    break
+            data = bytes(buffer[:value.data.val_buffer.length])
+            return data.rstrip(b"\0").decode() if value_type == EconfValueType.ValueString else data
+        result = getattr(value.data, _NUMERIC_FIELDS[value_type])
+        if id in _PARAMETER_ENUMS:
+            return _PARAMETER_ENUMS[id](result)
+        return result
+
+    def set(self, id: "ParameterId", parameter: Any, expected_sequence: Optional[int] = None) -> EconfSetResult:
+        """Write a parameter, the value is converted to the type of the parameter. Returns whether it was changed or clamped.
+        With expected_sequence the write raises StatusConflict if the parameter was written since then"""
+        value, _buffer = self._to_value(id, parameter)
+        result = ctypes.c_int()
+        if expected_sequence is None:
+            self._check(self._lib.econf_set_value(self._interface, id, value, ctypes.byref(result)), self._interface)
+        else:
+            self._check(self._lib.econf_set_value_if(self._interface, id, value, expected_sequence, ctypes.byref(result)), self._interface)
+        return EconfSetResult(result.value)
+
+    def set_staged(self, id: "ParameterId", parameter: Any) -> None:
+        """Stage a change, written with the other staged changes by apply_staged"""
+        value, _buffer = self._to_value(id, parameter)
+        self._check(self._lib.econf_set_staged(self._interface, id, value), self._interface)
+
+    def apply_staged(self) -> None:
+        """Write the staged changes together, none of them if any is not accepted"""
+        self._check(self._lib.econf_apply_staged(self._interface), self._interface)
+
+    def discard_staged(self) -> None:
+        self._check(self._lib.econf_discard_staged(self._interface), self._interface)
+
+    @staticmethod
+    def _to_value(id: "ParameterId", parameter: Any):
+        """The value converted to the type of the parameter and the buffer it refers to, which must be kept"""
+        value = EconfValue()
+        value_type = _PARAMETER_TYPES[id]
+        value.value_type = value_type
+        buffer = None
+        if value_type in (EconfValueType.ValueString, EconfValueType.ValueBlob):
+            data = parameter.encode() + b"\0" if value_type == EconfValueType.ValueString else bytes(parameter)
+            buffer = (ctypes.c_uint8 * max(len(data), 1)).from_buffer_copy(data.ljust(1, b"\0"))
+            value.data.val_buffer = EconfBuffer(ctypes.cast(buffer, ctypes.POINTER(ctypes.c_uint8)), len(data), len(data))
+        else:
+            setattr(value.data, _NUMERIC_FIELDS[value_type], parameter)
+        return value, buffer
+
+    def get_sequence(self, id: "ParameterId") -> int:
+        """Sequence number of the last write of the parameter for set(expected_sequence=...), 0 if never written"""
+        sequence = ctypes.c_uint64()
+        self._check(self._lib.econf_get_sequence(self._interface, id, ctypes.byref(sequence)), self._interface)
+        return sequence.value
+
+    def add_callback(self, id: "ParameterId", callback: Callable[[ChangeEvent], None]) -> int:
+        """Add a change callback of the parameter, returns the handle for delete_callback"""
+        function = _ChangeCallback(lambda event, _: callback(ChangeEvent(event.contents)))
+        handle = ctypes.c_uint64()
+        self._check(self._lib.econf_add_callback_ex(self._interface, id, function, None, ctypes.byref(handle)), self._interface)
+        self._callbacks.append(function)
+        return handle.value
+
+    def delete_callback(self, handle: int) -> None:
+        self._check(self._lib.econf_delete_callback_handle(self._interface, handle), self._interface)
+
+    @staticmethod
+    def _progress(progress: Optional[Callable[[int, int], None]]):
+        return _ProgressCallback(lambda done, total, _: progress(done, total)) if progress else _ProgressCallback()
+
+    def save(self, progress: Optional[Callable[[int, int], None]] = None) -> None:
+        """Save the parameters, progress is called with the parameters processed and the total"""
+        self._check(self._lib.econf_save_with_progress(self._interface, self._progress(progress), None), self._interface)
+
+    def load(self, progress: Optional[Callable[[int, int], None]] = None) -> None:
+        """Load the saved parameters, progress is called with the pages copied and the total"""
+        self._check(self._lib.econf_load_with_progress(self._interface, self._progress(progress), None), self._interface)
+
+    def factory_reset(self) -> None:
+        self._check(self._lib.econf_factory_reset(self._interface), self._interface)
+
+    def refresh_defaults(self) -> None:
+        """Re-read the file defaults changed on disk"""
+        self._check(self._lib.econf_refresh_defaults(self._interface), self._interface)
+
+    def maintain(self) -> None:
+        """Truncate the WAL file and release the free pages of the database"""
+        self._check(self._lib.econf_maintain(self._interface), self._interface)
+
+    def update_poll(self) -> None:
+        self._check(self._lib.econf_update_poll(self._interface), self._interface)
+
+    def set_up_timer_poll(self, period_ms: int) -> None:
+        self._check(self._lib.econf_set_up_timer_poll(self._interface, period_ms), self._interface)
+
+    def stop_timer_poll(self) -> None:
+        self._check(self._lib.econf_stop_timer_poll(self._interface), self._interface)
+
+    def start_autosave(self, period_ms: int, only_if_dirty: bool = True) -> None:
+        self._check(self._lib.econf_start_autosave(self._interface, period_ms, only_if_dirty), self._interface)
+
+    def stop_autosave(self) -> None:
+        self._check(self._lib.econf_stop_autosave(self._interface), self._interface)
+"#;
+
+fn python_value_type(value_type: &ParameterValueType) -> &'static str {
+    match value_type {
+        ParameterValueType::TypeNone => "ValueNone",
+        ParameterValueType::TypeBool => "ValueBool",
+        ParameterValueType::TypeI32 => "ValueI32",
+        ParameterValueType::TypeU32 => "ValueU32",
+        ParameterValueType::TypeI64 => "ValueI64",
+        ParameterValueType::TypeU64 => "ValueU64",
+        ParameterValueType::TypeF32 => "ValueF32",
+        ParameterValueType::TypeF64 => "ValueF64",
+        ParameterValueType::TypeString => "ValueString",
+        ParameterValueType::TypeBlob => "ValueBlob",
+        ParameterValueType::TypeEnum(_) => "ValueEnum",
+    }
+}
+
+fn python_type_hint(value_type: &ParameterValueType) -> String {
+    match value_type {
+        ParameterValueType::TypeNone => "None".to_owned(),
+        ParameterValueType::TypeBool => "bool".to_owned(),
+        ParameterValueType::TypeI32 | ParameterValueType::TypeU32 | ParameterValueType::TypeI64 | ParameterValueType::TypeU64 => "int".to_owned(),
+        ParameterValueType::TypeF32 | ParameterValueType::TypeF64 => "float".to_owned(),
+        ParameterValueType::TypeString => "str".to_owned(),
+        ParameterValueType::TypeBlob => "bytes".to_owned(),
+        ParameterValueType::TypeEnum(name) => format!("\"{}_t\"", name),
+    }
+}
+
+/// Generates `econfmanager.py`, a ctypes wrapper with the `EconfManager` class and a property for every parameter
+pub(crate) fn generate_python_module(parameters: &Vec<Parameter>, module_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = File::create(module_path)?;
+    write!(f, "{}", PYTHON_MODULE_HEAD)?;
+
+    writeln!(f, "\n\nclass ParameterId(IntEnum):")?;
+    for p in parameters {
+        writeln!(f, "    {} = {}", get_parameter_name_for_enum(&p.name_id.to_string()), p.id)?;
+    }
+
+    let mut enums = Vec::new();
+    for p in parameters {
+        if let (ParameterValueType::TypeEnum(name), ValidationMethod::AllowedValues { values, names }) = (&p.value_type, &p.validation) {
+            if enums.contains(name) {
+                continue;
+            }
+            enums.push(name.clone());
+            writeln!(f, "\n\nclass {}_t(IntEnum):", name)?;
+            for (value, value_name) in values.iter().zip(names.iter()) {
+                writeln!(f, "    {} = {}", value_name, value_to_string(value))?;
+            }
+        }
+    }
+
+    writeln!(f, "\n\n_PARAMETER_TYPES = {{")?;
+    for p in parameters {
+        writeln!(f, "    ParameterId.{}: EconfValueType.{},", get_parameter_name_for_enum(&p.name_id.to_string()), python_value_type(&p.value_type))?;
+    }
+    writeln!(f, "}}\n")?;
+    writeln!(f, "_PARAMETER_ENUMS: Dict[ParameterId, Any] = {{")?;
+    for p in parameters {
+        if let ParameterValueType::TypeEnum(name) = &p.value_type {
+            writeln!(f, "    ParameterId.{}: {}_t,", get_parameter_name_for_enum(&p.name_id.to_string()), name)?;
+        }
+    }
+    writeln!(f, "}}")?;
+
+    write!(f, "{}", PYTHON_MANAGER_CLASS)?;
+
+    for p in parameters {
+        if matches!(p.value_type, ParameterValueType::TypeNone) {
+            continue;
+        }
+        let pm_name = get_parameter_name_for_function(&p.name_id.to_string());
+        let pm_id_name = get_parameter_name_for_enum(&p.name_id.to_string());
+        let type_hint = python_type_hint(&p.value_type);
+        let title = if p.title.is_empty() { p.name_id.to_string() } else { p.title.to_string() };
+
+        writeln!(f)?;
+        writeln!(f, "    @property")?;
+        writeln!(f, "    def {pm_name}(self) -> {type_hint}:")?;
+        writeln!(f, "        \"\"\"{}\"\"\"", title.replace('"', "\\\""))?;
+        writeln!(f, "        return self.get(ParameterId.{pm_id_name})")?;
+        if !p.is_const && !p.computed {
+            writeln!(f)?;
+            writeln!(f, "    @{pm_name}.setter")?;
+            writeln!(f, "    def {pm_name}(self, value: {type_hint}) -> None:")?;
+            writeln!(f, "        self.set(ParameterId.{pm_id_name}, value)")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::file_generator::{get_parameter_name_for_enum, get_parameter_name_for_function, value_to_string};
+use crate::schema::{Parameter, ParameterValueType, ValidationMethod};
+
+/// The part of the client that doesn't depend on the parameters
+const TS_CLIENT_HEAD: &str = r#"/*
+* Auto-generated - DO NOT EDIT
+* TypeScript client of the econfmanager JSON-RPC (WebSocket) and REST API
+*/
+
+/** Error reported by the server or the transport */
+export class EconfError extends Error {
+    /** JSON-RPC error code, when the error comes from the server */
+    readonly code?: number;
+
+    constructor(message: string, code?: number) {
+        super(message);
+        this.code = code;
+        this.name = "EconfError";
+    }
+}
+
+/** Parameter value as it is transferred by the API, blobs are base64 strings */
+export type ParameterValue = boolean | number | string;
+
+/** Common interface of the REST and WebSocket clients */
+export interface EconfClient {
+    read<K extends ParameterId>(id: K): Promise<ParameterTypes[K]>;
+    write<K extends WritableParameterId>(id: K, value: ParameterTypes[K]): Promise<ParameterTypes[K]>;
+}
+
+/** Notification of a parameter change, `sequence` is 0 if the server doesn't send it */
+export interface ChangeEvent<K extends ParameterId = ParameterId> {
+    id: K;
+    value: ParameterTypes[K];
+    sequence: number;
+}
+
+export type ChangeCallback<K extends ParameterId = ParameterId> = (event: ChangeEvent<K>) => void;
+
+/** Options of a subscription, shared by all the callbacks of the parameter */
+export interface SubscriptionOptions {
+    /** Shortest time between the notifications, the changes in between are coalesced to the latest value */
+    min_interval_ms?: number;
+    /** Skip the notifications with the same value as the last one */
+    on_change_only?: boolean;
+}
+
+type ValueKind = "bool" | "number" | "string" | "blob" | "enum";
+"#;
+
+const TS_CLIENT_CLASSES: &str = r#"
+/** Notifications carry the values as strings, and NaN and infinities are always sent as "NaN", "Infinity"
+ * and "-Infinity", convert them to the parameter type */
+function parseValue(id: ParameterId, value: unknown): ParameterValue {
+    if (typeof value !== "string") {
+        return value as ParameterValue;
+    }
+    switch (PARAMETER_KINDS[id]) {
+        case "bool":
+            return value === "true" || value === "1";
+        case "number":
+        case "enum":
+            return Number(value);
+        default:
+            return value;
+    }
+}
+
+export function parameterIdFromName(name: string): ParameterId | undefined {
+    const entry = Object.entries(PARAMETER_NAMES).find(([, parameterName]) => parameterName === name);
+    return entry === undefined ? undefined : (Number(entry[0]) as ParameterId);
+}
+
+/** Client of the REST API, `baseUrl` is like "http://localhost:8080", `token` is the API token of the server if it has one */
+export class EconfRestClient implements EconfClient {
+    constructor(private readonly baseUrl: string, private readonly token?: string) {}
+
+    async read<K extends ParameterId>(id: K): Promise<ParameterTypes[K]> {
+        const response = await fetch(`${this.baseUrl}/api/read/${encodeURIComponent(PARAMETER_NAMES[id])}`, {
+            headers: this.headers(),
+        });
+        return parseValue(id, await this.decode(response)) as ParameterTypes[K];
+    }
+
+    async write<K extends WritableParameterId>(id: K, value: ParameterTypes[K]): Promise<ParameterTypes[K]> {
+        const response = await fetch(`${this.baseUrl}/api/write/${encodeURIComponent(PARAMETER_NAMES[id])}`, {
+            method: "POST",
+            headers: this.headers(),
+            body: String(value),
+        });
+        return parseValue(id, await this.decode(response)) as ParameterTypes[K];
+    }
+
+    private headers(): Record<string, string> {
+        return this.token === undefined ? {} : { Authorization: `Bearer ${this.token}` };
+    }
+
+    private async decode(response: Response): Promise<unknown> {
+        const body = await response.json();
+        if (!response.ok) {
+            throw new EconfError(body && body.error ? body.error : `HTTP ${response.status}`);
+        }
+        return body;
+    }
+}
+
+interface PendingRequest {
+    resolve: (result: any) => void;
+    reject: (error: Error) => void;
+    timeout: ReturnType<typeof setTimeout>;
+}
+
+/**
+ * Client of the JSON-RPC API over WebSocket, `url` is like "ws://localhost:8080/api_ws", with "?token=<token>"
+ * when the server requires an API token. The server sends the changes of the parameters the client has subscribed to
+ */
+export class EconfWsClient implements EconfClient {
+    private socket: WebSocket | null = null;
+    private nextId = 1;
+    private readonly pending = new Map<number, PendingRequest>();
+    private readonly callbacks = new Map<ParameterId, Set<ChangeCallback>>();
+    private readonly lastSequence = new Map<ParameterId, number>();
+
+    constructor(private readonly url: string, private readonly requestTimeoutMs = 5000) {}
+
+    connect(): Promise<void> {
+        return new Promise((resolve, reject) => {
+            const socket = new WebSocket(this.url);
+            socket.onopen = () => {
+                this.socket = socket;
+                resolve();
+            };
+            socket.onerror = () => reject(new EconfError(`Could not connect to ${this.url}`));
+            socket.onclose = () => this.handleClose();
+            socket.onmessage = (event: MessageEvent) => this.handleMessage(event.data);
+        });
+    }
+
+    close(): void {
+        this.socket?.close(1000);
+    }
+
+    call(method: string, params?: object): Promise<any> {
+        return new Promise((resolve, reject) => {
+            if (this.socket === null || this.socket.readyState !== WebSocket.OPEN) {
+                reject(new EconfError("WebSocket is not connected"));
+                return;
+            }
+            const id = this.nextId++;
+            const timeout = setTimeout(() => {
+                this.pending.delete(id);
+                reject(new EconfError(`Request ${id} timed out after ${this.requestTimeoutMs}ms`));
+            }, this.requestTimeoutMs);
+            this.pending.set(id, { resolve, reject, timeout });
+            this.socket.send(JSON.stringify({ jsonrpc: "2.0", id, method, params }));
+        });
+    }
+
+    async read<K extends ParameterId>(id: K): Promise<ParameterTypes[K]> {
+        const result = await this.call("read", { name: PARAMETER_NAMES[id] });
+        return parseValue(id, result.pm[PARAMETER_NAMES[id]]) as ParameterTypes[K];
+    }
+
+    async write<K extends WritableParameterId>(id: K, value: ParameterTypes[K]): Promise<ParameterTypes[K]> {
+        // JSON.stringify turns NaN and infinities into null
+        const sent = typeof value === "number" && !Number.isFinite(value) ? String(value) : value;
+        const result = await this.call("write", { name: PARAMETER_NAMES[id], value: sent });
+        return parseValue(id, result.pm[PARAMETER_NAMES[id]]) as ParameterTypes[K];
+    }
+
+    async save(): Promise<void> {
+        await this.call("save");
+    }
+
+    async restore(): Promise<void> {
+        await this.call("restore");
+    }
+
+    async factory_reset(): Promise<void> {
+        await this.call("factory_reset");
+    }
+
+    async list_profiles(): Promise<string[]> {
+        const result = await this.call("list_profiles");
+        return result.profiles;
+    }
+
+    async save_profile(name: string): Promise<void> {
+        await this.call("save_profile", { name });
+    }
+
+    async load_profile(name: string): Promise<void> {
+        await this.call("load_profile", { name });
+    }
+
+    async delete_profile(name: string): Promise<void> {
+        await this.call("delete_profile", { name });
+    }
+
+    /**
+     * Call `callback` on every change of the parameter, returns the function removing the callback.
+     * The callback is called with the current value once the server confirms the subscription
+     */
+    async subscribe<K extends ParameterId>(id: K, callback: ChangeCallback<K>, options?: SubscriptionOptions): Promise<() => void> {
+        let callbacks = this.callbacks.get(id);
+        if (callbacks === undefined) {
+            callbacks = new Set();
+            this.callbacks.set(id, callbacks);
+        }
+        const stored = callback as unknown as ChangeCallback;
+        callbacks.add(stored);
+        try {
+            const result = await this.call("subscribe", { name: PARAMETER_NAMES[id], options });
+            const value = parseValue(id, result.pm[PARAMETER_NAMES[id]]) as ParameterTypes[K];
+            const sequence: number = result.seq ?? 0;
+            if (sequence > (this.lastSequence.get(id) ?? 0)) {
+                this.lastSequence.set(id, sequence);
+            }
+            callback({ id, value, sequence });
+        } catch (error) {
+            callbacks.delete(stored);
+            throw error;
+        }
+        return () => {
+            const callbacks = this.callbacks.get(id);
+            callbacks?.delete(stored);
+            if (callbacks?.size === 0 && this.socket !== null) {
+                this.call("unsubscribe", { name: PARAMETER_NAMES[id] }).catch(() => undefined);
+            }
+        };
+    }
+
+    private handleMessage(data: string): void {
+        let msg: any;
+        try {
+            msg = JSON.parse(data);
+        } catch {
+            return;
+        }
+        if (msg.method === "notify") {
+            this.handleNotification(msg);
+            return;
+        }
+        const request = this.pending.get(msg.id);
+        if (request === undefined) {
+            return;
+        }
+        this.pending.delete(msg.id);
+        clearTimeout(request.timeout);
+        if (msg.error !== undefined) {
+            request.reject(new EconfError(msg.error.message, msg.error.code));
+        } else {
+            request.resolve(msg.result);
+        }
+    }
+
+    private handleNotification(msg: any): void {
+        if (!msg.params) {
+            return;
+        }
+        const sequence: number = msg.seq ?? 0;
+        for (const name of Object.keys(msg.params)) {
+            const id = parameterIdFromName(name);
+            if (id === undefined) {
+                continue;
+            }
+            // Discard the notifications older than the last one applied
+            const last = this.lastSequence.get(id);
+            if (sequence > 0) {
+                if (last !== undefined && sequence <= last) {
+                    continue;
+                }
+                this.lastSequence.set(id, sequence);
+            }
+            const event = { id, value: parseValue(id, msg.params[name]), sequence } as ChangeEvent;
+            this.callbacks.get(id)?.forEach((callback) => callback(event));
+        }
+    }
+
+    private handleClose(): void {
+        this.socket = null;
+        for (const request of this.pending.values()) {
+            clearTimeout(request.timeout);
+            request.reject(new EconfError("WebSocket closed"));
+        }
+        this.pending.clear();
+    }
+}
+"#;
+
+fn ts_type(value_type: &ParameterValueType) -> Option<String> {
+    Some(match value_type {
+        ParameterValueType::TypeBool => "boolean".to_owned(),
+        ParameterValueType::TypeI32
+        | ParameterValueType::TypeU32
+        | ParameterValueType::TypeI64
+        | ParameterValueType::TypeU64
+        | ParameterValueType::TypeF32
+        | ParameterValueType::TypeF64 => "number".to_owned(),
+        ParameterValueType::TypeString | ParameterValueType::TypeBlob => "string".to_owned(),
+        ParameterValueType::TypeEnum(name) => format!("{}_t", name),
+        ParameterValueType::TypeNone => return None,
+    })
+}
+
+fn ts_value_kind(value_type: &ParameterValueType) -> &'static str {
+    match value_type {
+        ParameterValueType::TypeBool => "bool",
+        ParameterValueType::TypeString | ParameterValueType::TypeNone => "string",
+        ParameterValueType::TypeBlob => "blob",
+        ParameterValueType::TypeEnum(_) => "enum",
+        _ => "number",
+    }
+}
+
+/// Generates `client.ts` with the typed `ParameterId` enum, the REST and WebSocket clients
+/// and a read and write helper for every parameter reachable through the API
+pub(crate) fn generate_ts_client(parameters: &Vec<Parameter>, client_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut f = File::create(client_path)?;
+    write!(f, "{}", TS_CLIENT_HEAD)?;
+
+    writeln!(f, "\nexport enum ParameterId {{")?;
+    for p in parameters {
+        writeln!(f, "    {} = {},", get_parameter_name_for_enum(&p.name_id.to_string()), p.id)?;
+    }
+    writeln!(f, "}}")?;
+
+    let mut enums = Vec::new();
+    for p in parameters {
+        if let (ParameterValueType::TypeEnum(name), ValidationMethod::AllowedValues { values, names }) = (&p.value_type, &p.validation) {
+            if enums.contains(name) {
+                continue;
+            }
+            enums.push(name.clone());
+            writeln!(f, "\nexport enum {}_t {{", name)?;
+            for (value, value_name) in values.iter().zip(names.iter()) {
+                writeln!(f, "    {} = {},", value_name, value_to_string(value))?;
+            }
+            writeln!(f, "}}")?;
+        }
+    }
+
+    writeln!(f, "\n/** Names used by the API */")?;
+    writeln!(f, "export const PARAMETER_NAMES: Record<ParameterId, string> = {{")?;
+    for p in parameters {
+        writeln!(f, "    [ParameterId.{}]: \"{}\",", get_parameter_name_for_enum(&p.name_id.to_string()), p.name_id)?;
+    }
+    writeln!(f, "}};")?;
+
+    writeln!(f, "\nexport interface ParameterTypes {{")?;
+    for p in parameters {
+        let value_type = ts_type(&p.value_type).unwrap_or_else(|| "never".to_owned());
+        writeln!(f, "    [ParameterId.{}]: {};", get_parameter_name_for_enum(&p.name_id.to_string()), value_type)?;
+    }
+    writeln!(f, "}}")?;
+
+    let writable: Vec<String> = parameters
+        .iter()
+        .filter(|p| !p.is_const && !p.readonly && !p.computed && !p.internal && ts_type(&p.value_type).is_some())
+        .map(|p| format!("ParameterId.{}", get_parameter_name_for_enum(&p.name_id.to_string())))
+        .collect();
+    writeln!(f, "\n/** Parameters the server accepts writes for */")?;
+    if writable.is_empty() {
+        writeln!(f, "export type WritableParameterId = never;")?;
+    } else {
+        writeln!(f, "export type WritableParameterId =")?;
+        writeln!(f, "    | {};", writable.join("\n    | "))?;
+    }
+
+    writeln!(f, "\nconst PARAMETER_KINDS: Record<ParameterId, ValueKind> = {{")?;
+    for p in parameters {
+        writeln!(f, "    [ParameterId.{}]: \"{}\",", get_parameter_name_for_enum(&p.name_id.to_string()), ts_value_kind(&p.value_type))?;
+    }
+    writeln!(f, "}};")?;
+
+    write!(f, "{}", TS_CLIENT_CLASSES)?;
+
+    for p in parameters {
+        // Internal parameters are not accessible through the API
+        if p.internal {
+            continue;
+        }
+        let Some(value_type) = ts_type(&p.value_type) else {
+            continue;
+        };
+        let pm_name = get_parameter_name_for_function(&p.name_id.to_string());
+        let pm_id_name = get_parameter_name_for_enum(&p.name_id.to_string());
+        let title = if p.title.is_empty() { p.name_id.to_string() } else { p.title.to_string() };
+
+        writeln!(f)?;
+        writeln!(f, "/** {} */", title.replace("*/", "* /"))?;
+        writeln!(f, "export function read_{pm_name}(client: EconfClient): Promise<{value_type}> {{")?;
+        writeln!(f, "    return client.read(ParameterId.{pm_id_name});")?;
+        writeln!(f, "}}")?;
+        if !p.is_const && !p.readonly && !p.computed {
+            writeln!(f)?;
+            writeln!(f, "export function write_{pm_name}(client: EconfClient, value: {value_type}): Promise<{value_type}> {{")?;
+            writeln!(f, "    return client.write(ParameterId.{pm_id_name}, value);")?;
+            writeln!(f, "}}")?;
+        }
+    }
+    Ok(())
+}
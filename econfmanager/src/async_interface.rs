@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use log::{debug, info};
+use tokio::sync::broadcast;
+
+use crate::error::EconfError;
+use crate::generated::{PARAMETERS_NUM, ParameterId};
+use crate::interface::{InterfaceInstance, ParameterChangeEvent, SetOutcome};
+use crate::schema::ParameterValue;
+
+pub type AsyncError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Number of change events kept for the slow subscribers before they start lagging
+const EVENTS_CAPACITY: usize = 256;
+
+/// Async wrapper of [`InterfaceInstance`] for tokio applications.
+///
+/// The SQLite work runs on the blocking thread pool, so the executor is never blocked,
+/// and the parameter changes are delivered as a broadcast stream.
+/// The wrapper owns the change callbacks of all the parameters.
+#[derive(Clone)]
+pub struct AsyncInterfaceInstance {
+    interface: Arc<InterfaceInstance>,
+    events: broadcast::Sender<ParameterChangeEvent>,
+}
+
+impl AsyncInterfaceInstance {
+    pub fn new(mut interface: InterfaceInstance) -> Result<Self, EconfError> {
+        let (events, _) = broadcast::channel(EVENTS_CAPACITY);
+        for index in 0..PARAMETERS_NUM {
+            let sender = events.clone();
+            interface.add_change_callback(
+                ParameterId::from_index(index).ok_or_else(|| EconfError::NotFound(format!("Parameter index {}", index)))?,
+                Arc::new(move |event: &ParameterChangeEvent| {
+                    // Sending only fails when there are no subscribers
+                    let _ = sender.send(event.clone());
+                }),
+            )?;
+        }
+        info!("Async interface created");
+        Ok(Self {
+            interface: Arc::new(interface),
+            events,
+        })
+    }
+
+    /// The synchronous interface, for the metadata functions that don't access the database
+    pub fn interface(&self) -> &InterfaceInstance {
+        &self.interface
+    }
+
+    /// Subscribe to the changes of all the parameters. A subscriber that falls behind
+    /// by more than the channel capacity gets `RecvError::Lagged` and should re-read the values
+    pub fn subscribe(&self) -> broadcast::Receiver<ParameterChangeEvent> {
+        self.events.subscribe()
+    }
+
+    async fn run_blocking<T, F>(&self, f: F) -> Result<T, AsyncError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&InterfaceInstance) -> Result<T, EconfError> + Send + 'static,
+    {
+        let interface = self.interface.clone();
+        Ok(tokio::task::spawn_blocking(move || f(&interface)).await??)
+    }
+
+    pub async fn get(&self, id: ParameterId, force: bool) -> Result<ParameterValue, AsyncError> {
+        debug!("Async get {}", id as usize);
+        self.run_blocking(move |interface| interface.get(id, force)).await
+    }
+
+    pub async fn get_group_values(&self, group: String) -> Result<Vec<(ParameterId, ParameterValue)>, AsyncError> {
+        self.run_blocking(move |interface| interface.get_group_values(&group)).await
+    }
+
+    pub async fn set(&self, id: ParameterId, parameter: ParameterValue) -> Result<SetOutcome, AsyncError> {
+        debug!("Async set {}", id as usize);
+        self.run_blocking(move |interface| interface.set(id, parameter)).await
+    }
+
+    pub async fn set_with_source(
+        &self,
+        id: ParameterId,
+        parameter: ParameterValue,
+        source: Option<String>,
+    ) -> Result<SetOutcome, AsyncError> {
+        self.run_blocking(move |interface| interface.set_with_source(id, parameter, source.as_deref()))
+            .await
+    }
+
+    pub async fn set_all(
+        &self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        source: Option<String>,
+    ) -> Result<Vec<(ParameterId, SetOutcome)>, AsyncError> {
+        self.run_blocking(move |interface| interface.set_all(values, source.as_deref())).await
+    }
+
+    pub async fn apply_staged(&self, source: Option<String>) -> Result<Vec<(ParameterId, SetOutcome)>, AsyncError> {
+        self.run_blocking(move |interface| interface.apply_staged(source.as_deref())).await
+    }
+
+    pub async fn save(&self) -> Result<(), AsyncError> {
+        self.run_blocking(|interface| interface.save()).await
+    }
+
+    pub async fn load(&self) -> Result<(), AsyncError> {
+        self.run_blocking(|interface| interface.load()).await
+    }
+
+    pub async fn factory_reset(&self) -> Result<(), AsyncError> {
+        self.run_blocking(|interface| interface.factory_reset()).await
+    }
+
+    pub async fn refresh_defaults(&self) -> Result<Vec<ParameterId>, AsyncError> {
+        self.run_blocking(|interface| interface.refresh_defaults()).await
+    }
+
+    pub async fn maintain(&self) -> Result<(), AsyncError> {
+        self.run_blocking(|interface| interface.maintain()).await
+    }
+}
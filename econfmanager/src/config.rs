@@ -1,20 +1,56 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
 use log::info;
+use zeroize::Zeroizing;
+
+use crate::constants::{COALESCE_WINDOW, EMBED_VALUES, IN_MEMORY_DATABASE_PATH, MULTICAST_GROUP, MULTICAST_PORT, MULTICAST_TTL};
+use crate::error::EconfError;
+use crate::transport::TransportConfig;
 
 pub(crate) struct Config {
     pub database_path: String,
     pub saved_database_path: String,
     pub default_data_folder: String,
+    /// The working database is kept in memory (`database_path` is ":memory:"), nothing is shared
+    /// with the other processes and the values are lost when the instance is dropped
+    pub in_memory: bool,
+    /// The database is opened read-only, the changes are refused and no notifications are sent
+    pub read_only: bool,
+    pub multicast_group: Ipv4Addr,
+    pub multicast_port: u16,
+    pub multicast_ttl: u32,
+    /// Notifications fired within this time are sent as one batch
+    pub coalesce_window: Duration,
+    pub transport: TransportConfig,
+    /// Embed the new values in the notifications
+    pub embed_values: bool,
+    /// Folder of the blobs stored as files, None keeps all the blobs in the database
+    pub blob_directory: Option<String>,
+    /// Blobs of this size and larger are stored as files
+    pub blob_file_threshold: usize,
+    /// Fail the initialisation if a file default can't be read
+    pub strict_defaults: bool,
+    /// Number of the previous saved databases kept as backups, 0 disables the backups
+    pub backup_retention: usize,
+    /// Run the database maintenance after this number of writes, 0 disables it
+    pub maintenance_writes: u64,
+    /// SQLCipher key of the working and the saved databases, None keeps them in plaintext
+    pub encryption_key: Option<Zeroizing<String>>,
+    /// Device key of the secret parameters, required if there are any
+    pub secret_key: Option<Zeroizing<[u8; 32]>>,
 }
 
-impl Config {
-    pub(crate) fn new(database_path: &String, saved_database_path: &String, default_data_folder: &String) -> Result<Config, Box<dyn std::error::Error>> {
-        let expand_path = |path: &String| -> Result<String, Box<dyn std::error::Error>> {
-            let expanded = shellexpand::env(path)
-                .map_err(|e| format!("Failed to expand environment variables: {}", e))?
-                .to_string();
-            Ok(expanded)
-        };
+/// Expand the environment variables in a path
+pub(crate) fn expand_path(path: &str) -> Result<String, EconfError> {
+    let expanded = shellexpand::env(path)
+        .map_err(|e| EconfError::Config(format!("Failed to expand environment variables: {}", e)))?
+        .to_string();
+    Ok(expanded)
+}
 
+impl Config {
+    pub(crate) fn new(database_path: &str, saved_database_path: &str, default_data_folder: &str) -> Result<Config, EconfError> {
         let database_path = expand_path(database_path)?;
         let saved_database_path = expand_path(saved_database_path)?;
         let default_data_folder = expand_path(default_data_folder)?;
@@ -24,9 +60,24 @@ impl Config {
         info!("Default data folder: {}", default_data_folder);
 
         Ok(Config {
+            in_memory: database_path == IN_MEMORY_DATABASE_PATH,
+            read_only: false,
             database_path,
             saved_database_path,
             default_data_folder,
+            multicast_group: MULTICAST_GROUP,
+            multicast_port: MULTICAST_PORT,
+            multicast_ttl: MULTICAST_TTL,
+            coalesce_window: COALESCE_WINDOW,
+            transport: TransportConfig::default(),
+            embed_values: EMBED_VALUES,
+            blob_directory: None,
+            blob_file_threshold: 0,
+            strict_defaults: false,
+            backup_retention: 0,
+            maintenance_writes: 0,
+            encryption_key: None,
+            secret_key: None,
         })
     }
 }
@@ -1,5 +1,71 @@
 use std::net::Ipv4Addr;
+use std::time::Duration;
 
 
 pub(crate) const MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 123);
-pub(crate) const MULTICAST_PORT: u16 = 44321;
\ No newline at end of file
+pub(crate) const MULTICAST_PORT: u16 = 44321;
+/// Limit the notifications to the local network
+pub(crate) const MULTICAST_TTL: u32 = 1;
+
+/// Notifications are sent immediately by default
+pub(crate) const COALESCE_WINDOW: Duration = Duration::ZERO;
+/// Limit of the parameters in one notification datagram
+pub(crate) const MAX_BATCH_IDS: usize = 64;
+/// Largest notification accepted by the receivers
+pub(crate) const MAX_NOTIFICATION_SIZE: usize = 4096;
+/// Values up to this size are embedded in the notifications by default
+pub(crate) const EMBED_VALUES: bool = true;
+pub(crate) const MAX_EMBEDDED_VALUE_SIZE: usize = 1024;
+
+/// Database path of the in-memory working database, see the builder option `in_memory`.
+/// Used for the saved database path too when it is not set
+pub(crate) const IN_MEMORY_DATABASE_PATH: &str = ":memory:";
+
+/// Minimum time between the resyncs after lost notifications
+pub(crate) const RESYNC_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Backoff of the reconnection to the TCP notification broker
+pub(crate) const TCP_RECONNECT_MIN_DELAY: Duration = Duration::from_millis(100);
+pub(crate) const TCP_RECONNECT_MAX_DELAY: Duration = Duration::from_secs(10);
+/// Connect and write timeout of the TCP notification connections
+pub(crate) const TCP_IO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Source of the change events of the computed parameters
+pub(crate) const COMPUTED_SOURCE: &str = "computed";
+
+/// How often the receiver threads check for the shutdown while waiting for the notifications
+pub(crate) const RECEIVER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Entries kept in the change journal of the database, a poll that falls further behind rereads all the parameters
+pub(crate) const CHANGE_JOURNAL_SIZE: u64 = 1000;
+
+/// The saved database is copied in steps of this many pages, the progress is reported after each step
+pub(crate) const BACKUP_PAGES_PER_STEP: std::os::raw::c_int = 100;
+/// Writers wait this long for another process holding the database (a write, load or factory reset)
+pub(crate) const WRITE_BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+/// Readers wait less, they are only blocked by the recovery of the WAL
+pub(crate) const READ_BUSY_TIMEOUT: Duration = Duration::from_millis(300);
+/// Wait before retrying a step when the database is locked by another connection
+pub(crate) const BACKUP_BUSY_DELAY: Duration = Duration::from_millis(250);
+
+/// The autosave interval is randomly changed by up to this percentage, so the instances
+/// started together don't write at the same time
+pub(crate) const AUTOSAVE_JITTER_PERCENT: u64 = 10;
+/// The autosave waits until the parameters are not changed for this time, up to one interval
+pub(crate) const AUTOSAVE_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// A larger WAL fails the readiness check, the checkpoints are blocked by a long reader or failing
+pub(crate) const WAL_SIZE_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Shortest period of the checks for the due debounced writes
+pub(crate) const PERSIST_MIN_CHECK_PERIOD: Duration = Duration::from_millis(10);
+
+/// Largest key accepted from the key provider of econf_init_encrypted
+#[cfg(feature = "sqlcipher")]
+pub(crate) const ENCRYPTION_KEY_MAX_LENGTH: usize = 256;
+
+/// Default time after which a thread holding or waiting for an instance lock is reported
+pub(crate) const LOCK_WARNING_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Descriptor set of the parameters proto, used to decode structured blobs at runtime
+pub(crate) const DESCRIPTORS_BIN: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/descriptors.bin"));
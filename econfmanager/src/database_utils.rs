@@ -1,18 +1,27 @@
-use rusqlite::{Connection, OpenFlags, ToSql, backup::Backup, params};
+use regex::Regex;
+use rusqlite::{Connection, DatabaseName, OpenFlags, OptionalExtension, ToSql, Transaction, TransactionBehavior, backup::{Backup, StepResult}, params};
+use sha2::{Digest, Sha256};
 use strsim::levenshtein;
 use std::cmp::Ordering;
-use std::time::Duration;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::OnceLock;
+use zeroize::{Zeroize, Zeroizing};
 use std::{
-    error::Error,
     fmt, fs,
     path::{Path, PathBuf},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 
-use crate::schema::{ParameterValueType, ValidationMethod};
+use crate::constants::{BACKUP_BUSY_DELAY, BACKUP_PAGES_PER_STEP, CHANGE_JOURNAL_SIZE, IN_MEMORY_DATABASE_PATH, READ_BUSY_TIMEOUT, WRITE_BUSY_TIMEOUT};
+use crate::error::{EconfError, ValidationError};
+use crate::interface::{ParameterChangeEvent, StorageHealth, StorageRecovery, WriteCondition};
+use crate::manifest::to_hex;
+use crate::schema::{ParameterValueType, PersistPolicy, ValidationMethod};
+use crate::sensitive::{self, SecretCipher};
 use crate::{
     config::Config,
     generated::{PARAMETER_DATA, ParameterId},
@@ -20,6 +29,119 @@ use crate::{
 };
 
 const TABLE_NAME: &str = "parameters";
+const SEQUENCE_TABLE_NAME: &str = "write_sequence";
+/// Journal of the changes of the parameters table, filled by triggers so the writes of every process
+/// (including the older versions) are recorded, read by the update poll
+const CHANGES_TABLE_NAME: &str = "changes";
+/// Temporary table of the connection with the blobs being streamed. The parameters table is
+/// WITHOUT ROWID and doesn't support the incremental blob I/O
+const BLOB_STREAMS_TABLE_NAME: &str = "blob_streams";
+/// The blobs stored as files are kept in the database as TEXT with this prefix and the SHA-256
+/// of the content, which is also the name of the file in the blob directory
+const BLOB_FILE_PREFIX: &str = "sha256:";
+/// Folder of the profiles next to the saved database, one database file per profile
+const PROFILES_DIRECTORY: &str = "profiles";
+const PROFILE_EXTENSION: &str = "sqlite";
+
+/// Check a blob against the `max_size` option of the parameter
+pub(crate) fn check_blob_size(id: ParameterId, blob: &[u8]) -> Result<(), ValidationError> {
+    match PARAMETER_DATA[id.index()].max_size {
+        Some(max_size) if blob.len() > max_size as usize => Err(ValidationError::TooLarge { size: blob.len(), max_size }),
+        _ => Ok(()),
+    }
+}
+
+/// Check a string against the `max_length` and `pattern` options of the parameter.
+/// The pattern has to match the whole string, like the pattern attribute of HTML inputs
+pub(crate) fn check_text(id: ParameterId, value: &str) -> Result<(), ValidationError> {
+    // Compiled patterns by the parameter index, the patterns are checked by the build
+    static PATTERNS: OnceLock<Vec<Option<Regex>>> = OnceLock::new();
+    let ValidationMethod::Text { max_length, pattern } = &PARAMETER_DATA[id.index()].validation else {
+        return Ok(());
+    };
+    if let Some(max_length) = max_length {
+        let length = value.chars().count();
+        if length > *max_length as usize {
+            return Err(ValidationError::TooLong { length, max_length: *max_length });
+        }
+    }
+    if let Some(pattern) = pattern {
+        let patterns = PATTERNS.get_or_init(|| {
+            PARAMETER_DATA.iter().map(|parameter| match &parameter.validation {
+                ValidationMethod::Text { pattern: Some(pattern), .. } => Regex::new(&format!("^(?:{})$", pattern)).ok(),
+                _ => None,
+            }).collect()
+        });
+        if patterns[id.index()].as_ref().is_some_and(|regex| !regex.is_match(value)) {
+            return Err(ValidationError::PatternMismatch { pattern });
+        }
+    }
+    Ok(())
+}
+
+/// Check the value against the validation rules of the parameter, the out of range values are fixed
+pub(crate) fn validate_value(id: ParameterId, value: Status<ParameterValue>) -> Result<Status<ParameterValue>, EconfError> {
+    let input = value.unwrap();
+    debug!("Validating {}", id as usize);
+    if !input.is_finite() && !PARAMETER_DATA[id.index()].allow_non_finite {
+        debug!("{} non-finite value rejected {}", id as usize, sensitive::log_value(id, &input));
+        return Ok(Status::StatusErrorNotAccepted(input));
+    }
+    if let ParameterValue::ValBlob(blob) = &input {
+        if let Err(e) = check_blob_size(id, blob) {
+            debug!("{} not accepted: {}", id as usize, e);
+            return Ok(Status::StatusErrorNotAccepted(input));
+        }
+    }
+    match &PARAMETER_DATA[id.index()].validation {
+        ValidationMethod::None => Ok(Status::StatusOkChanged(input)),
+
+        ValidationMethod::Range { min, max } => {
+            if input < *min {
+                debug!("{} min overflow fixed {} -> {}", id as usize, sensitive::log_value(id, &input), *min);
+                Ok(Status::StatusOkOverflowFixed(min.clone()))
+            } else if input > *max {
+                debug!("{} max overflow fixed {} -> {}", id as usize, sensitive::log_value(id, &input), *max);
+                Ok(Status::StatusOkOverflowFixed(max.clone()))
+            } else {
+                Ok(Status::StatusOkChanged(input))
+            }
+        }
+
+        ValidationMethod::AllowedValues { values, names: _ } => {
+            match values
+                .iter()
+                .map(|val| (val, val.distance(&input)))
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            {
+                Some((closest, _)) => {
+                    if *closest == input {
+                        Ok(Status::StatusOkChanged(input))
+                    }
+                    else
+                    {
+                        debug!("{} allowed val overflow fixed {} -> {}", id as usize, sensitive::log_value(id, &input), *closest);
+                        Ok(Status::StatusOkOverflowFixed(closest.clone()))
+                    }
+                }
+                None => Ok(Status::StatusErrorNotAccepted(input)),
+            }
+        }
+
+        ValidationMethod::Text { .. } => match &input {
+            ParameterValue::ValString(text) => match check_text(id, text) {
+                Ok(()) => Ok(Status::StatusOkChanged(input)),
+                Err(e) => {
+                    debug!("{} not accepted: {}", id as usize, e);
+                    Ok(Status::StatusErrorNotAccepted(input))
+                }
+            },
+            _ => Ok(Status::StatusErrorNotAccepted(input)),
+        },
+
+        ValidationMethod::CustomCallback => todo!(),
+    }
+}
 
 impl ParameterValue {
     pub(crate) fn distance(&self, other: &ParameterValue) -> Option<f64> {
@@ -70,12 +192,267 @@ impl PartialOrd for ParameterValue {
     }
 }
 
+/// Databases created by older versions don't have all the columns, add them in place
+fn add_missing_columns(conn: &Connection) -> Result<(), rusqlite::Error> {
+    for (column, column_type) in [("source", "TEXT"), ("sequence", "INTEGER")] {
+        let exists = conn.prepare(&format!("SELECT {} FROM {} LIMIT 0", column, TABLE_NAME)).is_ok();
+        if !exists {
+            conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", TABLE_NAME, column, column_type), [])?;
+            info!("Column {} added to the parameters database", column);
+        }
+    }
+    Ok(())
+}
+
+/// Create the tables of a new database, the existing tables are brought up to date
+fn create_tables(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            key INTEGER UNIQUE PRIMARY KEY,
+            value BLOB,
+            timestamp REAL,
+            source TEXT,
+            sequence INTEGER
+        ) WITHOUT ROWID;
+        CREATE TABLE IF NOT EXISTS {} (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            value INTEGER NOT NULL
+        );
+        INSERT OR IGNORE INTO {} (id, value) VALUES (0, 0);",
+        TABLE_NAME, SEQUENCE_TABLE_NAME, SEQUENCE_TABLE_NAME
+    );
+    let tx = conn.transaction()?;
+    tx.execute_batch(&sql)?;
+    tx.commit()?;
+
+    add_missing_columns(conn)?;
+    create_change_journal(conn)
+}
+
+/// Create the change journal and the triggers recording the inserted, updated and removed rows.
+/// The journal keeps the last CHANGE_JOURNAL_SIZE entries
+fn create_change_journal(conn: &mut Connection) -> Result<(), rusqlite::Error> {
+    let sql = format!(
+        "CREATE TABLE IF NOT EXISTS {changes} (
+            counter INTEGER PRIMARY KEY AUTOINCREMENT,
+            key TEXT NOT NULL,
+            value BLOB,
+            timestamp REAL,
+            source TEXT,
+            sequence INTEGER,
+            removed INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TRIGGER IF NOT EXISTS {parameters}_inserted AFTER INSERT ON {parameters} BEGIN
+            INSERT INTO {changes} (key, value, timestamp, source, sequence)
+            VALUES (NEW.key, NEW.value, NEW.timestamp, NEW.source, NEW.sequence);
+        END;
+        CREATE TRIGGER IF NOT EXISTS {parameters}_updated AFTER UPDATE ON {parameters} BEGIN
+            INSERT INTO {changes} (key, value, timestamp, source, sequence)
+            VALUES (NEW.key, NEW.value, NEW.timestamp, NEW.source, NEW.sequence);
+        END;
+        CREATE TRIGGER IF NOT EXISTS {parameters}_removed AFTER DELETE ON {parameters} BEGIN
+            INSERT INTO {changes} (key, timestamp, removed)
+            VALUES (OLD.key, (julianday('now') - 2440587.5) * 86400.0, 1);
+        END;
+        CREATE TRIGGER IF NOT EXISTS {changes}_pruned AFTER INSERT ON {changes} BEGIN
+            DELETE FROM {changes} WHERE counter <= NEW.counter - {size};
+        END;",
+        changes = CHANGES_TABLE_NAME,
+        parameters = TABLE_NAME,
+        size = CHANGE_JOURNAL_SIZE,
+    );
+    let tx = conn.transaction()?;
+    tx.execute_batch(&sql)?;
+    tx.commit()
+}
+
+/// Counter of the newest entry of the change journal, 0 if nothing was recorded
+fn newest_change(conn: &Connection) -> Result<u64, rusqlite::Error> {
+    conn.query_row(
+        &format!("SELECT COALESCE(MAX(counter), 0) FROM {}", CHANGES_TABLE_NAME),
+        [],
+        |row| row.get::<usize, i64>(0),
+    )
+    .map(|counter| counter as u64)
+}
+
+/// Allocate the next value of the global write sequence.
+/// Must be called inside a write transaction, so that writers from all the processes
+/// get unique and increasing values in the order the writes are applied
+fn next_sequence(conn: &Connection) -> Result<u64, rusqlite::Error> {
+    conn.execute(&format!("UPDATE {} SET value = value + 1", SEQUENCE_TABLE_NAME), [])?;
+    conn.query_row(&format!("SELECT value FROM {}", SEQUENCE_TABLE_NAME), [], |row| row.get::<usize, i64>(0))
+        .map(|value| value as u64)
+}
+
+/// Sequence number of the last write of the parameter, 0 if it was not written
+fn stored_sequence(conn: &Connection, key: &str) -> Result<u64, rusqlite::Error> {
+    conn.query_row(
+        &format!("SELECT sequence FROM {} WHERE key = ?", TABLE_NAME),
+        params![key],
+        |row| row.get::<usize, Option<i64>>(0),
+    )
+    .optional()
+    .map(|sequence| sequence.flatten().unwrap_or(0) as u64)
+}
+
+/// Reference to the blob file stored for the parameter, if the blob is stored as a file
+fn stored_blob_file(conn: &Connection, key: &str) -> Result<Option<String>, rusqlite::Error> {
+    conn.query_row(
+        &format!("SELECT value FROM {} WHERE key = ? AND typeof(value) = 'text'", TABLE_NAME),
+        params![key],
+        |row| row.get::<usize, String>(0),
+    )
+    .optional()
+    .map(|reference| reference.filter(|reference| reference.starts_with(BLOB_FILE_PREFIX)))
+}
+
+/// Copy the database page by page, reporting the pages copied and the total number of pages
+fn copy_pages(src_conn: &Connection, dst_conn: &mut Connection, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+    // Stepping manually, the progress callback of run_to_completion can't capture the caller state
+    let backup = Backup::new(src_conn, dst_conn)?;
+    loop {
+        let step = backup.step(BACKUP_PAGES_PER_STEP)?;
+        let state = backup.progress();
+        progress((state.pagecount - state.remaining) as u64, state.pagecount as u64);
+        match step {
+            StepResult::Done => return Ok(()),
+            StepResult::More => {}
+            _ => std::thread::sleep(BACKUP_BUSY_DELAY),
+        }
+    }
+}
+
+/// Open a database, with the key set the database is decrypted by SQLCipher.
+/// A wrong key is reported as a configuration error, not as a corrupted database
+fn open_database(path: &Path, flags: OpenFlags, key: Option<&str>) -> Result<Connection, EconfError> {
+    let conn = Connection::open_with_flags(path, flags)?;
+    let Some(key) = key else {
+        return Ok(conn);
+    };
+    conn.pragma_update(None, "key", key)?;
+    // The key is only checked when the first page is read
+    match conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<usize, i64>(0)) {
+        Ok(_) => Ok(conn),
+        Err(e) if e.sqlite_error_code() == Some(rusqlite::ErrorCode::NotADatabase) => Err(EconfError::Config(format!(
+            "{} can't be decrypted, the key is wrong or the database is not encrypted",
+            path.display()
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Errors of the reads while another process holds or replaces the database are reported as retryable,
+/// older versions drop the table before loading the saved database.
+/// There is no separate lock between the processes: load, restore and factory reset replace the values in
+/// one SQLite write transaction, its lock makes the other writers wait (WRITE_BUSY_TIMEOUT) and the WAL
+/// readers keep the previous snapshot until the commit
+fn read_error(error: rusqlite::Error) -> EconfError {
+    let busy = matches!(
+        error.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    ) || matches!(&error, rusqlite::Error::SqliteFailure(_, Some(message)) if message.starts_with("no such table"));
+    if busy {
+        EconfError::DbBusy
+    } else {
+        error.into()
+    }
+}
+
+/// The error means the file is damaged or not a database, not just busy or missing
+fn is_corruption(error: &rusqlite::Error) -> bool {
+    matches!(
+        error.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+    )
+}
+
+/// Run integrity_check, or quick_check that skips the index contents, and return the problems found
+fn integrity_problems(conn: &Connection, quick: bool) -> Result<Vec<String>, rusqlite::Error> {
+    let pragma = if quick { "quick_check" } else { "integrity_check" };
+    let result = conn
+        .prepare(&format!("PRAGMA {}", pragma))
+        .and_then(|mut stmt| stmt.query_map([], |row| row.get::<usize, String>(0))?.collect::<Result<Vec<String>, _>>());
+    match result {
+        Ok(rows) => Ok(rows.into_iter().filter(|row| row != "ok").collect()),
+        Err(e) if is_corruption(&e) => Ok(vec![e.to_string()]),
+        Err(e) => Err(e),
+    }
+}
+
+/// References to the blob files from the blob parameters of the database
+fn referenced_blob_files(conn: &Connection, references: &mut HashSet<String>) -> Result<(), rusqlite::Error> {
+    // The saved database is empty until the first save
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        params![TABLE_NAME],
+        |row| row.get(0),
+    )?;
+    if tables == 0 {
+        return Ok(());
+    }
+    let mut stmt = conn.prepare(&format!("SELECT key, value FROM {} WHERE typeof(value) = 'text'", TABLE_NAME))?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        let key = row.get::<usize, String>(0)?;
+        let is_blob = ParameterId::from_name(&key)
+            .is_some_and(|id| PARAMETER_DATA[id.index()].value_type == ParameterValueType::TypeBlob);
+        let reference = row.get::<usize, String>(1)?;
+        if is_blob && reference.starts_with(BLOB_FILE_PREFIX) {
+            references.insert(reference);
+        }
+    }
+    Ok(())
+}
+
+/// How a value is stored in the database, prepared before the write transaction
+struct StoredValue {
+    /// Reference of the blob file, the blob is stored in the file
+    blob_file: Option<String>,
+    /// The encrypted secret
+    encrypted: Option<Vec<u8>>,
+}
+
+/// A change of a parameter with a deferred persist policy, not written to the database yet
+struct PendingWrite {
+    value: ParameterValue,
+    source: Option<String>,
+    /// When the debounced write is due, None for the writes done on save
+    due: Option<Instant>,
+}
+
+/// The SQLite parameter store, the default store of `InterfaceInstance`
 #[derive(Default)]
-pub(crate) struct DatabaseManager {
+pub struct DatabaseManager {
     database_path: String,
     saved_database_path: String,
     default_data_folder: String,
-    last_update_timestamp: f64,
+    blob_directory: Option<PathBuf>,
+    blob_file_threshold: usize,
+    /// SQLCipher key of the working and the saved databases
+    encryption_key: Option<Zeroizing<String>>,
+    /// Encrypts the values of the secret parameters
+    secret_cipher: Option<SecretCipher>,
+    /// Deferred writes by parameter index, see PersistPolicy
+    pending: BTreeMap<usize, PendingWrite>,
+    backup_retention: usize,
+    /// Run the maintenance after this number of writes, 0 disables the automatic maintenance
+    maintenance_writes: u64,
+    writes_since_maintenance: u64,
+    /// Write sequence at the last save, None if not saved by this instance
+    saved_sequence: Option<u64>,
+    storage_health: StorageHealth,
+    /// Modification time and size of the file defaults when they were last checked, by parameter index
+    default_files: Vec<Option<(SystemTime, u64)>>,
+    /// Counter of the newest entry of the change journal read by update(), 0 before the first update
+    last_change: u64,
+    /// The working database exists only in the connection, which is never closed
+    in_memory: bool,
+    /// The connection is opened read-only and the changes are refused
+    read_only: bool,
+    /// Connection kept open between the operations, the other processes are handled
+    /// by SQLite with WAL and busy_timeout. Opened on the first use
+    connection: Option<DbConnection>,
 }
 
 pub struct DbConnection {
@@ -87,7 +464,17 @@ impl DbConnection {
         database_path: &String,
         write_required: bool,
         create_required: bool,
-    ) -> Result<Self, Box<dyn Error>> {
+    ) -> Result<Self, EconfError> {
+        Self::open(database_path, write_required, create_required, None)
+    }
+
+    /// Same as `new` for a database encrypted with the key, see the `sqlcipher` feature
+    pub fn open(
+        database_path: &String,
+        write_required: bool,
+        create_required: bool,
+        key: Option<&str>,
+    ) -> Result<Self, EconfError> {
         let flags = if write_required {
             let mut f = OpenFlags::SQLITE_OPEN_READ_WRITE;
             if create_required {
@@ -98,40 +485,30 @@ impl DbConnection {
             OpenFlags::SQLITE_OPEN_READ_ONLY
         };
 
-        let mut conn = match Connection::open_with_flags(database_path, flags) {
+        let mut conn = match open_database(Path::new(database_path), flags, key) {
             Ok(conn) => {
-                let _ = conn.busy_timeout(std::time::Duration::from_millis(300));
+                let _ = conn.busy_timeout(if write_required { WRITE_BUSY_TIMEOUT } else { READ_BUSY_TIMEOUT });
                 conn
             }
             Err(e) => {
-                return Err(format!("Failed to open connection: {}", e).into());
+                error!("Failed to open connection to {}: {}", database_path, e);
+                return Err(e);
             }
         };
         debug!("> DB connection opened with flags {:?}", flags);
 
         if create_required {
-            let sql = format!(
-                "CREATE TABLE IF NOT EXISTS {} (
-                    key INTEGER UNIQUE PRIMARY KEY,
-                    value BLOB,
-                    timestamp REAL
-                ) WITHOUT ROWID;",
-                TABLE_NAME
-            );
-            let tx = conn.transaction()?;
-            tx.execute_batch(&sql)?;
-            tx.commit()?;
+            create_tables(&mut conn)?;
 
             conn.pragma_update(None, "locking_mode", "NORMAL")?;
             conn.pragma_update(None, "journal_mode", "WAL")?;
 
-            // TODO: Optional: needs testing
-            // conn.execute_batch(&format!("PRAGMA auto_vacuum = {};", "INCREMENTAL"))?;
+            // Takes effect with the VACUUM below, the free pages are then released by maintain()
+            conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
             conn.execute("VACUUM", [])?;
 
             conn.pragma_update(None, "wal_autocheckpoint", "1000")?; // Pages
             conn.pragma_update(None, "synchronous", "NORMAL")?;
-            conn.pragma_update(None, "busy_timeout", "10000")?; // 10 second timeout
 
             info!("Parameters database created");
         }
@@ -170,9 +547,14 @@ pub enum Status<T> {
     StatusOkNotChecked(T),
     StatusOkOverflowFixed(T),
     StatusErrorNotAccepted(T),
+    /// The write condition doesn't hold, the parameter was changed by another writer
+    StatusErrorConflict(T),
     StatusErrorFailed,
 }
 
+/// Status of a value written by `write_all` and the sequence number of the write, None if not written
+pub type WrittenValue = (ParameterId, Status<ParameterValue>, Option<u64>);
+
 impl<T> Status<T> {
     pub fn unwrap(self) -> T {
         match self {
@@ -180,7 +562,8 @@ impl<T> Status<T> {
             | Status::StatusOkNotChanged(val)
             | Status::StatusOkNotChecked(val)
             | Status::StatusOkOverflowFixed(val)
-            | Status::StatusErrorNotAccepted(val) => val,
+            | Status::StatusErrorNotAccepted(val)
+            | Status::StatusErrorConflict(val) => val,
             Status::StatusErrorFailed => panic!("called `Status::unwrap()` on a `StatusErrorFailed`"),
         }
     }
@@ -194,11 +577,23 @@ impl<T: fmt::Display> fmt::Display for Status<T> {
             Status::StatusOkNotChecked(value) => write!(f, "OK (not checked): {}", value),
             Status::StatusOkOverflowFixed(value) => write!(f, "OK (overflow fixed): {}", value),
             Status::StatusErrorNotAccepted(value) => write!(f, "Error (not accepted): {}", value),
+            Status::StatusErrorConflict(value) => write!(f, "Error (conflict): {}", value),
             Status::StatusErrorFailed => write!(f, "Error (operation failed)"),
         }
     }
 }
 
+impl Drop for DatabaseManager {
+    fn drop(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if let Err(e) = self.flush_pending(true) {
+            error!("Deferred writes lost: {}", e);
+        }
+    }
+}
+
 impl DatabaseManager {
     /******************************************************************************
      * PRIVATE FUNCTIONS
@@ -206,7 +601,7 @@ impl DatabaseManager {
 
     /// Returns current timestamp with seconds and milliseconds as a floating-point number
     /// (e.g. 1712345678.456 for 456 milliseconds past the second)
-    fn get_timestamp() -> f64 {
+    pub(crate) fn get_timestamp() -> f64 {
         let duration = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards");
@@ -217,55 +612,121 @@ impl DatabaseManager {
         seconds + milliseconds
     }
 
+    /// Copy the database file with the key of this instance
     fn copy_database(
+        &self,
         source_path: &Path,
         backup_path: &Path,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let src_conn = Connection::open(source_path)?;
-        let mut dst_conn = Connection::open(backup_path)?;
-
-        let backup = Backup::new(&src_conn, &mut dst_conn)?;
-        Ok(backup.run_to_completion(100, Duration::from_millis(250), None)?)
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        let src_conn = open_database(source_path, OpenFlags::default(), self.key())?;
+        let mut dst_conn = open_database(backup_path, OpenFlags::default(), self.key())?;
+        copy_pages(&src_conn, &mut dst_conn, progress)
     }
 
+    /// Copy the rows accepted by the filter, reporting the rows processed and the total number of rows
     fn copy_database_with_filter(
-        source_path: &Path,
+        &self,
+        src_conn: &Connection,
         backup_path: &Path,
         filter: &dyn Fn(&String) -> bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let src_conn = Connection::open(source_path)?;
-        let dst_conn = Connection::open(backup_path)?;
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        let dst_conn = open_database(backup_path, OpenFlags::default(), self.key())?;
 
         dst_conn.execute(
             &format!(
                 "CREATE TABLE IF NOT EXISTS {} (
                     key INTEGER UNIQUE PRIMARY KEY,
                     value BLOB,
-                    timestamp REAL
+                    timestamp REAL,
+                    source TEXT,
+                    sequence INTEGER
                 ) WITHOUT ROWID;",
                 TABLE_NAME
             ),
             [],
         )?;
+        add_missing_columns(&dst_conn)?;
 
+        let total: i64 = src_conn.query_row(&format!("SELECT COUNT(*) FROM {}", TABLE_NAME), [], |row| row.get(0))?;
         let mut src_stmt =
-            src_conn.prepare(&format!("SELECT key, value, timestamp FROM {}", TABLE_NAME))?;
+            src_conn.prepare(&format!("SELECT key, value, timestamp, source, sequence FROM {}", TABLE_NAME))?;
         let mut rows = src_stmt.query([])?;
 
         let mut dst_stmt = dst_conn.prepare(&format!(
-            "INSERT INTO {} (key, value, timestamp) VALUES (?1, ?2, ?3)",
+            "INSERT INTO {} (key, value, timestamp, source, sequence) VALUES (?1, ?2, ?3, ?4, ?5)",
             TABLE_NAME
         ))?;
 
+        let mut copied = 0;
         while let Some(row) = rows.next()? {
+            copied += 1;
+            progress(copied, total as u64);
             let key = row.get(0).unwrap_or("".to_string());
             if filter(&key) {
                 let value: rusqlite::types::Value = row.get(1)?;
                 let timestamp: f64 = std::f64::MAX;
-                dst_stmt.execute(params![key, value, timestamp])?;
+                let source: Option<String> = row.get(3)?;
+                let sequence: Option<i64> = row.get(4)?;
+                dst_stmt.execute(params![key, value, timestamp, source, sequence])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn key(&self) -> Option<&str> {
+        self.encryption_key.as_deref().map(String::as_str)
+    }
+
+    /// Open the persistent connection if it is not open yet
+    fn open_connection(&mut self) -> Result<(), EconfError> {
+        if self.connection.is_none() {
+            self.connection = Some(DbConnection::open(&self.database_path, !self.read_only, false, self.key())?);
+        }
+        Ok(())
+    }
+
+    fn connection(&mut self) -> Result<&mut DbConnection, EconfError> {
+        self.open_connection()?;
+        Ok(self.connection.as_mut().expect("Connection is opened above"))
+    }
+
+    /// Run on a new connection to the working database, `create` creates the tables if needed.
+    /// The in-memory database has only the persistent connection
+    fn with_working_database<T>(
+        &mut self,
+        create: bool,
+        f: impl FnOnce(&mut Connection) -> Result<T, EconfError>,
+    ) -> Result<T, EconfError> {
+        if self.in_memory {
+            let db = self.connection()?;
+            if create {
+                create_tables(db.conn_mut())?;
             }
+            return f(db.conn_mut());
         }
+        let mut db = DbConnection::open(&self.database_path, true, create, self.key())?;
+        f(db.conn_mut())
+    }
 
+    /// The in-memory database can be used without the saved database, see `Config::in_memory`
+    fn has_saved_database(&self) -> bool {
+        self.saved_database_path != IN_MEMORY_DATABASE_PATH
+    }
+
+    fn require_writable(&self) -> Result<(), EconfError> {
+        if self.read_only {
+            return Err(EconfError::ReadOnlyInstance);
+        }
+        Ok(())
+    }
+
+    fn require_saved_database(&self) -> Result<(), EconfError> {
+        if !self.has_saved_database() {
+            return Err(EconfError::Config("The saved database path is not set".to_owned()));
+        }
         Ok(())
     }
 
@@ -280,104 +741,628 @@ impl DatabaseManager {
      * PUBLIC FUNCTIONS
      ******************************************************************************/
 
-    pub(crate) fn drop_database(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub(crate) fn drop_database(&mut self) -> Result<(), EconfError> {
+        self.require_writable()?;
         info!("Deleting database");
+        self.discard_pending();
+        if !self.in_memory {
+            // VACUUM needs no other connections in the middle of a statement, reopen later
+            self.connection = None;
+        }
     
-        let result = {
-            let db = DbConnection::new(&self.database_path, true, false)?;
-            db.conn().execute(&format!("DROP TABLE {};", TABLE_NAME), [])?;
-            db.conn().execute("VACUUM", [])
-        };
+        // One transaction, the other processes see either all the values or none
+        let result = self.with_working_database(false, |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.execute(&format!("DELETE FROM {};", TABLE_NAME), [])?;
+            tx.commit()?;
+            conn.execute("VACUUM", [])?;
+            Ok(())
+        });
     
-        Self::create_dirs_for_file(&self.database_path)?;
-        let _ = DbConnection::new(&self.database_path, true, true)?;
+        if !self.in_memory {
+            Self::create_dirs_for_file(&self.database_path)?;
+        }
+        self.with_working_database(true, |_| Ok(()))?;
 
         result?;
+        self.collect_blob_files()?;
         Ok(())
     }
 
-    pub(crate) fn load_database(&self) -> Result<(), Box<dyn std::error::Error>> {
-        info!("Loading database");
-        if let Err(error) = self.drop_database() {
-            error!("Could not drop the database: {}", error);
-        }
+    /// Replace the working database with the saved one, serialized with the other processes by the SQLite
+    /// write lock, see `read_error`
+    pub(crate) fn load_database(&mut self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.require_writable()?;
+        self.require_saved_database()?;
+        let saved_database_path = self.saved_database_path.clone();
+        self.load_database_from(Path::new(&saved_database_path), progress)
+    }
+
+    fn load_database_from(&mut self, source_path: &Path, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.require_writable()?;
+        info!("Loading database from {}", source_path.display());
+        // The saved database replaces the sequence table and the change journal too, remember where they were
+        let last_sequence = self.current_sequence().unwrap_or(0);
+        let last_change = self.connection().ok().and_then(|db| newest_change(db.conn()).ok()).unwrap_or(0);
+        self.discard_pending();
+        // The database is not dropped first: the copy replaces all the pages in one write transaction,
+        // which makes the other writers wait and the readers see the previous values until it is done
         info!("Copying database");
-        if let Err(error) = Self::create_dirs_for_file(&self.database_path){
-            error!("Could not create the folders: {} for {}", error, self.database_path);
-            return Err(error.into());
-        }
-        if let Err(error) = Self::copy_database(
-            Path::new(&self.saved_database_path),
-            Path::new(&self.database_path)) {
+        let copied = if self.in_memory {
+            open_database(source_path, OpenFlags::default(), self.key()).and_then(|src_conn| {
+                let db = self.connection.as_mut().expect("The in-memory database is always open");
+                copy_pages(&src_conn, db.conn_mut(), progress)
+            })
+        } else {
+            if let Err(error) = Self::create_dirs_for_file(&self.database_path){
+                error!("Could not create the folders: {} for {}", error, self.database_path);
+                return Err(error.into());
+            }
+            self.copy_database(source_path, Path::new(&self.database_path), progress)
+        };
+        if let Err(error) = copied {
             error!("Could not copy the database: {}", error);
         }
+        self.collect_blob_files()?;
+        // The saved database could be created by an older version, bring the schema up to date
+        self.with_working_database(true, |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            tx.execute(
+                &format!(
+                    "UPDATE {} SET value = MAX(value, ?1, (SELECT COALESCE(MAX(sequence), 0) FROM {}))",
+                    SEQUENCE_TABLE_NAME, TABLE_NAME
+                ),
+                params![last_sequence as i64],
+            )?;
+            // The journal continues after a gap, so the pollers notice it and reread all the parameters,
+            // including the ones removed by the load
+            tx.execute("DELETE FROM sqlite_sequence WHERE name = ?1", params![CHANGES_TABLE_NAME])?;
+            tx.execute(
+                "INSERT INTO sqlite_sequence (name, seq) VALUES (?1, ?2)",
+                params![CHANGES_TABLE_NAME, (last_change + 1) as i64],
+            )?;
+            // The loaded values are new writes for the conditional writes
+            let sequence = next_sequence(&tx)?;
+            tx.execute(
+                &format!("UPDATE {} SET sequence = ?1, timestamp = ?2", TABLE_NAME),
+                params![sequence as i64, Self::get_timestamp()],
+            )?;
+            tx.commit()?;
+            Ok(())
+        })?;
         info!("Done");
         Ok(())
     }
 
     pub(crate) fn save_database(
-        &self,
+        &mut self,
         filter: &dyn Fn(&String) -> bool,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        self.require_writable()?;
+        self.require_saved_database()?;
         info!("Saving database");
+        self.flush_pending(true)?;
+        Self::create_dirs_for_file(&self.saved_database_path)?;
+        // Taken before the copy, the writes made during the save leave the database unsaved
+        let sequence = self.current_sequence()?;
+        self.replace_saved_database(filter, false, progress)?;
+        self.saved_sequence = Some(sequence);
+        Ok(())
+    }
+
+    /// Save only the parameters selected by the filter, the other parameters keep their saved values.
+    /// The other changes stay unsaved
+    pub(crate) fn save_selected(&mut self, filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        self.require_writable()?;
+        self.require_saved_database()?;
+        info!("Saving the selected parameters");
+        self.flush_pending(true)?;
         Self::create_dirs_for_file(&self.saved_database_path)?;
-        Self::copy_database_with_filter(
-            Path::new(&self.database_path),
-            Path::new(&self.saved_database_path),
-            &filter,
+        self.replace_saved_database(filter, true, &|_, _| {})
+    }
+
+    /// Write the saved database, `merge` keeps the saved values of the parameters not selected by the filter
+    fn replace_saved_database(
+        &mut self,
+        filter: &dyn Fn(&String) -> bool,
+        merge: bool,
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        let backup = self.backup_saved_database()?;
+        let saved_path = PathBuf::from(&self.saved_database_path);
+        let base = if merge { Some(saved_path.as_path()) } else { None };
+        if let Err(e) = self.save_database_to(&saved_path, base, filter, progress) {
+            if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+            return Err(e);
+        }
+        if backup.is_some() {
+            self.prune_backups()?;
+        }
+        Ok(())
+    }
+
+    /// Keep the current saved database under a timestamped name before it is replaced, returns the backup.
+    /// The backup is a hard link when possible, the save replaces the saved database with a new file
+    fn backup_saved_database(&self) -> Result<Option<PathBuf>, EconfError> {
+        let saved_path = Path::new(&self.saved_database_path);
+        if self.backup_retention == 0 || fs::metadata(saved_path).map(|metadata| metadata.len()).unwrap_or(0) == 0 {
+            return Ok(None);
+        }
+        let mut backup_path = saved_path.as_os_str().to_owned();
+        backup_path.push(format!(".{}", chrono::Utc::now().format("%Y-%m-%dT%H-%M-%S%.3fZ")));
+        let backup_path = PathBuf::from(backup_path);
+        if fs::hard_link(saved_path, &backup_path).is_err() {
+            fs::copy(saved_path, &backup_path)?;
+        }
+        info!("Previous saved database kept as {}", backup_path.display());
+        Ok(Some(backup_path))
+    }
+
+    fn prune_backups(&mut self) -> Result<(), EconfError> {
+        let backups = self.list_backups()?;
+        if backups.len() <= self.backup_retention {
+            return Ok(());
+        }
+        for backup in &backups[self.backup_retention..] {
+            info!("Removing old backup {}", backup);
+            fs::remove_file(self.backup_path(backup)?)?;
+        }
+        self.collect_blob_files()
+    }
+
+    /// Names of the backups of the saved database, the newest first
+    pub(crate) fn list_backups(&self) -> Result<Vec<String>, EconfError> {
+        if !self.has_saved_database() {
+            return Ok(Vec::new());
+        }
+        let saved_path = Path::new(&self.saved_database_path);
+        let (Some(directory), Some(saved_name)) = (saved_path.parent(), saved_path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{}.", saved_name.to_string_lossy());
+        let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            // The timestamps start with the year, the temporary files of the saves are skipped
+            if name.strip_prefix(&prefix).is_some_and(|suffix| suffix.starts_with(|c: char| c.is_ascii_digit())) {
+                backups.push(name);
+            }
+        }
+        // The timestamps sort in the chronological order
+        backups.sort_by(|a, b| b.cmp(a));
+        Ok(backups)
+    }
+
+    /// Path of the backup, the name must be one of list_backups
+    fn backup_path(&self, name: &str) -> Result<PathBuf, EconfError> {
+        if !self.list_backups()?.iter().any(|backup| backup == name) {
+            return Err(EconfError::NotFound(format!("Backup {}", name)));
+        }
+        Ok(Path::new(&self.saved_database_path).with_file_name(name))
+    }
+
+    /// Replace the working database with the backup, the saved database is not changed
+    pub(crate) fn restore_backup(&mut self, name: &str, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        let path = self.backup_path(name)?;
+        self.load_database_from(&path, progress)
+    }
+
+    /// Whether anything was written by any process since the last save by this instance
+    pub(crate) fn has_unsaved_changes(&mut self) -> Result<bool, EconfError> {
+        let sequence = self.current_sequence()?;
+        Ok(!self.pending.is_empty() || self.saved_sequence != Some(sequence))
+    }
+
+    /// Write the filtered copy to a temporary file and replace the destination with it,
+    /// so a failed save keeps the previous copy. With a base database the copy starts from
+    /// its values of the parameters not selected by the filter
+    fn save_database_to(
+        &self,
+        path: &Path,
+        base: Option<&Path>,
+        filter: &dyn Fn(&String) -> bool,
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        // Per process, two processes saving at once must not remove the file of each other
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(format!(".tmp.{}", std::process::id()));
+        let temporary_path = PathBuf::from(temporary_path);
+        let _ = fs::remove_file(&temporary_path);
+        let result = match base {
+            Some(base) => self.copy_unselected(base, &temporary_path, filter),
+            None => Ok(()),
+        }
+        .and_then(|_| match &self.connection {
+            Some(db) if self.in_memory => self.copy_database_with_filter(db.conn(), &temporary_path, &filter, progress),
+            _ => open_database(Path::new(&self.database_path), OpenFlags::default(), self.key())
+                .and_then(|src_conn| self.copy_database_with_filter(&src_conn, &temporary_path, &filter, progress)),
+        });
+        if let Err(e) = result {
+            let _ = fs::remove_file(&temporary_path);
+            return Err(e);
+        }
+        fs::rename(&temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Copy the base database without the values of the parameters selected by the filter
+    fn copy_unselected(&self, base: &Path, path: &Path, filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        // The saved database is empty until the first save
+        if fs::metadata(base).map(|metadata| metadata.len() == 0).unwrap_or(true) {
+            return Ok(());
+        }
+        fs::copy(base, path)?;
+        let conn = open_database(path, OpenFlags::SQLITE_OPEN_READ_WRITE, self.key())?;
+        let tables: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+            params![TABLE_NAME],
+            |row| row.get(0),
         )?;
+        if tables == 0 {
+            return Ok(());
+        }
+        let mut stmt = conn.prepare(&format!("DELETE FROM {} WHERE key = ?", TABLE_NAME))?;
+        for parameter in PARAMETER_DATA.iter().filter(|parameter| filter(&parameter.name_id.to_string())) {
+            stmt.execute(params![parameter.name_id])?;
+        }
         Ok(())
     }
 
-    pub(crate) fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
-        let database_manager = Self {
+    fn profiles_directory(&self) -> PathBuf {
+        Path::new(&self.saved_database_path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(PROFILES_DIRECTORY)
+    }
+
+    /// Path of the profile database, the names are limited to letters, digits, '-' and '_'
+    fn profile_path(&self, name: &str) -> Result<PathBuf, EconfError> {
+        self.require_saved_database()?;
+        if name.is_empty() || name.len() > 64 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(EconfError::Config(format!("Invalid profile name |{}|", name)));
+        }
+        Ok(self.profiles_directory().join(format!("{}.{}", name, PROFILE_EXTENSION)))
+    }
+
+    fn existing_profile_path(&self, name: &str) -> Result<PathBuf, EconfError> {
+        let path = self.profile_path(name)?;
+        if !path.is_file() {
+            return Err(EconfError::NotFound(format!("Profile {}", name)));
+        }
+        Ok(path)
+    }
+
+    /// Save the database as the profile, replacing the previous one with the same name
+    pub(crate) fn save_profile(
+        &mut self,
+        name: &str,
+        filter: &dyn Fn(&String) -> bool,
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        self.require_writable()?;
+        let path = self.profile_path(name)?;
+        info!("Saving profile {}", name);
+        self.flush_pending(true)?;
+        fs::create_dir_all(self.profiles_directory())?;
+        self.save_database_to(&path, None, filter, progress)
+    }
+
+    pub(crate) fn load_profile(&mut self, name: &str, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        let path = self.existing_profile_path(name)?;
+        self.load_database_from(&path, progress)
+    }
+
+    /// Names of the saved profiles in alphabetical order
+    pub(crate) fn list_profiles(&self) -> Result<Vec<String>, EconfError> {
+        if !self.has_saved_database() {
+            return Ok(Vec::new());
+        }
+        let entries = match fs::read_dir(self.profiles_directory()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut profiles = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == PROFILE_EXTENSION) {
+                if let Some(name) = path.file_stem() {
+                    profiles.push(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    pub(crate) fn delete_profile(&mut self, name: &str) -> Result<(), EconfError> {
+        self.require_writable()?;
+        let path = self.existing_profile_path(name)?;
+        info!("Deleting profile {}", name);
+        fs::remove_file(path)?;
+        self.collect_blob_files()
+    }
+
+    pub(crate) fn new(config: &Config) -> Result<Self, EconfError> {
+        let mut database_manager = Self {
             database_path: config.database_path.clone(),
             saved_database_path: config.saved_database_path.clone(),
-            last_update_timestamp: 0.0,
+            last_change: 0,
             default_data_folder: config.default_data_folder.clone(),
+            blob_directory: config.blob_directory.as_ref().map(PathBuf::from),
+            blob_file_threshold: config.blob_file_threshold,
+            encryption_key: config.encryption_key.clone(),
+            secret_cipher: config.secret_key.as_ref().map(|key| SecretCipher::new(key)),
+            pending: BTreeMap::new(),
+            backup_retention: config.backup_retention,
+            maintenance_writes: config.maintenance_writes,
+            writes_since_maintenance: 0,
+            saved_sequence: None,
+            storage_health: StorageHealth::default(),
+            default_files: Vec::new(),
+            in_memory: config.in_memory,
+            read_only: config.read_only,
+            connection: None,
         };
+        database_manager.default_files = PARAMETER_DATA
+            .iter()
+            .map(|parameter| database_manager.default_file_state(parameter).ok().flatten())
+            .collect();
+        if database_manager.secret_cipher.is_none() {
+            if let Some(parameter) = PARAMETER_DATA.iter().find(|parameter| parameter.secret) {
+                return Err(EconfError::Config(format!("Parameter {} is secret, the secret key is not set", parameter.name_id)));
+            }
+        }
+        if config.strict_defaults {
+            let failures: Vec<String> = database_manager
+                .default_files_status()
+                .into_iter()
+                .filter_map(|(id, path, result)| {
+                    result.err().map(|e| format!("{} ({}): {}", PARAMETER_DATA[id.index()].name_id, path, e))
+                })
+                .collect();
+            if !failures.is_empty() {
+                error!("Default data can't be read: {}", failures.join(", "));
+                return Err(EconfError::Config(format!("Default data can't be read: {}", failures.join(", "))));
+            }
+        }
+        if database_manager.read_only {
+            if !Path::new(&database_manager.database_path).is_file() {
+                return Err(EconfError::NotFound(format!("Database {}", database_manager.database_path)));
+            }
+            database_manager.open_connection()?;
+            info!("Database manager initialised read-only");
+            return Ok(database_manager);
+        }
+
+        if let Some(blob_directory) = &database_manager.blob_directory {
+            fs::create_dir_all(blob_directory)?;
+            info!("Blobs of {} bytes and larger are stored in {}", config.blob_file_threshold, blob_directory.display());
+        }
+
+        if database_manager.in_memory {
+            database_manager.connection = Some(DbConnection::open(&database_manager.database_path, true, true, database_manager.key())?);
+            if database_manager.has_saved_database() {
+                info!("Database is in memory, load");
+                database_manager.load_database(&|_, _| {})?;
+            }
+            info!("Database manager initialised in memory");
+            return Ok(database_manager);
+        }
+
         Self::create_dirs_for_file(&database_manager.database_path)?;
 
         match fs::metadata(&database_manager.database_path) {
             Ok(metadata) if metadata.is_file() => {
-                info!("Database exists, continue");
+                info!("Database exists, checking the integrity");
+                database_manager.recover_if_corrupted()?;
             }
             Ok(_) => {
                 error!(
                     "Database file {} exists but is not a file",
                     database_manager.database_path
                 );
-                return Err(format!(
+                return Err(EconfError::Config(format!(
                     "Database file {} exists but is not a file",
                     database_manager.database_path
-                )
-                .into());
+                )));
             }
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
                 info!("Database doesn't exist, load");
-                database_manager.load_database()?;
+                database_manager.load_database(&|_, _| {})?;
             }
             Err(e) => {
                 error!(
                     "Error checking database file {}: {}",
                     database_manager.database_path, e
                 );
-                return Err(format!(
-                    "Error checking database file {}: {}",
-                    database_manager.database_path, e
-                )
-                .into());
+                return Err(e.into());
             }
         }
 
-        DbConnection::new(&database_manager.database_path, true, true)?;
+        DbConnection::open(&database_manager.database_path, true, true, database_manager.key())?;
         info!("Database manager initialised");
         Ok(database_manager)
     }
 
+    fn check_database_file(&self, path: &Path) -> Result<Vec<String>, EconfError> {
+        let conn = open_database(path, OpenFlags::SQLITE_OPEN_READ_WRITE, self.key())?;
+        let _ = conn.busy_timeout(READ_BUSY_TIMEOUT);
+        Ok(integrity_problems(&conn, false)?)
+    }
+
+    /// Replace a corrupted database with the saved database, or with an empty one if the saved
+    /// database can't be used either. The corrupted files are kept with the ".corrupted" suffix
+    fn recover_if_corrupted(&mut self) -> Result<(), EconfError> {
+        let problems = self.check_database_file(Path::new(&self.database_path))?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        error!("Database {} is corrupted: {}", self.database_path, problems.join("; "));
+        let corrupted_path = format!("{}.corrupted", self.database_path);
+        for suffix in ["", "-wal", "-shm"] {
+            let path = format!("{}{}", self.database_path, suffix);
+            if Path::new(&path).exists() {
+                fs::rename(&path, format!("{}{}", corrupted_path, suffix))?;
+            }
+        }
+        warn!("Corrupted database moved to {}", corrupted_path);
+
+        let saved_path = Path::new(&self.saved_database_path);
+        let saved_problems = if fs::metadata(saved_path).map(|metadata| metadata.len() > 0).unwrap_or(false) {
+            self.check_database_file(saved_path)?
+        } else {
+            vec!["the saved database doesn't exist".to_owned()]
+        };
+        let recovery = if saved_problems.is_empty() {
+            self.load_database(&|_, _| {})?;
+            warn!("Database restored from the saved database {}", self.saved_database_path);
+            StorageRecovery::RestoredSaved
+        } else {
+            warn!("Saved database can't be used ({}), starting with the defaults", saved_problems.join("; "));
+            StorageRecovery::FactoryDefaults
+        };
+        self.storage_health = StorageHealth { recovery, problems, corrupted_path: Some(corrupted_path) };
+        Ok(())
+    }
+
+    pub(crate) fn storage_health(&self) -> StorageHealth {
+        self.storage_health.clone()
+    }
+
+    /// Check the integrity of the database now, the problems found replace the ones in the storage health
+    pub(crate) fn check_integrity(&mut self, quick: bool) -> Result<Vec<String>, EconfError> {
+        let problems = integrity_problems(self.connection()?.conn(), quick)?;
+        if !problems.is_empty() {
+            error!("Database {} is corrupted: {}", self.database_path, problems.join("; "));
+        }
+        self.storage_health.problems = problems.clone();
+        Ok(problems)
+    }
+
+    /// Size of the WAL file of the working database, None for the in-memory database
+    pub(crate) fn wal_size(&self) -> Option<u64> {
+        if self.in_memory {
+            return None;
+        }
+        Some(fs::metadata(format!("{}-wal", self.database_path)).map(|metadata| metadata.len()).unwrap_or(0))
+    }
+
+    /// Open the saved database and read its schema. Before the first save only its folder has to exist
+    pub(crate) fn check_saved_database(&self) -> Result<(), EconfError> {
+        if !self.has_saved_database() {
+            return Ok(());
+        }
+        let saved_path = Path::new(&self.saved_database_path);
+        if !saved_path.exists() {
+            let folder = saved_path.parent().filter(|folder| !folder.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !folder.is_dir() {
+                return Err(EconfError::Config(format!("Folder of the saved database {} doesn't exist", folder.display())));
+            }
+            return Ok(());
+        }
+        let conn = open_database(saved_path, OpenFlags::SQLITE_OPEN_READ_ONLY, self.key())?;
+        let _ = conn.busy_timeout(READ_BUSY_TIMEOUT);
+        conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<usize, i64>(0))?;
+        Ok(())
+    }
+
+    /// Release the free pages of the database file and move the WAL contents to the database,
+    /// truncating the WAL file. The checkpoint is partial while other connections read the database
+    pub(crate) fn maintain(&mut self) -> Result<(), EconfError> {
+        self.require_writable()?;
+        self.writes_since_maintenance = 0;
+        let db = self.connection()?;
+        db.conn().execute_batch("PRAGMA incremental_vacuum;")?;
+        let (busy, wal_pages, checkpointed) = db.conn().query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |row| {
+            Ok((row.get::<usize, i64>(0)?, row.get::<usize, i64>(1)?, row.get::<usize, i64>(2)?))
+        })?;
+        if busy != 0 {
+            warn!("WAL checkpoint incomplete, {} of {} pages copied, the database is in use", checkpointed, wal_pages);
+        } else {
+            debug!("Database maintenance done");
+        }
+        Ok(())
+    }
+
+    /// Last value of the global write sequence, 0 if nothing was written yet
+    pub(crate) fn current_sequence(&mut self) -> Result<u64, EconfError> {
+        let db = self.connection()?;
+        let value = db.conn().query_row(
+            &format!("SELECT value FROM {}", SEQUENCE_TABLE_NAME),
+            [],
+            |row| row.get::<usize, i64>(0),
+        )?;
+        Ok(value as u64)
+    }
+
+    /// Sequence number of the last write of the parameter stored in the database, 0 if it was not written.
+    /// The deferred writes are not counted until they are written
+    pub(crate) fn parameter_sequence(&mut self, id: ParameterId) -> Result<u64, EconfError> {
+        let db = self.connection()?;
+        Ok(stored_sequence(db.conn(), PARAMETER_DATA[id.index()].name_id)?)
+    }
+
+    /// Check the condition of a conditional write, returns the sequence number the parameter must
+    /// still have when the value is stored, None if the condition doesn't hold
+    fn check_condition(&mut self, id: ParameterId, condition: &WriteCondition) -> Result<Option<u64>, EconfError> {
+        if self.pending.contains_key(&id.index()) {
+            self.flush_pending(true)?;
+        }
+        // Taken before the value, a write in between changes the sequence and fails the write
+        let sequence = self.parameter_sequence(id)?;
+        let holds = match condition {
+            WriteCondition::Sequence(expected) => sequence == *expected,
+            WriteCondition::Value(expected) => {
+                let current = self.read_or_create(id)?;
+                let equal = current == *expected;
+                sensitive::discard(id, current);
+                equal
+            }
+        };
+        Ok(holds.then_some(sequence))
+    }
+
+    /// Delete the stored values of the parameters, so they have the default values.
+    /// Returns the sequence number of the change
+    pub(crate) fn reset_values(&mut self, ids: &[ParameterId]) -> Result<u64, EconfError> {
+        self.require_writable()?;
+        for id in ids {
+            if let Some(pending) = self.pending.remove(&id.index()) {
+                sensitive::discard(*id, pending.value);
+            }
+        }
+        let db = self.connection()?;
+        let tx = db.conn_mut().transaction_with_behavior(TransactionBehavior::Immediate)?;
+        {
+            let mut stmt = tx.prepare(&format!("DELETE FROM {} WHERE key = ?", TABLE_NAME))?;
+            for id in ids {
+                stmt.execute(params![PARAMETER_DATA[id.index()].name_id])?;
+            }
+        }
+        let sequence = next_sequence(&tx)?;
+        tx.commit()?;
+        self.collect_blob_files()?;
+        Ok(sequence)
+    }
+
+    /// Allocate a sequence value for the changes not made by a single write (load, factory reset)
+    pub(crate) fn allocate_sequence(&mut self) -> Result<u64, EconfError> {
+        self.require_writable()?;
+        let db = self.connection()?;
+        let tx = db.conn_mut().transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let sequence = next_sequence(&tx)?;
+        tx.commit()?;
+        Ok(sequence)
+    }
+
     #[allow(unused)]
-    pub(crate) fn set_sqlite_version(&self, version: u32) -> Result<(), Box<dyn Error>> {
-        let db = DbConnection::new(&self.database_path, false, false)?;
+    pub(crate) fn set_sqlite_version(&self, version: u32) -> Result<(), EconfError> {
+        let db = DbConnection::open(&self.database_path, false, false, self.key())?;
 
         db.conn().pragma_update(None, "user_version", version)?;
 
@@ -387,101 +1372,279 @@ impl DatabaseManager {
         Ok(())
     }
 
-    fn db_to_bool(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_bool(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValBool(i != 0)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValBool(f != 0.0)),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for bool", sql_value.data_type()))),
         }
     }
 
-    fn db_to_i32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_i32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValI32(i as i32)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValI32(f as i32)),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for i32", sql_value.data_type()))),
         }
     }
 
-    fn db_to_u32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_u32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValU32(i as u32)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValU32(f as u32)),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for u32", sql_value.data_type()))),
         }
     }
 
-    fn db_to_i64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_i64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValI64(i as i64)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValI64(f as i64)),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for i64", sql_value.data_type()))),
         }
     }
 
-    fn db_to_u64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_u64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValU64(i as u64)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValU64(f as u64)),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for u64", sql_value.data_type()))),
         }
     }
 
-    fn db_to_f32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_f32(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValF32(i as f32)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValF32(f as f32)),
-            _ => {
-                return Err("".into());
-            }
+            // SQLite stores NaN as NULL
+            rusqlite::types::Value::Null => Ok(ParameterValue::ValF32(f32::NAN)),
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for f32", sql_value.data_type()))),
         }
     }
 
-    fn db_to_f64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_f64(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Integer(i) => Ok(ParameterValue::ValF64(i as f64)),
             rusqlite::types::Value::Real(f) => Ok(ParameterValue::ValF64(f as f64)),
-            _ => {
-                return Err("".into());
-            }
+            rusqlite::types::Value::Null => Ok(ParameterValue::ValF64(f64::NAN)),
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for f64", sql_value.data_type()))),
         }
     }
 
-    fn db_to_string(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_string(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Text(string) => Ok(ParameterValue::ValString(string.into())),
-            _ => {
-                return Err("".into());
-            }
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for string", sql_value.data_type()))),
         }
     }
 
-    fn db_to_blob(sql_value: rusqlite::types::Value) -> Result<ParameterValue, Box<dyn Error>> {
+    fn db_to_blob(sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
         match sql_value {
             rusqlite::types::Value::Blob(blob) => Ok(ParameterValue::ValBlob(blob)),
-            _ => {
-                return Err("".into());
+            _ => Err(EconfError::Schema(format!("Unexpected SQL type {} for blob", sql_value.data_type()))),
+        }
+    }
+
+    /// Decrypt the value of a secret parameter
+    fn db_to_secret(&self, parameter_def: &Parameter, sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
+        let data = match sql_value {
+            rusqlite::types::Value::Blob(data) => data,
+            rusqlite::types::Value::Text(_) if parameter_def.value_type == ParameterValueType::TypeString => {
+                warn!("Secret {} is stored in plaintext, it is encrypted on the next write", parameter_def.name_id);
+                return Self::db_to_string(sql_value);
+            }
+            _ => return Err(EconfError::Schema(format!("Unexpected SQL type {} for secret", sql_value.data_type()))),
+        };
+        let cipher = self.secret_cipher.as_ref().expect("The secret key is checked on the initialisation");
+        let plaintext = cipher.decrypt(parameter_def.name_id, &data).inspect_err(|e| error!("{}", e))?;
+        if parameter_def.value_type == ParameterValueType::TypeBlob {
+            return Ok(ParameterValue::ValBlob(plaintext));
+        }
+        String::from_utf8(plaintext)
+            .map(|string| ParameterValue::ValString(string.into()))
+            .map_err(|e| {
+                e.into_bytes().zeroize();
+                EconfError::Schema(format!("Secret {} is not a valid string", parameter_def.name_id))
+            })
+    }
+
+    /// Blob stored in the database or as a file in the blob directory
+    fn db_to_blob_file(&self, sql_value: rusqlite::types::Value) -> Result<ParameterValue, EconfError> {
+        let rusqlite::types::Value::Text(reference) = sql_value else {
+            return Self::db_to_blob(sql_value);
+        };
+        let Some(path) = self.blob_file_path(&reference) else {
+            return Err(EconfError::Schema(format!("Unexpected blob reference {}", reference)));
+        };
+        match fs::read(&path) {
+            Ok(blob) => Ok(ParameterValue::ValBlob(blob)),
+            Err(e) => {
+                error!("Error reading blob file {}: {}", path.display(), e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Path of the referenced blob file, None if the reference is invalid or the blob files are not enabled
+    fn blob_file_path(&self, reference: &str) -> Option<PathBuf> {
+        let hash = reference.strip_prefix(BLOB_FILE_PREFIX)?;
+        if hash.len() != 64 || !hash.bytes().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(self.blob_directory.as_ref()?.join(hash))
+    }
+
+    fn uses_blob_file(&self, size: usize) -> bool {
+        self.blob_directory.is_some() && size >= self.blob_file_threshold
+    }
+
+    /// Store the blob as a file named by its hash, returns the reference to save in the database.
+    /// The data is written to a temporary file first, so a crash never leaves a partial blob under the final name
+    fn store_blob_file(&self, data: &mut dyn Read, name: &str) -> Result<String, EconfError> {
+        let directory = self.blob_directory.as_ref().expect("Blob files must be enabled");
+        let temporary_path = directory.join(format!(".{}.tmp", name));
+        let mut hasher = Sha256::new();
+        {
+            let mut file = fs::File::create(&temporary_path)?;
+            let mut buffer = [0u8; 64 * 1024];
+            loop {
+                let read = data.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+                file.write_all(&buffer[..read])?;
+            }
+            file.sync_all()?;
+        }
+        let hash = to_hex(&hasher.finalize());
+        fs::rename(&temporary_path, directory.join(&hash))?;
+        Ok(format!("{}{}", BLOB_FILE_PREFIX, hash))
+    }
+
+    /// All the blob files referenced by the working and the saved databases, including the profiles
+    fn referenced_blob_files(&mut self) -> Result<HashSet<String>, EconfError> {
+        let mut references = HashSet::new();
+        referenced_blob_files(self.connection()?.conn(), &mut references)?;
+        let mut saved_paths = vec![PathBuf::from(&self.saved_database_path)];
+        for profile in self.list_profiles()? {
+            saved_paths.push(self.profile_path(&profile)?);
+        }
+        for backup in self.list_backups()? {
+            saved_paths.push(Path::new(&self.saved_database_path).with_file_name(backup));
+        }
+        for path in saved_paths.iter().filter(|path| path.is_file()) {
+            let saved = DbConnection::open(&path.to_string_lossy().into_owned(), false, false, self.key())?;
+            referenced_blob_files(saved.conn(), &mut references)?;
+        }
+        Ok(references)
+    }
+
+    /// Delete the file of the overwritten blob unless it is still referenced
+    fn release_blob_file(&mut self, reference: &str) -> Result<(), EconfError> {
+        if self.referenced_blob_files()?.contains(reference) {
+            return Ok(());
+        }
+        if let Some(path) = self.blob_file_path(reference) {
+            debug!("Removing blob file {}", path.display());
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the blob files not referenced by the working or the saved database
+    fn collect_blob_files(&mut self) -> Result<(), EconfError> {
+        let Some(directory) = self.blob_directory.clone() else {
+            return Ok(());
+        };
+        let references = self.referenced_blob_files()?;
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let reference = format!("{}{}", BLOB_FILE_PREFIX, entry.file_name().to_string_lossy());
+            if self.blob_file_path(&reference).is_some() && !references.contains(&reference) {
+                debug!("Removing unreferenced blob file {}", entry.path().display());
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn default_file_path(&self, path: &str) -> PathBuf {
+        PathBuf::from(self.default_data_folder.clone()).join(path)
+    }
+
+    /// Modification time and size of the file default, None if the default is not a file
+    fn default_file_state(&self, parameter_def: &Parameter) -> Result<Option<(SystemTime, u64)>, EconfError> {
+        let ParameterValue::ValPath(path) = parameter_def.value_default else {
+            return Ok(None);
+        };
+        let metadata = fs::metadata(self.default_file_path(path))?;
+        Ok(Some((metadata.modified()?, metadata.len())))
+    }
+
+    /// Try to read the file defaults of all the parameters, returns the parameter, the file and the result
+    pub(crate) fn default_files_status(&self) -> Vec<(ParameterId, &'static str, std::io::Result<()>)> {
+        PARAMETER_DATA
+            .iter()
+            .enumerate()
+            .filter_map(|(index, parameter_def)| match parameter_def.value_default {
+                ParameterValue::ValPath(path) => {
+                    let result = fs::File::open(self.default_file_path(path)).map(|_| ());
+                    Some((ParameterId::from_index(index)?, path, result))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Check the file defaults for changes since the last check, returns the changed parameters
+    /// that don't have a stored value and use the default. Nothing is updated if any of the
+    /// changed files can't be read
+    pub(crate) fn refresh_default_files(&mut self) -> Result<Vec<ParameterId>, EconfError> {
+        let mut changed = Vec::new();
+        for (index, parameter_def) in PARAMETER_DATA.iter().enumerate() {
+            let ParameterValue::ValPath(path) = parameter_def.value_default else {
+                continue;
+            };
+            let read_error = |e: std::io::Error| {
+                error!("Error reading file {} with default data: {}", path, e);
+                EconfError::Io(e)
+            };
+            let state = self.default_file_state(parameter_def).map_err(|e| match e {
+                EconfError::Io(e) => read_error(e),
+                e => e,
+            })?;
+            if state != self.default_files[index] {
+                fs::File::open(self.default_file_path(path)).map_err(read_error)?;
+                changed.push((index, state));
+            }
+        }
+
+        let mut unset = Vec::new();
+        for (index, state) in changed {
+            self.default_files[index] = state;
+            let key = PARAMETER_DATA[index].name_id;
+            info!("Default of {} changed", key);
+            let stored = self.connection()?.conn().query_row(
+                &format!("SELECT 1 FROM {} WHERE key = ?", TABLE_NAME),
+                params![key],
+                |_| Ok(()),
+            ).optional()?;
+            if stored.is_none() {
+                unset.extend(ParameterId::from_index(index));
             }
         }
+        Ok(unset)
     }
 
-    fn get_default_value(
+    /// The default of the parameter, the file defaults are read from the default data folder
+    pub(crate) fn get_default_value(
         &self,
         parameter_def: &Parameter,
     ) -> Result<ParameterValue, rusqlite::Error> {
         match parameter_def.value_default {
             ParameterValue::ValPath(p) => {
-                let full_path = PathBuf::from(self.default_data_folder.clone()).join(p);
+                let full_path = self.default_file_path(p);
                 let bytes = fs::read(full_path)
                     .map_err(|e| {
                         let err = format!("Error reading file {} with default data: {}", p, e);
@@ -495,119 +1658,182 @@ impl DatabaseManager {
         }
     }
 
-    pub(crate) fn read_or_create(&self, id: ParameterId) -> Result<ParameterValue, Box<dyn Error>> {
-        let db = DbConnection::new(&self.database_path, false, false)?;
+    pub(crate) fn read_or_create(&mut self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        self.open_connection()?;
+        let result = self.read_value(id);
+        if let (Err(EconfError::Database(_) | EconfError::Corrupt(_) | EconfError::DbBusy), false) = (&result, self.in_memory) {
+            // Reopen the connection next time, the database could be replaced
+            self.connection = None;
+        }
+        result
+    }
+
+    /// Read the parameters in one read transaction, so all the values come from the same state of the database
+    pub(crate) fn read_snapshot(&mut self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        self.connection()?.conn().execute_batch("BEGIN DEFERRED")?;
+        let mut values = Vec::with_capacity(ids.len());
+        let mut result = Ok(());
+        for id in ids {
+            match self.read_or_create(*id) {
+                Ok(value) => values.push(value),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        // After a database error the connection is closed, which ends the transaction too
+        if let Some(db) = &self.connection {
+            if let Err(e) = db.conn().execute_batch("COMMIT") {
+                warn!("Could not end the read transaction: {}", e);
+            }
+        }
+        if let Err(e) = result {
+            for (id, value) in ids.iter().zip(values) {
+                sensitive::discard(*id, value);
+            }
+            return Err(e);
+        }
+        Ok(values)
+    }
+
+    fn read_value(&self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        if let Some(pending) = self.pending.get(&id.index()) {
+            return Ok(pending.value.clone());
+        }
+        let db = self.connection.as_ref().expect("Connection must be opened before reading");
+        self.read_stored_value(db.conn(), id)
+    }
+
+    /// Convert the stored value to the type of the parameter, a value that can't be converted is
+    /// replaced by the default
+    fn value_from_sql(
+        &self,
+        parameter_def: &Parameter,
+        sql_value: rusqlite::types::Value,
+    ) -> Result<ParameterValue, rusqlite::Error> {
+        let data_type = sql_value.data_type();
+
+        let value_result = match parameter_def.value_type {
+            _ if parameter_def.secret => self.db_to_secret(parameter_def, sql_value),
+            ParameterValueType::TypeBool => Self::db_to_bool(sql_value),
+            ParameterValueType::TypeI32 => Self::db_to_i32(sql_value),
+            ParameterValueType::TypeU32 => Self::db_to_u32(sql_value),
+            ParameterValueType::TypeI64 => Self::db_to_i64(sql_value),
+            ParameterValueType::TypeU64 => Self::db_to_u64(sql_value),
+            ParameterValueType::TypeF32 => Self::db_to_f32(sql_value),
+            ParameterValueType::TypeF64 => Self::db_to_f64(sql_value),
+            ParameterValueType::TypeString => Self::db_to_string(sql_value),
+            ParameterValueType::TypeBlob => self.db_to_blob_file(sql_value),
+            ParameterValueType::TypeEnum(_) => Self::db_to_i32(sql_value),
+            ParameterValueType::TypeNone => Self::db_to_i32(sql_value),
+        };
+
+        match value_result {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                warn!(
+                    "Type mismatch for [{}], using default (SQL is {}, required is {})",
+                    parameter_def.name_id, data_type, parameter_def.value_type
+                );
+                self.get_default_value(parameter_def)
+            }
+        }
+    }
 
+    fn read_stored_value(&self, conn: &Connection, id: ParameterId) -> Result<ParameterValue, EconfError> {
         let sql = format!("SELECT value FROM {} WHERE key = ?", TABLE_NAME);
-        let mut stmt = match db.conn().prepare(&sql) {
+        let mut stmt = match conn.prepare_cached(&sql) {
             Ok(s) => s,
             Err(e) => {
                 error!("Failed to prepare statement: {}", e);
-                return Err(format!("Failed to prepare statement: {}", e).into());
+                return Err(read_error(e));
             }
         };
 
-        let parameter_def = &PARAMETER_DATA[id as usize];
+        let parameter_def = &PARAMETER_DATA[id.index()];
         let key = parameter_def.name_id;
-        let result = match stmt.query_row(params![key], |row| {
-            let sql_value: rusqlite::types::Value = row.get(0)?;
-            let data_type = sql_value.data_type();
-
-            let value_result = match parameter_def.value_type {
-                ParameterValueType::TypeBool => Self::db_to_bool(sql_value),
-                ParameterValueType::TypeI32 => Self::db_to_i32(sql_value),
-                ParameterValueType::TypeU32 => Self::db_to_u32(sql_value),
-                ParameterValueType::TypeI64 => Self::db_to_i64(sql_value),
-                ParameterValueType::TypeU64 => Self::db_to_u64(sql_value),
-                ParameterValueType::TypeF32 => Self::db_to_f32(sql_value),
-                ParameterValueType::TypeF64 => Self::db_to_f64(sql_value),
-                ParameterValueType::TypeString => Self::db_to_string(sql_value),
-                ParameterValueType::TypeBlob => Self::db_to_blob(sql_value),
-                ParameterValueType::TypeEnum(_) => Self::db_to_i32(sql_value),
-                ParameterValueType::TypeNone => Self::db_to_i32(sql_value),
-            };
-
-            match value_result {
-                Ok(value) => Ok(value),
-                Err(_) => {
-                    warn!(
-                        "Type mismatch for [{}], using default (SQL is {}, required is {})",
-                        key, data_type, parameter_def.value_type
-                    );
-                    self.get_default_value(parameter_def)
-                }
-            }
-        }) {
+        let result = match stmt.query_row(params![key], |row| self.value_from_sql(parameter_def, row.get(0)?)) {
             Ok(val) => Ok(val),
-            Err(e) => {
-                info!("Error reading parameter {}: {}", key, e);
-                self.get_default_value(parameter_def)
-            }
+            Err(e) => match read_error(e) {
+                EconfError::DbBusy => Err(EconfError::DbBusy),
+                e => {
+                    info!("Error reading parameter {}: {}", key, e);
+                    Ok(self.get_default_value(parameter_def)?)
+                }
+            },
         };
         Ok(result?)
     }
 
+    /// Read the values of the parameters from the saved database, the parameters
+    /// that were never saved have the default values
+    pub(crate) fn read_saved_values(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        self.require_saved_database()?;
+        let conn = if Path::new(&self.saved_database_path).is_file() {
+            let conn = open_database(Path::new(&self.saved_database_path), OpenFlags::SQLITE_OPEN_READ_ONLY, self.key())?;
+            let _ = conn.busy_timeout(READ_BUSY_TIMEOUT);
+            // The saved database is empty until the first save
+            let tables: i64 = conn.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+                params![TABLE_NAME],
+                |row| row.get(0),
+            )?;
+            if tables > 0 { Some(conn) } else { None }
+        } else {
+            None
+        };
+        ids.iter()
+            .map(|id| match &conn {
+                Some(conn) => self.read_stored_value(conn, *id),
+                None => Ok(self.get_default_value(&PARAMETER_DATA[id.index()])?),
+            })
+            .collect()
+    }
+
     pub fn validate(
         &self,
         id: ParameterId,
         value: Status<ParameterValue>,
-    ) -> Result<Status<ParameterValue>, Box<dyn Error>> {
-        let input = value.unwrap();
-        debug!("Validating {}", id as usize);
-        match &PARAMETER_DATA[id as usize].validation {
-            ValidationMethod::None => Ok(Status::StatusOkChanged(input)),
-    
-            ValidationMethod::Range { min, max } => {
-                if input < *min {
-                    debug!("{} min overflow fixed {input} -> {}", id as usize, *min);
-                    Ok(Status::StatusOkOverflowFixed(min.clone()))
-                } else if input > *max {
-                    debug!("{} max overflow fixed {input} -> {}", id as usize, *max);
-                    Ok(Status::StatusOkOverflowFixed(max.clone()))
-                } else {
-                    Ok(Status::StatusOkChanged(input))
-                }
-            }
-    
-            ValidationMethod::AllowedValues { values, names: _ } => {
-                match values
-                    .iter()
-                    .map(|val| (val, val.distance(&input)))
-                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
-                {
-                    Some((closest, _)) => {
-                        if *closest == input {
-                            Ok(Status::StatusOkChanged(input))
-                        }
-                        else
-                        {
-                            debug!("{} allowed val overflow fixed {input} -> {}", id as usize, *closest);
-                            Ok(Status::StatusOkOverflowFixed(closest.clone()))
-                        }
-                    }
-                    None => Ok(Status::StatusErrorNotAccepted(input)),
-                }
-            }
-    
-            ValidationMethod::CustomCallback => todo!(),
-        }
+    ) -> Result<Status<ParameterValue>, EconfError> {
+        validate_value(id, value)
     }
 
+    /// Write the parameter value. Returns the status and, if the value was written,
+    /// the global sequence number assigned to the write. With the condition the value is written
+    /// immediately, even if it is the same, and only if the condition still holds in the write transaction
     pub fn write(
-        &self,
+        &mut self,
         id: ParameterId,
         value: ParameterValue,
         force: bool,
-    ) -> Result<Status<ParameterValue>, Box<dyn Error>> {
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<(Status<ParameterValue>, Option<u64>), EconfError> {
+        self.require_writable()?;
+
+        let expected_sequence = match condition {
+            Some(condition) => match self.check_condition(id, condition)? {
+                Some(sequence) => Some(sequence),
+                None => {
+                    debug!("Write condition of {} doesn't hold", id as usize);
+                    return Ok((Status::StatusErrorConflict(value), None));
+                }
+            },
+            None => None,
+        };
     
         // Skip writing if current value equals new value (unless forced)
-        if !force {
+        if !force && condition.is_none() {
             match self.read_or_create(id) {
-                Ok(current) if current == value => {
-                    debug!("Values are equal, skip writing");
-                    return Ok(Status::StatusOkNotChanged(value));
+                Ok(current) => {
+                    let equal = current == value;
+                    sensitive::discard(id, current);
+                    if equal {
+                        debug!("Values are equal, skip writing");
+                        return Ok((Status::StatusOkNotChanged(value), None));
+                    }
                 }
-                Ok(_) => {} // proceed to write
                 Err(e) => error!("Error reading current value: {}", e),
             }
         }
@@ -617,35 +1843,263 @@ impl DatabaseManager {
             Ok(v) => v,
             Err(e) => {
                 error!("Error validating parameter {}: {}", id as usize, e);
-                return Ok(Status::StatusErrorFailed);
+                return Ok((Status::StatusErrorFailed, None));
             }
         };
     
-        debug!("Write to DB: {:?}", validated_status);
+        if let Status::StatusErrorNotAccepted(_) = validated_status {
+            return Ok((validated_status, None));
+        }
     
         let inner_value = match validated_status {
             Status::StatusOkChanged(ref v)
             | Status::StatusOkNotChanged(ref v)
             | Status::StatusOkNotChecked(ref v)
             | Status::StatusOkOverflowFixed(ref v)
-            | Status::StatusErrorNotAccepted(ref v) => v,
+            | Status::StatusErrorNotAccepted(ref v)
+            | Status::StatusErrorConflict(ref v) => v,
             Status::StatusErrorFailed => {
-                return Ok(Status::StatusErrorFailed);
+                return Ok((Status::StatusErrorFailed, None));
             }
         };
+        debug!("Write to DB: {} {}", id as usize, sensitive::log_value(id, inner_value));
     
-        let db = DbConnection::new(&self.database_path, true, false)?;
+        let policy = PARAMETER_DATA[id.index()].persist_policy;
+        if policy != PersistPolicy::Immediate && expected_sequence.is_none() {
+            debug!("Write of {} deferred ({})", id as usize, policy.name());
+            let due = policy.debounce_ms().map(|ms| Instant::now() + Duration::from_millis(ms as u64));
+            let pending = PendingWrite { value: inner_value.clone(), source: source.map(str::to_owned), due };
+            if let Some(previous) = self.pending.insert(id.index(), pending) {
+                sensitive::discard(id, previous.value);
+            }
+            return Ok((validated_status, None));
+        }
+
+        match self.store_value(id, inner_value, source, expected_sequence) {
+            Ok(sequence) => Ok((validated_status, Some(sequence))),
+            Err(EconfError::Conflict(_)) => Ok((Status::StatusErrorConflict(validated_status.unwrap()), None)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Validate and write several values in one transaction, none of them if any is not accepted.
+    /// The values are written immediately whatever the persist policy. The condition is the one of the
+    /// first value like in [`Self::write`], nothing is written if it doesn't hold (`EconfError::Conflict`).
+    /// Returns the status of each value and the sequence number of the ones written
+    pub(crate) fn write_all(
+        &mut self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<WrittenValue>, EconfError> {
+        self.require_writable()?;
+
+        let expected_sequence = match (condition, values.first()) {
+            (Some(condition), Some((id, _))) => match self.check_condition(*id, condition)? {
+                Some(sequence) => Some(sequence),
+                None => {
+                    let id = *id;
+                    debug!("Write condition of {} doesn't hold", id as usize);
+                    for (id, value) in values {
+                        sensitive::discard(id, value);
+                    }
+                    return Err(EconfError::Conflict(id));
+                }
+            },
+            _ => None,
+        };
+
+        let mut statuses: Vec<(ParameterId, Status<ParameterValue>)> = Vec::with_capacity(values.len());
+        let mut rejected = None;
+        for (index, (id, value)) in values.into_iter().enumerate() {
+            if rejected.is_some() {
+                sensitive::discard(id, value);
+                continue;
+            }
+            // The value with the condition is written even if it is the same
+            let conditional = index == 0 && expected_sequence.is_some();
+            let equal = !conditional && match self.read_or_create(id) {
+                Ok(current) => {
+                    let equal = current == value;
+                    sensitive::discard(id, current);
+                    equal
+                }
+                Err(e) => {
+                    error!("Error reading current value: {}", e);
+                    false
+                }
+            };
+            if equal {
+                statuses.push((id, Status::StatusOkNotChanged(value)));
+                continue;
+            }
+            match self.validate(id, Status::StatusOkChanged(value))? {
+                Status::StatusErrorNotAccepted(value) => {
+                    let reason = format!("value {} not accepted", sensitive::log_value(id, &value));
+                    sensitive::discard(id, value);
+                    rejected = Some(EconfError::Validation { id, reason });
+                }
+                Status::StatusErrorFailed => {
+                    rejected = Some(EconfError::Validation { id, reason: "validation failed".to_owned() });
+                }
+                status => statuses.push((id, status)),
+            }
+        }
+        if let Some(e) = rejected {
+            for (id, status) in statuses {
+                sensitive::discard(id, status.unwrap());
+            }
+            return Err(e);
+        }
+
+        let changed: Vec<(ParameterId, ParameterValue)> = statuses.iter()
+            .filter(|(_, status)| !matches!(status, Status::StatusOkNotChanged(_)))
+            .map(|(id, status)| (*id, status.clone().unwrap()))
+            .collect();
+        let mut sequences = self.store_values(&changed, source, expected_sequence)?.into_iter();
+        for (id, value) in changed {
+            debug!("Write to DB: {} {}", id as usize, sensitive::log_value(id, &value));
+            sensitive::discard(id, value);
+            // The deferred write would overwrite the value written now
+            if let Some(previous) = self.pending.remove(&id.index()) {
+                sensitive::discard(id, previous.value);
+            }
+        }
+        Ok(statuses.into_iter()
+            .map(|(id, status)| match status {
+                Status::StatusOkNotChanged(_) => (id, status, None),
+                _ => (id, status, sequences.next()),
+            })
+            .collect())
+    }
+
+    /// Write the value to the database, returns the sequence number of the write.
+    /// Fails with `EconfError::Conflict` if the sequence of the parameter is not the expected one
+    fn store_value(
+        &mut self,
+        id: ParameterId,
+        inner_value: &ParameterValue,
+        source: Option<&str>,
+        expected_sequence: Option<u64>,
+    ) -> Result<u64, EconfError> {
+        let stored = self.prepare_value(id, inner_value)?;
+
+        let db = self.connection()?;
+        // IMMEDIATE takes the write lock at the start, so the sequence order matches the order
+        // in which the writes from all the processes are applied
+        let tx = db.conn_mut().transaction_with_behavior(TransactionBehavior::Immediate)?;
+        if let Some(expected) = expected_sequence {
+            if stored_sequence(&tx, PARAMETER_DATA[id.index()].name_id)? != expected {
+                drop(tx);
+                if let Some(reference) = stored.blob_file {
+                    self.release_blob_file(&reference)?;
+                }
+                return Err(EconfError::Conflict(id));
+            }
+        }
+        let (sequence, previous_blob_file) = Self::insert_value(&tx, id, inner_value, &stored, source)?;
+        tx.commit()?;
+
+        self.finish_write(stored, previous_blob_file);
+        Ok(sequence)
+    }
+
+    /// Write several values in one transaction, all of them or none. Returns the sequence numbers of the writes.
+    /// Fails with `EconfError::Conflict` if the sequence of the first parameter is not the expected one
+    pub(crate) fn store_values(
+        &mut self,
+        values: &[(ParameterId, ParameterValue)],
+        source: Option<&str>,
+        expected_sequence: Option<u64>,
+    ) -> Result<Vec<u64>, EconfError> {
+        let mut prepared = Vec::with_capacity(values.len());
+        let mut result = Ok(());
+        for (id, value) in values {
+            match self.prepare_value(*id, value) {
+                Ok(stored) => prepared.push(stored),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        let result = result.and_then(|_| {
+            let db = self.connection()?;
+            let tx = db.conn_mut().transaction_with_behavior(TransactionBehavior::Immediate)?;
+            if let (Some(expected), Some((id, _))) = (expected_sequence, values.first()) {
+                if stored_sequence(&tx, PARAMETER_DATA[id.index()].name_id)? != expected {
+                    return Err(EconfError::Conflict(*id));
+                }
+            }
+            let written = values.iter()
+                .zip(&prepared)
+                .map(|((id, value), stored)| Self::insert_value(&tx, *id, value, stored, source))
+                .collect::<Result<Vec<_>, _>>()?;
+            tx.commit()?;
+            Ok(written)
+        });
+        match result {
+            Ok(written) => Ok(prepared.into_iter()
+                .zip(written)
+                .map(|(stored, (sequence, previous_blob_file))| {
+                    self.finish_write(stored, previous_blob_file);
+                    sequence
+                })
+                .collect()),
+            Err(e) => {
+                for reference in prepared.into_iter().filter_map(|stored| stored.blob_file) {
+                    if let Err(e) = self.release_blob_file(&reference) {
+                        warn!("Failed to remove the blob file {}: {}", reference, e);
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Store the blob in a file or encrypt the secret before the write transaction
+    fn prepare_value(&self, id: ParameterId, inner_value: &ParameterValue) -> Result<StoredValue, EconfError> {
+        let parameter_def = &PARAMETER_DATA[id.index()];
+        // The secret blobs are not stored as files, the files are not encrypted
+        let blob_file = match inner_value {
+            ParameterValue::ValBlob(blob) if !parameter_def.secret && self.uses_blob_file(blob.len()) => {
+                Some(self.store_blob_file(&mut blob.as_slice(), parameter_def.name_id)?)
+            }
+            _ => None,
+        };
+        let encrypted = match (&self.secret_cipher, inner_value) {
+            (Some(cipher), ParameterValue::ValString(text)) if parameter_def.secret => Some(cipher.encrypt(parameter_def.name_id, text.as_bytes())?),
+            (Some(cipher), ParameterValue::ValBlob(blob)) if parameter_def.secret => Some(cipher.encrypt(parameter_def.name_id, blob)?),
+            _ => None,
+        };
+        Ok(StoredValue { blob_file, encrypted })
+    }
+
+    /// Insert the value in the transaction, returns the sequence number of the write
+    /// and the blob file of the previous value
+    fn insert_value(
+        tx: &Transaction,
+        id: ParameterId,
+        inner_value: &ParameterValue,
+        stored: &StoredValue,
+        source: Option<&str>,
+    ) -> Result<(u64, Option<String>), EconfError> {
+        let parameter_def = &PARAMETER_DATA[id.index()];
+        let sequence = next_sequence(tx)?;
+        let previous_blob_file = stored_blob_file(tx, parameter_def.name_id)?;
     
         let sql = format!(
-            "INSERT OR REPLACE INTO {} (key, value, timestamp) VALUES (?,?,?);",
+            "INSERT OR REPLACE INTO {} (key, value, timestamp, source, sequence) VALUES (?,?,?,?,?);",
             TABLE_NAME
         );
-        let mut stmt = db.conn.as_ref().unwrap().prepare(&sql)?;
+        let mut stmt = tx.prepare_cached(&sql)?;
     
-        let parameter_def = &PARAMETER_DATA[id as usize];
         stmt.execute(params![
             parameter_def.name_id,
-            match inner_value {
+            match (&stored.blob_file, &stored.encrypted, inner_value) {
+                (Some(reference), _, _) => reference.to_sql()?,
+                (None, Some(encrypted), _) => encrypted.to_sql()?,
+                (None, None, inner_value) => match inner_value {
                 ParameterValue::ValBool(v) => v.to_sql()?,
                 ParameterValue::ValI32(v) => v.to_sql()?,
                 ParameterValue::ValU32(v) => v.to_sql()?,
@@ -662,48 +2116,275 @@ impl DatabaseManager {
                 ParameterValue::ValNone => {
                     todo!("ValNone handling not implemented")
                 }
+                },
             },
             Self::get_timestamp(),
+            source,
+            sequence as i64,
         ])?;
-    
-        Ok(validated_status)
+        Ok((sequence, previous_blob_file))
     }
 
-    pub fn update(&mut self) -> Result<Vec<ParameterId>, Box<dyn Error>> {
-        let sql = format!("SELECT key FROM {} WHERE timestamp >= ?", TABLE_NAME);
-        let check_start = Self::get_timestamp();
-        let mut pending_callbacks: Vec<ParameterId> = Vec::new();
+    /// Remove the replaced blob file and run the maintenance when due, after a committed write
+    fn finish_write(&mut self, stored: StoredValue, previous_blob_file: Option<String>) {
+        if let Some(previous) = previous_blob_file.filter(|previous| Some(previous) != stored.blob_file.as_ref()) {
+            if let Err(e) = self.release_blob_file(&previous) {
+                warn!("Failed to remove the blob file {}: {}", previous, e);
+            }
+        }
+
+        self.writes_since_maintenance += 1;
+        if self.maintenance_writes > 0 && self.writes_since_maintenance >= self.maintenance_writes {
+            // The write itself succeeded, the maintenance is retried after the next writes
+            if let Err(e) = self.maintain() {
+                warn!("Database maintenance failed: {}", e);
+            }
+        }
+    }
 
-        let db = DbConnection::new(&self.database_path, false, false)?;
+    /// Write the deferred values to the database, all of them or only the ones due by the debounce time.
+    /// Returns the number of the values written
+    pub(crate) fn flush_pending(&mut self, all: bool) -> Result<usize, EconfError> {
+        let now = Instant::now();
+        let due: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| all || pending.due.is_some_and(|due| due <= now))
+            .map(|(index, _)| *index)
+            .collect();
+        for index in &due {
+            let Some(id) = ParameterId::from_index(*index) else {
+                continue;
+            };
+            let pending = self.pending.remove(index).expect("Index is taken from the pending writes");
+            debug!("Flush deferred write of {}", PARAMETER_DATA[*index].name_id);
+            if let Err(e) = self.store_value(id, &pending.value, pending.source.as_deref(), None) {
+                self.pending.insert(*index, pending);
+                return Err(e);
+            }
+            sensitive::discard(id, pending.value);
+        }
+        Ok(due.len())
+    }
+
+    /// Drop the deferred values replaced by load or factory reset
+    fn discard_pending(&mut self) {
+        for (index, pending) in std::mem::take(&mut self.pending) {
+            if let Some(id) = ParameterId::from_index(index) {
+                warn!("Deferred write of {} discarded", PARAMETER_DATA[index].name_id);
+                sensitive::discard(id, pending.value);
+            }
+        }
+    }
+
+    fn blob_streams(&mut self) -> Result<&mut DbConnection, EconfError> {
+        let db = self.connection()?;
+        db.conn().execute(&format!("CREATE TEMP TABLE IF NOT EXISTS {} (value BLOB)", BLOB_STREAMS_TABLE_NAME), [])?;
+        Ok(db)
+    }
+
+    /// Copy the blob to the streams table for the chunked reads, returns the row and the size.
+    /// The reader sees the value at the time of the call even if the parameter is changed later
+    pub(crate) fn blob_stream_open_read(&mut self, id: ParameterId) -> Result<(i64, usize), EconfError> {
+        if self.pending.contains_key(&id.index()) {
+            self.flush_pending(true)?;
+        }
+        let parameter_def = &PARAMETER_DATA[id.index()];
+        let db = self.blob_streams()?;
+        let blob_file = stored_blob_file(db.conn(), parameter_def.name_id)?;
+        if let Some(path) = blob_file.and_then(|reference| self.blob_file_path(&reference)) {
+            let mut file = fs::File::open(&path)?;
+            let size = file.metadata()?.len() as usize;
+            let row = self.blob_stream_open_write(size)?;
+            let db = self.connection()?;
+            let mut blob = db.conn().blob_open(DatabaseName::Temp, BLOB_STREAMS_TABLE_NAME, "value", row, false)?;
+            std::io::copy(&mut file, &mut blob)?;
+            return Ok((row, size));
+        }
+        let db = self.connection()?;
+        let copied = db.conn().execute(
+            &format!(
+                "INSERT INTO temp.{} (value) SELECT value FROM {} WHERE key = ? AND typeof(value) = 'blob'",
+                BLOB_STREAMS_TABLE_NAME, TABLE_NAME
+            ),
+            params![parameter_def.name_id],
+        )?;
+        if copied == 0 {
+            let ParameterValue::ValBlob(default) = self.get_default_value(parameter_def)? else {
+                return Err(EconfError::Schema(format!("Default of {} is not a blob", parameter_def.name_id)));
+            };
+            let db = self.connection()?;
+            db.conn().execute(&format!("INSERT INTO temp.{} (value) VALUES (?)", BLOB_STREAMS_TABLE_NAME), params![default])?;
+        }
+        let db = self.connection()?;
+        let row = db.conn().last_insert_rowid();
+        let size = db.conn().query_row(
+            &format!("SELECT length(value) FROM temp.{} WHERE rowid = ?", BLOB_STREAMS_TABLE_NAME),
+            params![row],
+            |row| row.get::<usize, i64>(0),
+        )?;
+        Ok((row, size as usize))
+    }
+
+    /// Reserve a zero-filled blob of the size for the chunked writes, returns the row
+    pub(crate) fn blob_stream_open_write(&mut self, size: usize) -> Result<i64, EconfError> {
+        let db = self.blob_streams()?;
+        db.conn().execute(&format!("INSERT INTO temp.{} (value) VALUES (zeroblob(?))", BLOB_STREAMS_TABLE_NAME), params![size as i64])?;
+        Ok(db.conn().last_insert_rowid())
+    }
+
+    pub(crate) fn blob_stream_read(&mut self, row: i64, offset: usize, buffer: &mut [u8]) -> Result<usize, EconfError> {
+        let db = self.connection()?;
+        let blob = db.conn().blob_open(DatabaseName::Temp, BLOB_STREAMS_TABLE_NAME, "value", row, true)?;
+        Ok(blob.read_at(buffer, offset)?)
+    }
+
+    pub(crate) fn blob_stream_write(&mut self, row: i64, offset: usize, data: &[u8]) -> Result<(), EconfError> {
+        let db = self.connection()?;
+        let mut blob = db.conn().blob_open(DatabaseName::Temp, BLOB_STREAMS_TABLE_NAME, "value", row, false)?;
+        Ok(blob.write_at(data, offset)?)
+    }
+
+    /// Move the written blob to the parameter, returns the sequence number of the write
+    pub(crate) fn blob_stream_commit(&mut self, id: ParameterId, row: i64, source: Option<&str>) -> Result<u64, EconfError> {
+        self.require_writable()?;
+        let name_id = PARAMETER_DATA[id.index()].name_id;
+        if let Some(pending) = self.pending.remove(&id.index()) {
+            sensitive::discard(id, pending.value);
+        }
+        self.open_connection()?;
+        let db = self.connection.as_ref().expect("Connection is opened above");
+        let mut blob = db.conn().blob_open(DatabaseName::Temp, BLOB_STREAMS_TABLE_NAME, "value", row, true)?;
+        let size = blob.len();
+        let blob_file = if self.uses_blob_file(size) {
+            Some(self.store_blob_file(&mut blob, name_id)?)
+        } else {
+            None
+        };
+        drop(blob);
+
+        let db = self.connection()?;
+        let tx = db.conn_mut().transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let sequence = next_sequence(&tx)?;
+        let previous_blob_file = stored_blob_file(&tx, name_id)?;
+        tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (key, value, timestamp, source, sequence) SELECT ?, COALESCE(?, value), ?, ?, ? FROM temp.{} WHERE rowid = ?",
+                TABLE_NAME, BLOB_STREAMS_TABLE_NAME
+            ),
+            params![name_id, blob_file, Self::get_timestamp(), source, sequence as i64, row],
+        )?;
+        tx.execute(&format!("DELETE FROM temp.{} WHERE rowid = ?", BLOB_STREAMS_TABLE_NAME), params![row])?;
+        tx.commit()?;
+
+        if let Some(previous) = previous_blob_file.filter(|previous| Some(previous) != blob_file.as_ref()) {
+            if let Err(e) = self.release_blob_file(&previous) {
+                warn!("Failed to remove the blob file {}: {}", previous, e);
+            }
+        }
+        Ok(sequence)
+    }
+
+    pub(crate) fn blob_stream_discard(&mut self, row: i64) -> Result<(), EconfError> {
+        let db = self.connection()?;
+        db.conn().execute(&format!("DELETE FROM temp.{} WHERE rowid = ?", BLOB_STREAMS_TABLE_NAME), params![row])?;
+        Ok(())
+    }
 
-        let mut stmt = db.conn().prepare(&sql)?;
-        let mut rows = stmt.query(params![self.last_update_timestamp])?;
+    /// The changes made by all the processes since the last update, read from the change journal with the
+    /// new values. The value is None for a removed row, the parameter has the default value then. The first
+    /// update reports the stored parameters, and an update that missed the pruned entries or a replaced
+    /// journal (load) reports all the parameters
+    pub fn update(&mut self) -> Result<Vec<(ParameterChangeEvent, Option<ParameterValue>)>, EconfError> {
+        self.open_connection()?;
+        let db = self.connection.as_ref().expect("Connection is opened above");
+        // One read transaction, the entries are not pruned between the checks and the reads
+        db.conn().execute_batch("BEGIN DEFERRED").map_err(read_error)?;
+        let result = self.read_changes(db.conn(), self.last_change);
+        if let Err(e) = db.conn().execute_batch("COMMIT") {
+            warn!("Could not end the read transaction: {}", e);
+        }
+        let (changes, newest) = result.map_err(read_error)?;
+        self.last_change = newest;
+        Ok(changes)
+    }
 
+    /// The journal entries after the last change and the counter of the newest one
+    #[allow(clippy::type_complexity)]
+    fn read_changes(
+        &self,
+        conn: &Connection,
+        last_change: u64,
+    ) -> Result<(Vec<(ParameterChangeEvent, Option<ParameterValue>)>, u64), rusqlite::Error> {
+        let journal: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+            params![CHANGES_TABLE_NAME],
+            |row| row.get(0),
+        )?;
+        if journal == 0 {
+            // A read-only instance of a database created by an older version
+            debug!("The database has no change journal");
+            return Ok((Vec::new(), last_change));
+        }
+        let (oldest, newest) = conn.query_row(
+            &format!("SELECT COALESCE(MIN(counter), 0), COALESCE(MAX(counter), 0) FROM {}", CHANGES_TABLE_NAME),
+            [],
+            |row| Ok((row.get::<usize, i64>(0)? as u64, row.get::<usize, i64>(1)? as u64)),
+        )?;
+        let missed = newest < last_change || oldest > last_change + 1;
+        let rescan = last_change == 0 || missed;
+        if missed && last_change != 0 {
+            warn!("Changes after {} are not in the journal (oldest {}), rereading all the parameters", last_change, oldest);
+        }
+
+        let check_start = Self::get_timestamp();
+        let mut changes = Vec::new();
+        let mut stmt;
+        let mut rows = if rescan {
+            stmt = conn.prepare_cached(&format!("SELECT key, value, timestamp, source, sequence, 0 FROM {}", TABLE_NAME))?;
+            stmt.query([])?
+        } else {
+            stmt = conn.prepare_cached(&format!(
+                "SELECT key, value, timestamp, source, sequence, removed FROM {} WHERE counter > ? ORDER BY counter",
+                CHANGES_TABLE_NAME
+            ))?;
+            stmt.query(params![last_change as i64])?
+        };
         while let Some(row) = rows.next()? {
             let key = row.get::<usize, String>(0)?;
 
             // TODO: Ignore unknown parameters for now, later a proper database migration should be implemented
-            let id_find = PARAMETER_DATA
-                .iter()
-                .position(|pm| pm.name_id == key);
-            
-            let id = match id_find {
-                Some(id) => id,
-                None => continue,
+            let Some(id) = ParameterId::from_name(&key) else {
+                continue;
             };
-
-            let pm_id = match ParameterId::try_from(id) {
-                Ok(param) => param,
-                Err(_) => {
-                    return Err(format!("Invalid parameter value: {}", id).into());
-                }
+            let value = match row.get::<usize, i64>(5)? {
+                0 => Some(self.value_from_sql(&PARAMETER_DATA[id.index()], row.get(1)?)?),
+                _ => None,
             };
-            info!("Parameter {} {} updated by timestamp", key, pm_id as usize);
-            pending_callbacks.push(pm_id);
+            let sequence = row.get::<usize, Option<i64>>(4)?.unwrap_or(0) as u64;
+            info!("Parameter {} {} changed, sequence {}", key, id as usize, sequence);
+            changes.push((
+                ParameterChangeEvent {
+                    id,
+                    timestamp: row.get::<usize, Option<f64>>(2)?.unwrap_or(check_start),
+                    source: row.get::<usize, Option<String>>(3)?,
+                    sequence,
+                },
+                value,
+            ));
         }
-
-        self.last_update_timestamp = check_start;
-
-        Ok(pending_callbacks)
+        if missed && last_change != 0 {
+            // The parameters removed in the missed changes have the default values now
+            let mut stored = vec![false; PARAMETER_DATA.len()];
+            for (event, _) in &changes {
+                stored[event.id.index()] = true;
+            }
+            for id in (0..PARAMETER_DATA.len()).filter_map(ParameterId::from_index) {
+                if !stored[id.index()] {
+                    changes.push((ParameterChangeEvent { id, timestamp: check_start, source: None, sequence: 0 }, None));
+                }
+            }
+        }
+        Ok((changes, newest))
     }
 }
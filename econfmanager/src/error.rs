@@ -0,0 +1,100 @@
+use thiserror::Error;
+
+use crate::generated::{PARAMETER_DATA, ParameterId};
+use crate::schema::{ParameterValue, ParameterValueType};
+
+fn name(id: &ParameterId) -> &'static str {
+    PARAMETER_DATA[id.index()].name_id
+}
+
+/// Errors returned by the interface and the database layer. They convert to `Box<dyn Error + Send + Sync>`
+/// for the callers that don't branch on the kind
+#[derive(Debug, Error)]
+pub enum EconfError {
+    #[error("Database error: {0}")]
+    Database(#[source] rusqlite::Error),
+    /// The database file is damaged or is not a database, see `InterfaceInstance::check_storage`
+    #[error("The database is corrupted: {0}")]
+    Corrupt(#[source] rusqlite::Error),
+    #[error("Validation failed for {}: {reason}", name(id))]
+    Validation { id: ParameterId, reason: String },
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// The value has another type than the parameter
+    #[error("Value of {} has the type {actual}, expected {expected}", name(id))]
+    TypeMismatch { id: ParameterId, expected: ParameterValueType, actual: ParameterValueType },
+    /// A pre-change hook refused the value, see `InterfaceInstance::add_pre_change_hook`
+    #[error("Change of {} vetoed: {reason}", name(id))]
+    Vetoed { id: ParameterId, reason: String },
+    #[error("Parameter {} is const", name(.0))]
+    Const(ParameterId),
+    #[error("Parameter {} is read-only", name(.0))]
+    ReadOnly(ParameterId),
+    /// The instance was created by `InterfaceInstance::new_readonly` and can't change the parameters
+    #[error("The instance is read-only")]
+    ReadOnlyInstance,
+    /// The condition of a conditional write doesn't hold, see `InterfaceInstance::set_if`
+    #[error("Parameter {} was changed by another writer", name(.0))]
+    Conflict(ParameterId),
+    #[error("Lock timeout")]
+    LockTimeout,
+    /// Another process holds or replaces the database (load, factory reset), the call can be retried
+    #[error("The database is busy, retry later")]
+    DbBusy,
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// The stored data doesn't match the parameters schema
+    #[error("Schema error: {0}")]
+    Schema(String),
+    /// Incorrect instance configuration
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// The parameter store of the instance can't do the operation, see `store::ParameterStore`
+    #[error("Not supported by the parameter store: {0}")]
+    Unsupported(String),
+}
+
+impl EconfError {
+    /// The parameter the error is about, if it names one
+    pub fn parameter(&self) -> Option<ParameterId> {
+        match self {
+            EconfError::Validation { id, .. }
+            | EconfError::TypeMismatch { id, .. }
+            | EconfError::Vetoed { id, .. }
+            | EconfError::Const(id)
+            | EconfError::ReadOnly(id)
+            | EconfError::Conflict(id) => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for EconfError {
+    fn from(error: rusqlite::Error) -> Self {
+        match error.sqlite_error_code() {
+            Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase) => EconfError::Corrupt(error),
+            _ => EconfError::Database(error),
+        }
+    }
+}
+
+/// Why a value doesn't pass the validation rules of a parameter, see `InterfaceInstance::validate_value`
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ValidationError {
+    #[error("Expected {expected}, got {actual}")]
+    TypeMismatch { expected: ParameterValueType, actual: ParameterValueType },
+    #[error("Value {value} is below the minimum {min}")]
+    BelowMin { value: ParameterValue, min: ParameterValue },
+    #[error("Value {value} is above the maximum {max}")]
+    AboveMax { value: ParameterValue, max: ParameterValue },
+    #[error("Value {value} is not one of the allowed values")]
+    NotAllowed { value: ParameterValue },
+    #[error("Value {value} is not finite")]
+    NonFinite { value: ParameterValue },
+    #[error("String of {length} characters is longer than {max_length}")]
+    TooLong { length: usize, max_length: u32 },
+    #[error("String doesn't match the pattern {pattern}")]
+    PatternMismatch { pattern: &'static str },
+    #[error("Blob of {size} bytes is larger than {max_size}")]
+    TooLarge { size: usize, max_size: u32 },
+}
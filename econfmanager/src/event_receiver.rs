@@ -1,114 +1,402 @@
-use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::collections::BTreeSet;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
 
 use log::{debug, error, info, warn};
 use prost::Message;
-use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::constants::{MULTICAST_GROUP, MULTICAST_PORT};
-use crate::generated::ParameterId;
+use crate::constants::{COMPUTED_SOURCE, RECEIVER_POLL_INTERVAL, RESYNC_MIN_INTERVAL};
+use crate::database_utils::DatabaseManager;
+use crate::generated::{PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
 
-use crate::interface::SharedRuntimeData;
-use crate::service_events::ParameterNotification;
+use crate::interface::{Computed, ParameterChangeEvent, SharedRuntimeData, Watch};
+use crate::error::EconfError;
+use crate::lock_monitor::MonitoredMutex;
+use crate::notifier::decode_value;
+use crate::schema::ParameterValue;
+use crate::sensitive;
+use crate::store::ParameterStore;
+use crate::service_events::{NotificationValue, ParameterNotification};
+use crate::transport::{NotificationReceiver, NotificationTransport};
 
-#[derive (Clone, Default)]
-pub(crate) struct EventReceiver {
-    runtime_data: Arc<Mutex<SharedRuntimeData>>
+/// Tracks the write sequences seen in the notifications to detect the lost ones
+struct SequenceTracker {
+    /// All the sequences below it were received or resynchronized
+    next: u64,
+    /// Sequences received after a missing one
+    ahead: BTreeSet<u64>,
+    last_resync: Option<Instant>,
 }
 
-impl EventReceiver {
+impl SequenceTracker {
+    fn new(current_sequence: u64) -> Self {
+        Self { next: current_sequence + 1, ahead: BTreeSet::new(), last_resync: None }
+    }
+
+    fn observe(&mut self, sequence: u64) {
+        // 0 is sent by the writers that don't allocate the sequences
+        if sequence == 0 || sequence < self.next {
+            return;
+        }
+        self.ahead.insert(sequence);
+        while self.ahead.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+
+    /// A resync is due when a sequence is missing. The resyncs are rate limited, so a peer
+    /// that doesn't send the notifications for some of the writes can't cause a resync storm
+    fn resync_due(&self) -> bool {
+        !self.ahead.is_empty() && self.last_resync.is_none_or(|last| last.elapsed() >= RESYNC_MIN_INTERVAL)
+    }
+
+    fn resynced(&mut self, current_sequence: u64) {
+        self.next = self.next.max(current_sequence + 1);
+        self.ahead.retain(|sequence| *sequence > current_sequence);
+        self.last_resync = Some(Instant::now());
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct EventReceiver<S: ParameterStore> {
+    runtime_data: Arc<MonitoredMutex<SharedRuntimeData>>,
+    database: Arc<MonitoredMutex<S>>,
+    /// Serializes the callbacks from the multicast and polling threads,
+    /// so a subscriber never observes the events out of order
+    dispatch_lock: Arc<Mutex<()>>,
+    /// Set to stop the receiving thread
+    stop_flag: Arc<AtomicBool>,
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
 
-    pub(crate) fn new(runtime_data: Arc<Mutex<SharedRuntimeData>>) -> Result<Self, Box<dyn std::error::Error>> {
-        let instance = EventReceiver{runtime_data};
-        let thread_instance = instance.clone();
-        let _ = std::thread::spawn(move || {
-            if let Err(e) = thread_instance.multicast_receiver(MULTICAST_GROUP, MULTICAST_PORT) {
-                println!("Receiver error: {}", e);
+// Not derived, the store doesn't have to be Clone
+impl<S: ParameterStore> Clone for EventReceiver<S> {
+    fn clone(&self) -> Self {
+        Self {
+            runtime_data: self.runtime_data.clone(),
+            database: self.database.clone(),
+            dispatch_lock: self.dispatch_lock.clone(),
+            stop_flag: self.stop_flag.clone(),
+            thread: self.thread.clone(),
+        }
+    }
+}
+
+impl<S: ParameterStore> EventReceiver<S> {
+
+    pub(crate) fn new(
+        runtime_data: Arc<MonitoredMutex<SharedRuntimeData>>,
+        database: Arc<MonitoredMutex<S>>,
+        transport: &dyn NotificationTransport,
+    ) -> Result<Self, EconfError> {
+        let instance = EventReceiver {
+            runtime_data,
+            database,
+            dispatch_lock: Default::default(),
+            stop_flag: Default::default(),
+            thread: Default::default(),
+        };
+        // Bind the receiver before returning, so the notifications of the first writes are not missed.
+        // Without the receiver the changes are still picked up by the polling
+        match transport.receiver() {
+            Ok(receiver) => {
+                // Read after binding, so every later write is either received or detected as missing
+                let tracker = SequenceTracker::new(instance.database.lock().current_sequence()?);
+                let thread_instance = instance.clone();
+                let handle = std::thread::Builder::new()
+                    .name("econf-receiver".to_owned())
+                    .spawn(move || {
+                        if let Err(e) = thread_instance.receive(receiver, tracker) {
+                            error!("Receiver error: {}", e);
+                        }
+                    })?;
+                *instance.thread.lock().unwrap() = Some(handle);
             }
-        });
+            Err(e) => error!("Notification receiver not started: {}", e),
+        }
         Ok(instance)
     }
 
-    pub(crate) fn multicast_receiver(&self, multicast_group: Ipv4Addr, port: u16) -> Result<(), Box<dyn std::error::Error>> {
-        let local_addr = Ipv4Addr::new(0, 0, 0, 0);
-        
-        info!("Starting multicast receiver on {}:{}", multicast_group, port);
-    
-        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
-            .map_err(|e| {
-                error!("Socket creation failed: {}", e);
-                e
-            })?;
-    
-        socket.set_reuse_address(true)
-            .map_err(|e| warn!("SO_REUSEADDR failed (non-fatal): {}", e)).ok();
-    
-        #[cfg(target_os = "linux")]
-        socket.set_reuse_port(true)
-            .map_err(|e| warn!("SO_REUSEPORT failed (non-fatal): {}", e)).ok();
-    
-        socket.bind(&SocketAddrV4::new(local_addr, port).into())
-            .map_err(|e| {
-                error!("Failed to bind to port {}: {}", port, e);
-                e
-            })?;
-        info!("Successfully bound to UDP port {}", port);
-    
-        socket.join_multicast_v4(&multicast_group, &local_addr)
-            .map_err(|e| {
-                error!("Multicast join failed: {}", e);
+    /// The receiving thread was started and didn't exit
+    pub(crate) fn is_running(&self) -> bool {
+        self.thread.lock().unwrap().as_ref().is_some_and(|handle| !handle.is_finished())
+    }
+
+    /// Stop the receiving thread and wait until it exits
+    pub(crate) fn shutdown(&self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let Some(handle) = self.thread.lock().unwrap().take() else {
+            return;
+        };
+        if handle.thread().id() == std::thread::current().id() {
+            // The instance is dropped from a callback, the thread exits when the callback returns
+            return;
+        }
+        if handle.join().is_err() {
+            error!("Receiver thread panicked");
+        }
+    }
+
+    /// Read the changes made since the last update from the database and call the callbacks
+    pub(crate) fn update(&self) -> Result<Vec<ParameterId>, EconfError> {
+        let pending_callbacks = self.database.lock().update()?;
+        let ids: Vec<ParameterId> = pending_callbacks.iter().map(|(event, _)| event.id).collect();
+        // Invalidate all changed parameters first, including the ones without callbacks,
+        // so callbacks never observe stale values of other changed parameters
+        self.runtime_data.lock().invalidate(&ids);
+        let mut delivered = Vec::new();
+        for (event, value) in pending_callbacks {
+            let id = event.id;
+            if self.notify_callback(event, value) {
+                delivered.push(id);
+            }
+        }
+        self.notify_computed(&mut delivered);
+        self.notify_watches(&delivered);
+        Ok(ids)
+    }
+
+    /// Drop the whole cache and deliver the changes from the database, after notifications were lost
+    fn resync(&self) -> Result<Vec<ParameterId>, EconfError> {
+        let all_ids: Vec<ParameterId> = (0..PARAMETERS_NUM)
+            .filter_map(ParameterId::from_index)
+            .collect();
+        self.runtime_data.lock().invalidate(&all_ids);
+        self.update()
+    }
+
+    fn receive(&self, mut receiver: Box<dyn NotificationReceiver>, mut tracker: SequenceTracker) -> Result<(), EconfError> {
+        info!("Listening for notifications...");
+        while !self.stop_flag.load(Ordering::Relaxed) {
+            let Some((message, src)) = receiver.recv(RECEIVER_POLL_INTERVAL).map_err(|e| {
+                error!("Receive error: {}", e);
                 e
-            })?;
-        socket.set_multicast_loop_v4(false)?;
-    
-        let socket: UdpSocket = socket.into();
-        info!("Listening for multicast messages...");
-    
-        let mut buf = [0u8; 1024];
-        loop {
-            match socket.recv_from(&mut buf) {
-                Ok((num_bytes, src)) => {
-                    match ParameterNotification::decode(&buf[..num_bytes]) {
-                        Ok(notification) => {
-                            info!("Received parameter notification from {}: id={}", src, notification.id);
-                            match ParameterId::try_from(notification.id as usize) {
-                                Ok(id) => self.notify_callback(id),
-                                Err(e) => {
-                                    error!("Could not decode ID {}: {}", notification.id, e);
-                                    continue
+            })? else {
+                continue;
+            };
+            match ParameterNotification::decode(&message[..]) {
+                Ok(notification) => {
+                    info!("Received parameter notification from {}: id={} ids={:?} source={}", src, notification.id, notification.ids, notification.source);
+                    let changes: Vec<(i32, u64, Option<NotificationValue>)> = if notification.ids.is_empty() {
+                        vec![(notification.id, notification.sequence, notification.value.clone())]
+                    } else {
+                        notification.ids.iter().enumerate()
+                            .map(|(i, id)| (
+                                *id,
+                                notification.sequences.get(i).copied().unwrap_or(notification.sequence),
+                                notification.values.get(i).cloned(),
+                            ))
+                            .collect()
+                    };
+                    for sequence in &notification.merged_sequences {
+                        tracker.observe(*sequence);
+                    }
+                    let mut delivered = Vec::new();
+                    for (raw_id, sequence, value) in changes {
+                        tracker.observe(sequence);
+                        match ParameterId::try_from(raw_id as usize) {
+                            Ok(id) => {
+                                let event = ParameterChangeEvent {
+                                    id,
+                                    source: if notification.source.is_empty() { None } else { Some(notification.source.clone()) },
+                                    timestamp: notification.timestamp,
+                                    sequence,
+                                };
+                                if self.notify_callback(event, value.and_then(|value| Self::embedded_value(id, value))) {
+                                    delivered.push(id);
                                 }
                             }
-                        }
-                        Err(e) => {
-                            error!("Failed to decode ParameterNotification from {}: {}", src, e);
-                            // Optionally continue or return error
-                            continue;
+                            Err(e) => error!("Could not decode ID {}: {}", raw_id, e),
                         }
                     }
+                    self.notify_computed(&mut delivered);
+                    self.notify_watches(&delivered);
                 }
                 Err(e) => {
-                    error!("Receive error: {}", e);
-                    return Err(Box::new(e));
+                    error!("Failed to decode ParameterNotification from {}: {}", src, e);
+                    continue;
                 }
             }
+            if tracker.resync_due() {
+                warn!(
+                    "Missed notifications from sequence {} (received {:?}), resynchronizing",
+                    tracker.next, tracker.ahead.first()
+                );
+                match self.resync().and_then(|_| self.database.lock().current_sequence()) {
+                    Ok(current_sequence) => tracker.resynced(current_sequence),
+                    Err(e) => error!("Resync failed: {}", e),
+                }
+            }
+        }
+        info!("Notification receiver stopped");
+        Ok(())
+    }
+
+    /// The value embedded in the notification, if it has the type of the parameter
+    fn embedded_value(id: ParameterId, value: NotificationValue) -> Option<ParameterValue> {
+        let value = decode_value(value)?;
+        if mem::discriminant(&value.parameter_type()) != mem::discriminant(&PARAMETER_DATA[id.index()].value_type) {
+            warn!("Embedded value {} doesn't match the type of {}", sensitive::log_value(id, &value), PARAMETER_DATA[id.index()].name_id);
+            return None;
         }
+        Some(value)
     }
 
-    pub(crate) fn notify_callback(&self, id: ParameterId) {
-        let index = id as usize;
+    /// Update the cache and call the callback of the parameter. The new value is stored in the cache
+    /// when it was embedded in the notification, otherwise the next read goes to the database.
+    /// Returns false if the event is older than the last delivered one and was discarded
+    pub(crate) fn notify_callback(&self, event: ParameterChangeEvent, value: Option<ParameterValue>) -> bool {
+        let id = event.id;
+        let index = id.index();
+        let _dispatch = self.dispatch_lock.lock().unwrap();
         let callback;
+        let subscribers;
         {
-            let mut data = self.runtime_data.lock().unwrap();
-            // Invalidate the cache so the next time the parameter is read it will be updated from the database
-            data.parameters_data[index].value = None;
+            let mut data = self.runtime_data.lock();
+            let last_sequence = data.parameters_data[index].sequence;
+            if event.sequence != 0 && event.sequence <= last_sequence {
+                data.invalidate(&[id]);
+                debug!("Discard stale event for {}: sequence {} <= {}", index, event.sequence, last_sequence);
+                return false;
+            }
+            match value {
+                Some(value) => data.store(id, value),
+                None => data.invalidate(&[id]),
+            }
+            data.parameters_data[index].sequence = data.parameters_data[index].sequence.max(event.sequence);
             callback = data.parameters_data[index].callback.clone();
+            subscribers = data.parameters_data[index].subscribers.clone();
+        }
+        if callback.is_none() && subscribers.is_empty() {
+            debug!("Callback for {} not defined", id as usize);
+            return true;
         }
-        if callback.is_some() {
+        // The callbacks are called without the runtime data lock, so they can add and remove callbacks
+        if let Some(callback) = callback {
             debug!("Call callback for {}", id as usize);
-            callback.unwrap()(id);
+            callback(&event);
         }
-        else {
-            debug!("Callback for {} not defined", id as usize);
+        for (handle, subscriber) in subscribers {
+            // Skip the callbacks removed by the ones called before
+            if self.runtime_data.lock().is_subscribed(index, handle) {
+                debug!("Call callback {} for {}", handle, id as usize);
+                subscriber(&event);
+            }
         }
+        true
     }
-}
\ No newline at end of file
+
+    /// Compute the parameters depending on the changed ones and call their callbacks,
+    /// the computed parameters delivered are added to the changed ones
+    fn notify_computed(&self, changed: &mut Vec<ParameterId>) {
+        let dependents: Vec<(ParameterId, Computed)> = {
+            let data = self.runtime_data.lock();
+            (0..PARAMETERS_NUM)
+                .filter_map(|index| {
+                    let compute = data.parameters_data[index].compute.as_ref()?;
+                    if !compute.dependencies.iter().any(|id| changed.contains(id)) {
+                        return None;
+                    }
+                    Some((ParameterId::from_index(index)?, compute.clone()))
+                })
+                .collect()
+        };
+        for (id, compute) in dependents {
+            let values = match self.snapshot(&compute.dependencies) {
+                Ok(values) => values,
+                Err(e) => {
+                    error!("{} not computed, the dependencies can't be read: {}", PARAMETER_DATA[id.index()].name_id, e);
+                    continue;
+                }
+            };
+            let value = (compute.function)(&values);
+            for (dependency, value) in compute.dependencies.iter().zip(values) {
+                sensitive::discard(*dependency, value);
+            }
+            if mem::discriminant(&value.parameter_type()) != mem::discriminant(&PARAMETER_DATA[id.index()].value_type) {
+                error!("Computed value {} doesn't match the type of {}", sensitive::log_value(id, &value), PARAMETER_DATA[id.index()].name_id);
+                continue;
+            }
+            let event = ParameterChangeEvent {
+                id,
+                source: Some(COMPUTED_SOURCE.to_owned()),
+                timestamp: DatabaseManager::get_timestamp(),
+                sequence: 0,
+            };
+            if self.notify_callback(event, Some(value)) {
+                changed.push(id);
+            }
+        }
+    }
+
+    /// Call the watches of the changed parameters, each one once
+    fn notify_watches(&self, changed: &[ParameterId]) {
+        if changed.is_empty() {
+            return;
+        }
+        let _dispatch = self.dispatch_lock.lock().unwrap();
+        let watches: Vec<Watch> = self.runtime_data.lock().watches
+            .iter()
+            .filter(|watch| watch.ids.iter().any(|id| changed.contains(id)))
+            .cloned()
+            .collect();
+        for watch in watches {
+            // Skip the watches removed by the ones called before
+            if !self.runtime_data.lock().is_watching(watch.handle) {
+                continue;
+            }
+            match self.snapshot(&watch.ids) {
+                Ok(values) => {
+                    debug!("Call watch {}", watch.handle);
+                    (watch.callback)(&values);
+                    for (id, value) in watch.ids.iter().zip(values) {
+                        sensitive::discard(*id, value);
+                    }
+                }
+                Err(e) => error!("Watch {} not called, the values can't be read: {}", watch.handle, e),
+            }
+        }
+    }
+
+    /// Values of the parameters as returned by `get`: the cached values, the others read from the database
+    /// together. The cache is locked meanwhile, so no notification changes the values in between
+    fn snapshot(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        let data = self.runtime_data.lock();
+        let missing: Vec<ParameterId> = ids.iter()
+            .filter(|id| data.parameters_data[id.index()].value.is_none())
+            .copied()
+            .collect();
+        let mut read = self.database.lock().read_snapshot(&missing)?.into_iter();
+        Ok(ids.iter()
+            .map(|id| match &data.parameters_data[id.index()].value {
+                Some(value) => value.clone(),
+                None => read.next().expect("A value is read for every parameter not cached"),
+            })
+            .collect())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_ignores_old_sequences() {
+        let mut tracker = SequenceTracker::new(4);
+        tracker.observe(5);
+        tracker.observe(3);
+        tracker.observe(5);
+        assert_eq!(tracker.next, 6);
+        assert!(tracker.ahead.is_empty());
+        assert!(!tracker.resync_due());
+
+        // 7 is missing
+        tracker.observe(8);
+        assert!(tracker.resync_due());
+        tracker.resynced(8);
+        assert_eq!(tracker.next, 9);
+        assert!(tracker.ahead.is_empty());
+    }
+}
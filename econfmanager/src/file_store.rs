@@ -0,0 +1,561 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{debug, error, info};
+use serde_json::{Map, Value, json};
+use zeroize::Zeroizing;
+
+use crate::config::expand_path;
+use crate::constants::IN_MEMORY_DATABASE_PATH;
+use crate::database_utils::{validate_value, DatabaseManager, Status, WrittenValue};
+use crate::error::EconfError;
+use crate::generated::{PARAMETER_DATA, ParameterId};
+use crate::interface::{value_from_json, ParameterChangeEvent, WriteCondition};
+use crate::schema::{Parameter, ParameterValue};
+use crate::sensitive;
+use crate::store::{read_default, ParameterStore};
+
+/// Encoding of the files of a [`FileStore`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Json,
+    /// Smaller files, needs the `cbor` feature
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl FileFormat {
+    /// CBOR for the ".cbor" extension if the feature is enabled, JSON otherwise
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "cbor")]
+            Some("cbor") => FileFormat::Cbor,
+            _ => FileFormat::Json,
+        }
+    }
+
+    fn encode(&self, content: &Value) -> Result<Zeroizing<Vec<u8>>, EconfError> {
+        let encoded = match self {
+            FileFormat::Json => serde_json::to_vec_pretty(content).map_err(|e| EconfError::Schema(e.to_string()))?,
+            #[cfg(feature = "cbor")]
+            FileFormat::Cbor => {
+                let mut encoded = Vec::new();
+                ciborium::into_writer(content, &mut encoded).map_err(|e| EconfError::Schema(e.to_string()))?;
+                encoded
+            }
+        };
+        Ok(Zeroizing::new(encoded))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Value, EconfError> {
+        match self {
+            FileFormat::Json => serde_json::from_slice(bytes).map_err(|e| EconfError::Schema(e.to_string())),
+            #[cfg(feature = "cbor")]
+            FileFormat::Cbor => ciborium::from_reader(bytes).map_err(|e| EconfError::Schema(e.to_string())),
+        }
+    }
+}
+
+struct StoredEntry {
+    value: ParameterValue,
+    timestamp: f64,
+    source: Option<String>,
+    sequence: u64,
+}
+
+/// Content of a file: the last sequence number and the stored values by the parameter index
+#[derive(Default)]
+struct FileContent {
+    sequence: u64,
+    values: BTreeMap<usize, StoredEntry>,
+}
+
+impl FileContent {
+    fn to_value(&self) -> Value {
+        let parameters: Map<String, Value> = self.values.iter()
+            .map(|(index, entry)| {
+                (PARAMETER_DATA[*index].name_id.to_owned(), json!({
+                    "value": entry.value,
+                    "timestamp": entry.timestamp,
+                    "source": entry.source,
+                    "sequence": entry.sequence,
+                }))
+            })
+            .collect();
+        json!({ "sequence": self.sequence, "parameters": parameters })
+    }
+
+    fn from_value(content: &Value) -> Result<Self, EconfError> {
+        let mut file_content = FileContent {
+            sequence: content["sequence"].as_u64().unwrap_or(0),
+            values: BTreeMap::new(),
+        };
+        let Some(parameters) = content["parameters"].as_object() else {
+            return Err(EconfError::Schema("The file has no parameters".to_owned()));
+        };
+        for (name, entry) in parameters {
+            // The parameters removed from the schema are dropped, like the unknown rows of the database
+            let Some(id) = ParameterId::from_name(name) else {
+                debug!("Unknown parameter {} skipped", name);
+                continue;
+            };
+            let value = value_from_json(id, &entry["value"])
+                .map_err(|e| EconfError::Schema(format!("Value of {}: {}", name, e)))?;
+            file_content.values.insert(id.index(), StoredEntry {
+                value,
+                timestamp: entry["timestamp"].as_f64().unwrap_or(0.0),
+                source: entry["source"].as_str().map(str::to_owned),
+                sequence: entry["sequence"].as_u64().unwrap_or(0),
+            });
+        }
+        Ok(file_content)
+    }
+}
+
+impl Drop for FileContent {
+    fn drop(&mut self) {
+        for (index, entry) in std::mem::take(&mut self.values) {
+            if let Some(id) = ParameterId::from_index(index) {
+                sensitive::discard(id, entry.value);
+            }
+        }
+    }
+}
+
+/// Parameter store keeping the values in a JSON (or CBOR) file, for the platforms where SQLite is
+/// not available. The whole file is rewritten on every change through a temporary file, so it is
+/// never left half written. The changes of the other processes are found by the modification time
+/// of the file, the writes of several processes at the same time can overwrite each other.
+///
+/// The values are written immediately whatever the persist policy. The blob streams, the profiles and
+/// the backups are not supported, and the secret parameters are refused as the file is not encrypted
+pub struct FileStore {
+    /// None keeps the values in memory
+    path: Option<PathBuf>,
+    saved_path: Option<PathBuf>,
+    default_data_folder: String,
+    format: FileFormat,
+    content: FileContent,
+    /// Modification time and size of the file when it was last read or written
+    file_state: Option<(SystemTime, u64)>,
+    /// Changes of the other processes found when the file was reread, returned by the next update
+    changes: Vec<(ParameterChangeEvent, Option<ParameterValue>)>,
+    saved_sequence: Option<u64>,
+}
+
+fn file_state(path: &Path) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+fn read_file(path: &Path, format: FileFormat) -> Result<FileContent, EconfError> {
+    let bytes = Zeroizing::new(fs::read(path)?);
+    FileContent::from_value(&format.decode(&bytes)?)
+}
+
+/// Replace the file through a temporary one, a failed write keeps the previous content
+fn write_file(path: &Path, format: FileFormat, content: &FileContent) -> Result<(), EconfError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(format!(".tmp.{}", std::process::id()));
+    let temporary_path = PathBuf::from(temporary_path);
+    let encoded = format.encode(&content.to_value())?;
+    let result = fs::write(&temporary_path, encoded.as_slice()).and_then(|_| fs::rename(&temporary_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    Ok(result?)
+}
+
+impl FileStore {
+    /// Store in the file `path` saved to `saved_path`, the format is chosen by the extension of each file.
+    /// The path ":memory:" keeps the values in memory. Environment variables in the paths are expanded
+    pub fn new(path: &str, saved_path: &str, default_data_folder: &str) -> Result<Self, EconfError> {
+        if let Some(parameter) = PARAMETER_DATA.iter().find(|parameter| parameter.secret) {
+            return Err(EconfError::Config(format!(
+                "Parameter {} is secret, the file store can't encrypt it", parameter.name_id
+            )));
+        }
+        let path = expand_path(path)?;
+        let saved_path = expand_path(saved_path)?;
+        let format = FileFormat::from_path(&path);
+        let to_path = |path: String| (path != IN_MEMORY_DATABASE_PATH && !path.is_empty()).then(|| PathBuf::from(path));
+        let mut store = FileStore {
+            path: to_path(path),
+            saved_path: to_path(saved_path),
+            default_data_folder: expand_path(default_data_folder)?,
+            format,
+            content: FileContent::default(),
+            file_state: None,
+            changes: Vec::new(),
+            saved_sequence: None,
+        };
+        if store.path.is_some() && store.path == store.saved_path {
+            return Err(EconfError::Config("File and saved file paths must be different".to_owned()));
+        }
+        store.refresh()?;
+        // Changes made before the start are not reported
+        store.changes.clear();
+        info!("File store opened: {:?}", store.path);
+        Ok(store)
+    }
+
+    fn require_saved_path(&self) -> Result<&Path, EconfError> {
+        self.saved_path.as_deref().ok_or_else(|| EconfError::Config("The saved file path is not set".to_owned()))
+    }
+
+    fn saved_format(&self) -> Result<FileFormat, EconfError> {
+        let path = self.require_saved_path()?;
+        Ok(FileFormat::from_path(&path.to_string_lossy()))
+    }
+
+    /// Reread the file if another process changed it and remember the changes for the update
+    fn refresh(&mut self) -> Result<(), EconfError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let state = file_state(path);
+        if state == self.file_state {
+            return Ok(());
+        }
+        let content = match state {
+            Some(_) => read_file(path, self.format)?,
+            None => FileContent { sequence: self.content.sequence, values: BTreeMap::new() },
+        };
+        for (index, entry) in &content.values {
+            let changed = self.content.values.get(index).is_none_or(|previous| previous.sequence != entry.sequence);
+            if let (true, Some(id)) = (changed, ParameterId::from_index(*index)) {
+                let event = ParameterChangeEvent {
+                    id,
+                    source: entry.source.clone(),
+                    timestamp: entry.timestamp,
+                    sequence: entry.sequence,
+                };
+                self.changes.push((event, Some(entry.value.clone())));
+            }
+        }
+        for index in self.content.values.keys().filter(|index| !content.values.contains_key(index)) {
+            if let Some(id) = ParameterId::from_index(*index) {
+                let event = ParameterChangeEvent {
+                    id,
+                    source: None,
+                    timestamp: DatabaseManager::get_timestamp(),
+                    sequence: content.sequence,
+                };
+                self.changes.push((event, None));
+            }
+        }
+        let sequence = self.content.sequence.max(content.sequence);
+        self.content = content;
+        self.content.sequence = sequence;
+        self.file_state = state;
+        Ok(())
+    }
+
+    /// Write the values to the file, the changes are undone if it fails
+    fn persist(&mut self, undo: FileContent) -> Result<(), EconfError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Err(e) = write_file(path, self.format, &self.content) {
+            error!("Could not write {}: {}", path.display(), e);
+            self.content = undo;
+            return Err(e);
+        }
+        self.file_state = file_state(path);
+        Ok(())
+    }
+
+    /// Copy of the content to restore when the file can't be written
+    fn snapshot(&self) -> FileContent {
+        FileContent {
+            sequence: self.content.sequence,
+            values: self.content.values.iter()
+                .map(|(index, entry)| (*index, StoredEntry {
+                    value: entry.value.clone(),
+                    timestamp: entry.timestamp,
+                    source: entry.source.clone(),
+                    sequence: entry.sequence,
+                }))
+                .collect(),
+        }
+    }
+
+    /// The condition of a write holds, true without the condition
+    fn condition_holds(&mut self, id: ParameterId, condition: Option<&WriteCondition>) -> Result<bool, EconfError> {
+        Ok(match condition {
+            Some(WriteCondition::Sequence(expected)) => self.parameter_sequence(id)? == *expected,
+            Some(WriteCondition::Value(expected)) => {
+                let current = self.read(id)?;
+                let equal = current == *expected;
+                sensitive::discard(id, current);
+                equal
+            }
+            None => true,
+        })
+    }
+
+    /// Store the values with one sequence number each, returns the sequence numbers
+    fn store_values(&mut self, values: Vec<(ParameterId, ParameterValue)>, source: Option<&str>) -> Result<Vec<u64>, EconfError> {
+        let undo = self.snapshot();
+        let timestamp = DatabaseManager::get_timestamp();
+        let mut sequences = Vec::with_capacity(values.len());
+        for (id, value) in values {
+            self.content.sequence += 1;
+            let entry = StoredEntry { value, timestamp, source: source.map(str::to_owned), sequence: self.content.sequence };
+            if let Some(previous) = self.content.values.insert(id.index(), entry) {
+                sensitive::discard(id, previous.value);
+            }
+            sequences.push(self.content.sequence);
+        }
+        self.persist(undo)?;
+        Ok(sequences)
+    }
+
+    fn read_saved_file(&self) -> Result<FileContent, EconfError> {
+        let path = self.require_saved_path()?;
+        if !path.is_file() {
+            // Nothing saved yet
+            return Ok(FileContent::default());
+        }
+        read_file(path, self.saved_format()?)
+    }
+}
+
+impl ParameterStore for FileStore {
+    fn read(&mut self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        self.refresh()?;
+        match self.content.values.get(&id.index()) {
+            Some(entry) => Ok(entry.value.clone()),
+            None => self.default_value(&PARAMETER_DATA[id.index()]),
+        }
+    }
+
+    fn write(
+        &mut self,
+        id: ParameterId,
+        value: ParameterValue,
+        force: bool,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<(Status<ParameterValue>, Option<u64>), EconfError> {
+        self.refresh()?;
+        if !self.condition_holds(id, condition)? {
+            debug!("Write condition of {} doesn't hold", id as usize);
+            return Ok((Status::StatusErrorConflict(value), None));
+        }
+        if !force && condition.is_none() {
+            let current = self.read(id)?;
+            let equal = current == value;
+            sensitive::discard(id, current);
+            if equal {
+                debug!("Values are equal, skip writing");
+                return Ok((Status::StatusOkNotChanged(value), None));
+            }
+        }
+
+        let status = validate_value(id, Status::StatusOkChanged(value))?;
+        let value = match &status {
+            Status::StatusErrorNotAccepted(_) | Status::StatusErrorFailed => return Ok((status, None)),
+            status => status.clone().unwrap(),
+        };
+        debug!("Write to file: {} {}", id as usize, sensitive::log_value(id, &value));
+        let sequence = self.store_values(vec![(id, value)], source)?.pop();
+        Ok((status, sequence))
+    }
+
+    fn write_all(
+        &mut self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<WrittenValue>, EconfError> {
+        self.refresh()?;
+        if let Some((id, _)) = values.first() {
+            let id = *id;
+            if !self.condition_holds(id, condition)? {
+                debug!("Write condition of {} doesn't hold", id as usize);
+                for (id, value) in values {
+                    sensitive::discard(id, value);
+                }
+                return Err(EconfError::Conflict(id));
+            }
+        }
+        let mut statuses: Vec<(ParameterId, Status<ParameterValue>)> = Vec::with_capacity(values.len());
+        for (index, (id, value)) in values.into_iter().enumerate() {
+            let current = self.read(id)?;
+            // The value with the condition is written even if it is the same
+            let equal = current == value && !(index == 0 && condition.is_some());
+            sensitive::discard(id, current);
+            if equal {
+                statuses.push((id, Status::StatusOkNotChanged(value)));
+                continue;
+            }
+            let rejected = match validate_value(id, Status::StatusOkChanged(value))? {
+                Status::StatusErrorNotAccepted(value) => {
+                    let reason = format!("value {} not accepted", sensitive::log_value(id, &value));
+                    sensitive::discard(id, value);
+                    EconfError::Validation { id, reason }
+                }
+                Status::StatusErrorFailed => EconfError::Validation { id, reason: "validation failed".to_owned() },
+                status => {
+                    statuses.push((id, status));
+                    continue;
+                }
+            };
+            for (id, status) in statuses {
+                sensitive::discard(id, status.unwrap());
+            }
+            return Err(rejected);
+        }
+
+        let changed: Vec<(ParameterId, ParameterValue)> = statuses.iter()
+            .filter(|(_, status)| !matches!(status, Status::StatusOkNotChanged(_)))
+            .map(|(id, status)| (*id, status.clone().unwrap()))
+            .collect();
+        let mut sequences = self.store_values(changed, source)?.into_iter();
+        Ok(statuses.into_iter()
+            .map(|(id, status)| match status {
+                Status::StatusOkNotChanged(_) => (id, status, None),
+                _ => (id, status, sequences.next()),
+            })
+            .collect())
+    }
+
+    fn reset(&mut self, ids: &[ParameterId]) -> Result<u64, EconfError> {
+        self.refresh()?;
+        let undo = self.snapshot();
+        for id in ids {
+            if let Some(entry) = self.content.values.remove(&id.index()) {
+                sensitive::discard(*id, entry.value);
+            }
+        }
+        self.content.sequence += 1;
+        self.persist(undo)?;
+        Ok(self.content.sequence)
+    }
+
+    fn update(&mut self) -> Result<Vec<(ParameterChangeEvent, Option<ParameterValue>)>, EconfError> {
+        self.refresh()?;
+        Ok(std::mem::take(&mut self.changes))
+    }
+
+    fn save(&mut self, filter: &dyn Fn(&String) -> bool, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        let path = self.require_saved_path()?.to_owned();
+        info!("Saving {}", path.display());
+        self.refresh()?;
+        let total = self.content.values.len() as u64;
+        let mut saved = FileContent { sequence: self.content.sequence, values: BTreeMap::new() };
+        for (processed, (index, entry)) in self.content.values.iter().enumerate() {
+            if filter(&PARAMETER_DATA[*index].name_id.to_owned()) {
+                saved.values.insert(*index, StoredEntry {
+                    value: entry.value.clone(),
+                    timestamp: entry.timestamp,
+                    source: entry.source.clone(),
+                    sequence: entry.sequence,
+                });
+            }
+            progress(processed as u64 + 1, total);
+        }
+        write_file(&path, self.saved_format()?, &saved)?;
+        self.saved_sequence = Some(self.content.sequence);
+        Ok(())
+    }
+
+    fn save_selected(&mut self, filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        let path = self.require_saved_path()?.to_owned();
+        info!("Saving the selected parameters to {}", path.display());
+        self.refresh()?;
+        let mut saved = self.read_saved_file()?;
+        for (index, parameter) in PARAMETER_DATA.iter().enumerate() {
+            if !filter(&parameter.name_id.to_owned()) {
+                continue;
+            }
+            let entry = self.content.values.get(&index).map(|entry| StoredEntry {
+                value: entry.value.clone(),
+                timestamp: entry.timestamp,
+                source: entry.source.clone(),
+                sequence: entry.sequence,
+            });
+            let previous = match entry {
+                Some(entry) => saved.values.insert(index, entry),
+                None => saved.values.remove(&index),
+            };
+            if let (Some(previous), Some(id)) = (previous, ParameterId::from_index(index)) {
+                sensitive::discard(id, previous.value);
+            }
+        }
+        saved.sequence = saved.sequence.max(self.content.sequence);
+        write_file(&path, self.saved_format()?, &saved)
+    }
+
+    fn load(&mut self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        let path = self.require_saved_path()?;
+        if !path.is_file() {
+            return Err(EconfError::NotFound(format!("Saved file {}", path.display())));
+        }
+        info!("Loading {}", path.display());
+        let mut loaded = self.read_saved_file()?;
+        self.refresh()?;
+        let undo = self.snapshot();
+        // The loaded values are new writes for the conditional writes and the other processes
+        let sequence = self.content.sequence + 1;
+        let timestamp = DatabaseManager::get_timestamp();
+        let total = loaded.values.len() as u64;
+        for (processed, entry) in loaded.values.values_mut().enumerate() {
+            entry.sequence = sequence;
+            entry.timestamp = timestamp;
+            progress(processed as u64 + 1, total);
+        }
+        self.content.values = std::mem::take(&mut loaded.values);
+        self.content.sequence = sequence;
+        self.persist(undo)
+    }
+
+    fn drop_values(&mut self) -> Result<(), EconfError> {
+        info!("Deleting the stored values");
+        self.reset(&(0..PARAMETER_DATA.len()).filter_map(ParameterId::from_index).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    fn read_saved(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        let saved = self.read_saved_file()?;
+        ids.iter()
+            .map(|id| match saved.values.get(&id.index()) {
+                Some(entry) => Ok(entry.value.clone()),
+                None => self.default_value(&PARAMETER_DATA[id.index()]),
+            })
+            .collect()
+    }
+
+    fn current_sequence(&mut self) -> Result<u64, EconfError> {
+        self.refresh()?;
+        Ok(self.content.sequence)
+    }
+
+    fn parameter_sequence(&mut self, id: ParameterId) -> Result<u64, EconfError> {
+        self.refresh()?;
+        Ok(self.content.values.get(&id.index()).map_or(0, |entry| entry.sequence))
+    }
+
+    fn allocate_sequence(&mut self) -> Result<u64, EconfError> {
+        self.refresh()?;
+        let undo = self.snapshot();
+        self.content.sequence += 1;
+        self.persist(undo)?;
+        Ok(self.content.sequence)
+    }
+
+    fn default_value(&self, parameter: &Parameter) -> Result<ParameterValue, EconfError> {
+        Ok(read_default(&self.default_data_folder, parameter))
+    }
+
+    fn has_unsaved_changes(&mut self) -> Result<bool, EconfError> {
+        let sequence = self.current_sequence()?;
+        Ok(self.saved_sequence != Some(sequence))
+    }
+}
@@ -1,42 +1,377 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::mem;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::{Result, anyhow};
 use base64::prelude::*;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
+use prost_reflect::{DescriptorPool, DynamicMessage};
 use serde_json::Value;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::config::Config;
-use crate::database_utils::{DatabaseManager, Status};
+use crate::config::{expand_path, Config};
+use crate::constants::{AUTOSAVE_JITTER_PERCENT, AUTOSAVE_SETTLE_TIME, COALESCE_WINDOW, PERSIST_MIN_CHECK_PERIOD, DESCRIPTORS_BIN, EMBED_VALUES, IN_MEMORY_DATABASE_PATH, MULTICAST_GROUP, MULTICAST_PORT, MULTICAST_TTL, WAL_SIZE_LIMIT};
+use crate::database_utils::{check_blob_size, check_text, DatabaseManager, Status};
+use crate::error::{EconfError, ValidationError};
 use crate::event_receiver::EventReceiver;
 use crate::generated;
+use crate::lock_monitor::{LockDiagnostics, MonitoredMutex};
+use crate::manifest;
 use crate::notifier::Notifier;
-use crate::schema::{ParameterValue, ParameterValueType};
+use crate::nvs;
+use crate::schema::{non_finite_name, AccessLevel, Parameter, ParameterValue, ParameterValueType, PersistPolicy, ValidationMethod};
+use crate::sensitive;
+use crate::store::ParameterStore;
+use crate::transport::{self, TransportConfig};
 
 use generated::{GROUPS_DATA, PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
 
+#[cfg(feature = "async")]
+pub use crate::async_interface::AsyncInterfaceInstance;
+
+/// A parameter change delivered to the change callbacks
+#[derive(Clone, Debug)]
+pub struct ParameterChangeEvent {
+    pub id: ParameterId,
+    /// Origin of the write (e.g. "ffi", "rest", "ws-client-42"), None if unknown
+    pub source: Option<String>,
+    /// UNIX timestamp of the write in seconds
+    pub timestamp: f64,
+    /// Global write sequence shared by all the processes using the database, 0 if unknown.
+    /// Events older than the last delivered one for the same parameter are discarded
+    pub sequence: u64,
+}
+
 pub type ParameterUpdateCallback = Arc<dyn Fn(ParameterId) + Send + Sync + 'static>;
+pub type ParameterChangeCallback = Arc<dyn Fn(&ParameterChangeEvent) + Send + Sync + 'static>;
+/// Computes the value of a computed parameter from the values of its dependencies,
+/// in the order of [`InterfaceInstance::set_compute`]
+pub type ComputeFunction = Arc<dyn Fn(&[ParameterValue]) -> ParameterValue + Send + Sync + 'static>;
+/// Receives the values of all the parameters of a watch, in the order of [`InterfaceInstance::add_watch`]
+pub type WatchCallback = Arc<dyn Fn(&[ParameterValue]) + Send + Sync + 'static>;
+/// Called before a write with the current and the new value, returns the value to write or the reason
+/// to refuse the change, see [`InterfaceInstance::add_pre_change_hook`]
+pub type PreChangeHook = Arc<dyn Fn(&ParameterValue, &ParameterValue) -> Result<ParameterValue, String> + Send + Sync + 'static>;
+/// Checks the values of the parameters of a rule, in the order of [`InterfaceInstance::add_rule`]
+pub type RuleFunction = Arc<dyn Fn(&[ParameterValue]) -> RuleAction + Send + Sync + 'static>;
+/// Identifies a callback added with [`InterfaceInstance::add_callback_ex`], [`InterfaceInstance::add_watch`],
+/// [`InterfaceInstance::add_pre_change_hook`] or a rule added with [`InterfaceInstance::add_rule`], never 0
+pub type CallbackHandle = u64;
+
+fn next_callback_handle() -> CallbackHandle {
+    static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+    NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Whether the file default of a parameter can be read, see [`InterfaceInstance::defaults_health`]
+#[derive(Clone, Debug)]
+pub struct DefaultFileStatus {
+    pub id: ParameterId,
+    /// File in the default data folder
+    pub path: String,
+    /// Why the file can't be read, None if it is readable. The parameters with unreadable
+    /// defaults get an empty blob as the default
+    pub error: Option<String>,
+}
+
+/// What was done with the database at the start, see [`InterfaceInstance::get_storage_health`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StorageRecovery {
+    /// The database passed the integrity check or was created
+    #[default]
+    None,
+    /// The database was corrupted and replaced by the saved database
+    RestoredSaved,
+    /// The database and the saved database couldn't be used, the parameters start with the defaults
+    FactoryDefaults,
+}
+
+/// Integrity of the database, see [`InterfaceInstance::get_storage_health`]
+#[derive(Clone, Debug, Default)]
+pub struct StorageHealth {
+    pub recovery: StorageRecovery,
+    /// Problems reported by the last integrity check, empty if it passed
+    pub problems: Vec<String>,
+    /// Where the corrupted database was moved, None if it was not corrupted
+    pub corrupted_path: Option<String>,
+}
+
+/// Result of one check of [`InterfaceInstance::check_liveness`] and [`InterfaceInstance::check_readiness`]
+#[derive(Clone, Debug)]
+pub struct HealthCheck {
+    pub name: &'static str,
+    pub healthy: bool,
+    /// What was found, the error if the check failed
+    pub detail: String,
+}
+
+/// Condition of [`InterfaceInstance::set_if`], checked in the write transaction so that
+/// the other processes can't change the parameter in between
+#[derive(Clone, Debug, PartialEq)]
+pub enum WriteCondition {
+    /// The parameter still has this value
+    Value(ParameterValue),
+    /// The parameter was not written since the write with this sequence number,
+    /// see [`InterfaceInstance::get_sequence`]. 0 if it was never written
+    Sequence(u64),
+}
+
+/// Decision of a rule on a write, see [`InterfaceInstance::add_rule`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleAction {
+    /// The values satisfy the rule
+    Accept,
+    /// Refuse the write with the reason
+    Reject(String),
+    /// Change the values of some parameters of the rule so they satisfy it, the changes
+    /// of the other parameters are written together with the parameter being set
+    Adjust(Vec<(ParameterId, ParameterValue)>),
+}
+
+/// Result of a write, see [`InterfaceInstance::set`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetOutcome {
+    /// Value after the validation, the one returned by the following reads
+    pub value: ParameterValue,
+    /// False if the value was the same as the current one and nothing was written
+    pub changed: bool,
+    /// The value was out of the range of the parameter and was clamped to it
+    pub clamped: bool,
+}
+
+/// Parameter with different values in the working and the saved database, see [`InterfaceInstance::diff_saved`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParameterDiff {
+    pub id: ParameterId,
+    /// Value in the working database, the one written by save
+    pub current: ParameterValue,
+    /// Value in the saved database, the one restored by load
+    pub saved: ParameterValue,
+}
+
+/// Selection of the parameters iterated by [`InterfaceInstance::iter_parameters`], the fields left
+/// empty match all the parameters
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParameterFilter {
+    /// Group of the parameter, the part of the name before '@'
+    pub group: Option<String>,
+    /// Tags the parameter must all have
+    pub tags: Vec<String>,
+    pub runtime: Option<bool>,
+    pub readonly: Option<bool>,
+    pub internal: Option<bool>,
+    pub hidden: Option<bool>,
+}
+
+impl ParameterFilter {
+    pub fn matches(&self, parameter: &Parameter) -> bool {
+        let flag_matches = |expected: Option<bool>, actual: bool| expected.is_none_or(|expected| expected == actual);
+        self.group.as_deref().is_none_or(|group| parameter.name_id.split('@').next() == Some(group))
+            && self.tags.iter().all(|tag| parameter.tags.contains(&tag.as_str()))
+            && flag_matches(self.runtime, parameter.runtime)
+            && flag_matches(self.readonly, parameter.readonly)
+            && flag_matches(self.internal, parameter.internal)
+            && flag_matches(self.hidden, parameter.hidden)
+    }
+}
+
+/// How long `get()` can return a cached value before re-reading the database
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Cached values are kept until a change notification or an update poll invalidates them
+    #[default]
+    Forever,
+    /// Cached values older than the duration are re-read from the database.
+    /// Useful when multicast is blocked and polling is the only sync mechanism
+    Ttl(Duration),
+    /// Every read goes to the database
+    None,
+}
+
+impl CachePolicy {
+    fn is_fresh(&self, cached_at: Option<Instant>) -> bool {
+        match self {
+            CachePolicy::Forever => true,
+            CachePolicy::Ttl(ttl) => cached_at.is_some_and(|cached_at| cached_at.elapsed() < *ttl),
+            CachePolicy::None => false,
+        }
+    }
+}
+
+/// Chunked read of a blob parameter, opened by [`InterfaceInstance::blob_open_read`].
+/// The blob is copied inside the database, so the reads see the value at the time of the opening
+#[derive(Debug)]
+pub struct BlobReader {
+    id: ParameterId,
+    row: i64,
+    size: usize,
+    position: usize,
+}
+
+impl BlobReader {
+    pub fn id(&self) -> ParameterId {
+        self.id
+    }
+
+    /// Size of the blob in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Move to the position of the next read, clamped to the size
+    pub fn seek(&mut self, position: usize) {
+        self.position = position.min(self.size);
+    }
+}
+
+/// Chunked write of a blob parameter, opened by [`InterfaceInstance::blob_open_write`].
+/// The parameter is changed by [`InterfaceInstance::blob_close_write`] when all the bytes are written
+#[derive(Debug)]
+pub struct BlobWriter {
+    id: ParameterId,
+    row: i64,
+    size: usize,
+    position: usize,
+}
+
+impl BlobWriter {
+    pub fn id(&self) -> ParameterId {
+        self.id
+    }
+
+    /// Number of bytes left to write
+    pub fn remaining(&self) -> usize {
+        self.size - self.position
+    }
+}
+
+fn get_descriptor_pool() -> Result<&'static DescriptorPool> {
+    static POOL: OnceLock<DescriptorPool> = OnceLock::new();
+    if let Some(pool) = POOL.get() {
+        return Ok(pool);
+    }
+    let pool = DescriptorPool::decode(DESCRIPTORS_BIN)?;
+    Ok(POOL.get_or_init(|| pool))
+}
 
 #[derive(Default)]
 pub(crate) struct RuntimeParametersData {
     pub(crate) value: Option<ParameterValue>,
-    pub(crate) callback: Option<ParameterUpdateCallback>,
+    /// When the value was cached, used by the TTL cache policy
+    pub(crate) cached_at: Option<Instant>,
+    pub(crate) callback: Option<ParameterChangeCallback>,
+    /// Callbacks added with a handle, any number per parameter
+    pub(crate) subscribers: Vec<(CallbackHandle, ParameterChangeCallback)>,
+    /// Sequence of the last change event delivered for the parameter
+    pub(crate) sequence: u64,
+    /// Set for the computed parameters by `set_compute`
+    pub(crate) compute: Option<Computed>,
+    pub(crate) pre_change_hooks: Vec<(CallbackHandle, PreChangeHook)>,
+}
+
+/// Function of a computed parameter, see [`InterfaceInstance::set_compute`]
+#[derive(Clone)]
+pub(crate) struct Computed {
+    pub(crate) dependencies: Vec<ParameterId>,
+    pub(crate) function: ComputeFunction,
+}
+
+/// Callback of several parameters, see [`InterfaceInstance::add_watch`]
+#[derive(Clone)]
+pub(crate) struct Watch {
+    pub(crate) handle: CallbackHandle,
+    pub(crate) ids: Vec<ParameterId>,
+    pub(crate) callback: WatchCallback,
+}
+
+/// Constraint between parameters, see [`InterfaceInstance::add_rule`]
+#[derive(Clone)]
+pub(crate) struct Rule {
+    pub(crate) handle: CallbackHandle,
+    pub(crate) name: String,
+    pub(crate) ids: Vec<ParameterId>,
+    pub(crate) function: RuleFunction,
 }
 
 pub(crate) struct SharedRuntimeData {
     pub(crate) parameters_data: [RuntimeParametersData; PARAMETERS_NUM],
+    pub(crate) watches: Vec<Watch>,
+    pub(crate) rules: Vec<Rule>,
+    /// Changes waiting for `apply_staged`
+    pub(crate) staged: Vec<(ParameterId, ParameterValue)>,
 }
 
 impl SharedRuntimeData {
-    pub(crate) fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub(crate) fn new() -> Result<Self, EconfError> {
         let parameters_data = std::array::from_fn(|_| RuntimeParametersData {
             value: None,
+            cached_at: None,
             callback: None,
+            subscribers: Vec::new(),
+            sequence: 0,
+            compute: None,
+            pre_change_hooks: Vec::new(),
         });
-        Ok(Self { parameters_data })
+        Ok(Self { parameters_data, watches: Vec::new(), rules: Vec::new(), staged: Vec::new() })
+    }
+}
+
+impl SharedRuntimeData {
+    /// Drop the cached values so the next read goes to the database
+    pub(crate) fn invalidate(&mut self, ids: &[ParameterId]) {
+        for id in ids {
+            self.clear(id.index());
+        }
+    }
+
+    pub(crate) fn store(&mut self, id: ParameterId, value: ParameterValue) {
+        self.clear(id.index());
+        let data = &mut self.parameters_data[id.index()];
+        data.value = Some(value);
+        data.cached_at = Some(Instant::now());
+    }
+
+    /// Wipe and drop the cached values of the sensitive parameters, returns the number of purged values
+    pub(crate) fn purge_sensitive(&mut self) -> usize {
+        (0..PARAMETERS_NUM)
+            .filter(|index| sensitive::is_sensitive_index(*index))
+            .filter(|index| self.clear(*index))
+            .count()
+    }
+
+    pub(crate) fn is_subscribed(&self, index: usize, handle: CallbackHandle) -> bool {
+        self.parameters_data[index].subscribers.iter().any(|(subscriber, _)| *subscriber == handle)
+    }
+
+    pub(crate) fn is_watching(&self, handle: CallbackHandle) -> bool {
+        self.watches.iter().any(|watch| watch.handle == handle)
+    }
+
+    fn clear(&mut self, index: usize) -> bool {
+        match self.parameters_data[index].value.take() {
+            Some(mut value) => {
+                if sensitive::is_sensitive_index(index) {
+                    value.zeroize();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for SharedRuntimeData {
+    fn drop(&mut self) {
+        self.purge_sensitive();
+        for (id, value) in self.staged.drain(..) {
+            sensitive::discard(id, value);
+        }
     }
 }
 
@@ -44,35 +379,447 @@ impl Default for SharedRuntimeData {
     fn default() -> Self {
         Self {
             parameters_data: std::array::from_fn(|_| RuntimeParametersData::default()),
+            watches: Vec::new(),
+            rules: Vec::new(),
+            staged: Vec::new(),
         }
     }
 }
 
+/// Access to the parameters kept in the store `S`, the SQLite database by default
 #[derive(Default)]
-pub struct InterfaceInstance {
-    database: Arc<Mutex<DatabaseManager>>,
-    notifier: Notifier,
-    runtime_data: Arc<Mutex<SharedRuntimeData>>,
-    event_receiver: Arc<Mutex<EventReceiver>>,
+pub struct InterfaceInstance<S: ParameterStore = DatabaseManager> {
+    database: Arc<MonitoredMutex<S>>,
+    /// None for the read-only instances
+    notifier: Option<Notifier>,
+    runtime_data: Arc<MonitoredMutex<SharedRuntimeData>>,
+    event_receiver: Arc<MonitoredMutex<EventReceiver<S>>>,
     timer_thread: Option<thread::JoinHandle<()>>,
     stop_flag: Arc<AtomicBool>,
+    autosave_thread: Option<thread::JoinHandle<()>>,
+    autosave_stop_flag: Arc<AtomicBool>,
+    /// Writes the debounced parameters when they are due
+    persist_thread: Option<thread::JoinHandle<()>>,
+    persist_stop_flag: Arc<AtomicBool>,
+    cache_policy: CachePolicy,
+}
+
+/// Value of the enum constant with the name, as listed in the allowed values of the parameter. The case is ignored
+fn enum_value_by_name(id: ParameterId, name: &str) -> Option<i32> {
+    match &PARAMETER_DATA[id.index()].validation {
+        ValidationMethod::AllowedValues { values, names } => values
+            .iter()
+            .zip(names.iter())
+            .find(|(_, allowed)| allowed.eq_ignore_ascii_case(name.trim()))
+            .and_then(|(value, _)| match value {
+                ParameterValue::ValEnum(value) => Some(*value),
+                _ => None,
+            }),
+        _ => None,
+    }
+}
+
+/// Convert the JSON of a value, as serialized from `ParameterValue`, to the type of the parameter.
+/// The enums can also be given by the names of the values
+pub(crate) fn value_from_json(id: ParameterId, value: &Value) -> Result<ParameterValue> {
+    let param_type = &PARAMETER_DATA[id.index()].value_type;
+
+    let converted_value = match param_type {
+        ParameterValueType::TypeBool => value
+                    .as_bool()
+                    .map(ParameterValue::ValBool)
+                    .ok_or_else(|| anyhow!("Expected a boolean"))?,
+        ParameterValueType::TypeI32 => value
+                    .as_i64()
+                    .map(|v| ParameterValue::ValI32(v as i32))
+                    .ok_or_else(|| anyhow!("Expected an integer"))?,
+        ParameterValueType::TypeU32 => value
+                    .as_u64()
+                    .map(|v| ParameterValue::ValU32(v as u32))
+                    .ok_or_else(|| anyhow!("Expected an unsigned integer"))?,
+        ParameterValueType::TypeI64 => value
+                    .as_i64()
+                    .map(ParameterValue::ValI64)
+                    .ok_or_else(|| anyhow!("Expected an integer"))?,
+        ParameterValueType::TypeU64 => value
+                    .as_u64()
+                    .map(ParameterValue::ValU64)
+                    .ok_or_else(|| anyhow!("Expected an unsigned integer"))?,
+        ParameterValueType::TypeF32 => value
+                    .as_f64()
+                    .or_else(|| value.as_str().and_then(|v| v.parse().ok()))
+                    .map(|v| ParameterValue::ValF32(v as f32))
+                    .ok_or_else(|| anyhow!("Expected a float"))?,
+        ParameterValueType::TypeF64 => value
+                    .as_f64()
+                    .or_else(|| value.as_str().and_then(|v| v.parse().ok()))
+                    .map(ParameterValue::ValF64)
+                    .ok_or_else(|| anyhow!("Expected a float"))?,
+        ParameterValueType::TypeString => value
+                    .as_str()
+                    .map(|v| ParameterValue::ValString(v.to_string().into()))
+                    .ok_or_else(|| anyhow!("Expected a string"))?,
+        ParameterValueType::TypeBlob => {
+                    let base64_str = value
+                        .as_str()
+                        .ok_or_else(|| anyhow!("Expected a base64 string"))?;
+                    let decoded = BASE64_STANDARD.decode(base64_str)?;
+                    ParameterValue::ValBlob(decoded)
+                }
+        ParameterValueType::TypeEnum(enum_name) => match value.as_str() {
+                    Some(name) => enum_value_by_name(id, name)
+                        .map(ParameterValue::ValEnum)
+                        .ok_or_else(|| anyhow!("{} is not a value of {}", name, enum_name))?,
+                    None => value
+                        .as_i64()
+                        .map(|v| ParameterValue::ValEnum(v as i32))
+                        .ok_or_else(|| anyhow!("Expected an integer or a name of {}", enum_name))?,
+                },
+        ParameterValueType::TypeNone => ParameterValue::ValNone,
+    };
+
+    Ok(converted_value)
+}
+
+/// Park the thread for the duration, returns false if stopped earlier
+fn park_unless_stopped(stop_flag: &AtomicBool, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while !stop_flag.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::park_timeout(deadline - now);
+    }
+    false
+}
+
+/// The interval changed randomly by up to AUTOSAVE_JITTER_PERCENT
+fn jittered(interval: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    let range = interval.as_millis() as u64 * AUTOSAVE_JITTER_PERCENT / 100;
+    if range == 0 {
+        return interval;
+    }
+    let offset = Duration::from_millis(random % (2 * range + 1));
+    (interval + offset).saturating_sub(Duration::from_millis(range))
+}
+
+/// Builder for [`InterfaceInstance`], the fields not set keep their defaults
+pub struct InterfaceInstanceBuilder {
+    database_path: Option<String>,
+    saved_database_path: Option<String>,
+    in_memory: bool,
+    read_only: bool,
+    default_data_folder: String,
+    multicast_group: Ipv4Addr,
+    multicast_port: u16,
+    multicast_ttl: u32,
+    coalesce_window: Duration,
+    transport: TransportConfig,
+    embed_values: bool,
+    cache_policy: CachePolicy,
+    blob_files: Option<(String, usize)>,
+    strict_defaults: bool,
+    backup_retention: usize,
+    maintenance_writes: u64,
+    encryption_key: Option<Zeroizing<String>>,
+    secret_key: Option<Zeroizing<[u8; 32]>>,
+}
+
+impl Default for InterfaceInstanceBuilder {
+    fn default() -> Self {
+        Self {
+            database_path: None,
+            saved_database_path: None,
+            in_memory: false,
+            read_only: false,
+            default_data_folder: ".".to_owned(),
+            multicast_group: MULTICAST_GROUP,
+            multicast_port: MULTICAST_PORT,
+            multicast_ttl: MULTICAST_TTL,
+            coalesce_window: COALESCE_WINDOW,
+            transport: TransportConfig::default(),
+            embed_values: EMBED_VALUES,
+            cache_policy: CachePolicy::default(),
+            blob_files: None,
+            strict_defaults: false,
+            backup_retention: 0,
+            maintenance_writes: 0,
+            encryption_key: None,
+            secret_key: None,
+        }
+    }
+}
+
+impl InterfaceInstanceBuilder {
+    /// Path of the working database (required). Environment variables are expanded
+    pub fn database_path(mut self, path: impl Into<String>) -> Self {
+        self.database_path = Some(path.into());
+        self
+    }
+
+    /// Path of the database used by save and load (required). Environment variables are expanded
+    pub fn saved_database_path(mut self, path: impl Into<String>) -> Self {
+        self.saved_database_path = Some(path.into());
+        self
+    }
+
+    /// Keep the working database in memory instead of a file, for the tests and simulations (same as
+    /// the database path ":memory:"). Nothing is shared with the other processes and the values are lost
+    /// when the instance is dropped. The saved database path is optional then, without it the save,
+    /// load and profile calls fail with `EconfError::Config`
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// Open the existing working database read-only, for the monitoring and diagnostic tools. The changes
+    /// (set, save, load, factory reset, ...) fail with `EconfError::ReadOnlyInstance` and no notifications
+    /// are sent, the changes of the other processes are still received. The saved database path is optional
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Folder with the files referenced by the file defaults, the working directory by default
+    pub fn default_data_folder(mut self, path: impl Into<String>) -> Self {
+        self.default_data_folder = path.into();
+        self
+    }
+
+    /// Multicast group and port used for the change notifications.
+    /// All the processes sharing the database must use the same values
+    pub fn multicast(mut self, group: Ipv4Addr, port: u16) -> Self {
+        self.multicast_group = group;
+        self.multicast_port = port;
+        self
+    }
+
+    pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+        self.multicast_ttl = ttl;
+        self
+    }
+
+    /// Send the notifications fired within the window after the first one as a batch,
+    /// instead of one datagram per change. Zero (the default) sends them immediately
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Transport of the change notifications, UDP multicast by default.
+    /// All the processes sharing the database must use the same transport
+    pub fn transport(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Embed the new values in the notifications (enabled by default), so the other instances
+    /// update their caches without reading the database. The sensitive parameters and the large blobs
+    /// are never embedded
+    pub fn embed_values(mut self, embed: bool) -> Self {
+        self.embed_values = embed;
+        self
+    }
+
+    pub fn cache_policy(mut self, policy: CachePolicy) -> Self {
+        self.cache_policy = policy;
+        self
+    }
+
+    /// Store the blobs of `min_size` bytes and larger as files in the folder, the database keeps
+    /// the SHA-256 of the content. The files not referenced by the working or the saved database
+    /// are deleted when the blob is overwritten. Environment variables in the path are expanded
+    pub fn blob_files(mut self, directory: impl Into<String>, min_size: usize) -> Self {
+        self.blob_files = Some((directory.into(), min_size));
+        self
+    }
+
+    /// Fail the build with `EconfError::Config` if any of the file defaults can't be read, instead of
+    /// using empty blobs for them. [`InterfaceInstance::defaults_health`] reports the same at runtime
+    pub fn strict_defaults(mut self, strict: bool) -> Self {
+        self.strict_defaults = strict;
+        self
+    }
+
+    /// Keep up to `count` previous saved databases as backups named by the time of the save
+    /// (`saved.db.2024-05-01T12-00-00.000Z`), see [`InterfaceInstance::list_backups`]. Disabled by default
+    pub fn backup_retention(mut self, count: usize) -> Self {
+        self.backup_retention = count;
+        self
+    }
+
+    /// Run [`InterfaceInstance::maintain`] after every `writes` writes of this instance, 0 (the default) disables it
+    pub fn auto_maintenance(mut self, writes: u64) -> Self {
+        self.maintenance_writes = writes;
+        self
+    }
+
+    /// Encrypt the working and the saved databases (and the profiles and backups) with SQLCipher,
+    /// the key is used as a passphrase. All the processes sharing the database must use the same key
+    #[cfg(feature = "sqlcipher")]
+    pub fn encryption_key(mut self, key: impl Into<String>) -> Self {
+        self.encryption_key = Some(Zeroizing::new(key.into()));
+        self
+    }
+
+    /// Device key encrypting the values of the parameters with the `secret` option,
+    /// required if the parameters have any
+    pub fn secret_key(mut self, key: [u8; 32]) -> Self {
+        self.secret_key = Some(Zeroizing::new(key));
+        self
+    }
+
+    pub fn build(mut self) -> Result<InterfaceInstance, EconfError> {
+        let database_path = match self.database_path.take() {
+            _ if self.in_memory => IN_MEMORY_DATABASE_PATH.to_owned(),
+            Some(path) if !path.is_empty() => path,
+            _ => return Err(EconfError::Config("Database path is not set".to_owned())),
+        };
+        let saved_database_path = match self.saved_database_path.take() {
+            Some(path) if !path.is_empty() => path,
+            _ if self.read_only || database_path == IN_MEMORY_DATABASE_PATH => IN_MEMORY_DATABASE_PATH.to_owned(),
+            _ => return Err(EconfError::Config("Saved database path is not set".to_owned())),
+        };
+        let cache_policy = self.cache_policy;
+        let config = self.into_config(&database_path, &saved_database_path)?;
+        if !config.in_memory && config.database_path == config.saved_database_path {
+            return Err(EconfError::Config("Database and saved database paths must be different".to_owned()));
+        }
+        if config.in_memory && config.read_only {
+            return Err(EconfError::Config("The in-memory database can't be read-only".to_owned()));
+        }
+
+        let store = DatabaseManager::new(&config)?;
+        let mut instance = InterfaceInstance::with_store(store, &config)?;
+        instance.set_cache_policy(cache_policy);
+        Ok(instance)
+    }
+
+    /// Build the instance over another parameter store, for example
+    /// [`crate::file_store::FileStore`]. The database paths, the encryption and the blob files
+    /// options configure the SQLite database and are not used
+    pub fn build_with_store<S: ParameterStore>(self, store: S) -> Result<InterfaceInstance<S>, EconfError> {
+        let cache_policy = self.cache_policy;
+        let config = self.into_config(IN_MEMORY_DATABASE_PATH, IN_MEMORY_DATABASE_PATH)?;
+        let mut instance = InterfaceInstance::with_store(store, &config)?;
+        instance.set_cache_policy(cache_policy);
+        Ok(instance)
+    }
+
+    fn into_config(self, database_path: &str, saved_database_path: &str) -> Result<Config, EconfError> {
+        if !self.multicast_group.is_multicast() {
+            return Err(EconfError::Config(format!("{} is not a multicast address", self.multicast_group)));
+        }
+        if self.multicast_port == 0 {
+            return Err(EconfError::Config("Multicast port can't be 0".to_owned()));
+        }
+        if self.multicast_ttl == 0 || self.multicast_ttl > 255 {
+            return Err(EconfError::Config(format!("Multicast TTL {} is out of range 1..255", self.multicast_ttl)));
+        }
+
+        let mut config = Config::new(database_path, saved_database_path, &self.default_data_folder)?;
+        config.read_only = self.read_only;
+        config.multicast_group = self.multicast_group;
+        config.multicast_port = self.multicast_port;
+        config.multicast_ttl = self.multicast_ttl;
+        config.coalesce_window = self.coalesce_window;
+        config.transport = self.transport;
+        config.embed_values = self.embed_values;
+        config.strict_defaults = self.strict_defaults;
+        config.backup_retention = self.backup_retention;
+        config.maintenance_writes = self.maintenance_writes;
+        if self.encryption_key.as_ref().is_some_and(|key| key.is_empty()) {
+            return Err(EconfError::Config("Encryption key is empty".to_owned()));
+        }
+        config.encryption_key = self.encryption_key;
+        config.secret_key = self.secret_key;
+        if let Some((directory, min_size)) = self.blob_files {
+            if directory.is_empty() {
+                return Err(EconfError::Config("Blob directory is not set".to_owned()));
+            }
+            config.blob_directory = Some(expand_path(&directory)?);
+            config.blob_file_threshold = min_size;
+        }
+        Ok(config)
+    }
 }
 
 impl InterfaceInstance {
+    pub fn builder() -> InterfaceInstanceBuilder {
+        InterfaceInstanceBuilder::default()
+    }
+
     pub fn new(
         database_path: &String,
         saved_database_path: &String,
         default_data_folder: &String,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let config = Config::new(database_path, saved_database_path, default_data_folder)?;
-        let database = Arc::new(Mutex::new(DatabaseManager::new(&config)?));
-        let runtime_data = Arc::new(Mutex::new(SharedRuntimeData::new()?));
-        let notifier = Notifier::new()?;
-        let event_receiver = Arc::new(Mutex::new(EventReceiver::new(runtime_data.clone())?));
-        info!(
-            "Interface created: {} {}",
-            &config.database_path, &config.saved_database_path
-        );
+    ) -> Result<Self, EconfError> {
+        Self::builder()
+            .database_path(database_path)
+            .saved_database_path(saved_database_path)
+            .default_data_folder(default_data_folder)
+            .build()
+    }
+
+    /// Instance with the working database in memory and no saved database, for the tests and simulations.
+    /// The notifications use an in-process transport of this instance only
+    pub fn new_in_memory() -> Result<Self, EconfError> {
+        static NEXT_INSTANCE: AtomicU64 = AtomicU64::new(0);
+        let name = format!("econf-in-memory-{}", NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed));
+        Self::builder()
+            .in_memory(true)
+            .transport(TransportConfig::InProcess(name))
+            .build()
+    }
+
+    /// Instance reading the database of the other processes without changing it, see
+    /// [`InterfaceInstanceBuilder::read_only`]. Fails if the database doesn't exist
+    pub fn new_readonly(database_path: &String, default_data_folder: &String) -> Result<Self, EconfError> {
+        Self::builder()
+            .read_only(true)
+            .database_path(database_path)
+            .default_data_folder(default_data_folder)
+            .build()
+    }
+
+    pub fn value_to_string(value: &ParameterValue) -> String {
+        match value {
+            ParameterValue::ValBool(b) => b.to_string(),
+            ParameterValue::ValI32(i) => i.to_string(),
+            ParameterValue::ValU32(u) => u.to_string(),
+            ParameterValue::ValI64(i) => i.to_string(),
+            ParameterValue::ValU64(u) => u.to_string(),
+            ParameterValue::ValF32(f) => non_finite_name(*f as f64).map_or_else(|| f.to_string(), str::to_owned),
+            ParameterValue::ValF64(f) => non_finite_name(*f).map_or_else(|| f.to_string(), str::to_owned),
+            ParameterValue::ValString(s) => s.to_string(),
+            ParameterValue::ValBlob(data) => BASE64_STANDARD.encode(data),
+            ParameterValue::ValPath(_) => todo!(),
+            ParameterValue::ValNone => todo!(),
+            ParameterValue::ValEnum(i) => i.to_string(),
+        }
+    }
+
+}
+
+impl<S: ParameterStore> InterfaceInstance<S> {
+    fn with_store(store: S, config: &Config) -> Result<Self, EconfError> {
+        let database = Arc::new(MonitoredMutex::new("database", store));
+        let runtime_data = Arc::new(MonitoredMutex::new("runtime_data", SharedRuntimeData::new()?));
+        let transport = transport::create_transport(config)?;
+        let notifier = if config.read_only { None } else { Some(Notifier::new(config, transport.clone())?) };
+        let event_receiver = Arc::new(MonitoredMutex::new(
+            "event_receiver",
+            EventReceiver::new(runtime_data.clone(), database.clone(), transport.as_ref())?,
+        ));
+        info!("Interface created");
+        let persist_stop_flag = Arc::new(AtomicBool::new(false));
+        let persist_thread = if config.read_only {
+            None
+        } else {
+            Self::start_persist_thread(database.clone(), persist_stop_flag.clone())
+        };
         Ok(Self {
             database,
             notifier,
@@ -80,72 +827,635 @@ impl InterfaceInstance {
             event_receiver,
             timer_thread: None,
             stop_flag: Arc::new(AtomicBool::new(false)),
+            autosave_thread: None,
+            autosave_stop_flag: Arc::new(AtomicBool::new(false)),
+            persist_thread,
+            persist_stop_flag,
+            cache_policy: CachePolicy::default(),
         })
     }
 
+    /// Started only if some parameters have the debounce persist policy
+    fn start_persist_thread(
+        database: Arc<MonitoredMutex<S>>,
+        stop_flag: Arc<AtomicBool>,
+    ) -> Option<thread::JoinHandle<()>> {
+        let shortest_debounce = PARAMETER_DATA
+            .iter()
+            .filter_map(|parameter| parameter.persist_policy.debounce_ms())
+            .min()?;
+        let period = (Duration::from_millis(shortest_debounce as u64) / 4).max(PERSIST_MIN_CHECK_PERIOD);
+        Some(thread::spawn(move || {
+            while park_unless_stopped(&stop_flag, period) {
+                if let Err(e) = database.lock().flush_pending(false) {
+                    error!("Deferred write failed: {}", e);
+                }
+            }
+        }))
+    }
+
+    /// Notifier of the writable instances, the read-only ones refuse the changes
+    fn notifier(&self) -> Result<&Notifier, EconfError> {
+        self.notifier.as_ref().ok_or(EconfError::ReadOnlyInstance)
+    }
+
+    /// The instance was created by [`Self::new_readonly`], the changes are refused
+    pub fn is_read_only(&self) -> bool {
+        self.notifier.is_none()
+    }
+
+    /// Write the changes deferred by the persist policies (debounce, on save only) to the database
+    pub fn flush(&self) -> Result<(), EconfError> {
+        self.database.lock().flush_pending(true)?;
+        Ok(())
+    }
+
+    pub fn set_cache_policy(&mut self, policy: CachePolicy) {
+        info!("Cache policy set to {:?}", policy);
+        self.cache_policy = policy;
+    }
+
+    pub fn get_cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Usage statistics of the instance locks, for field debugging of contention and deadlocks.
+    /// The warning threshold is set with `lock_monitor::set_warning_threshold`
+    pub fn lock_diagnostics(&self) -> Vec<LockDiagnostics> {
+        vec![
+            self.database.diagnostics(),
+            self.runtime_data.diagnostics(),
+            self.event_receiver.diagnostics(),
+        ]
+    }
+
     pub fn get(
         &self,
         id: ParameterId,
         force: bool,
-    ) -> Result<ParameterValue, Box<dyn std::error::Error>> {
-        let index: usize = id as usize;
-        let mut data = self.runtime_data.lock().unwrap();
-        if !force && data.parameters_data[index].value.is_some() {
-            let value = data.parameters_data[index].value.clone().unwrap();
+    ) -> Result<ParameterValue, EconfError> {
+        let index: usize = id.index();
+        if PARAMETER_DATA[index].computed {
+            return self.get_computed(id);
+        }
+        let mut data = self.runtime_data.lock();
+        let cached = &data.parameters_data[index];
+        if !force && cached.value.is_some() && self.cache_policy.is_fresh(cached.cached_at) {
+            let value = cached.value.clone().unwrap();
             debug!(
                 "Get parameter {}:[{}] from cache: {}",
-                index, PARAMETER_DATA[index].name_id, value
+                index, PARAMETER_DATA[index].name_id, sensitive::log_value(id, &value)
             );
             return Ok(value);
         } else {
-            let value = self.database.lock().unwrap().read_or_create(id)?;
+            let value = self.database.lock().read(id)?;
             debug!(
                 "Get parameter {}:[{}]: {}",
-                index, PARAMETER_DATA[index].name_id, value
+                index, PARAMETER_DATA[index].name_id, sensitive::log_value(id, &value)
             );
-            data.parameters_data[index].value = Some(value.clone());
+            data.store(id, value.clone());
             Ok(value)
         }
     }
 
+    /// Compute the value from the current values of the dependencies, the default if no function is set
+    fn get_computed(&self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        let compute = self.runtime_data.lock().parameters_data[id.index()].compute.clone();
+        let Some(compute) = compute else {
+            debug!("Compute function of {} is not set, using the default", PARAMETER_DATA[id.index()].name_id);
+            return self.get_default(id);
+        };
+        let values = compute.dependencies
+            .iter()
+            .map(|dependency| self.get(*dependency, false))
+            .collect::<Result<Vec<_>, _>>()?;
+        let value = (compute.function)(&values);
+        for (dependency, value) in compute.dependencies.iter().zip(values) {
+            sensitive::discard(*dependency, value);
+        }
+        Self::check_type(id, &value)?;
+        Ok(value)
+    }
+
+    /// Set the function of a computed parameter (`(options.computed) = true`). The value is computed
+    /// on every read and when any of the dependencies changes, the change callbacks of the parameter
+    /// are then called with the source "computed". The value is not stored, every process reading
+    /// the parameter sets the function, the others get the default
+    pub fn set_compute(&self, id: ParameterId, dependencies: &[ParameterId], function: ComputeFunction) -> Result<(), EconfError> {
+        if !PARAMETER_DATA[id.index()].computed {
+            return Err(EconfError::Config(format!("Parameter {} is not computed", PARAMETER_DATA[id.index()].name_id)));
+        }
+        if let Some(dependency) = dependencies.iter().find(|dependency| PARAMETER_DATA[dependency.index()].computed) {
+            return Err(EconfError::Config(format!(
+                "Parameter {} can't depend on the computed parameter {}",
+                PARAMETER_DATA[id.index()].name_id, PARAMETER_DATA[dependency.index()].name_id
+            )));
+        }
+        self.runtime_data.lock().parameters_data[id.index()].compute = Some(Computed { dependencies: dependencies.to_vec(), function });
+        info!("Compute function set for {}", PARAMETER_DATA[id.index()].name_id);
+        Ok(())
+    }
+
+    /// The default value of the parameter. The defaults stored in files are returned as blobs
+    pub fn get_default(&self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        self.database.lock().default_value(&PARAMETER_DATA[id.index()])
+    }
+
+    /// Set the parameter, the outcome tells whether the value was changed or clamped
     pub fn set(
         &self,
         id: ParameterId,
         parameter: ParameterValue,
-    ) -> Result<ParameterValue, Box<dyn std::error::Error>> {
-        let index: usize = id as usize;
-        if PARAMETER_DATA[index].is_const {
-            return Err(format!("Parameter {index} is const. Setting denied").into());
-        }
-        let result = self.database.lock().unwrap().write(id, parameter, false);
-        let value = match result {
-            Ok(status) => match status {
+    ) -> Result<SetOutcome, EconfError> {
+        self.set_with_source(id, parameter, None)
+    }
+
+    /// Set an enum parameter by the name of the value, e.g. "SOURCE_CANON". The case is ignored
+    pub fn set_enum_by_name(&self, id: ParameterId, name: &str) -> Result<SetOutcome, EconfError> {
+        let value_type = &PARAMETER_DATA[id.index()].value_type;
+        let ParameterValueType::TypeEnum(enum_name) = value_type else {
+            return Err(EconfError::TypeMismatch {
+                id,
+                expected: value_type.clone(),
+                actual: ParameterValue::ValEnum(0).parameter_type(),
+            });
+        };
+        let value = enum_value_by_name(id, name)
+            .ok_or_else(|| EconfError::Validation { id, reason: format!("{} is not a value of {}", name, enum_name) })?;
+        self.set(id, ParameterValue::ValEnum(value))
+    }
+
+    /// Set the parameter and record who made the change. The source is stored with the write
+    /// and delivered to the change callbacks so services can ignore their own writes
+    pub fn set_with_source(
+        &self,
+        id: ParameterId,
+        parameter: ParameterValue,
+        source: Option<&str>,
+    ) -> Result<SetOutcome, EconfError> {
+        self.write_parameter(id, parameter, None, source)
+    }
+
+    /// Set the parameter only if it was not changed since the caller read it (optimistic concurrency
+    /// between the processes sharing the database). Fails with `EconfError::Conflict` if the condition
+    /// doesn't hold, the caller can read the parameter again and retry. The value is written immediately
+    /// even if it is the same or the parameter has a deferred persist policy
+    pub fn set_if(
+        &self,
+        id: ParameterId,
+        parameter: ParameterValue,
+        condition: WriteCondition,
+        source: Option<&str>,
+    ) -> Result<SetOutcome, EconfError> {
+        self.write_parameter(id, parameter, Some(&condition), source)
+    }
+
+    /// Sequence number of the last write of the parameter for [`WriteCondition::Sequence`], 0 if it was
+    /// never written. Take it before reading the value, so a write in between fails the conditional write
+    pub fn get_sequence(&self, id: ParameterId) -> Result<u64, EconfError> {
+        self.database.lock().parameter_sequence(id)
+    }
+
+    fn write_parameter(
+        &self,
+        id: ParameterId,
+        parameter: ParameterValue,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<SetOutcome, EconfError> {
+        Self::check_writable(id)?;
+        Self::check_type(id, &parameter)?;
+        let (parameter, adjustments) = self.apply_rules(id, parameter, Vec::new())?;
+        if adjustments.is_empty() {
+            return self.store_parameter(id, parameter, condition, source);
+        }
+        // The parameter and the values adjusted by the rules are written together or not at all
+        let mut values = vec![(id, parameter)];
+        values.extend(adjustments);
+        let mut written = self.write_together(values, condition, source)?;
+        for (adjusted_id, adjusted) in &written[1..] {
+            if adjusted.changed {
+                info!(
+                    "{} adjusted to {} by a rule with the write of {}",
+                    PARAMETER_DATA[adjusted_id.index()].name_id,
+                    sensitive::log_value(*adjusted_id, &adjusted.value),
+                    PARAMETER_DATA[id.index()].name_id
+                );
+            }
+        }
+        let (_, outcome) = written.swap_remove(0);
+        Ok(outcome)
+    }
+
+    /// Check the rules of the parameter with the new value and the new values of the other parameters
+    /// written together. Returns the value to write, changed if a rule adjusted it, and the new values
+    /// of the other parameters with the adjusted ones
+    fn apply_rules(
+        &self,
+        id: ParameterId,
+        mut parameter: ParameterValue,
+        others: Vec<(ParameterId, ParameterValue)>,
+    ) -> Result<(ParameterValue, Vec<(ParameterId, ParameterValue)>), EconfError> {
+        let rules: Vec<Rule> = self.runtime_data.lock().rules
+            .iter()
+            .filter(|rule| rule.ids.contains(&id))
+            .cloned()
+            .collect();
+        let mut adjustments = others;
+        for rule in rules {
+            let values = rule.ids
+                .iter()
+                .map(|rule_id| {
+                    if *rule_id == id {
+                        return Ok(parameter.clone());
+                    }
+                    match adjustments.iter().find(|(adjusted_id, _)| adjusted_id == rule_id) {
+                        Some((_, value)) => Ok(value.clone()),
+                        None => self.get(*rule_id, false),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let action = (rule.function)(&values);
+            for (rule_id, value) in rule.ids.iter().zip(values) {
+                sensitive::discard(*rule_id, value);
+            }
+            match action {
+                RuleAction::Accept => {}
+                RuleAction::Reject(reason) => {
+                    debug!("Rule {} rejected the write of {}: {}", rule.name, PARAMETER_DATA[id.index()].name_id, reason);
+                    return Err(EconfError::Validation { id, reason: format!("rule {} failed: {}", rule.name, reason) });
+                }
+                RuleAction::Adjust(changes) => {
+                    for (adjusted_id, value) in changes {
+                        if !rule.ids.contains(&adjusted_id) {
+                            return Err(EconfError::Config(format!(
+                                "Rule {} adjusted {}, which is not one of its parameters",
+                                rule.name, PARAMETER_DATA[adjusted_id.index()].name_id
+                            )));
+                        }
+                        if adjusted_id == id {
+                            parameter = value;
+                            continue;
+                        }
+                        adjustments.retain(|(previous_id, _)| *previous_id != adjusted_id);
+                        adjustments.push((adjusted_id, value));
+                    }
+                }
+            }
+        }
+        Ok((parameter, adjustments))
+    }
+
+    /// Stage a change of the parameter, written by [`Self::apply_staged`] together with the other staged
+    /// changes, so an editor can collect the changes without writing and notifying every one. Replaces the
+    /// change staged before. Only the type is checked here, the value is validated when applied
+    pub fn set_staged(&self, id: ParameterId, parameter: ParameterValue) -> Result<(), EconfError> {
+        Self::check_writable(id)?;
+        Self::check_type(id, &parameter)?;
+        let mut data = self.runtime_data.lock();
+        match data.staged.iter_mut().find(|(staged_id, _)| *staged_id == id) {
+            Some((_, staged)) => sensitive::discard(id, mem::replace(staged, parameter)),
+            None => data.staged.push((id, parameter)),
+        }
+        debug!("Staged a change of {}", PARAMETER_DATA[id.index()].name_id);
+        Ok(())
+    }
+
+    /// The staged value of the parameter, the current value if no change is staged
+    pub fn get_staged(&self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        let staged = self.runtime_data.lock().staged
+            .iter()
+            .find(|(staged_id, _)| *staged_id == id)
+            .map(|(_, value)| value.clone());
+        match staged {
+            Some(value) => Ok(value),
+            None => self.get(id, false),
+        }
+    }
+
+    /// The parameters with staged changes, in the order they were staged
+    pub fn get_staged_ids(&self) -> Vec<ParameterId> {
+        self.runtime_data.lock().staged.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Drop the staged changes, returns their number
+    pub fn discard_staged(&self) -> usize {
+        let staged = mem::take(&mut self.runtime_data.lock().staged);
+        let count = staged.len();
+        for (id, value) in staged {
+            sensitive::discard(id, value);
+        }
+        debug!("Discarded {} staged changes", count);
+        count
+    }
+
+    /// Write the staged changes together, see [`Self::set_all`]. The staged changes are kept if the
+    /// write fails
+    pub fn apply_staged(&self, source: Option<&str>) -> Result<Vec<(ParameterId, SetOutcome)>, EconfError> {
+        let values = self.runtime_data.lock().staged.clone();
+        let written = self.set_all(values, source)?;
+        for (id, value) in mem::take(&mut self.runtime_data.lock().staged) {
+            sensitive::discard(id, value);
+        }
+        Ok(written)
+    }
+
+    /// Write several parameters together. The rules are checked with all the new values, then the
+    /// values are validated and written in one transaction, none of them if any is not accepted, and the
+    /// other processes get one notification. A parameter given twice gets the last value.
+    /// Returns the outcome of each written parameter, including the ones adjusted by the rules
+    pub fn set_all(&self, values: Vec<(ParameterId, ParameterValue)>, source: Option<&str>) -> Result<Vec<(ParameterId, SetOutcome)>, EconfError> {
+        self.notifier()?;
+        if values.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut unique: Vec<(ParameterId, ParameterValue)> = Vec::with_capacity(values.len());
+        for (id, value) in values {
+            Self::check_type(id, &value)?;
+            match unique.iter_mut().find(|(other, _)| *other == id) {
+                Some((_, replaced)) => sensitive::discard(id, mem::replace(replaced, value)),
+                None => unique.push((id, value)),
+            }
+        }
+        let mut values = unique;
+        let ids: Vec<ParameterId> = values.iter().map(|(id, _)| *id).collect();
+        for id in ids {
+            let position = values.iter()
+                .position(|(other, _)| *other == id)
+                .expect("Every parameter is kept in the values");
+            let (_, value) = values.remove(position);
+            let (value, others) = self.apply_rules(id, value, values)?;
+            values = others;
+            values.push((id, value));
+        }
+        self.write_together(values, None, source)
+    }
+
+    /// Write the values after the rules in one transaction and send one notification. The condition
+    /// is the one of the first value, see [`Self::set_if`]
+    fn write_together(
+        &self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<(ParameterId, SetOutcome)>, EconfError> {
+        let notifier = self.notifier()?;
+        let mut checked = Vec::with_capacity(values.len());
+        for (id, value) in values {
+            Self::check_writable(id)?;
+            checked.push((id, self.run_pre_change_hooks(id, value)?));
+        }
+        let values = checked;
+
+        let mut database = self.database.lock();
+        let written = match database.write_all(values, condition, source) {
+            Ok(written) => written,
+            Err(EconfError::Conflict(id)) => {
+                drop(database);
+                // The cached value is older than the one of the other writer
+                self.runtime_data.lock().invalidate(&[id]);
+                return Err(EconfError::Conflict(id));
+            }
+            Err(e) => return Err(e),
+        };
+        let changes: Vec<(ParameterId, u64, &ParameterValue)> = written.iter()
+            .filter_map(|(id, status, sequence)| match status {
+                Status::StatusOkChanged(value) | Status::StatusOkNotChecked(value) | Status::StatusOkOverflowFixed(value) => {
+                    Some((*id, sequence.unwrap_or(0), value))
+                }
+                _ => None,
+            })
+            .collect();
+        info!("Applied {} changes together, {} written", written.len(), changes.len());
+        notifier.notify_of_values_change(&changes, source)?;
+        drop(database);
+
+        let mut data = self.runtime_data.lock();
+        Ok(written.into_iter()
+            .map(|(id, status, _)| {
+                let outcome = match status {
+                    Status::StatusOkOverflowFixed(value) => SetOutcome { value, changed: true, clamped: true },
+                    Status::StatusOkNotChanged(value) => SetOutcome { value, changed: false, clamped: false },
+                    status => SetOutcome { value: status.unwrap(), changed: true, clamped: false },
+                };
+                data.store(id, outcome.value.clone());
+                (id, outcome)
+            })
+            .collect())
+    }
+
+    /// Write the parameter without checking the rules
+    fn store_parameter(
+        &self,
+        id: ParameterId,
+        parameter: ParameterValue,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<SetOutcome, EconfError> {
+        let index: usize = id.index();
+        Self::check_writable(id)?;
+        Self::check_type(id, &parameter)?;
+        let notifier = self.notifier()?;
+        let parameter = self.run_pre_change_hooks(id, parameter)?;
+        if PARAMETER_DATA[index].deprecated {
+            warn!(
+                "Write of the deprecated parameter {} from {}",
+                PARAMETER_DATA[index].name_id, source.unwrap_or("unknown")
+            );
+        }
+        // Keep the database locked until the notification is sent, so the notifications
+        // from this process leave in the same order as the writes were applied
+        let mut database = self.database.lock();
+        let result = database.write(id, parameter, false, condition, source);
+        let clamped = matches!(result, Ok((Status::StatusOkOverflowFixed(_), _)));
+        let outcome = match result {
+            Ok((status, sequence)) => match status {
                 Status::StatusOkChanged(value)
                 | Status::StatusOkNotChecked(value)
                 | Status::StatusOkOverflowFixed(value) => {
                     debug!(
-                        "Set parameter {}:[{}]: {}",
-                        index, PARAMETER_DATA[index].name_id, value
+                        "Set parameter {}:[{}]: {} (sequence {:?})",
+                        index, PARAMETER_DATA[index].name_id, sensitive::log_value(id, &value), sequence
                     );
-                    self.notifier.notify_of_parameter_change(id)?;
-                    value
+                    notifier.notify_of_parameter_change(id, source, sequence.unwrap_or(0), Some(&value))?;
+                    SetOutcome { value, changed: true, clamped }
                 }
                 Status::StatusOkNotChanged(value) => {
                     debug!(
                         "Parameter {}:[{}] not changed",
                         index, PARAMETER_DATA[index].name_id
                     );
-                    value
+                    SetOutcome { value, changed: false, clamped: false }
+                }
+                Status::StatusErrorNotAccepted(value) => {
+                    // The errors are logged by the callers
+                    return Err(EconfError::Validation { id, reason: format!("value {} not accepted", sensitive::log_value(id, &value)) })
+                }
+                Status::StatusErrorConflict(value) => {
+                    sensitive::discard(id, value);
+                    drop(database);
+                    // The cached value is older than the one of the other writer
+                    self.runtime_data.lock().invalidate(&[id]);
+                    return Err(EconfError::Conflict(id));
+                }
+                Status::StatusErrorFailed => {
+                    return Err(EconfError::Validation { id, reason: "validation failed".to_owned() })
                 }
-                Status::StatusErrorNotAccepted(_) => return Err("Parameter not accepted".into()),
-                Status::StatusErrorFailed => return Err("Failed to write the parameter".into()),
             },
-            Err(e) => return Err(format!("Failed to write in the database: {}", e).into()),
+            Err(e) => {
+                error!("Failed to write in the database: {}", e);
+                return Err(e);
+            }
         };
+        drop(database);
+
+        let mut data = self.runtime_data.lock();
+        data.store(id, outcome.value.clone());
+        Ok(outcome)
+    }
+
+    /// Pass the new value through the pre-change hooks of the parameter, in the order they were added
+    fn run_pre_change_hooks(&self, id: ParameterId, mut parameter: ParameterValue) -> Result<ParameterValue, EconfError> {
+        let hooks = self.runtime_data.lock().parameters_data[id.index()].pre_change_hooks.clone();
+        if hooks.is_empty() {
+            return Ok(parameter);
+        }
+        let current = self.get(id, false)?;
+        let mut result = Ok(());
+        for (handle, hook) in hooks {
+            match hook(&current, &parameter) {
+                Ok(value) => {
+                    if let Err(e) = Self::check_type(id, &value) {
+                        warn!("Pre-change hook {} returned a value of another type: {}", handle, e);
+                        sensitive::discard(id, value);
+                        result = Err(e);
+                        break;
+                    }
+                    if value != parameter {
+                        debug!("Pre-change hook {} rewrote the value of {}", handle, PARAMETER_DATA[id.index()].name_id);
+                    }
+                    sensitive::discard(id, mem::replace(&mut parameter, value));
+                }
+                Err(reason) => {
+                    info!("Pre-change hook {} vetoed the change of {}: {}", handle, PARAMETER_DATA[id.index()].name_id, reason);
+                    result = Err(EconfError::Vetoed { id, reason });
+                    break;
+                }
+            }
+        }
+        sensitive::discard(id, current);
+        match result {
+            Ok(()) => Ok(parameter),
+            Err(e) => {
+                sensitive::discard(id, parameter);
+                Err(e)
+            }
+        }
+    }
+
+    /// The const and the computed parameters are never written
+    fn check_writable(id: ParameterId) -> Result<(), EconfError> {
+        if PARAMETER_DATA[id.index()].is_const {
+            return Err(EconfError::Const(id));
+        }
+        if PARAMETER_DATA[id.index()].computed {
+            return Err(EconfError::ReadOnly(id));
+        }
+        Ok(())
+    }
+
+    /// Values of another type than the parameter are refused before the rules and the validation
+    fn check_type(id: ParameterId, value: &ParameterValue) -> Result<(), EconfError> {
+        let expected = &PARAMETER_DATA[id.index()].value_type;
+        if mem::discriminant(&value.parameter_type()) != mem::discriminant(expected) {
+            return Err(EconfError::TypeMismatch { id, expected: expected.clone(), actual: value.parameter_type() });
+        }
+        Ok(())
+    }
+
+    fn check_blob_stream(id: ParameterId) -> Result<(), EconfError> {
+        if PARAMETER_DATA[id.index()].value_type != ParameterValueType::TypeBlob {
+            return Err(EconfError::Validation { id, reason: "only blob parameters can be streamed".to_owned() });
+        }
+        // The streams are kept unencrypted in the SQLite temporary storage
+        if PARAMETER_DATA[id.index()].secret {
+            return Err(EconfError::Validation { id, reason: "secret parameters can't be streamed".to_owned() });
+        }
+        Ok(())
+    }
+
+    /// Open a blob parameter for the chunked reads with [`Self::blob_read_chunk`], the caller
+    /// doesn't need to hold the whole blob. The streams are lost when the database is replaced by `load`
+    pub fn blob_open_read(&self, id: ParameterId) -> Result<BlobReader, EconfError> {
+        Self::check_blob_stream(id)?;
+        let (row, size) = self.database.lock().blob_stream_open_read(id)?;
+        debug!("Opened {} for reading, {} bytes", PARAMETER_DATA[id.index()].name_id, size);
+        Ok(BlobReader { id, row, size, position: 0 })
+    }
+
+    /// Read the next chunk to the buffer, returns the number of bytes read, 0 at the end of the blob
+    pub fn blob_read_chunk(&self, reader: &mut BlobReader, buffer: &mut [u8]) -> Result<usize, EconfError> {
+        let read = self.database.lock().blob_stream_read(reader.row, reader.position, buffer)?;
+        reader.position += read;
+        Ok(read)
+    }
+
+    pub fn blob_close_read(&self, reader: BlobReader) -> Result<(), EconfError> {
+        self.database.lock().blob_stream_discard(reader.row)
+    }
+
+    /// Open a blob parameter for writing `size` bytes in chunks with [`Self::blob_write_chunk`]
+    pub fn blob_open_write(&self, id: ParameterId, size: usize) -> Result<BlobWriter, EconfError> {
+        Self::check_blob_stream(id)?;
+        Self::check_writable(id)?;
+        self.notifier()?;
+        if let Some(max_size) = PARAMETER_DATA[id.index()].max_size {
+            if size > max_size as usize {
+                return Err(EconfError::Validation { id, reason: ValidationError::TooLarge { size, max_size }.to_string() });
+            }
+        }
+        let row = self.database.lock().blob_stream_open_write(size)?;
+        Ok(BlobWriter { id, row, size, position: 0 })
+    }
+
+    /// Write the next chunk, the chunks can't go past the size given to [`Self::blob_open_write`]
+    pub fn blob_write_chunk(&self, writer: &mut BlobWriter, data: &[u8]) -> Result<(), EconfError> {
+        if data.len() > writer.remaining() {
+            return Err(EconfError::Validation {
+                id: writer.id,
+                reason: format!("chunk of {} bytes exceeds the {} bytes left", data.len(), writer.remaining()),
+            });
+        }
+        self.database.lock().blob_stream_write(writer.row, writer.position, data)?;
+        writer.position += data.len();
+        Ok(())
+    }
 
-        let mut data = self.runtime_data.lock().unwrap();
-        data.parameters_data[index].value = Some(value.clone());
-        Ok(value)
+    /// Store the written blob in the parameter and notify the other processes.
+    /// An incomplete blob is discarded and the parameter is left unchanged
+    pub fn blob_close_write(&self, writer: BlobWriter, source: Option<&str>) -> Result<(), EconfError> {
+        let id = writer.id;
+        let index = id.index();
+        let notifier = self.notifier()?;
+        let mut database = self.database.lock();
+        if writer.remaining() > 0 {
+            database.blob_stream_discard(writer.row)?;
+            return Err(EconfError::Validation { id, reason: format!("blob is incomplete, {} bytes were not written", writer.remaining()) });
+        }
+        if PARAMETER_DATA[index].deprecated {
+            warn!(
+                "Write of the deprecated parameter {} from {}",
+                PARAMETER_DATA[index].name_id, source.unwrap_or("unknown")
+            );
+        }
+        let sequence = database.blob_stream_commit(id, writer.row, source)?;
+        debug!("Set parameter {}:[{}] from a stream of {} bytes (sequence {})", index, PARAMETER_DATA[index].name_id, writer.size, sequence);
+        // The value is not embedded, the receivers read it from the database
+        notifier.notify_of_parameter_change(id, source, sequence, None)?;
+        drop(database);
+
+        self.runtime_data.lock().invalidate(&[id]);
+        Ok(())
     }
 
     pub fn get_groups(&self) -> Vec<(String, String, String)> {
@@ -162,7 +1472,7 @@ impl InterfaceInstance {
     }
 
     pub fn get_group(&self, id: ParameterId) -> String {
-        PARAMETER_DATA[id as usize]
+        PARAMETER_DATA[id.index()]
             .name_id
             .split("@")
             .next()
@@ -171,41 +1481,140 @@ impl InterfaceInstance {
     }
 
     pub fn get_name(&self, id: ParameterId) -> String {
-        PARAMETER_DATA[id as usize].name_id.to_owned()
+        PARAMETER_DATA[id.index()].name_id.to_owned()
     }
 
     pub fn get_comment(&self, id: ParameterId) -> String {
-        PARAMETER_DATA[id as usize].comment.to_owned()
+        PARAMETER_DATA[id.index()].comment.to_owned()
     }
 
     pub fn is_const(&self, id: ParameterId) -> bool {
-        PARAMETER_DATA[id as usize].is_const
+        PARAMETER_DATA[id.index()].is_const
     }
 
     pub fn is_runtime(&self, id: ParameterId) -> bool {
-        PARAMETER_DATA[id as usize].runtime
+        PARAMETER_DATA[id.index()].runtime
     }
 
     pub fn is_readonly(&self, id: ParameterId) -> bool {
-        PARAMETER_DATA[id as usize].readonly
+        PARAMETER_DATA[id.index()].readonly
     }
 
     pub fn is_internal(&self, id: ParameterId) -> bool {
-        PARAMETER_DATA[id as usize].internal
+        PARAMETER_DATA[id.index()].internal
+    }
+
+    /// Deprecated parameters still work, the writes are logged with a warning
+    pub fn is_deprecated(&self, id: ParameterId) -> bool {
+        PARAMETER_DATA[id.index()].deprecated
+    }
+
+    /// Hidden parameters are not listed in the UI, but can be accessed by name
+    pub fn is_hidden(&self, id: ParameterId) -> bool {
+        PARAMETER_DATA[id.index()].hidden
+    }
+
+    /// Lowest role allowed to change the parameter through the server, the library itself doesn't check it
+    pub fn get_access_level(&self, id: ParameterId) -> AccessLevel {
+        PARAMETER_DATA[id.index()].access_level
+    }
+
+    /// The parameter is tagged `sensitive`, its cached copies are zeroized when dropped
+    pub fn is_sensitive(&self, id: ParameterId) -> bool {
+        sensitive::is_sensitive(id)
+    }
+
+    /// The value is computed from other parameters, see [`Self::set_compute`]
+    pub fn is_computed(&self, id: ParameterId) -> bool {
+        PARAMETER_DATA[id.index()].computed
+    }
+
+    /// The parameter has the `secret` option: the value is encrypted in the database
+    /// and should be shown as [`sensitive::SECRET_MASK`]
+    pub fn is_secret(&self, id: ParameterId) -> bool {
+        sensitive::is_secret(id)
+    }
+
+    /// The parameter is tagged `no_log` or is secret, its values are not written to the logs
+    pub fn is_no_log(&self, id: ParameterId) -> bool {
+        sensitive::is_no_log(id)
+    }
+
+    /// Wipe the sensitive values from the cache (for example, when the device locks),
+    /// the next read of such a parameter goes to the database
+    pub fn purge_sensitive(&self) -> usize {
+        let purged = self.runtime_data.lock().purge_sensitive();
+        info!("Purged {} sensitive values from the cache", purged);
+        purged
+    }
+
+    /// Report of all the parameters, defaults and validation rules with the hash of the parameters schema,
+    /// the same as `parameters_manifest.json` generated by the build. Sign it with [`manifest::sign_manifest`]
+    pub fn get_manifest(&self) -> Value {
+        static SCHEMA_HASH: OnceLock<String> = OnceLock::new();
+        let schema_hash = SCHEMA_HASH.get_or_init(|| manifest::schema_hash(DESCRIPTORS_BIN));
+        manifest::parameters_manifest(PARAMETER_DATA, GROUPS_DATA, schema_hash, env!("CARGO_PKG_VERSION"))
     }
 
     pub fn get_tags(&self, id: ParameterId) -> Vec<String> {
-        PARAMETER_DATA[id as usize].tags.iter().map(|val|val.to_string()).collect()
+        PARAMETER_DATA[id.index()].tags.iter().map(|val|val.to_string()).collect()
     }
     
+    pub fn get_validation(&self, id: ParameterId) -> &'static ValidationMethod {
+        &PARAMETER_DATA[id.index()].validation
+    }
+
+    /// Check the value against the type and the validation rules of the parameter without writing it.
+    /// The setters clamp the values out of the range to the closest accepted one, this reports them as errors
+    pub fn validate_value(&self, id: ParameterId, value: &ParameterValue) -> Result<(), ValidationError> {
+        let parameter = &PARAMETER_DATA[id.index()];
+        let actual = value.parameter_type();
+        let type_matches = match (&parameter.value_type, value) {
+            (ParameterValueType::TypeEnum(_), ParameterValue::ValEnum(_)) => true,
+            (expected, _) => *expected == actual,
+        };
+        if !type_matches {
+            return Err(ValidationError::TypeMismatch { expected: parameter.value_type.clone(), actual });
+        }
+        if !value.is_finite() && !parameter.allow_non_finite {
+            return Err(ValidationError::NonFinite { value: value.clone() });
+        }
+        if let ParameterValue::ValBlob(blob) = value {
+            check_blob_size(id, blob)?;
+        }
+        match &parameter.validation {
+            ValidationMethod::Range { min, max } => {
+                if value < min {
+                    return Err(ValidationError::BelowMin { value: value.clone(), min: min.clone() });
+                }
+                if value > max {
+                    return Err(ValidationError::AboveMax { value: value.clone(), max: max.clone() });
+                }
+                Ok(())
+            }
+            ValidationMethod::AllowedValues { values, .. } => {
+                if values.contains(value) {
+                    Ok(())
+                } else {
+                    Err(ValidationError::NotAllowed { value: value.clone() })
+                }
+            }
+            ValidationMethod::Text { .. } => match value {
+                ParameterValue::ValString(text) => check_text(id, text),
+                _ => Ok(()),
+            },
+            ValidationMethod::None | ValidationMethod::CustomCallback => Ok(()),
+        }
+    }
+
     pub fn get_validation_json(&self, id: ParameterId) -> serde_json::Value {
-        match &PARAMETER_DATA[id as usize].validation {
+        match &PARAMETER_DATA[id.index()].validation {
             crate::schema::ValidationMethod::None => serde_json::json!("none"),
             crate::schema::ValidationMethod::Range { min, max } => {
                 serde_json::json!({
                     "range": {
-                        "min": Self::value_to_string(&min),
-                        "max": Self::value_to_string(&max)
+                        "min": InterfaceInstance::value_to_string(min),
+                        "max": InterfaceInstance::value_to_string(max)
                     }
                 })
             },
@@ -218,25 +1627,33 @@ impl InterfaceInstance {
                         match value {
                             ParameterValue::ValEnum(_) =>
                                 serde_json::json!({
-                                    "value": Self::value_to_string(value),
+                                    "value": InterfaceInstance::value_to_string(value),
                                     "name": name
                                 }),
                             _ =>
                             serde_json::json!({
-                                "value": Self::value_to_string(value),
-                                "name": Self::value_to_string(value),
+                                "value": InterfaceInstance::value_to_string(value),
+                                "name": InterfaceInstance::value_to_string(value),
                             }),
                         }
                     })
                     .collect();
                 serde_json::json!({ "allowed_values": value_pairs })
             },
+            crate::schema::ValidationMethod::Text { max_length, pattern } => {
+                serde_json::json!({
+                    "text": {
+                        "max_length": max_length,
+                        "pattern": pattern
+                    }
+                })
+            },
             crate::schema::ValidationMethod::CustomCallback => serde_json::json!("custom"),
         }
     }
     
     pub fn get_type_string(&self, id: ParameterId) -> String {
-        match &PARAMETER_DATA[id as usize].value_type {
+        match &PARAMETER_DATA[id.index()].value_type {
             ParameterValueType::TypeBool => "Bool".to_owned(),
             ParameterValueType::TypeI32 => "I32".to_owned(),
             ParameterValueType::TypeU32 => "U32".to_owned(),
@@ -252,28 +1669,96 @@ impl InterfaceInstance {
     }
 
     pub fn get_title(&self, id: ParameterId) -> String {
-        PARAMETER_DATA[id as usize].title.to_owned()
+        PARAMETER_DATA[id.index()].title.to_owned()
     }
 
-    pub fn value_to_string(value: &ParameterValue) -> String {
-        match value {
-            ParameterValue::ValBool(b) => b.to_string(),
-            ParameterValue::ValI32(i) => i.to_string(),
-            ParameterValue::ValU32(u) => u.to_string(),
-            ParameterValue::ValI64(i) => i.to_string(),
-            ParameterValue::ValU64(u) => u.to_string(),
-            ParameterValue::ValF32(f) => f.to_string(),
-            ParameterValue::ValF64(f) => f.to_string(),
-            ParameterValue::ValString(s) => s.to_string(),
-            ParameterValue::ValBlob(data) => BASE64_STANDARD.encode(data),
-            ParameterValue::ValPath(_) => todo!(),
-            ParameterValue::ValNone => todo!(),
-            ParameterValue::ValEnum(i) => i.to_string(),
+    pub fn get_blob_schema(&self, id: ParameterId) -> Option<String> {
+        PARAMETER_DATA[id.index()].blob_schema.map(|schema| schema.to_owned())
+    }
+
+    pub fn get_unit(&self, id: ParameterId) -> Option<String> {
+        PARAMETER_DATA[id.index()].unit.map(|unit| unit.to_owned())
+    }
+
+    /// Increment of the value for the UI controls
+    pub fn get_step(&self, id: ParameterId) -> Option<f64> {
+        PARAMETER_DATA[id.index()].step
+    }
+
+    /// Number of decimal places to show
+    pub fn get_precision(&self, id: ParameterId) -> Option<u32> {
+        PARAMETER_DATA[id.index()].precision
+    }
+
+    /// Preferred UI control of the parameter, e.g. "slider"
+    pub fn get_widget(&self, id: ParameterId) -> Option<String> {
+        PARAMETER_DATA[id.index()].widget.map(|widget| widget.to_owned())
+    }
+
+    /// Maximum size of a blob parameter in bytes
+    pub fn get_max_size(&self, id: ParameterId) -> Option<u32> {
+        PARAMETER_DATA[id.index()].max_size
+    }
+
+    /// MIME type of a blob parameter
+    pub fn get_content_type(&self, id: ParameterId) -> Option<String> {
+        PARAMETER_DATA[id.index()].content_type.map(|content_type| content_type.to_owned())
+    }
+
+    /// Name of the enum type of an enum parameter, e.g. "CameraType"
+    pub fn get_enum_name(&self, id: ParameterId) -> Option<String> {
+        match &PARAMETER_DATA[id.index()].value_type {
+            ParameterValueType::TypeEnum(name) => Some(name.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Name of the enum value in the allowed values of the parameter, None for the unknown values
+    pub fn get_enum_label(&self, id: ParameterId, value: i32) -> Option<String> {
+        match &PARAMETER_DATA[id.index()].validation {
+            ValidationMethod::AllowedValues { values, names } => values
+                .iter()
+                .zip(names.iter())
+                .find(|(allowed, _)| **allowed == ParameterValue::ValEnum(value))
+                .map(|(_, name)| name.to_string()),
+            _ => None,
         }
     }
 
+    pub fn get_persist_policy(&self, id: ParameterId) -> PersistPolicy {
+        PARAMETER_DATA[id.index()].persist_policy
+    }
+
+    /// Read a blob parameter with a `blob_schema` and decode it to JSON using the proto descriptors
+    pub fn get_decoded_json(&self, id: ParameterId) -> Result<Value> {
+        let schema = PARAMETER_DATA[id.index()]
+            .blob_schema
+            .ok_or_else(|| anyhow!("Parameter {} has no blob_schema", PARAMETER_DATA[id.index()].name_id))?;
+        let descriptor = get_descriptor_pool()?
+            .get_message_by_name(schema)
+            .ok_or_else(|| anyhow!("Message {} not found in descriptors", schema))?;
+        let blob = match self.get(id, false).map_err(|e| anyhow!("{}", e))? {
+            ParameterValue::ValBlob(blob) => blob,
+            other => return Err(anyhow!("Expected a blob, got {}", other)),
+        };
+        let message = DynamicMessage::decode(descriptor, blob.as_slice())?;
+        Ok(serde_json::to_value(&message)?)
+    }
+
+    /// Encode JSON into a blob value for a parameter with a `blob_schema`
+    pub fn set_from_decoded_json(&self, id: ParameterId, value: &Value) -> Result<ParameterValue> {
+        let schema = PARAMETER_DATA[id.index()]
+            .blob_schema
+            .ok_or_else(|| anyhow!("Parameter {} has no blob_schema", PARAMETER_DATA[id.index()].name_id))?;
+        let descriptor = get_descriptor_pool()?
+            .get_message_by_name(schema)
+            .ok_or_else(|| anyhow!("Message {} not found in descriptors", schema))?;
+        let message = DynamicMessage::deserialize(descriptor, value)?;
+        Ok(ParameterValue::ValBlob(prost::Message::encode_to_vec(&message)))
+    }
+
     pub fn set_from_string(&self, id: ParameterId, value: &str) -> Result<ParameterValue> {
-        let param_type = &PARAMETER_DATA[id as usize].value_type;
+        let param_type = &PARAMETER_DATA[id.index()].value_type;
 
         let converted_value = match param_type {
             ParameterValueType::TypeBool => match value.to_lowercase().as_str() {
@@ -305,15 +1790,20 @@ impl InterfaceInstance {
                         .parse::<f64>()
                         .map(ParameterValue::ValF64)
                         .map_err(|_| anyhow!("Expected a 64-bit float"))?,
-            ParameterValueType::TypeString => ParameterValue::ValString(value.to_string().into()),
+            ParameterValueType::TypeString => {
+                        check_text(id, value)?;
+                        ParameterValue::ValString(value.to_string().into())
+                    }
             ParameterValueType::TypeBlob => {
                         let decoded = BASE64_STANDARD.decode(value)?;
                         ParameterValue::ValBlob(decoded)
                     }
-            ParameterValueType::TypeEnum(_) => value
-                        .parse::<i32>()
-                        .map(ParameterValue::ValEnum)
-                        .map_err(|_| anyhow!("Expected a 32-bit integer"))?,
+            ParameterValueType::TypeEnum(enum_name) => match value.parse::<i32>() {
+                        Ok(number) => ParameterValue::ValEnum(number),
+                        Err(_) => enum_value_by_name(id, value)
+                            .map(ParameterValue::ValEnum)
+                            .ok_or_else(|| anyhow!("Expected a 32-bit integer or a name of {}", enum_name))?,
+                    },
             ParameterValueType::TypeNone => ParameterValue::ValNone,
         };
 
@@ -321,56 +1811,16 @@ impl InterfaceInstance {
     }
 
     pub fn set_from_json(&self, id: ParameterId, value: &Value) -> Result<ParameterValue> {
-        let param_type = &PARAMETER_DATA[id as usize].value_type;
-
-        let converted_value = match param_type {
-            ParameterValueType::TypeBool => value
-                        .as_bool()
-                        .map(ParameterValue::ValBool)
-                        .ok_or_else(|| anyhow!("Expected a boolean"))?,
-            ParameterValueType::TypeI32 => value
-                        .as_i64()
-                        .map(|v| ParameterValue::ValI32(v as i32))
-                        .ok_or_else(|| anyhow!("Expected an integer"))?,
-            ParameterValueType::TypeU32 => value
-                        .as_u64()
-                        .map(|v| ParameterValue::ValU32(v as u32))
-                        .ok_or_else(|| anyhow!("Expected an unsigned integer"))?,
-            ParameterValueType::TypeI64 => value
-                        .as_i64()
-                        .map(ParameterValue::ValI64)
-                        .ok_or_else(|| anyhow!("Expected an integer"))?,
-            ParameterValueType::TypeU64 => value
-                        .as_u64()
-                        .map(ParameterValue::ValU64)
-                        .ok_or_else(|| anyhow!("Expected an unsigned integer"))?,
-            ParameterValueType::TypeF32 => value
-                        .as_f64()
-                        .map(|v| ParameterValue::ValF32(v as f32))
-                        .ok_or_else(|| anyhow!("Expected a float"))?,
-            ParameterValueType::TypeF64 => value
-                        .as_f64()
-                        .map(ParameterValue::ValF64)
-                        .ok_or_else(|| anyhow!("Expected a float"))?,
-            ParameterValueType::TypeString => value
-                        .as_str()
-                        .map(|v| ParameterValue::ValString(v.to_string().into()))
-                        .ok_or_else(|| anyhow!("Expected a string"))?,
-            ParameterValueType::TypeBlob => {
-                        let base64_str = value
-                            .as_str()
-                            .ok_or_else(|| anyhow!("Expected a base64 string"))?;
-                        let decoded = BASE64_STANDARD.decode(base64_str)?;
-                        ParameterValue::ValBlob(decoded)
-                    }
-            ParameterValueType::TypeEnum(_) => value
-                        .as_i64()
-                        .map(|v| ParameterValue::ValEnum(v as i32))
-                        .ok_or_else(|| anyhow!("Expected an integer"))?,
-            ParameterValueType::TypeNone => ParameterValue::ValNone,
-        };
+        value_from_json(id, value)
+    }
 
-        Ok(converted_value)
+    /// The parameters matching the filter with their definitions, in the order of the IDs
+    pub fn iter_parameters(&self, filter: ParameterFilter) -> impl Iterator<Item = (ParameterId, &'static Parameter)> {
+        PARAMETER_DATA
+            .iter()
+            .enumerate()
+            .filter(move |(_, parameter)| filter.matches(parameter))
+            .filter_map(|(index, parameter)| ParameterId::from_index(index).map(|id| (id, parameter)))
     }
 
     pub fn get_parameter_names(&self) -> Vec<String> {
@@ -385,20 +1835,17 @@ impl InterfaceInstance {
     }
 
     pub fn get_parameter_id_from_name(&self, name: String) -> Option<ParameterId> {
-        PARAMETER_DATA
-            .iter()
-            .enumerate()
-            .find(|(_, parameter)| parameter.name_id.to_string() == name)
-            .and_then(|(id, _)| ParameterId::try_from(id).ok())
+        ParameterId::from_name(&name)
     }
 
-    pub fn update(&mut self) -> Result<Vec<ParameterId>, Box<dyn std::error::Error>> {
+    pub fn update(&mut self) -> Result<Vec<ParameterId>, EconfError> {
         info!("Update called");
-        let pending_callbacks = self.database.lock().unwrap().update()?;
-        for id in &pending_callbacks {
-            self.event_receiver.lock().unwrap().notify_callback(*id);
-        }
-        Ok(pending_callbacks)
+        self.event_receiver.lock().update()
+    }
+
+    /// For the update poll without holding the instance lock during the callbacks
+    pub(crate) fn event_receiver(&self) -> Arc<MonitoredMutex<EventReceiver<S>>> {
+        self.event_receiver.clone()
     }
 
     pub fn start_periodic_update(&mut self, interval: Duration) {
@@ -407,31 +1854,24 @@ impl InterfaceInstance {
         let stop_flag = Arc::new(AtomicBool::new(false));
         self.stop_flag = stop_flag.clone();
 
-        let shared_database = self.database.clone();
         let shared_event_receiver = self.event_receiver.clone();
         
         let handle = thread::spawn(move || {
-            loop {
-                if stop_flag.load(Ordering::Relaxed) {
-                    break;
+            while !stop_flag.load(Ordering::Relaxed) {
+                debug!("Timer update");
+                if let Err(e) = shared_event_receiver.lock().update() {
+                    error!("Timer update failed: {}", e)
                 }
 
-                let pending_callbacks = 
-                {
-                    debug!("Timer update");
-                    let mut database = shared_database.lock().unwrap();
-                    database.update()
-                };
-
-                match pending_callbacks {
-                    Ok(pending_callbacks) =>
-                        for id in &pending_callbacks {
-                            shared_event_receiver.lock().unwrap().notify_callback(*id);
-                        },
-                    Err(e) => error!("Timer update failed: {}", e)
+                // Parked instead of sleeping, so stop_periodic_update doesn't wait for the whole interval
+                let deadline = Instant::now() + interval;
+                while !stop_flag.load(Ordering::Relaxed) {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    thread::park_timeout(deadline - now);
                 }
-
-                thread::sleep(interval);
             }
         });
 
@@ -439,88 +1879,516 @@ impl InterfaceInstance {
     }
 
     pub fn stop_periodic_update(&mut self) {
-        if let Some(flag) = Arc::get_mut(&mut self.stop_flag) {
-            flag.store(true, Ordering::Relaxed);
-        }
-        
+        self.stop_flag.store(true, Ordering::Relaxed);
+
         if let Some(handle) = self.timer_thread.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+
+    /// Save the parameters in the background every interval, like `save`. With `only_if_dirty`
+    /// the save is skipped when nothing was written since the last save. The interval is randomly
+    /// changed by up to 10%, and the save waits until the writes stop for a moment, so bursts
+    /// of writes are saved once
+    pub fn start_autosave(&mut self, interval: Duration, only_if_dirty: bool) {
+        self.stop_autosave();
+        if self.is_read_only() {
+            warn!("Autosave not started, the instance is read-only");
+            return;
+        }
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        self.autosave_stop_flag = stop_flag.clone();
+
+        let database = self.database.clone();
+        let wait = move |duration: Duration| park_unless_stopped(&stop_flag, duration);
+
+        let handle = thread::spawn(move || {
+            while wait(jittered(interval)) {
+                let mut settled = Duration::ZERO;
+                loop {
+                    let sequence = database.lock().current_sequence().ok();
+                    if settled >= interval || !wait(AUTOSAVE_SETTLE_TIME) {
+                        break;
+                    }
+                    settled += AUTOSAVE_SETTLE_TIME;
+                    if database.lock().current_sequence().ok() == sequence {
+                        break;
+                    }
+                }
+
+                let mut database = database.lock();
+                if only_if_dirty {
+                    match database.has_unsaved_changes() {
+                        Ok(false) => continue,
+                        Ok(true) => {}
+                        Err(e) => error!("Autosave failed to check for changes: {}", e),
+                    }
+                }
+                debug!("Autosave");
+                if let Err(e) = database.save(&Self::save_filter, &|_, _| {}) {
+                    error!("Autosave failed: {}", e);
+                }
+            }
+        });
+
+        self.autosave_thread = Some(handle);
+    }
+
+    pub fn stop_autosave(&mut self) {
+        self.autosave_stop_flag.store(true, Ordering::Relaxed);
+
+        if let Some(handle) = self.autosave_thread.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+
+    /// Stop the timer and the notification receiver threads. The instance keeps working,
+    /// but the changes made by the other instances are no longer delivered. Called on drop
+    pub fn shutdown(&mut self) {
+        self.stop_periodic_update();
+        self.stop_autosave();
+        self.persist_stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.persist_thread.take() {
+            handle.thread().unpark();
             let _ = handle.join();
         }
+        if let Err(e) = self.flush() {
+            error!("Deferred writes failed on shutdown: {}", e);
+        }
+        self.event_receiver.lock().shutdown();
+        info!("Interface stopped");
     }
 
     pub fn add_callback(
         &mut self,
         id: ParameterId,
         callback: ParameterUpdateCallback,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let index = id as usize;
+    ) -> Result<(), EconfError> {
+        self.add_change_callback(id, Arc::new(move |event: &ParameterChangeEvent| callback(event.id)))
+    }
+
+    /// Register a callback receiving the full change event (including the source of the write)
+    pub fn add_change_callback(
+        &mut self,
+        id: ParameterId,
+        callback: ParameterChangeCallback,
+    ) -> Result<(), EconfError> {
+        let index = id.index();
         if index < PARAMETERS_NUM {
             {
-                let mut data = self.runtime_data.lock().unwrap();
+                let mut data = self.runtime_data.lock();
                 data.parameters_data[index].callback = Some(callback);
                 info!("Callback added for ID {}", index);
             }
             Ok(())
         } else {
-            Err("Incorrect parameter ID".into())
+            Err(EconfError::NotFound(format!("Parameter ID {}", index)))
         }
     }
 
-    pub fn delete_callback(&mut self, id: ParameterId) -> Result<(), Box<dyn std::error::Error>> {
-        let index = id as usize;
+    pub fn delete_callback(&mut self, id: ParameterId) -> Result<(), EconfError> {
+        let index = id.index();
         if index < PARAMETERS_NUM {
             {
-                let mut data = self.runtime_data.lock().unwrap();
+                let mut data = self.runtime_data.lock();
                 data.parameters_data[index].callback = None;
                 info!("Callback removed for ID {}", index);
             }
             Ok(())
         } else {
-            Err("Incorrect parameter ID".into())
+            Err(EconfError::NotFound(format!("Parameter ID {}", index)))
         }
     }
 
-    pub fn notify_all_force(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for id in 0..PARAMETER_DATA.len() {
-            self.notifier.notify_of_parameter_change(ParameterId::try_from(id)?)?;
+    /// Add a callback of the parameter and return its handle. Unlike [`Self::add_change_callback`],
+    /// the callbacks added by other subscribers are kept
+    pub fn add_callback_ex(&self, id: ParameterId, callback: ParameterChangeCallback) -> Result<CallbackHandle, EconfError> {
+        let index = id.index();
+        if index >= PARAMETERS_NUM {
+            return Err(EconfError::NotFound(format!("Parameter ID {}", index)));
         }
-        Ok(())
+        let handle = next_callback_handle();
+        self.runtime_data.lock().parameters_data[index].subscribers.push((handle, callback));
+        info!("Callback {} added for ID {}", handle, index);
+        Ok(handle)
     }
 
-    pub fn load(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.database.lock().unwrap().load_database()?;
-        self.notify_all_force()
+    /// Call the hook before every write of the parameter by this process, with the current and the new value.
+    /// The hook returns the value to write, the new one or a rewritten one, or the reason to refuse the change,
+    /// returned to the caller as `EconfError::Vetoed`. The hooks are called in the order they were added,
+    /// without the instance locks. Removed with [`Self::delete_callback_handle`]
+    pub fn add_pre_change_hook(&self, id: ParameterId, hook: PreChangeHook) -> Result<CallbackHandle, EconfError> {
+        Self::check_writable(id)?;
+        let handle = next_callback_handle();
+        self.runtime_data.lock().parameters_data[id.index()].pre_change_hooks.push((handle, hook));
+        info!("Pre-change hook {} added for ID {}", handle, id.index());
+        Ok(handle)
     }
 
-    pub fn factory_reset(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.database.lock().unwrap().drop_database()?;
-        self.notify_all_force()
+    /// Call the callback once when any of the parameters changes, with the values of all of them read
+    /// together, for the settings derived from several parameters. The changes delivered in one
+    /// notification call the callback once. Removed with [`Self::delete_callback_handle`]
+    pub fn add_watch(&self, ids: &[ParameterId], callback: WatchCallback) -> Result<CallbackHandle, EconfError> {
+        if ids.is_empty() {
+            return Err(EconfError::Config("The watch has no parameters".to_owned()));
+        }
+        if let Some(id) = ids.iter().find(|id| id.index() >= PARAMETERS_NUM) {
+            return Err(EconfError::NotFound(format!("Parameter ID {}", id.index())));
+        }
+        let handle = next_callback_handle();
+        self.runtime_data.lock().watches.push(Watch { handle, ids: ids.to_vec(), callback });
+        info!("Watch {} added for IDs {:?}", handle, ids.iter().map(|id| id.index()).collect::<Vec<_>>());
+        Ok(handle)
     }
 
-    pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let filter = |key: &String| {
-            PARAMETER_DATA
-                .iter()
-                .enumerate()
-                .find(|(_, parameter)| parameter.name_id.to_string() == *key)
-                .and_then(|(id, _)| {
-                    let to_save = !PARAMETER_DATA[id].runtime;
-                    if to_save {
-                        info!("Saving parameter {}", key);
-                    }
-                    else {
-                        info!("Skipping runtime parameter {}", key);
-                    }
-                    Some(to_save)
-                })
-                .unwrap_or(false)
+    /// Add a constraint between the parameters, checked by `set` in this process when any of them is written.
+    /// The function gets the values of the parameters with the new one and accepts them, rejects the write
+    /// (`EconfError::Validation` naming the rule) or adjusts the values. The adjusted values of the other
+    /// parameters are written in one transaction with the parameter being set, without checking their rules again.
+    /// Removed with [`Self::delete_callback_handle`]
+    pub fn add_rule(&self, name: &str, ids: &[ParameterId], function: RuleFunction) -> Result<CallbackHandle, EconfError> {
+        if ids.is_empty() {
+            return Err(EconfError::Config(format!("The rule {} has no parameters", name)));
+        }
+        if let Some(id) = ids.iter().find(|id| id.index() >= PARAMETERS_NUM) {
+            return Err(EconfError::NotFound(format!("Parameter ID {}", id.index())));
+        }
+        let handle = next_callback_handle();
+        self.runtime_data.lock().rules.push(Rule { handle, name: name.to_owned(), ids: ids.to_vec(), function });
+        info!("Rule {} ({}) added for IDs {:?}", name, handle, ids.iter().map(|id| id.index()).collect::<Vec<_>>());
+        Ok(handle)
+    }
+
+    /// Remove a callback added with [`Self::add_callback_ex`], [`Self::add_watch`], [`Self::add_pre_change_hook`]
+    /// or a rule added with [`Self::add_rule`]. Can be called from a callback, including the removed one: it is not called again
+    /// after this returns. A call already running in another thread is not waited for
+    pub fn delete_callback_handle(&self, handle: CallbackHandle) -> Result<(), EconfError> {
+        let mut data = self.runtime_data.lock();
+        if let Some(position) = data.watches.iter().position(|watch| watch.handle == handle) {
+            data.watches.remove(position);
+            info!("Watch {} removed", handle);
+            return Ok(());
+        }
+        if let Some(position) = data.rules.iter().position(|rule| rule.handle == handle) {
+            let rule = data.rules.remove(position);
+            info!("Rule {} ({}) removed", rule.name, handle);
+            return Ok(());
+        }
+        for (index, parameter) in data.parameters_data.iter_mut().enumerate() {
+            if let Some(position) = parameter.subscribers.iter().position(|(subscriber, _)| *subscriber == handle) {
+                parameter.subscribers.remove(position);
+                info!("Callback {} removed for ID {}", handle, index);
+                return Ok(());
+            }
+            if let Some(position) = parameter.pre_change_hooks.iter().position(|(hook, _)| *hook == handle) {
+                parameter.pre_change_hooks.remove(position);
+                info!("Pre-change hook {} removed for ID {}", handle, index);
+                return Ok(());
+            }
+        }
+        Err(EconfError::NotFound(format!("Callback handle {}", handle)))
+    }
+
+    pub fn notify_all_force(&self, source: Option<&str>) -> Result<(), EconfError> {
+        let notifier = self.notifier()?;
+        let mut database = self.database.lock();
+        let sequence = database.allocate_sequence()?;
+        let ids: Vec<ParameterId> = (0..PARAMETER_DATA.len())
+            .filter_map(ParameterId::from_index)
+            .collect();
+        notifier.notify_of_parameters_change(&ids, source, sequence)
+    }
+
+    pub fn load(&self) -> Result<(), EconfError> {
+        self.load_with_progress(&|_, _| {})
+    }
+
+    /// Load the saved database, the progress is called with the pages copied and the total number of pages
+    pub fn load_with_progress(&self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.database.lock().load(progress)?;
+        self.notify_all_force(Some("load"))
+    }
+
+    /// Checkpoint and truncate the WAL file and release the free pages of the database file,
+    /// so the files don't keep growing on small flash storage
+    pub fn maintain(&self) -> Result<(), EconfError> {
+        self.database.lock().maintain()
+    }
+
+    /// Result of the integrity check done at the start and how the database was recovered
+    /// if it was corrupted, updated by [`Self::check_storage`]
+    pub fn get_storage_health(&self) -> StorageHealth {
+        self.database.lock().storage_health()
+    }
+
+    /// Run the integrity check of the database, `quick` uses quick_check that is faster on large
+    /// databases but doesn't verify the indexes. Returns the problems found, the database is not repaired
+    pub fn check_storage(&self, quick: bool) -> Result<Vec<String>, EconfError> {
+        self.database.lock().check_integrity(quick)
+    }
+
+    /// Checks of the instance itself: the database can be read and the notification receiver is running.
+    /// Cheap enough for frequent liveness probes
+    pub fn check_liveness(&self) -> Vec<HealthCheck> {
+        let database = match self.database.lock().current_sequence() {
+            Ok(sequence) => HealthCheck { name: "database", healthy: true, detail: format!("sequence {}", sequence) },
+            Err(e) => HealthCheck { name: "database", healthy: false, detail: e.to_string() },
+        };
+        let running = self.event_receiver.lock().is_running();
+        let receiver = HealthCheck {
+            name: "event_receiver",
+            healthy: running,
+            detail: if running { "running" } else { "stopped, the changes of the other processes are only polled" }.to_owned(),
+        };
+        vec![database, receiver]
+    }
+
+    /// The liveness checks, and that the WAL is not growing without the checkpoints
+    /// and the saved database can be read
+    pub fn check_readiness(&self) -> Vec<HealthCheck> {
+        let mut checks = self.check_liveness();
+        let database = self.database.lock();
+        checks.push(match database.wal_size() {
+            Some(size) if size > WAL_SIZE_LIMIT => HealthCheck {
+                name: "wal",
+                healthy: false,
+                detail: format!("{} bytes, over the limit of {}", size, WAL_SIZE_LIMIT),
+            },
+            Some(size) => HealthCheck { name: "wal", healthy: true, detail: format!("{} bytes", size) },
+            None => HealthCheck { name: "wal", healthy: true, detail: "not used".to_owned() },
+        });
+        checks.push(match database.check_saved() {
+            Ok(()) => HealthCheck { name: "saved_database", healthy: true, detail: "readable".to_owned() },
+            Err(e) => HealthCheck { name: "saved_database", healthy: false, detail: e.to_string() },
+        });
+        checks
+    }
+
+    /// Status of the file defaults of all the parameters that have them
+    pub fn defaults_health(&self) -> Vec<DefaultFileStatus> {
+        self.database
+            .lock()
+            .default_files_status()
+            .into_iter()
+            .map(|(id, path, result)| DefaultFileStatus {
+                id,
+                path: path.to_owned(),
+                error: result.err().map(|e| e.to_string()),
+            })
+            .collect()
+    }
+
+    /// Re-check the file defaults in the default data folder (a new calibration file was deployed),
+    /// and notify the parameters that use the changed defaults because they have no stored value.
+    /// Returns the notified parameters. Fails if a changed file can't be read
+    pub fn refresh_defaults(&self) -> Result<Vec<ParameterId>, EconfError> {
+        let mut database = self.database.lock();
+        let ids = database.refresh_default_files()?;
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+        // The read-only instances only update their own cache
+        if let Some(notifier) = &self.notifier {
+            let sequence = database.allocate_sequence()?;
+            notifier.notify_of_parameters_change(&ids, Some("refresh_defaults"), sequence)?;
+        }
+        drop(database);
+
+        self.runtime_data.lock().invalidate(&ids);
+        Ok(ids)
+    }
+
+    pub fn factory_reset(&self) -> Result<(), EconfError> {
+        self.database.lock().drop_values()?;
+        self.notify_all_force(Some("factory_reset"))
+    }
+
+    /// Reset only the parameters with the tag ("network") to the defaults and notify them.
+    /// The const and computed parameters are not stored and are skipped. Returns the reset parameters
+    pub fn reset_by_tag(&self, tag: &str) -> Result<Vec<ParameterId>, EconfError> {
+        let ids: Vec<ParameterId> = self
+            .tagged_parameters(tag)?
+            .into_iter()
+            .filter(|id| Self::check_writable(*id).is_ok())
+            .collect();
+        if ids.is_empty() {
+            return Ok(ids);
+        }
+        let notifier = self.notifier()?;
+        let mut database = self.database.lock();
+        let sequence = database.reset(&ids)?;
+        info!("Reset {} parameters with the tag {}", ids.len(), tag);
+        notifier.notify_of_parameters_change(&ids, Some("reset_by_tag"), sequence)?;
+        Ok(ids)
+    }
+
+    pub fn save(&self) -> Result<(), EconfError> {
+        self.save_with_progress(&|_, _| {})
+    }
+
+    /// Save the database, the progress is called with the parameters processed and the total number of them
+    pub fn save_with_progress(&self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.database.lock().save(&Self::save_filter, progress)
+    }
+
+    /// Whether anything was written since the last save by this instance, true until its first save.
+    /// The check of `start_autosave` with `only_if_dirty`
+    pub fn has_unsaved_changes(&self) -> Result<bool, EconfError> {
+        self.database.lock().has_unsaved_changes()
+    }
+
+    /// Parameters that save or load would change: the ones with different values in the working and
+    /// the saved database. The parameters never saved are compared with their defaults, the runtime
+    /// and computed parameters are not stored by save and are skipped
+    pub fn diff_saved(&self) -> Result<Vec<ParameterDiff>, EconfError> {
+        let ids: Vec<ParameterId> = (0..PARAMETER_DATA.len())
+            .filter_map(ParameterId::from_index)
+            .filter(|id| !PARAMETER_DATA[id.index()].runtime && !PARAMETER_DATA[id.index()].computed)
+            .collect();
+        let saved_values = self.database.lock().read_saved(&ids)?;
+        let mut diffs = Vec::new();
+        for (id, saved) in ids.into_iter().zip(saved_values) {
+            let current = self.get(id, false)?;
+            if current == saved {
+                sensitive::discard(id, current);
+                sensitive::discard(id, saved);
+            } else {
+                diffs.push(ParameterDiff { id, current, saved });
+            }
+        }
+        Ok(diffs)
+    }
+
+    /// Save only the parameters with the tag, the other parameters keep their saved values
+    /// and their changes stay unsaved
+    pub fn save_by_tag(&self, tag: &str) -> Result<(), EconfError> {
+        let names: Vec<&str> = self
+            .tagged_parameters(tag)?
+            .into_iter()
+            .map(|id| PARAMETER_DATA[id.index()].name_id)
+            .collect();
+        self.database
+            .lock()
+            .save_selected(&|key| names.contains(&key.as_str()) && Self::save_filter(key))
+    }
+
+    /// Values of the parameters with the tag ("calibration"), read in one transaction.
+    /// The computed parameters are skipped
+    pub fn export_by_tag(&self, tag: &str) -> Result<Vec<(ParameterId, ParameterValue)>, EconfError> {
+        let ids: Vec<ParameterId> = self
+            .tagged_parameters(tag)?
+            .into_iter()
+            .filter(|id| !PARAMETER_DATA[id.index()].computed)
+            .collect();
+        let values = self.database.lock().read_snapshot(&ids)?;
+        Ok(ids.into_iter().zip(values).collect())
+    }
+
+    /// Values of the parameters of the group, read in one transaction so they come from the same state.
+    /// The computed parameters are computed from the current values
+    pub fn get_group_values(&self, group: &str) -> Result<Vec<(ParameterId, ParameterValue)>, EconfError> {
+        if !GROUPS_DATA.iter().any(|data| data.name == group) {
+            return Err(EconfError::NotFound(format!("Group {}", group)));
+        }
+        let filter = ParameterFilter { group: Some(group.to_owned()), ..Default::default() };
+        let ids: Vec<ParameterId> = self.iter_parameters(filter).map(|(id, _)| id).collect();
+        let stored: Vec<ParameterId> = ids.iter().copied().filter(|id| !PARAMETER_DATA[id.index()].computed).collect();
+        let mut values = self.database.lock().read_snapshot(&stored)?.into_iter();
+        ids.into_iter()
+            .map(|id| {
+                let value = if PARAMETER_DATA[id.index()].computed {
+                    self.get_computed(id)?
+                } else {
+                    values.next().expect("Every stored parameter has a value in the snapshot")
+                };
+                Ok((id, value))
+            })
+            .collect()
+    }
+
+    /// Binary image of the parameters with the tag for a companion microcontroller, see `nvs.rs` and the
+    /// generated `econf_nvs.h` for the layout. The secret parameters can't be exported in clear
+    pub fn export_nvs(&self, tag: &str) -> Result<Vec<u8>, EconfError> {
+        let values = self.export_by_tag(tag)?;
+        let secret = values.iter().find(|(id, _)| PARAMETER_DATA[id.index()].secret).map(|(id, _)| *id);
+        let image = match secret {
+            Some(id) => Err(EconfError::Validation { id, reason: "secret parameters can't be exported".to_owned() }),
+            None => {
+                let entries: Vec<(&Parameter, &ParameterValue)> =
+                    values.iter().map(|(id, value)| (&PARAMETER_DATA[id.index()], value)).collect();
+                nvs::encode_nvs_image(&entries).map_err(|e| EconfError::Config(format!("NVS image of {}: {}", tag, e)))
+            }
         };
-        self.database.lock().unwrap().save_database(&filter)
+        for (id, value) in values {
+            sensitive::discard(id, value);
+        }
+        image
+    }
+
+    /// The parameters with the tag, a tag no parameter has is reported as not found
+    fn tagged_parameters(&self, tag: &str) -> Result<Vec<ParameterId>, EconfError> {
+        let filter = ParameterFilter { tags: vec![tag.to_owned()], ..Default::default() };
+        let ids: Vec<ParameterId> = self.iter_parameters(filter).map(|(id, _)| id).collect();
+        if ids.is_empty() {
+            return Err(EconfError::NotFound(format!("Tag {}", tag)));
+        }
+        Ok(ids)
+    }
+
+    /// Save the parameters as a named profile ("factory", "site-A", ...) next to the saved database.
+    /// The names can contain letters, digits, '-' and '_'. An existing profile is replaced
+    pub fn save_profile(&self, name: &str) -> Result<(), EconfError> {
+        self.database.lock().save_profile(name, &Self::save_filter, &|_, _| {})
+    }
+
+    /// Replace the parameters with the profile and notify the subscribers, like `load`
+    pub fn load_profile(&self, name: &str) -> Result<(), EconfError> {
+        self.database.lock().load_profile(name, &|_, _| {})?;
+        self.notify_all_force(Some("load_profile"))
+    }
+
+    pub fn list_profiles(&self) -> Result<Vec<String>, EconfError> {
+        self.database.lock().list_profiles()
+    }
+
+    pub fn delete_profile(&self, name: &str) -> Result<(), EconfError> {
+        self.database.lock().delete_profile(name)
+    }
+
+    /// Backups of the saved database kept by the `backup_retention` builder option, the newest first
+    pub fn list_backups(&self) -> Result<Vec<String>, EconfError> {
+        self.database.lock().list_backups()
+    }
+
+    /// Replace the parameters with the backup and notify the subscribers, like `load`.
+    /// The saved database is not changed until the next save
+    pub fn restore_backup(&self, name: &str) -> Result<(), EconfError> {
+        self.database.lock().restore_backup(name, &|_, _| {})?;
+        self.notify_all_force(Some("restore_backup"))
+    }
+
+    /// The parameters stored by save, the runtime parameters are skipped
+    fn save_filter(key: &String) -> bool {
+        ParameterId::from_name(key)
+            .map(|id| {
+                let to_save = !PARAMETER_DATA[id.index()].runtime;
+                if to_save {
+                    info!("Saving parameter {}", key);
+                }
+                else {
+                    info!("Skipping runtime parameter {}", key);
+                }
+                to_save
+            })
+            .unwrap_or(false)
     }
 }
 
-impl Drop for InterfaceInstance {
+impl<S: ParameterStore> Drop for InterfaceInstance<S> {
     fn drop(&mut self) {
-        self.stop_periodic_update();
+        self.shutdown();
     }
 }
\ No newline at end of file
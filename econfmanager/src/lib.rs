@@ -6,6 +6,20 @@ pub mod constants;
 pub mod database_utils;
 pub mod event_receiver;
 pub mod lib_helper_functions;
+pub mod lock_monitor;
+pub mod error;
+pub mod sensitive;
+pub mod manifest;
+pub mod nvs;
+pub mod transport;
+pub mod store;
+pub mod file_store;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+#[cfg(feature = "mqtt")]
+pub mod mqtt_bridge;
+#[cfg(feature = "async")]
+pub mod async_interface;
 
 include!(concat!(env!("OUT_DIR"), "/generated_mod.rs"));
 
@@ -21,19 +35,106 @@ pub mod generated;
 use std::io::Write;
 use std::time::Duration;
 use env_logger::Env;
-use lib_helper_functions::interface_execute;
+use lib_helper_functions::{interface_execute, FfiError};
 use log::error;
 use log::info;
 use parking_lot::Mutex;
 use std::{ffi::{c_char, CString}, ptr, sync::Arc};
-use interface::InterfaceInstance;
+use interface::{BlobReader, BlobWriter, CachePolicy, CallbackHandle, InterfaceInstance, InterfaceInstanceBuilder, ParameterChangeCallback, ParameterChangeEvent, WriteCondition};
 use generated::ParameterId;
+use schema::ParameterValue;
 use ansi_term::Colour;
+#[cfg(feature = "sqlcipher")]
+use constants::ENCRYPTION_KEY_MAX_LENGTH;
 
+/// Result of the C API calls, `econf_get_last_error` returns the message of the failure
 #[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum EconfStatus {
     StatusOk = 0,
-    StatusError = 1
+    /// Failures not covered by the other statuses: invalid arguments, I/O and configuration errors
+    StatusError = 1,
+    /// Unknown parameter ID, name or callback handle
+    StatusNotFound = 2,
+    /// The parameter has a different type than requested
+    StatusTypeMismatch = 3,
+    /// The value is rejected by the validation of the parameter
+    StatusValidationFailed = 4,
+    /// The parameter is const or read-only
+    StatusReadonly = 5,
+    /// The interface is locked by another thread
+    StatusLockTimeout = 6,
+    /// SQLite error or data not matching the schema
+    StatusDbError = 7,
+    /// The parameter was changed by another writer since the expected sequence, see `econf_set_value_if`
+    StatusConflict = 8,
+    /// Another process is replacing the database (load, factory reset), the call can be retried
+    StatusDbBusy = 9,
+}
+
+/// Type of the value in [`EconfValue`]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EconfValueType {
+    ValueNone = 0,
+    ValueBool = 1,
+    ValueI32 = 2,
+    ValueU32 = 3,
+    ValueI64 = 4,
+    ValueU64 = 5,
+    ValueF32 = 6,
+    ValueF64 = 7,
+    ValueString = 8,
+    ValueBlob = 9,
+    ValueEnum = 10,
+}
+
+/// What a successful write did, see `econf_set_value`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EconfSetResult {
+    /// The value was written
+    SetChanged = 0,
+    /// The value was the same as the current one, nothing was written
+    SetNotChanged = 1,
+    /// The value was out of the range of the parameter, the clamped value was written
+    SetClamped = 2,
+}
+
+/// Memory of a string or blob value, owned by the caller.
+/// For the reads `data` and `capacity` are set by the caller and `length` receives the size of the value
+/// (with the terminating null for the strings), the value is not copied if it is larger than `capacity`.
+/// For the writes the strings are null-terminated and the blobs have `length` bytes
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EconfBuffer {
+    pub data: *mut u8,
+    pub capacity: usize,
+    pub length: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union EconfValueData {
+    pub val_bool: bool,
+    pub val_i32: i32,
+    pub val_u32: u32,
+    pub val_i64: i64,
+    pub val_u64: u64,
+    pub val_f32: f32,
+    pub val_f64: f64,
+    pub val_enum: i32,
+    pub val_buffer: EconfBuffer,
+}
+
+/// Parameter value of the bulk functions, `value_type` selects the field of `data`.
+/// `status` receives the result for this parameter
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct EconfValue {
+    pub value_type: EconfValueType,
+    pub status: EconfStatus,
+    pub data: EconfValueData,
 }
 
 #[repr(C)]
@@ -94,6 +195,80 @@ impl Drop for CInterfaceInstance {
     }
 }
 
+fn init_logger() {
+    let start_time = std::time::Instant::now();
+    let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+    .format(move |buf, record| {
+        let file_name = record.file().unwrap_or("unknown");
+        let file_name = std::path::Path::new(file_name)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy();
+
+        // Calculate elapsed time since start in seconds with 3 decimal places
+        let elapsed = start_time.elapsed();
+        let timestamp = format!("{:.3}", elapsed.as_secs_f32());
+
+        // Color the level based on its severity
+        let level = match record.level() {
+            log::Level::Error => Colour::Red.paint("ERROR"),
+            log::Level::Warn => Colour::Yellow.paint("WARN "),
+            log::Level::Info => Colour::Green.paint("INFO "),
+            log::Level::Debug => Colour::Fixed(8).paint("DEBUG"),
+            log::Level::Trace => Colour::Purple.paint("TRACE"),
+        };
+
+        writeln!(
+            buf,
+            "{} {} {} {}",
+            Colour::Fixed(8).paint(timestamp),
+            level,
+            Colour::Fixed(8).paint(format!("{}:{}", file_name, record.line().unwrap_or(0))),
+            record.args()
+        )
+    })
+    .try_init();
+}
+
+/// Initialisation shared by the econf_init variants, `configure` sets the builder options besides the paths
+///
+/// # Safety
+/// See `econf_init`
+unsafe fn init_interface(
+        database_path: *const std::os::raw::c_char,
+        saved_database_path: *const std::os::raw::c_char,
+        default_data_folder: *const std::os::raw::c_char,
+        interface: *mut *mut CInterfaceInstance,
+        configure: impl FnOnce(InterfaceInstanceBuilder) -> InterfaceInstanceBuilder,
+    ) -> EconfStatus {
+    init_logger();
+
+    let database_path = unsafe { std::ffi::CStr::from_ptr(database_path).to_string_lossy().into_owned() };
+    let saved_database_path = unsafe { std::ffi::CStr::from_ptr(saved_database_path).to_string_lossy().into_owned() };
+    let default_data_folder = unsafe { std::ffi::CStr::from_ptr(default_data_folder).to_string_lossy().into_owned() };
+
+    let builder = InterfaceInstance::builder()
+        .database_path(&database_path)
+        .saved_database_path(&saved_database_path)
+        .default_data_folder(&default_data_folder);
+    let r_instance = match configure(builder).build() {
+        Ok(value) => value,
+        Err(e) => {
+            error!("Initialisation failed: {}", e);
+            // There is no interface yet, the error is read with a NULL interface
+            lib_helper_functions::set_last_error(ptr::null(), e.to_string());
+            return EconfStatus::from(&e);
+        }
+    };
+
+    let c_instance = CInterfaceInstance::new(r_instance);
+
+    unsafe { *interface = Box::into_raw(Box::new(c_instance))};
+
+    info!("Initialisation done: database_path={} saved_database_path={}", database_path, saved_database_path);
+    EconfStatus::StatusOk
+}
+
 #[unsafe(no_mangle)]
 /// Initialize the econf manager
 ///
@@ -109,56 +284,397 @@ pub unsafe extern "C" fn econf_init(
         default_data_folder: *const std::os::raw::c_char,
         interface: *mut *mut CInterfaceInstance
     ) -> EconfStatus {
-        let start_time = std::time::Instant::now();
-        let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .format(move |buf, record| {
-            let file_name = record.file().unwrap_or("unknown");
-            let file_name = std::path::Path::new(file_name)
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy();
-
-            // Calculate elapsed time since start in seconds with 3 decimal places
-            let elapsed = start_time.elapsed();
-            let timestamp = format!("{:.3}", elapsed.as_secs_f32());
-
-            // Color the level based on its severity
-            let level = match record.level() {
-                log::Level::Error => Colour::Red.paint("ERROR"),
-                log::Level::Warn => Colour::Yellow.paint("WARN "),
-                log::Level::Info => Colour::Green.paint("INFO "),
-                log::Level::Debug => Colour::Fixed(8).paint("DEBUG"),
-                log::Level::Trace => Colour::Purple.paint("TRACE"),
-            };
-
-            writeln!(
-                buf,
-                "{} {} {} {}",
-                Colour::Fixed(8).paint(timestamp),
-                level,
-                Colour::Fixed(8).paint(format!("{}:{}", file_name, record.line().unwrap_or(0))),
-                record.args()
-            )
-        })
-        .try_init();
+    unsafe { init_interface(database_path, saved_database_path, default_data_folder, interface, |builder| builder) }
+}
 
-    let database_path = unsafe { std::ffi::CStr::from_ptr(database_path).to_string_lossy().into_owned() };
-    let saved_database_path = unsafe { std::ffi::CStr::from_ptr(saved_database_path).to_string_lossy().into_owned() };
-    let default_data_folder = unsafe { std::ffi::CStr::from_ptr(default_data_folder).to_string_lossy().into_owned() };
+#[unsafe(no_mangle)]
+/// Initialize the econf manager with the device key of the parameters with the `secret` option.
+/// `econf_init` fails if the parameters have any
+///
+/// # Safety
+/// Same as `econf_init`, `key` must point to `key_length` readable bytes
+pub unsafe extern "C" fn econf_init_with_secret_key(
+        database_path: *const std::os::raw::c_char,
+        saved_database_path: *const std::os::raw::c_char,
+        default_data_folder: *const std::os::raw::c_char,
+        key: *const u8,
+        key_length: usize,
+        interface: *mut *mut CInterfaceInstance
+    ) -> EconfStatus {
+    if key.is_null() || key_length != 32 {
+        error!("The secret key must be 32 bytes, got {}", key_length);
+        lib_helper_functions::set_last_error(ptr::null(), format!("The secret key must be 32 bytes, got {}", key_length));
+        return EconfStatus::StatusError;
+    }
+    let mut secret_key = [0u8; 32];
+    secret_key.copy_from_slice(unsafe { std::slice::from_raw_parts(key, key_length) });
+    let status = unsafe { init_interface(database_path, saved_database_path, default_data_folder, interface, |builder| builder.secret_key(secret_key)) };
+    zeroize::Zeroize::zeroize(&mut secret_key);
+    status
+}
 
-    let r_instance = match InterfaceInstance::new(&database_path, &saved_database_path, &default_data_folder) {
-        Ok(value) => value,
-        Err(_) => return EconfStatus::StatusError,
-    };
+/// Writes the key to `key` (not NUL-terminated) and returns its length, at most `capacity`.
+/// Returns 0 if the key is not available
+#[cfg(feature = "sqlcipher")]
+pub type EconfKeyProvider = extern "C" fn(key: *mut c_char, capacity: usize, user_data: *mut std::ffi::c_void) -> usize;
 
-    let c_instance = CInterfaceInstance::new(r_instance);
+#[cfg(feature = "sqlcipher")]
+#[unsafe(no_mangle)]
+/// Initialize the econf manager with the databases encrypted by SQLCipher. The key provider is called
+/// once during the initialisation, the copies of the key are wiped when the interface is deleted
+///
+/// # Safety
+/// Same as `econf_init`, `key_provider` must not write more than `capacity` bytes to `key`
+pub unsafe extern "C" fn econf_init_encrypted(
+        database_path: *const std::os::raw::c_char,
+        saved_database_path: *const std::os::raw::c_char,
+        default_data_folder: *const std::os::raw::c_char,
+        key_provider: EconfKeyProvider,
+        user_data: *mut std::ffi::c_void,
+        interface: *mut *mut CInterfaceInstance
+    ) -> EconfStatus {
+    let mut buffer = zeroize::Zeroizing::new([0u8; ENCRYPTION_KEY_MAX_LENGTH]);
+    let length = key_provider(buffer.as_mut_ptr() as *mut c_char, buffer.len(), user_data);
+    if length == 0 || length > buffer.len() {
+        error!("Key provider returned an invalid key length {}", length);
+        lib_helper_functions::set_last_error(ptr::null(), format!("Invalid encryption key length {}", length));
+        return EconfStatus::StatusError;
+    }
+    let key = String::from_utf8_lossy(&buffer[..length]).into_owned();
+    unsafe { init_interface(database_path, saved_database_path, default_data_folder, interface, |builder| builder.encryption_key(key)) }
+}
 
-    unsafe { *interface = Box::into_raw(Box::new(c_instance))};
+#[unsafe(no_mangle)]
+/// Read a parameter of any type. The type of the value is set from the parameter,
+/// a string or a blob is copied to the buffer set by the caller in `data.val_buffer`
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `value` must be a valid pointer to a writable value
+/// - For a string or a blob parameter, the buffer must be writable for its `capacity`
+pub unsafe extern "C" fn econf_get_value(interface: *const CInterfaceInstance, id: ParameterId, value: *mut EconfValue) -> EconfStatus {
+    if value.is_null() {
+        error!("Null pointer provided for the value of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    let value = unsafe { &mut *value };
+    let status = interface_execute(interface, |interface| {
+        let parameter = interface.get(id, false)
+            .map_err(|e| FfiError::context(e, format!("Error getting ID {}", id as usize)))?;
+        let result = unsafe { lib_helper_functions::parameter_to_c_value(id, &parameter, value) };
+        sensitive::discard(id, parameter);
+        result
+    });
+    value.status = status;
+    status
+}
 
-    info!("Initialisation done: database_path={} saved_database_path={}", database_path, saved_database_path);
+#[unsafe(no_mangle)]
+/// Write a parameter of any type, the type of the value must match the parameter.
+/// `result` tells whether the value was changed or clamped, can be NULL
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - A string value must be null-terminated, a blob value must have `length` readable bytes
+/// - `result` must be NULL or a valid pointer to a writable `EconfSetResult`
+pub unsafe extern "C" fn econf_set_value(interface: *const CInterfaceInstance, id: ParameterId, value: EconfValue, result: *mut EconfSetResult) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let parameter = unsafe { lib_helper_functions::c_value_to_parameter(id, &value)? };
+        let outcome = interface.set_with_source(id, parameter, Some(lib_helper_functions::FFI_SOURCE))
+            .map_err(|e| FfiError::context(e, format!("Error setting ID {}", id as usize)))?;
+        unsafe { lib_helper_functions::set_result(&outcome, result) };
+        sensitive::discard(id, outcome.value);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Write a parameter only if it was not written since `expected_sequence` (from `econf_get_sequence`
+/// or the change callbacks), otherwise return `StatusConflict`. The value is written even if it is the same.
+/// `result` tells whether the value was clamped, can be NULL
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - A string value must be null-terminated, a blob value must have `length` readable bytes
+/// - `result` must be NULL or a valid pointer to a writable `EconfSetResult`
+pub unsafe extern "C" fn econf_set_value_if(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    value: EconfValue,
+    expected_sequence: u64,
+    result: *mut EconfSetResult,
+) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let parameter = unsafe { lib_helper_functions::c_value_to_parameter(id, &value)? };
+        let condition = WriteCondition::Sequence(expected_sequence);
+        let outcome = interface.set_if(id, parameter, condition, Some(lib_helper_functions::FFI_SOURCE))
+            .map_err(|e| FfiError::context(e, format!("Error setting ID {}", id as usize)))?;
+        unsafe { lib_helper_functions::set_result(&outcome, result) };
+        sensitive::discard(id, outcome.value);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the sequence number of the last write of the parameter for `econf_set_value_if`, 0 if it was never written
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `sequence` must be a valid pointer to a writable u64
+pub unsafe extern "C" fn econf_get_sequence(interface: *const CInterfaceInstance, id: ParameterId, sequence: *mut u64) -> EconfStatus {
+    if sequence.is_null() {
+        error!("Null pointer provided for the sequence of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |interface| {
+        let value = interface.get_sequence(id)
+            .map_err(|e| FfiError::context(e, format!("Error getting the sequence of ID {}", id as usize)))?;
+        unsafe { *sequence = value };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Stage a change of a parameter, written with the other staged changes by `econf_apply_staged`.
+/// Only the type is checked, the value is validated when applied
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - A string value must be null-terminated, a blob value must have `length` readable bytes
+pub unsafe extern "C" fn econf_set_staged(interface: *const CInterfaceInstance, id: ParameterId, value: EconfValue) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let parameter = unsafe { lib_helper_functions::c_value_to_parameter(id, &value)? };
+        interface.set_staged(id, parameter)
+            .map_err(|e| FfiError::context(e, format!("Error staging ID {}", id as usize)))?;
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Read the staged value of a parameter, the current value if no change is staged. See `econf_get_value`
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `value` must be a valid pointer to a writable value
+/// - For a string or a blob parameter, the buffer must be writable for its `capacity`
+pub unsafe extern "C" fn econf_get_staged(interface: *const CInterfaceInstance, id: ParameterId, value: *mut EconfValue) -> EconfStatus {
+    if value.is_null() {
+        error!("Null pointer provided for the staged value of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    let value = unsafe { &mut *value };
+    let status = interface_execute(interface, |interface| {
+        let parameter = interface.get_staged(id)
+            .map_err(|e| FfiError::context(e, format!("Error getting the staged ID {}", id as usize)))?;
+        let result = unsafe { lib_helper_functions::parameter_to_c_value(id, &parameter, value) };
+        sensitive::discard(id, parameter);
+        result
+    });
+    value.status = status;
+    status
+}
+
+#[unsafe(no_mangle)]
+/// Write the staged changes in one transaction, none of them if any is not accepted.
+/// The staged changes are kept if the write fails
+pub extern "C" fn econf_apply_staged(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let written = interface.apply_staged(Some(lib_helper_functions::FFI_SOURCE))?;
+        for (id, outcome) in written {
+            sensitive::discard(id, outcome.value);
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_discard_staged(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        interface.discard_staged();
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Read a parameter by name as a string, in the format of `econf_set_by_name`:
+/// numbers in decimal, booleans as `true`/`false`, enums as integers and blobs in base64
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `name` must be a valid pointer to a null-terminated C string
+/// - `out` must be a valid pointer to a writable buffer of at least `max_length` bytes
+pub unsafe extern "C" fn econf_get_by_name(interface: *const CInterfaceInstance, name: *const c_char, out: *mut c_char, max_length: usize) -> EconfStatus {
+    if name.is_null() || out.is_null() {
+        error!("Null pointer provided for the access by name");
+        return EconfStatus::StatusError;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned() };
+    interface_execute(interface, |interface| {
+        let id = lib_helper_functions::parameter_id_from_name(interface, &name)?;
+        let parameter = interface.get(id, false)
+            .map_err(|e| FfiError::context(e, format!("Error getting {}", name)))?;
+        let string = match &parameter {
+            ParameterValue::ValNone | ParameterValue::ValPath(_) => None,
+            // The string can be a copy of a sensitive value, it is wiped when dropped
+            parameter => Some(zeroize::Zeroizing::new(InterfaceInstance::value_to_string(parameter))),
+        };
+        sensitive::discard(id, parameter);
+        let string = string.ok_or_else(|| {
+            FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Value of {} can't be converted to a string", name))
+        })?;
+        let length = unsafe { lib_helper_functions::copy_string_to_c_buffer(&string, out, max_length, id)? };
+        if length > max_length {
+            return Err(format!("Buffer too small for {}: {} bytes needed", name, length).into());
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Write a parameter by name from a string, converted to the type of the parameter
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `name` and `value` must be valid pointers to null-terminated C strings
+pub unsafe extern "C" fn econf_set_by_name(interface: *const CInterfaceInstance, name: *const c_char, value: *const c_char) -> EconfStatus {
+    if name.is_null() || value.is_null() {
+        error!("Null pointer provided for the access by name");
+        return EconfStatus::StatusError;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name).to_string_lossy().into_owned() };
+    let value = zeroize::Zeroizing::new(unsafe { std::ffi::CStr::from_ptr(value).to_string_lossy().into_owned() });
+    interface_execute(interface, |interface| {
+        let id = lib_helper_functions::parameter_id_from_name(interface, &name)?;
+        let parameter = interface.set_from_string(id, &value).map_err(|e| {
+            FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Invalid value for {}: {}", name, e))
+        })?;
+        let outcome = interface.set_with_source(id, parameter, Some(lib_helper_functions::FFI_SOURCE))
+            .map_err(|e| FfiError::context(e, format!("Error setting {}", name)))?;
+        sensitive::discard(id, outcome.value);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Read several parameters under one lock of the interface. The type of every value is set from the parameter,
+/// the strings and blobs are copied to the buffers set by the caller in `data.val_buffer`.
+/// The result for each parameter is set in its `status`, the function returns the first failure
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `ids` must be a valid pointer to `count` parameter IDs
+/// - `values` must be a valid pointer to `count` writable values
+/// - The buffers of the string and blob parameters must be writable for their `capacity`
+pub unsafe extern "C" fn econf_get_all(interface: *const CInterfaceInstance, ids: *const ParameterId, count: usize, values: *mut EconfValue) -> EconfStatus {
+    if ids.is_null() || values.is_null() {
+        error!("Null pointer provided for the bulk read");
+        return EconfStatus::StatusError;
+    }
+    let ids = unsafe { std::slice::from_raw_parts(ids, count) };
+    let values = unsafe { std::slice::from_raw_parts_mut(values, count) };
+    interface_execute(interface, |interface| {
+        let mut first_error = None;
+        for (id, value) in ids.iter().zip(values.iter_mut()) {
+            let result = interface.get(*id, false)
+                .map_err(|e| FfiError::context(e, format!("Error getting ID {}", *id as usize)))
+                .and_then(|parameter| {
+                    let result = unsafe { lib_helper_functions::parameter_to_c_value(*id, &parameter, value) };
+                    sensitive::discard(*id, parameter);
+                    result
+                });
+            value.status = lib_helper_functions::result_status(&result);
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Write several parameters under one lock of the interface. The type of every value must match the parameter.
+/// All the values are written even if some fail, the result for each parameter is set in its `status`
+/// and the function returns the first failure
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `ids` must be a valid pointer to `count` parameter IDs
+/// - `values` must be a valid pointer to `count` values
+/// - The strings must be null-terminated and the blobs must have `length` readable bytes
+pub unsafe extern "C" fn econf_set_all(interface: *const CInterfaceInstance, ids: *const ParameterId, count: usize, values: *mut EconfValue) -> EconfStatus {
+    if ids.is_null() || values.is_null() {
+        error!("Null pointer provided for the bulk write");
+        return EconfStatus::StatusError;
+    }
+    let ids = unsafe { std::slice::from_raw_parts(ids, count) };
+    let values = unsafe { std::slice::from_raw_parts_mut(values, count) };
+    interface_execute(interface, |interface| {
+        let mut first_error = None;
+        for (id, value) in ids.iter().zip(values.iter_mut()) {
+            let result = unsafe { lib_helper_functions::c_value_to_parameter(*id, value) }
+                .and_then(|parameter| {
+                    interface.set_with_source(*id, parameter, Some(lib_helper_functions::FFI_SOURCE))
+                        .map_err(|e| FfiError::context(e, format!("Error setting ID {}", *id as usize)))
+                })
+                .map(|outcome| sensitive::discard(*id, outcome.value));
+            value.status = lib_helper_functions::result_status(&result);
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the message of the last failed call made by the calling thread on the interface,
+/// empty if there was none. The errors of `econf_init` are read with a NULL `interface`.
+/// The message is truncated to fit the buffer
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `buffer` must be a valid pointer to a writable buffer of at least `max_length` bytes
+pub unsafe extern "C" fn econf_get_last_error(interface: *const CInterfaceInstance, buffer: *mut c_char, max_length: usize) -> EconfStatus {
+    if buffer.is_null() || max_length == 0 {
+        error!("Invalid buffer provided for the last error");
+        return EconfStatus::StatusError;
+    }
+    let message = lib_helper_functions::last_error(interface);
+    let mut length = message.len().min(max_length - 1);
+    while !message.is_char_boundary(length) {
+        length -= 1;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, buffer, length);
+        *buffer.add(length) = 0;
+    }
     EconfStatus::StatusOk
 }
 
+#[unsafe(no_mangle)]
+/// Stop the timer poll and the notification receiver, and free the interface.
+/// On error the interface is not freed
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `interface` must be a pointer returned by `econf_init`
+/// - `interface` must not be used after this call, by any thread
+/// - The function must not be called from a parameter callback
+pub unsafe extern "C" fn econf_deinit(interface: *mut CInterfaceInstance) -> EconfStatus {
+    // The threads are stopped under the instance lock, so a callback still running
+    // can't call into the interface after it is freed
+    let status = interface_execute(interface, |interface| {
+        interface.shutdown();
+        Ok(())
+    });
+    if status == EconfStatus::StatusOk {
+        drop(unsafe { Box::from_raw(interface) });
+        lib_helper_functions::take_last_error(interface);
+        info!("Deinitialisation done");
+    }
+    status
+}
+
 #[unsafe(no_mangle)]
 /// Get the name of a parameter
 ///
@@ -169,23 +685,177 @@ pub unsafe extern "C" fn econf_init(
 /// - The buffer pointed to by `name` must be writable
 pub unsafe extern "C" fn econf_get_name(interface: *const CInterfaceInstance, id: ParameterId, name: *mut c_char, max_length: usize) -> EconfStatus {
     interface_execute(interface, |interface| {
-        let rust_string = interface.get_name(id);
+        unsafe { lib_helper_functions::copy_text(&interface.get_name(id), name, max_length) }
+    })
+}
 
-        let c_string = match CString::new(rust_string) {
-            Ok(s) => s,
-            Err(e) => return Err(Box::new(e)),
-        };
+/// Number of the parameters, use econf_get_parameter_id_by_index to get the IDs, they can have gaps
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_get_parameters_count() -> usize {
+    generated::PARAMETERS_NUM
+}
 
-        let bytes = c_string.as_bytes_with_nul();
-        
-        if bytes.len() > max_length {
-            return Err("Max length exceeded".into());
+#[unsafe(no_mangle)]
+/// Get the ID of the parameter with the index, for iterating over all the parameters
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `id` must be a valid pointer to a writable `ParameterId`
+pub unsafe extern "C" fn econf_get_parameter_id_by_index(index: usize, id: *mut ParameterId) -> EconfStatus {
+    match ParameterId::from_index(index) {
+        Some(parameter_id) => match unsafe { lib_helper_functions::write_out(id, parameter_id) } {
+            Ok(()) => EconfStatus::StatusOk,
+            Err(e) => {
+                error!("{}", e);
+                EconfStatus::StatusError
+            }
+        },
+        None => {
+            error!("Parameter index {} out of range", index);
+            EconfStatus::StatusNotFound
         }
+    }
+}
 
-        unsafe {
-            ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, name, bytes.len());
+/// Number of the parameter groups
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_get_groups_count() -> usize {
+    generated::GROUPS_DATA.len()
+}
+
+#[unsafe(no_mangle)]
+/// Get the name, title and comment of the group with the index. The buffers can be NULL
+/// for the fields that are not needed
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - The non-NULL buffers must be writable for their maximum length
+pub unsafe extern "C" fn econf_get_group_info(
+    index: usize,
+    name: *mut c_char,
+    name_max_length: usize,
+    title: *mut c_char,
+    title_max_length: usize,
+    comment: *mut c_char,
+    comment_max_length: usize,
+) -> EconfStatus {
+    let Some(group) = generated::GROUPS_DATA.get(index) else {
+        error!("Group index {} out of range", index);
+        return EconfStatus::StatusNotFound;
+    };
+    for (text, out, max_length) in [
+        (group.name, name, name_max_length),
+        (group.title, title, title_max_length),
+        (group.comment, comment, comment_max_length),
+    ] {
+        if out.is_null() {
+            continue;
         }
-        Ok(())
+        if let Err(e) = unsafe { lib_helper_functions::copy_text(text, out, max_length) } {
+            error!("Group {}: {}", group.name, e);
+            return EconfStatus::StatusError;
+        }
+    }
+    EconfStatus::StatusOk
+}
+
+#[unsafe(no_mangle)]
+/// Get the title of a parameter, shown in the user interfaces
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `title` must be a valid pointer to a writable buffer of at least `max_length` bytes
+pub unsafe extern "C" fn econf_get_title(interface: *const CInterfaceInstance, id: ParameterId, title: *mut c_char, max_length: usize) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        unsafe { lib_helper_functions::copy_text(&interface.get_title(id), title, max_length) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the comment of a parameter
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `comment` must be a valid pointer to a writable buffer of at least `max_length` bytes
+pub unsafe extern "C" fn econf_get_comment(interface: *const CInterfaceInstance, id: ParameterId, comment: *mut c_char, max_length: usize) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        unsafe { lib_helper_functions::copy_text(&interface.get_comment(id), comment, max_length) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the name of the group of a parameter
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `group` must be a valid pointer to a writable buffer of at least `max_length` bytes
+pub unsafe extern "C" fn econf_get_group(interface: *const CInterfaceInstance, id: ParameterId, group: *mut c_char, max_length: usize) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        unsafe { lib_helper_functions::copy_text(&interface.get_group(id), group, max_length) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the type of a parameter, the type of the values in `econf_get_value` and `econf_set_value`
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `value_type` must be a valid pointer to a writable `EconfValueType`
+pub unsafe extern "C" fn econf_get_type(interface: *const CInterfaceInstance, id: ParameterId, value_type: *mut EconfValueType) -> EconfStatus {
+    interface_execute(interface, |_| {
+        let parameter_type = &generated::PARAMETER_DATA[id.index()].value_type;
+        unsafe { lib_helper_functions::write_out(value_type, lib_helper_functions::c_value_type(parameter_type)) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Check if a parameter is const, the const parameters can't be changed at runtime
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `is_const` must be a valid pointer to a writable `bool`
+pub unsafe extern "C" fn econf_is_const(interface: *const CInterfaceInstance, id: ParameterId, is_const: *mut bool) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        unsafe { lib_helper_functions::write_out(is_const, interface.is_const(id)) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Check if a parameter is read-only for the external interfaces
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `is_readonly` must be a valid pointer to a writable `bool`
+pub unsafe extern "C" fn econf_is_readonly(interface: *const CInterfaceInstance, id: ParameterId, is_readonly: *mut bool) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        unsafe { lib_helper_functions::write_out(is_readonly, interface.is_readonly(id)) }
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Get the limits of a parameter validated by range. Returns `StatusNotFound` when the parameter
+/// has another validation method
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `min` and `max` must be valid pointers to writable values
+pub unsafe extern "C" fn econf_get_validation_range(interface: *const CInterfaceInstance, id: ParameterId, min: *mut EconfValue, max: *mut EconfValue) -> EconfStatus {
+    if min.is_null() || max.is_null() {
+        error!("Null pointer provided for the range of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |_| match &generated::PARAMETER_DATA[id.index()].validation {
+        schema::ValidationMethod::Range { min: range_min, max: range_max } => unsafe {
+            lib_helper_functions::parameter_to_c_value(id, range_min, &mut *min)?;
+            lib_helper_functions::parameter_to_c_value(id, range_max, &mut *max)?;
+            (*min).status = EconfStatus::StatusOk;
+            (*max).status = EconfStatus::StatusOk;
+            Ok(())
+        },
+        _ => Err(FfiError::with_status(
+            EconfStatus::StatusNotFound,
+            format!("Parameter {} has no range validation", id as usize),
+        )),
     })
 }
 
@@ -216,25 +886,121 @@ pub extern "C" fn econf_add_callback(interface: *const CInterfaceInstance, id: P
     // Now the closure only captures the Arc which is Send + Sync
     let cb_boxed = Arc::new(closure);
     interface_execute(interface, |interface| {
-        interface.add_callback(id, cb_boxed)
+        Ok(interface.add_callback(id, cb_boxed)?)
     })
 }
 
+/// Parameter change event passed to the change callbacks.
+/// `source` is a null-terminated string that is only valid during the callback, empty if unknown.
+/// `sequence` is the global write sequence (0 if unknown), it only grows for a parameter
+#[repr(C)]
+pub struct EconfParameterChangeEvent {
+    pub id: ParameterId,
+    pub source: *const c_char,
+    pub timestamp: f64,
+    pub sequence: u64,
+}
+
+pub type ParameterChangeCallbackFFI = extern "C" fn(event: *const EconfParameterChangeEvent, arg: *mut std::ffi::c_void);
+
+fn change_callback_ffi(callback: ParameterChangeCallbackFFI, user_data: *mut std::ffi::c_void) -> ParameterChangeCallback {
+    struct CallbackWrapper {
+        callback: ParameterChangeCallbackFFI,
+        user_data: *mut std::ffi::c_void,
+    }
+    
+    // SAFETY: We implement Send and Sync manually, assuming the callback and user_data
+    // are safe to use across threads.
+    unsafe impl Send for CallbackWrapper {}
+    unsafe impl Sync for CallbackWrapper {}
+    
+    let wrapper = Arc::new(CallbackWrapper {
+        callback,
+        user_data,
+    });
+    
+    let closure = move |event: &ParameterChangeEvent| {
+        let source = CString::new(event.source.clone().unwrap_or_default()).unwrap_or_default();
+        let c_event = EconfParameterChangeEvent {
+            id: event.id,
+            source: source.as_ptr(),
+            timestamp: event.timestamp,
+            sequence: event.sequence,
+        };
+        (wrapper.callback)(&c_event, wrapper.user_data);
+    };
+    
+    Arc::new(closure)
+}
+
 #[unsafe(no_mangle)]
-pub extern "C" fn econf_delete_callback(interface: *const CInterfaceInstance, id: ParameterId) -> EconfStatus {
+pub extern "C" fn econf_add_change_callback(interface: *const CInterfaceInstance, id: ParameterId, callback: ParameterChangeCallbackFFI, user_data: *mut std::ffi::c_void) -> EconfStatus {
+    let cb_boxed = change_callback_ffi(callback, user_data);
     interface_execute(interface, |interface| {
-        interface.delete_callback(id)
+        Ok(interface.add_change_callback(id, cb_boxed)?)
     })
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn econf_update_poll(interface: *const CInterfaceInstance) -> EconfStatus {
+/// Add a change callback of the parameter without replacing the other callbacks.
+/// The handle for `econf_delete_callback_handle` is written to `handle`
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `interface` must be a valid pointer to a CInterfaceInstance
+/// - `handle` must be a valid pointer to a writable `uint64_t`
+pub unsafe extern "C" fn econf_add_callback_ex(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    callback: ParameterChangeCallbackFFI,
+    user_data: *mut std::ffi::c_void,
+    handle: *mut CallbackHandle,
+) -> EconfStatus {
+    if handle.is_null() {
+        error!("Null pointer provided to handle");
+        return EconfStatus::StatusError;
+    }
+    let cb_boxed = change_callback_ffi(callback, user_data);
     interface_execute(interface, |interface| {
-        let _ = interface.update();
+        let new_handle = interface.add_callback_ex(id, cb_boxed)?;
+        unsafe { *handle = new_handle };
         Ok(())
     })
 }
 
+/// Remove a callback added with `econf_add_callback_ex`. Can be called from a callback, including
+/// the removed one. A call already running in another thread is not waited for
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_delete_callback_handle(interface: *const CInterfaceInstance, handle: CallbackHandle) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        Ok(interface.delete_callback_handle(handle)?)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_delete_callback(interface: *const CInterfaceInstance, id: ParameterId) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        Ok(interface.delete_callback(id)?)
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_update_poll(interface: *const CInterfaceInstance) -> EconfStatus {
+    // The callbacks are called after releasing the instance lock, so they can use the library functions
+    let mut event_receiver = None;
+    let status = interface_execute(interface, |interface| {
+        event_receiver = Some(interface.event_receiver());
+        Ok(())
+    });
+    if let Some(event_receiver) = event_receiver {
+        info!("Update called");
+        if let Err(e) = event_receiver.lock().update() {
+            error!("Update failed: {}", e);
+        }
+    }
+    status
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn econf_set_up_timer_poll(interface: *const CInterfaceInstance, timer_period_ms: i64) -> EconfStatus {
     interface_execute(interface, |interface_guard| {
@@ -243,6 +1009,38 @@ pub extern "C" fn econf_set_up_timer_poll(interface: *const CInterfaceInstance,
     })
 }
 
+/// Set how long the cached values are used before re-reading the database:
+/// `ttl_ms` < 0 keeps them until a change is notified (default), 0 disables the cache
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_set_cache_ttl(interface: *const CInterfaceInstance, ttl_ms: i64) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let policy = match ttl_ms {
+            ..0 => CachePolicy::Forever,
+            0 => CachePolicy::None,
+            ttl_ms => CachePolicy::Ttl(Duration::from_millis(ttl_ms as u64)),
+        };
+        interface.set_cache_policy(policy);
+        Ok(())
+    })
+}
+
+/// Wipe the cached values of the parameters tagged `sensitive`, for example when the device locks
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_purge_sensitive(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        interface.purge_sensitive();
+        Ok(())
+    })
+}
+
+/// Set the time after which a thread holding or waiting for an internal lock is logged with a warning
+/// and the backtraces (set RUST_BACKTRACE=1 to record the backtraces of the lock holders)
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_set_lock_warning_threshold(threshold_ms: u32) -> EconfStatus {
+    lock_monitor::set_warning_threshold(Duration::from_millis(threshold_ms.into()));
+    EconfStatus::StatusOk
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn econf_stop_timer_poll(interface: *const CInterfaceInstance) -> EconfStatus {
     interface_execute(interface, |interface| {
@@ -251,16 +1049,262 @@ pub extern "C" fn econf_stop_timer_poll(interface: *const CInterfaceInstance) ->
     })
 }
 
+/// Save the parameters every `period_ms` in a background thread, with `only_if_dirty`
+/// only when something was written since the last save
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_start_autosave(interface: *const CInterfaceInstance, period_ms: i64, only_if_dirty: bool) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        let period_ms = u64::try_from(period_ms)
+            .map_err(|_| FfiError::with_status(EconfStatus::StatusError, format!("Invalid autosave period {}", period_ms)))?;
+        interface.start_autosave(Duration::from_millis(period_ms), only_if_dirty);
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_stop_autosave(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        interface.stop_autosave();
+        Ok(())
+    })
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn econf_load(interface: *const CInterfaceInstance) -> EconfStatus {
     interface_execute(interface, |interface| {
-        interface.load()
+        Ok(interface.load()?)
     })
 }
 
 #[unsafe(no_mangle)]
 pub extern "C" fn econf_save(interface: *const CInterfaceInstance) -> EconfStatus {
     interface_execute(interface, |interface| {
-        interface.save()
+        Ok(interface.save()?)
+    })
+}
+
+/// Called with the amount of work done and the total, pages for the load and parameters for the save
+pub type EconfProgressCallback = extern "C" fn(done: u64, total: u64, user_data: *mut std::ffi::c_void);
+
+fn progress_ffi(callback: Option<EconfProgressCallback>, user_data: *mut std::ffi::c_void) -> impl Fn(u64, u64) {
+    move |done, total| {
+        if let Some(callback) = callback {
+            callback(done, total, user_data);
+        }
+    }
+}
+
+/// Load the saved database, reporting the progress to the callback
+///
+/// # Safety
+/// The callback may be NULL. It is called from the calling thread while the database is locked,
+/// so it must not call the library functions. `user_data` is passed to the callback as is
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_load_with_progress(
+    interface: *const CInterfaceInstance,
+    callback: Option<EconfProgressCallback>,
+    user_data: *mut std::ffi::c_void,
+) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        Ok(interface.load_with_progress(&progress_ffi(callback, user_data))?)
+    })
+}
+
+/// Save the database, reporting the progress to the callback
+///
+/// # Safety
+/// The same requirements as for `econf_load_with_progress`
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_save_with_progress(
+    interface: *const CInterfaceInstance,
+    callback: Option<EconfProgressCallback>,
+    user_data: *mut std::ffi::c_void,
+) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        Ok(interface.save_with_progress(&progress_ffi(callback, user_data))?)
+    })
+}
+
+/// Drop all the parameters, so the defaults are used, and notify the subscribers
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_factory_reset(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        Ok(interface.factory_reset()?)
+    })
+}
+
+/// Re-read the file defaults changed on disk and notify the parameters using them
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_refresh_defaults(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        interface.refresh_defaults()?;
+        Ok(())
+    })
+}
+
+/// Checkpoint and truncate the WAL file and release the free pages of the database
+#[unsafe(no_mangle)]
+pub extern "C" fn econf_maintain(interface: *const CInterfaceInstance) -> EconfStatus {
+    interface_execute(interface, |interface| {
+        interface.maintain()?;
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Export the parameters with the tag as a binary image for a microcontroller, the layout is described
+/// in the generated `econf_nvs.h`. `size` receives the size of the image, call with a NULL buffer
+/// and 0 capacity to get it
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `tag` must be a valid pointer to a null-terminated C string
+/// - `buffer` must be writable for `capacity` bytes, or NULL if `capacity` is 0
+/// - `size` must be a valid pointer to a writable value
+pub unsafe extern "C" fn econf_export_nvs(interface: *const CInterfaceInstance, tag: *const c_char, buffer: *mut u8, capacity: usize, size: *mut usize) -> EconfStatus {
+    if tag.is_null() || size.is_null() || (buffer.is_null() && capacity != 0) {
+        error!("Null pointer provided for the NVS export");
+        return EconfStatus::StatusError;
+    }
+    let tag = unsafe { std::ffi::CStr::from_ptr(tag).to_string_lossy().into_owned() };
+    interface_execute(interface, |interface| {
+        let image = interface.export_nvs(&tag)
+            .map_err(|e| FfiError::context(e, format!("Error exporting tag {}", tag)))?;
+        unsafe { *size = image.len() };
+        if capacity == 0 {
+            return Ok(());
+        }
+        if image.len() > capacity {
+            return Err(format!("Buffer too small for the image of {}: {} bytes needed", tag, image.len()).into());
+        }
+        unsafe { ptr::copy_nonoverlapping(image.as_ptr(), buffer, image.len()) };
+        Ok(())
+    })
+}
+
+/// Chunked read or write of a blob parameter, opened by `econf_blob_open_read` or `econf_blob_open_write`
+/// and released by `econf_blob_close`
+pub enum EconfBlobStream {
+    Read(BlobReader),
+    Write(BlobWriter),
+}
+
+#[unsafe(no_mangle)]
+/// Open a blob parameter for the chunked reads with `econf_blob_read_chunk`, so the caller doesn't need
+/// a buffer for the whole blob. The stream sees the value at the time of the opening
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `stream` must be a valid pointer to a writable stream pointer
+/// - `size` must be a valid pointer to a writable value, it receives the size of the blob
+pub unsafe extern "C" fn econf_blob_open_read(interface: *const CInterfaceInstance, id: ParameterId, stream: *mut *mut EconfBlobStream, size: *mut usize) -> EconfStatus {
+    if stream.is_null() || size.is_null() {
+        error!("Null pointer provided for the blob stream of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |interface| {
+        let reader = interface.blob_open_read(id)
+            .map_err(|e| FfiError::context(e, format!("Error opening ID {} for reading", id as usize)))?;
+        unsafe {
+            *size = reader.size();
+            *stream = Box::into_raw(Box::new(EconfBlobStream::Read(reader)));
+        }
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Read the next chunk of the blob, `length` receives the number of bytes read, 0 at the end of the blob
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `stream` must be opened by `econf_blob_open_read` and not closed
+/// - `buffer` must be writable for `capacity` bytes
+/// - `length` must be a valid pointer to a writable value
+pub unsafe extern "C" fn econf_blob_read_chunk(interface: *const CInterfaceInstance, stream: *mut EconfBlobStream, buffer: *mut u8, capacity: usize, length: *mut usize) -> EconfStatus {
+    if stream.is_null() || buffer.is_null() || length.is_null() {
+        error!("Null pointer provided for the blob chunk");
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |interface| {
+        let EconfBlobStream::Read(reader) = (unsafe { &mut *stream }) else {
+            return Err(FfiError::with_status(EconfStatus::StatusError, "The blob stream is opened for writing".to_owned()));
+        };
+        let buffer = unsafe { std::slice::from_raw_parts_mut(buffer, capacity) };
+        let read = interface.blob_read_chunk(reader, buffer)
+            .map_err(|e| FfiError::context(e, format!("Error reading ID {}", reader.id() as usize)))?;
+        unsafe { *length = read };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Open a blob parameter for writing `size` bytes in chunks with `econf_blob_write_chunk`.
+/// The parameter is changed by `econf_blob_close` when all the bytes are written
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `stream` must be a valid pointer to a writable stream pointer
+pub unsafe extern "C" fn econf_blob_open_write(interface: *const CInterfaceInstance, id: ParameterId, size: usize, stream: *mut *mut EconfBlobStream) -> EconfStatus {
+    if stream.is_null() {
+        error!("Null pointer provided for the blob stream of {}", id as usize);
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |interface| {
+        let writer = interface.blob_open_write(id, size)
+            .map_err(|e| FfiError::context(e, format!("Error opening ID {} for writing", id as usize)))?;
+        unsafe { *stream = Box::into_raw(Box::new(EconfBlobStream::Write(writer))) };
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Write the next chunk of the blob, the chunks can't go past the size given to `econf_blob_open_write`
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `stream` must be opened by `econf_blob_open_write` and not closed
+/// - `data` must be readable for `length` bytes
+pub unsafe extern "C" fn econf_blob_write_chunk(interface: *const CInterfaceInstance, stream: *mut EconfBlobStream, data: *const u8, length: usize) -> EconfStatus {
+    if stream.is_null() || data.is_null() {
+        error!("Null pointer provided for the blob chunk");
+        return EconfStatus::StatusError;
+    }
+    interface_execute(interface, |interface| {
+        let EconfBlobStream::Write(writer) = (unsafe { &mut *stream }) else {
+            return Err(FfiError::with_status(EconfStatus::StatusError, "The blob stream is opened for reading".to_owned()));
+        };
+        let data = unsafe { std::slice::from_raw_parts(data, length) };
+        interface.blob_write_chunk(writer, data)
+            .map_err(|e| FfiError::context(e, format!("Error writing ID {}", writer.id() as usize)))?;
+        Ok(())
+    })
+}
+
+#[unsafe(no_mangle)]
+/// Close the blob stream and release it. For a write stream the blob is stored in the parameter
+/// if all the bytes were written, otherwise it is discarded and `StatusValidationFailed` is returned
+///
+/// # Safety
+/// This function is unsafe because it operates on raw pointers. The caller must ensure:
+/// - `stream` must be opened by `econf_blob_open_read` or `econf_blob_open_write` and not closed,
+///   it must not be used after the call
+pub unsafe extern "C" fn econf_blob_close(interface: *const CInterfaceInstance, stream: *mut EconfBlobStream) -> EconfStatus {
+    if stream.is_null() {
+        error!("Null pointer provided for the blob stream");
+        return EconfStatus::StatusError;
+    }
+    let stream = unsafe { Box::from_raw(stream) };
+    interface_execute(interface, |interface| match *stream {
+        EconfBlobStream::Read(reader) => {
+            let id = reader.id();
+            interface.blob_close_read(reader)
+                .map_err(|e| FfiError::context(e, format!("Error closing ID {}", id as usize)))
+        }
+        EconfBlobStream::Write(writer) => {
+            let id = writer.id();
+            interface.blob_close_write(writer, Some(lib_helper_functions::FFI_SOURCE))
+                .map_err(|e| FfiError::context(e, format!("Error writing ID {}", id as usize)))
+        }
     })
 }
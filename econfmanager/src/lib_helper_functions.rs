@@ -1,19 +1,120 @@
 use std::{
     any::type_name,
+    cell::RefCell,
+    collections::HashMap,
+    error::Error,
     ffi::{CStr, CString, c_char},
-    ptr, slice,
+    fmt, ptr, slice,
     time::Duration,
 };
 
 use log::{debug, error};
+use zeroize::Zeroizing;
 
 use crate::{
-    CInterfaceInstance, EconfStatus, InterfaceInstance,
-    generated::ParameterId,
-    schema::{ParameterType, ParameterValue},
+    CInterfaceInstance, EconfSetResult, EconfStatus, EconfValue, EconfValueData, EconfValueType, InterfaceInstance,
+    error::EconfError,
+    interface::SetOutcome,
+    generated::{PARAMETER_DATA, ParameterId},
+    schema::{ParameterType, ParameterValue, ParameterValueType},
+    sensitive,
 };
 
 const LOCK_TRYING_DURATION: Duration = Duration::from_secs(1);
+/// Source recorded for the writes made through the C API
+pub(crate) const FFI_SOURCE: &str = "ffi";
+
+/// Error of a C API call, with the status returned to the caller
+#[derive(Debug)]
+pub(crate) struct FfiError {
+    status: EconfStatus,
+    message: String,
+}
+
+impl FfiError {
+    pub(crate) fn with_status(status: EconfStatus, message: String) -> Box<dyn Error> {
+        Box::new(Self { status, message })
+    }
+
+    /// Keep the status of the interface error, with the context added to the message
+    pub(crate) fn context(error: EconfError, context: String) -> Box<dyn Error> {
+        Box::new(Self { status: EconfStatus::from(&error), message: format!("{} - {}", context, error) })
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for FfiError {}
+
+impl From<&EconfError> for EconfStatus {
+    fn from(error: &EconfError) -> Self {
+        match error {
+            EconfError::NotFound(_) => EconfStatus::StatusNotFound,
+            EconfError::Validation { .. } | EconfError::Vetoed { .. } => EconfStatus::StatusValidationFailed,
+            EconfError::TypeMismatch { .. } => EconfStatus::StatusTypeMismatch,
+            EconfError::Const(_) | EconfError::ReadOnly(_) | EconfError::ReadOnlyInstance => EconfStatus::StatusReadonly,
+            EconfError::Conflict(_) => EconfStatus::StatusConflict,
+            EconfError::LockTimeout => EconfStatus::StatusLockTimeout,
+            EconfError::DbBusy => EconfStatus::StatusDbBusy,
+            EconfError::Database(_) | EconfError::Corrupt(_) | EconfError::Schema(_) => EconfStatus::StatusDbError,
+            EconfError::Io(_) | EconfError::Config(_) | EconfError::Unsupported(_) => EconfStatus::StatusError,
+        }
+    }
+}
+
+fn error_status(error: &(dyn Error + 'static)) -> EconfStatus {
+    if let Some(error) = error.downcast_ref::<FfiError>() {
+        error.status
+    } else if let Some(error) = error.downcast_ref::<EconfError>() {
+        EconfStatus::from(error)
+    } else {
+        EconfStatus::StatusError
+    }
+}
+
+pub(crate) fn result_status<T>(result: &Result<T, Box<dyn Error>>) -> EconfStatus {
+    match result {
+        Ok(_) => EconfStatus::StatusOk,
+        Err(e) => error_status(e.as_ref()),
+    }
+}
+
+/// Report the outcome of a write to the optional `EconfSetResult` of the caller
+///
+/// # Safety
+/// `result` must be NULL or a valid pointer to a writable `EconfSetResult`
+pub(crate) unsafe fn set_result(outcome: &SetOutcome, result: *mut EconfSetResult) {
+    if result.is_null() {
+        return;
+    }
+    let value = match outcome {
+        SetOutcome { clamped: true, .. } => EconfSetResult::SetClamped,
+        SetOutcome { changed: true, .. } => EconfSetResult::SetChanged,
+        SetOutcome { .. } => EconfSetResult::SetNotChanged,
+    };
+    unsafe { *result = value };
+}
+
+thread_local! {
+    /// Message of the last failed call of the thread, for each interface
+    static LAST_ERROR: RefCell<HashMap<usize, String>> = RefCell::new(HashMap::new());
+}
+
+pub(crate) fn set_last_error(interface: *const CInterfaceInstance, message: String) {
+    LAST_ERROR.with(|errors| errors.borrow_mut().insert(interface as usize, message));
+}
+
+pub(crate) fn take_last_error(interface: *const CInterfaceInstance) -> Option<String> {
+    LAST_ERROR.with(|errors| errors.borrow_mut().remove(&(interface as usize)))
+}
+
+pub(crate) fn last_error(interface: *const CInterfaceInstance) -> String {
+    LAST_ERROR.with(|errors| errors.borrow().get(&(interface as usize)).cloned().unwrap_or_default())
+}
 
 macro_rules! validate_ptr {
     ($ptr:expr, $type:ty) => {
@@ -30,18 +131,20 @@ where
 {
     validate_ptr!(interface, CInterfaceInstance);
 
+    let interface_ptr = interface;
     let interface = unsafe { &*interface };
     match interface.with_lock(|lock| {
         lock.try_lock_for(LOCK_TRYING_DURATION)
             .map(|mut guard| f(&mut guard))
             .unwrap_or_else(|| {
                 error!("Failed to acquire lock within timeout");
-                Err("Lock timeout".into())
+                Err(EconfError::LockTimeout.into())
             })
             .map(|_| EconfStatus::StatusOk)
             .unwrap_or_else(|e| {
                 error!("Operation failed: {}", e);
-                EconfStatus::StatusError
+                set_last_error(interface_ptr, e.to_string());
+                error_status(e.as_ref())
             })
     }) {
         Ok(status) => status,
@@ -49,30 +152,30 @@ where
     }
 }
 
-pub(crate) fn get_parameter_quick<T: ParameterType>(
+/// Convert the parameter value, the enums are read as i32
+fn convert_parameter<T: ParameterType>(parameter: ParameterValue) -> Option<T> {
+    match parameter {
+        ParameterValue::ValEnum(val) => T::from_parameter_value(ParameterValue::ValI32(val)),
+        parameter => T::from_parameter_value(parameter),
+    }
+}
+
+/// Get the parameter or its default when the read fails. The result is written to `ok` if it's not NULL
+pub(crate) fn get_parameter_quick<T: ParameterType + Default>(
     interface: *const CInterfaceInstance,
     id: ParameterId,
+    ok: *mut bool,
 ) -> T {
     debug!("Get ID {} quick:{}", id as usize, type_name::<T>());
     let mut out_parameter = None;
-    interface_execute(interface, |interface| match interface.get(id, false) {
+    let status = interface_execute(interface, |interface| match interface.get(id, false) {
         Ok(parameter) => {
-            if let Some(ret_val) = T::from_parameter_value(parameter.clone()) {
-                out_parameter = Some(ret_val);
+            out_parameter = convert_parameter::<T>(parameter.clone());
+            if out_parameter.is_some() {
                 Ok(())
-            } else if let ParameterValue::ValEnum(val) = parameter {
-                if let Some(ret_val) = T::from_parameter_value(ParameterValue::ValI32(val))
-                {
-                    out_parameter = Some(ret_val);
-                    Ok(())
-                }
-                else {
-                    error!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>());
-                    Err(format!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>()).into())
-                }
             } else {
-                error!("Error converting ID {}:{}", id as usize, type_name::<T>());
-                Err(format!("Error converting ID {}:{}", id as usize, type_name::<T>()).into())
+                error!("Error converting ID {}:{} parameter {}", id as usize, type_name::<T>(), sensitive::log_value(id, &parameter));
+                Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Error converting ID {}:{}", id as usize, type_name::<T>())))
             }
         }
         Err(e) => {
@@ -82,19 +185,26 @@ pub(crate) fn get_parameter_quick<T: ParameterType>(
                 type_name::<T>(),
                 e
             );
-            Err(format!(
-                "Error getting ID {}:{} - {}",
-                id as usize,
-                type_name::<T>(),
-                e
-            )
-            .into())
+            Err(FfiError::context(e, format!("Error getting ID {}:{}", id as usize, type_name::<T>())))
         }
     });
-    match out_parameter {
-        Some(val) => val,
-        // TODO: This may not be correct
-        None => T::from_parameter_value(ParameterValue::ValNone).unwrap(),
+    if !ok.is_null() {
+        unsafe { *ok = status == EconfStatus::StatusOk };
+    }
+    out_parameter.unwrap_or_else(|| {
+        PARAMETER_DATA
+            .get(id.index())
+            .and_then(|parameter| convert_parameter::<T>(parameter.value_default.clone()))
+            .unwrap_or_default()
+    })
+}
+
+/// Read the current or the default value of the parameter
+fn read_parameter(interface: &InterfaceInstance, id: ParameterId, default: bool) -> Result<ParameterValue, EconfError> {
+    if default {
+        interface.get_default(id)
+    } else {
+        interface.get(id, false)
     }
 }
 
@@ -103,8 +213,25 @@ pub(crate) fn get_parameter<T: ParameterType>(
     id: ParameterId,
     out_parameter: *mut T,
 ) -> EconfStatus {
-    debug!("Get ID {}:{}", id as usize, type_name::<T>());
-    interface_execute(interface, |interface| match interface.get(id, false) {
+    read_parameter_to::<T>(interface, id, out_parameter, false)
+}
+
+pub(crate) fn get_parameter_default<T: ParameterType>(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_parameter: *mut T,
+) -> EconfStatus {
+    read_parameter_to::<T>(interface, id, out_parameter, true)
+}
+
+fn read_parameter_to<T: ParameterType>(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_parameter: *mut T,
+    default: bool,
+) -> EconfStatus {
+    debug!("Get ID {}:{}{}", id as usize, type_name::<T>(), if default { " default" } else { "" });
+    interface_execute(interface, |interface| match read_parameter(interface, id, default) {
         Ok(parameter) => {
             if let Some(ret_val) = T::from_parameter_value(parameter.clone()) {
                 if out_parameter.is_null() {
@@ -125,11 +252,11 @@ pub(crate) fn get_parameter<T: ParameterType>(
                 }
                 else {
                     error!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>());
-                    Err(format!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>()).into())
+                    Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>())))
                 }
             }else {
-                error!("Error converting ID {}:{} paraemeter {}", id as usize, type_name::<T>(), &parameter);
-                Err(format!("Error converting ID {}:{} paraemeter {}", id as usize, type_name::<T>(), &parameter).into())
+                error!("Error converting ID {}:{} parameter {}", id as usize, type_name::<T>(), sensitive::log_value(id, &parameter));
+                Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Error converting ID {}:{}", id as usize, type_name::<T>())))
             }
         }
         Err(e) => {
@@ -139,13 +266,7 @@ pub(crate) fn get_parameter<T: ParameterType>(
                 type_name::<T>(),
                 e
             );
-            Err(format!(
-                "Error getting ID {}:{} - {}",
-                id as usize,
-                type_name::<T>(),
-                e
-            )
-            .into())
+            Err(FfiError::context(e, format!("Error getting ID {}:{}", id as usize, type_name::<T>())))
         }
     })
 }
@@ -158,8 +279,8 @@ pub(crate) fn set_parameter<T: ParameterType>(
 ) -> EconfStatus {
     debug!("Set ID {}:{}", id as usize, type_name::<T>());
     interface_execute(interface, |interface| {
-        match interface.set(id, parameter.to_parameter_value()) {
-            Ok(parameter) => {
+        match interface.set_with_source(id, parameter.to_parameter_value(), Some(FFI_SOURCE)) {
+            Ok(SetOutcome { value: parameter, .. }) => {
                 if let Some(ret_val) = T::from_parameter_value(parameter.clone()) {
                     if !out_parameter.is_null() {
                         unsafe { *out_parameter = ret_val };
@@ -175,11 +296,11 @@ pub(crate) fn set_parameter<T: ParameterType>(
                     }
                     else {
                         error!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>());
-                        Err(format!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>()).into())
+                        Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Error converting ID for Enum {}:{}", id as usize, type_name::<T>())))
                     }
                 } else {
                     error!("Error converting ID {}:{}", id as usize, type_name::<T>());
-                    Err(format!("Error converting ID {}:{}", id as usize, type_name::<T>()).into())
+                    Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Error converting ID {}:{}", id as usize, type_name::<T>())))
                 }
             }
             Err(e) => {
@@ -189,13 +310,7 @@ pub(crate) fn set_parameter<T: ParameterType>(
                     type_name::<T>(),
                     e
                 );
-                Err(format!(
-                    "Error setting ID {}:{} - {}",
-                    id as usize,
-                    type_name::<T>(),
-                    e
-                )
-                .into())
+                Err(FfiError::context(e, format!("Error setting ID {}:{}", id as usize, type_name::<T>())))
             }
         }
     })
@@ -215,7 +330,8 @@ pub unsafe fn copy_string_to_c_buffer(
         }
     };
 
-    let bytes = c_str.as_bytes_with_nul();
+    // The temporary copy can hold a sensitive value, it is wiped when dropped
+    let bytes = Zeroizing::new(c_str.into_bytes_with_nul());
     
     if out_c_string.is_null() {
         return Ok(bytes.len());
@@ -250,21 +366,46 @@ pub(crate) fn get_string(
     max_len: usize,
     out_len: *mut usize,
 ) -> EconfStatus {
-    debug!("Get ID {}: string", id as usize);
-    interface_execute(interface, |interface| match interface.get(id, false) {
-        Ok(parameter) => match parameter {
-            ParameterValue::ValString(val_str) => {
-                let bytes_copied = unsafe { copy_string_to_c_buffer(&val_str, out_c_string, max_len, id)? };
-                if !out_len.is_null(){
-                    unsafe { *out_len = bytes_copied };
+    read_string_to(interface, id, out_c_string, max_len, out_len, false)
+}
+
+pub(crate) fn get_string_default(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_c_string: *mut c_char,
+    max_len: usize,
+    out_len: *mut usize,
+) -> EconfStatus {
+    read_string_to(interface, id, out_c_string, max_len, out_len, true)
+}
+
+fn read_string_to(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_c_string: *mut c_char,
+    max_len: usize,
+    out_len: *mut usize,
+    default: bool,
+) -> EconfStatus {
+    debug!("Get ID {}: string{}", id as usize, if default { " default" } else { "" });
+    interface_execute(interface, |interface| match read_parameter(interface, id, default) {
+        Ok(parameter) => {
+            let result = match &parameter {
+                ParameterValue::ValString(val_str) => {
+                    let bytes_copied = unsafe { copy_string_to_c_buffer(val_str, out_c_string, max_len, id)? };
+                    if !out_len.is_null(){
+                        unsafe { *out_len = bytes_copied };
+                    }
+                    Ok(())
                 }
-                Ok(())
-            }
-            _ => {
-                Err(format!("Wrong type requested for ID {}: string", id as usize).into())
-            }
-        },
-        Err(e) => Err(format!("Error getting ID {}: string - {}", id as usize, e).into()),
+                _ => {
+                    Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Wrong type requested for ID {}: string", id as usize)))
+                }
+            };
+            sensitive::discard(id, parameter);
+            result
+        }
+        Err(e) => Err(FfiError::context(e, format!("Error getting ID {}: string", id as usize))),
     })
 }
 
@@ -283,9 +424,12 @@ pub(crate) fn set_string(
             }
         };
         let parameter = ParameterValue::ValString(rust_string.into());
-        match interface.set(id, parameter) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Error setting ID {}: string - {}", id as usize, e).into()),
+        match interface.set_with_source(id, parameter, Some(FFI_SOURCE)) {
+            Ok(outcome) => {
+                sensitive::discard(id, outcome.value);
+                Ok(())
+            }
+            Err(e) => Err(FfiError::context(e, format!("Error setting ID {}: string", id as usize))),
         }
     })
 }
@@ -334,19 +478,45 @@ pub(crate) fn get_blob(
     max_len: usize,
     out_len: *mut usize,
 ) -> EconfStatus {
-    debug!("Get ID {}: blob", id as usize);
-    interface_execute(interface, |interface| match interface.get(id, false) {
-        Ok(parameter) => match parameter {
-            ParameterValue::ValBlob(blob) => {
-                let bytes_copied = unsafe { copy_blob_to_c_buffer(&blob, out_buffer, max_len)? };
-                if !out_len.is_null(){
-                    unsafe { *out_len = bytes_copied };
+    read_blob_to(interface, id, out_buffer, max_len, out_len, false)
+}
+
+#[allow(dead_code)]
+pub(crate) fn get_blob_default(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_buffer: *mut u8,
+    max_len: usize,
+    out_len: *mut usize,
+) -> EconfStatus {
+    read_blob_to(interface, id, out_buffer, max_len, out_len, true)
+}
+
+fn read_blob_to(
+    interface: *const CInterfaceInstance,
+    id: ParameterId,
+    out_buffer: *mut u8,
+    max_len: usize,
+    out_len: *mut usize,
+    default: bool,
+) -> EconfStatus {
+    debug!("Get ID {}: blob{}", id as usize, if default { " default" } else { "" });
+    interface_execute(interface, |interface| match read_parameter(interface, id, default) {
+        Ok(parameter) => {
+            let result = match &parameter {
+                ParameterValue::ValBlob(blob) => {
+                    let bytes_copied = unsafe { copy_blob_to_c_buffer(blob, out_buffer, max_len)? };
+                    if !out_len.is_null(){
+                        unsafe { *out_len = bytes_copied };
+                    }
+                    Ok(())
                 }
-                Ok(())
-            }
-            _ => Err(format!("Wrong type requested for ID {}: blob", id as usize).into()),
-        },
-        Err(e) => Err(format!("Error getting ID {}: blob - {}", id as usize, e).into()),
+                _ => Err(FfiError::with_status(EconfStatus::StatusTypeMismatch, format!("Wrong type requested for ID {}: blob", id as usize))),
+            };
+            sensitive::discard(id, parameter);
+            result
+        }
+        Err(e) => Err(FfiError::context(e, format!("Error getting ID {}: blob", id as usize))),
     })
 }
 
@@ -361,9 +531,134 @@ pub(crate) fn set_blob(
     interface_execute(interface, |interface| {
         let blob = unsafe { c_buffer_to_blob(buffer, len, id)? };
         let parameter = ParameterValue::ValBlob(blob);
-        match interface.set(id, parameter) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(format!("Error setting ID {}: blob - {}", id as usize, e).into()),
+        match interface.set_with_source(id, parameter, Some(FFI_SOURCE)) {
+            Ok(outcome) => {
+                sensitive::discard(id, outcome.value);
+                Ok(())
+            }
+            Err(e) => Err(FfiError::context(e, format!("Error setting ID {}: blob", id as usize))),
         }
     })
 }
+
+/// Copy a metadata string to the buffer of the caller, fails when it doesn't fit
+///
+/// # Safety
+/// `out` must be writable for `max_length` bytes
+pub(crate) unsafe fn copy_text(text: &str, out: *mut c_char, max_length: usize) -> Result<(), Box<dyn Error>> {
+    if out.is_null() {
+        return Err("Null pointer provided for the text".into());
+    }
+    let c_string = CString::new(text)?;
+    let bytes = c_string.as_bytes_with_nul();
+    if bytes.len() > max_length {
+        return Err(format!("Max length exceeded: {} bytes needed", bytes.len()).into());
+    }
+    unsafe { ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, out, bytes.len()) };
+    Ok(())
+}
+
+/// # Safety
+/// `out` must be null or writable
+pub(crate) unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), Box<dyn Error>> {
+    if out.is_null() {
+        return Err(format!("Null pointer provided for {}", type_name::<T>()).into());
+    }
+    unsafe { *out = value };
+    Ok(())
+}
+
+pub(crate) fn c_value_type(value_type: &ParameterValueType) -> EconfValueType {
+    match value_type {
+        ParameterValueType::TypeNone => EconfValueType::ValueNone,
+        ParameterValueType::TypeBool => EconfValueType::ValueBool,
+        ParameterValueType::TypeI32 => EconfValueType::ValueI32,
+        ParameterValueType::TypeU32 => EconfValueType::ValueU32,
+        ParameterValueType::TypeI64 => EconfValueType::ValueI64,
+        ParameterValueType::TypeU64 => EconfValueType::ValueU64,
+        ParameterValueType::TypeF32 => EconfValueType::ValueF32,
+        ParameterValueType::TypeF64 => EconfValueType::ValueF64,
+        ParameterValueType::TypeString => EconfValueType::ValueString,
+        ParameterValueType::TypeBlob => EconfValueType::ValueBlob,
+        ParameterValueType::TypeEnum(_) => EconfValueType::ValueEnum,
+    }
+}
+
+pub(crate) fn parameter_id_from_name(interface: &InterfaceInstance, name: &str) -> Result<ParameterId, Box<dyn Error>> {
+    interface
+        .get_parameter_id_from_name(name.to_owned())
+        .ok_or_else(|| FfiError::with_status(EconfStatus::StatusNotFound, format!("Unknown parameter {}", name)))
+}
+
+/// Fill the C value from the parameter value, copying the strings and blobs to the buffer of the caller
+///
+/// # Safety
+/// The buffer in `value.data.val_buffer` must be writable for its capacity when the parameter is a string or a blob
+pub(crate) unsafe fn parameter_to_c_value(id: ParameterId, parameter: &ParameterValue, value: &mut EconfValue) -> Result<(), Box<dyn Error>> {
+    let (value_type, data) = match parameter {
+        ParameterValue::ValNone => (EconfValueType::ValueNone, EconfValueData { val_u64: 0 }),
+        ParameterValue::ValBool(v) => (EconfValueType::ValueBool, EconfValueData { val_bool: *v }),
+        ParameterValue::ValI32(v) => (EconfValueType::ValueI32, EconfValueData { val_i32: *v }),
+        ParameterValue::ValU32(v) => (EconfValueType::ValueU32, EconfValueData { val_u32: *v }),
+        ParameterValue::ValI64(v) => (EconfValueType::ValueI64, EconfValueData { val_i64: *v }),
+        ParameterValue::ValU64(v) => (EconfValueType::ValueU64, EconfValueData { val_u64: *v }),
+        ParameterValue::ValF32(v) => (EconfValueType::ValueF32, EconfValueData { val_f32: *v }),
+        ParameterValue::ValF64(v) => (EconfValueType::ValueF64, EconfValueData { val_f64: *v }),
+        ParameterValue::ValEnum(v) => (EconfValueType::ValueEnum, EconfValueData { val_enum: *v }),
+        ParameterValue::ValString(v) => {
+            let mut buffer = unsafe { value.data.val_buffer };
+            buffer.length = unsafe { copy_string_to_c_buffer(v, buffer.data as *mut c_char, buffer.capacity, id)? };
+            (EconfValueType::ValueString, EconfValueData { val_buffer: buffer })
+        }
+        ParameterValue::ValBlob(v) => {
+            let mut buffer = unsafe { value.data.val_buffer };
+            buffer.length = unsafe { copy_blob_to_c_buffer(v, buffer.data, buffer.capacity)? };
+            (EconfValueType::ValueBlob, EconfValueData { val_buffer: buffer })
+        }
+        ParameterValue::ValPath(_) => {
+            return Err(FfiError::with_status(
+                EconfStatus::StatusTypeMismatch,
+                format!("File value of ID {} is not supported", id as usize),
+            ));
+        }
+    };
+    value.value_type = value_type;
+    value.data = data;
+    Ok(())
+}
+
+/// Convert the C value to the parameter value, checking that the type matches the parameter
+///
+/// # Safety
+/// The strings must be null-terminated and the blobs must have `length` readable bytes
+pub(crate) unsafe fn c_value_to_parameter(id: ParameterId, value: &EconfValue) -> Result<ParameterValue, Box<dyn Error>> {
+    let expected = &PARAMETER_DATA[id.index()].value_type;
+    let parameter = unsafe {
+        match (value.value_type, expected) {
+            (EconfValueType::ValueBool, ParameterValueType::TypeBool) => ParameterValue::ValBool(value.data.val_bool),
+            (EconfValueType::ValueI32, ParameterValueType::TypeI32) => ParameterValue::ValI32(value.data.val_i32),
+            (EconfValueType::ValueU32, ParameterValueType::TypeU32) => ParameterValue::ValU32(value.data.val_u32),
+            (EconfValueType::ValueI64, ParameterValueType::TypeI64) => ParameterValue::ValI64(value.data.val_i64),
+            (EconfValueType::ValueU64, ParameterValueType::TypeU64) => ParameterValue::ValU64(value.data.val_u64),
+            (EconfValueType::ValueF32, ParameterValueType::TypeF32) => ParameterValue::ValF32(value.data.val_f32),
+            (EconfValueType::ValueF64, ParameterValueType::TypeF64) => ParameterValue::ValF64(value.data.val_f64),
+            // The enums can also be written as integers, like with the typed setters
+            (EconfValueType::ValueEnum, ParameterValueType::TypeEnum(_)) => ParameterValue::ValEnum(value.data.val_enum),
+            (EconfValueType::ValueI32, ParameterValueType::TypeEnum(_)) => ParameterValue::ValEnum(value.data.val_i32),
+            (EconfValueType::ValueString, ParameterValueType::TypeString) => {
+                ParameterValue::ValString(c_char_to_string(value.data.val_buffer.data as *const c_char, id)?.into())
+            }
+            (EconfValueType::ValueBlob, ParameterValueType::TypeBlob) => {
+                let buffer = value.data.val_buffer;
+                ParameterValue::ValBlob(c_buffer_to_blob(buffer.data, buffer.length, id)?)
+            }
+            (value_type, expected) => {
+                return Err(FfiError::with_status(
+                    EconfStatus::StatusTypeMismatch,
+                    format!("Value of type {:?} for ID {} of type {}", value_type, id as usize, expected),
+                ));
+            }
+        }
+    };
+    Ok(parameter)
+}
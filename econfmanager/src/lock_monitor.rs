@@ -0,0 +1,192 @@
+use std::backtrace::Backtrace;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use parking_lot::{Mutex, MutexGuard};
+
+use crate::constants::LOCK_WARNING_THRESHOLD;
+
+static WARNING_THRESHOLD_MS: AtomicU64 = AtomicU64::new(LOCK_WARNING_THRESHOLD.as_millis() as u64);
+
+/// Set the process-wide time after which a waiting or holding thread is reported with a warning
+pub fn set_warning_threshold(threshold: Duration) {
+    WARNING_THRESHOLD_MS.store(threshold.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn get_warning_threshold() -> Duration {
+    Duration::from_millis(WARNING_THRESHOLD_MS.load(Ordering::Relaxed))
+}
+
+/// Snapshot of the usage statistics of an instance lock
+#[derive(Clone, Debug, Default)]
+pub struct LockDiagnostics {
+    pub name: String,
+    pub acquisitions: u64,
+    /// Number of acquisitions that had to wait for another thread
+    pub contentions: u64,
+    pub total_hold: Duration,
+    pub max_hold: Duration,
+    pub max_wait: Duration,
+    /// Thread currently holding the lock
+    pub holder: Option<String>,
+    pub held_for: Option<Duration>,
+    pub waiting_threads: Vec<String>,
+}
+
+struct Holder {
+    thread: String,
+    since: Instant,
+    /// Only captured when RUST_BACKTRACE is enabled
+    backtrace: Backtrace,
+}
+
+#[derive(Default)]
+struct LockState {
+    acquisitions: u64,
+    contentions: u64,
+    total_hold: Duration,
+    max_hold: Duration,
+    max_wait: Duration,
+    holder: Option<Holder>,
+    waiting: Vec<String>,
+}
+
+fn thread_name() -> String {
+    let current = thread::current();
+    match current.name() {
+        Some(name) => name.to_owned(),
+        None => format!("{:?}", current.id()),
+    }
+}
+
+/// Mutex that records hold times, the holder and the waiting threads,
+/// and warns with the backtraces of both sides when a thread waits longer than the threshold
+pub(crate) struct MonitoredMutex<T> {
+    name: &'static str,
+    inner: Mutex<T>,
+    state: std::sync::Mutex<LockState>,
+}
+
+pub(crate) struct MonitoredGuard<'a, T> {
+    mutex: &'a MonitoredMutex<T>,
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> MonitoredMutex<T> {
+    pub(crate) fn new(name: &'static str, value: T) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(value),
+            state: std::sync::Mutex::new(LockState::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, LockState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub(crate) fn lock(&self) -> MonitoredGuard<'_, T> {
+        let start = Instant::now();
+        let guard = match self.inner.try_lock() {
+            Some(guard) => guard,
+            None => {
+                let waiter = thread_name();
+                {
+                    let mut state = self.state();
+                    state.contentions += 1;
+                    state.waiting.push(waiter.clone());
+                }
+                let guard = match self.inner.try_lock_for(get_warning_threshold()) {
+                    Some(guard) => guard,
+                    None => {
+                        self.warn_waiting(&waiter, start.elapsed());
+                        self.inner.lock()
+                    }
+                };
+                let mut state = self.state();
+                if let Some(position) = state.waiting.iter().position(|thread| *thread == waiter) {
+                    state.waiting.remove(position);
+                }
+                guard
+            }
+        };
+
+        let mut state = self.state();
+        state.acquisitions += 1;
+        state.max_wait = state.max_wait.max(start.elapsed());
+        state.holder = Some(Holder {
+            thread: thread_name(),
+            since: Instant::now(),
+            backtrace: Backtrace::capture(),
+        });
+        MonitoredGuard { mutex: self, guard }
+    }
+
+    fn warn_waiting(&self, waiter: &str, waited: Duration) {
+        let state = self.state();
+        let (holder, held_for, holder_backtrace) = match &state.holder {
+            Some(holder) => (holder.thread.as_str(), holder.since.elapsed(), holder.backtrace.to_string()),
+            None => ("unknown", Duration::ZERO, String::new()),
+        };
+        warn!(
+            "Lock {}: thread {} is waiting for {:?}, held by {} for {:?} (possible deadlock)\nHolder backtrace:\n{}\nWaiter backtrace:\n{}",
+            self.name, waiter, waited, holder, held_for, holder_backtrace, Backtrace::force_capture()
+        );
+    }
+
+    pub(crate) fn diagnostics(&self) -> LockDiagnostics {
+        let state = self.state();
+        LockDiagnostics {
+            name: self.name.to_owned(),
+            acquisitions: state.acquisitions,
+            contentions: state.contentions,
+            total_hold: state.total_hold,
+            max_hold: state.max_hold,
+            max_wait: state.max_wait,
+            holder: state.holder.as_ref().map(|holder| holder.thread.clone()),
+            held_for: state.holder.as_ref().map(|holder| holder.since.elapsed()),
+            waiting_threads: state.waiting.clone(),
+        }
+    }
+}
+
+impl<T: Default> Default for MonitoredMutex<T> {
+    fn default() -> Self {
+        Self::new(std::any::type_name::<T>(), T::default())
+    }
+}
+
+impl<T> Deref for MonitoredGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for MonitoredGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for MonitoredGuard<'_, T> {
+    fn drop(&mut self) {
+        // The inner guard is released after this, so the holder is cleared while the lock is still owned
+        let mut state = self.mutex.state();
+        if let Some(holder) = state.holder.take() {
+            let held = holder.since.elapsed();
+            state.total_hold += held;
+            state.max_hold = state.max_hold.max(held);
+            if held > get_warning_threshold() {
+                warn!(
+                    "Lock {} was held by {} for {:?}\n{}",
+                    self.mutex.name, holder.thread, held, holder.backtrace
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+// Machine-readable report of all the parameters, their defaults and validation rules.
+// The module is shared with build.rs, which writes the same manifest as a build output.
+
+use std::error::Error;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::schema::{non_finite_name, Group, Parameter, ParameterValue, ValidationMethod};
+
+/// Increment when the layout of the manifest changes
+pub const MANIFEST_FORMAT_VERSION: u32 = 1;
+const SIGNATURE_FIELD: &str = "signature";
+const SIGNATURE_ALGORITHM: &str = "ed25519";
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(hex: &str) -> Result<[u8; N], Box<dyn Error>> {
+    let hex = hex.trim();
+    if hex.len() != N * 2 {
+        return Err(format!("Expected {} hex characters, got {}", N * 2, hex.len()).into());
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+fn value_json(value: &ParameterValue) -> Value {
+    match value {
+        ParameterValue::ValNone => Value::Null,
+        ParameterValue::ValBool(v) => json!(v),
+        ParameterValue::ValI32(v) | ParameterValue::ValEnum(v) => json!(v),
+        ParameterValue::ValU32(v) => json!(v),
+        ParameterValue::ValI64(v) => json!(v),
+        ParameterValue::ValU64(v) => json!(v),
+        ParameterValue::ValF32(v) => non_finite_name(*v as f64).map_or_else(|| json!(v), |name| json!(name)),
+        ParameterValue::ValF64(v) => non_finite_name(*v).map_or_else(|| json!(v), |name| json!(name)),
+        ParameterValue::ValString(v) => json!(v),
+        ParameterValue::ValBlob(v) => json!({ "base64": BASE64_STANDARD.encode(v) }),
+        ParameterValue::ValPath(v) => json!({ "file": v }),
+    }
+}
+
+fn validation_json(validation: &ValidationMethod) -> Value {
+    match validation {
+        ValidationMethod::None => json!({ "method": "none" }),
+        ValidationMethod::Range { min, max } => json!({
+            "method": "range",
+            "min": value_json(min),
+            "max": value_json(max),
+        }),
+        ValidationMethod::AllowedValues { values, names } => json!({
+            "method": "allowed_values",
+            "values": values.iter().zip(names.iter())
+                .map(|(value, name)| json!({ "value": value_json(value), "name": name }))
+                .collect::<Vec<_>>(),
+        }),
+        ValidationMethod::Text { max_length, pattern } => json!({
+            "method": "text",
+            "max_length": max_length,
+            "pattern": pattern,
+        }),
+        ValidationMethod::CustomCallback => json!({ "method": "custom" }),
+    }
+}
+
+/// The bytes covered by the signature: the compact JSON of the manifest without the signature.
+/// serde_json keeps the object keys sorted, so the serialization is stable
+fn signed_bytes(manifest: &Value) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut unsigned = manifest.clone();
+    if let Some(object) = unsigned.as_object_mut() {
+        object.remove(SIGNATURE_FIELD);
+    }
+    Ok(serde_json::to_vec(&unsigned)?)
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+/// SHA-256 of the compiled descriptor set of the parameters proto
+pub fn schema_hash(descriptors: &[u8]) -> String {
+    to_hex(&Sha256::digest(descriptors))
+}
+
+pub fn parameters_manifest(parameters: &[Parameter], groups: &[Group], schema_hash: &str, version: &str) -> Value {
+    let parameters: Vec<Value> = parameters.iter().map(|p| json!({
+        "id": p.id,
+        "name": p.name_id,
+        "group": p.name_id.split('@').next().unwrap_or_default(),
+        "title": p.title,
+        "comment": p.comment,
+        "type": p.value_type.to_string(),
+        "default": value_json(&p.value_default),
+        "validation": validation_json(&p.validation),
+        "const": p.is_const,
+        "readonly": p.readonly,
+        "runtime": p.runtime,
+        "internal": p.internal,
+        "deprecated": p.deprecated,
+        "hidden": p.hidden,
+        "access_level": p.access_level.name(),
+        "allow_non_finite": p.allow_non_finite,
+        "max_size": p.max_size,
+        "content_type": p.content_type,
+        "persist_policy": p.persist_policy.name(),
+        "debounce_ms": p.persist_policy.debounce_ms(),
+        "secret": p.secret,
+        "computed": p.computed,
+        "tags": p.tags.iter().collect::<Vec<_>>(),
+        "blob_schema": p.blob_schema,
+        "unit": p.unit,
+        "step": p.step,
+        "precision": p.precision,
+        "widget": p.widget,
+    })).collect();
+    let groups: Vec<Value> = groups.iter().map(|g| json!({
+        "name": g.name,
+        "title": g.title,
+        "comment": g.comment,
+    })).collect();
+
+    json!({
+        "format_version": MANIFEST_FORMAT_VERSION,
+        "econfmanager_version": version,
+        "schema_hash": { "algorithm": "sha256", "value": schema_hash },
+        "groups": groups,
+        "parameters": parameters,
+    })
+}
+
+/// Sign the manifest with the Ed25519 key given as 64 hex characters of the secret seed.
+/// The signature and the public key are added in the `signature` field
+pub fn sign_manifest(manifest: &mut Value, secret_key_hex: &str) -> Result<(), Box<dyn Error>> {
+    let key = SigningKey::from_bytes(&from_hex::<32>(secret_key_hex)?);
+    let signature = key.sign(&signed_bytes(manifest)?);
+    manifest
+        .as_object_mut()
+        .ok_or("Manifest is not a JSON object")?
+        .insert(SIGNATURE_FIELD.to_owned(), json!({
+            "algorithm": SIGNATURE_ALGORITHM,
+            "public_key": to_hex(key.verifying_key().as_bytes()),
+            "value": to_hex(&signature.to_bytes()),
+        }));
+    Ok(())
+}
+
+/// Check the manifest signature against the expected public key (64 hex characters)
+pub fn verify_manifest(manifest: &Value, public_key_hex: &str) -> Result<(), Box<dyn Error>> {
+    let signature = manifest.get(SIGNATURE_FIELD).ok_or("Manifest is not signed")?;
+    if signature["algorithm"] != SIGNATURE_ALGORITHM {
+        return Err(format!("Unsupported signature algorithm {}", signature["algorithm"]).into());
+    }
+    let key = VerifyingKey::from_bytes(&from_hex::<32>(public_key_hex)?)?;
+    let value = Signature::from_bytes(&from_hex::<64>(signature["value"].as_str().unwrap_or_default())?);
+    key.verify(&signed_bytes(manifest)?, &value)?;
+    Ok(())
+}
@@ -0,0 +1,330 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Packet, QoS, TlsConfiguration, Transport};
+
+use crate::constants::{TCP_RECONNECT_MAX_DELAY, TCP_RECONNECT_MIN_DELAY};
+use crate::error::EconfError;
+use crate::generated::{PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
+use crate::interface::{CallbackHandle, InterfaceInstance, ParameterChangeEvent};
+use crate::schema::{AccessLevel, Parameter, ParameterValueType};
+use crate::sensitive;
+
+/// Topics start with this prefix if not set
+pub const DEFAULT_TOPIC_PREFIX: &str = "econf";
+pub const DEFAULT_PORT: u16 = 1883;
+
+/// Source of the writes received from the broker, passed to the change callbacks
+const WRITE_SOURCE: &str = "mqtt";
+/// Suffix of the topics accepting the writes, "<prefix>/<group>/<name>/set"
+const SET_SUFFIX: &str = "set";
+/// "online" while connected, "offline" as the last will
+const STATUS_TOPIC: &str = "status";
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Requests queued in the client, the publications are dropped when it is full
+const REQUESTS_CAPACITY: usize = 64;
+
+/******************************************************************************
+ * PUBLIC TYPES
+ ******************************************************************************/
+
+/// PEM files of the TLS connection to the broker
+#[derive(Clone, Debug)]
+pub struct MqttTls {
+    /// Certificate authority of the broker
+    pub ca_file: String,
+    /// Certificate and key of the client, for the brokers requiring the client authentication
+    pub client_auth: Option<(String, String)>,
+}
+
+/// Configuration of [`MqttBridge`]
+#[derive(Clone, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// Unique per device, the broker drops the older connection with the same ID
+    pub client_id: String,
+    /// First level of the topics, without the trailing slash
+    pub topic_prefix: String,
+    /// Plain TCP if not set
+    pub tls: Option<MqttTls>,
+    pub credentials: Option<(String, String)>,
+    /// Role of the writes received from the broker, the parameters with a higher access level are refused
+    pub role: AccessLevel,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_owned(),
+            port: DEFAULT_PORT,
+            client_id: format!("econfmanager-{}", std::process::id()),
+            topic_prefix: DEFAULT_TOPIC_PREFIX.to_owned(),
+            tls: None,
+            credentials: None,
+            role: AccessLevel::User,
+        }
+    }
+}
+
+/// Publishes the values of the parameters to `<prefix>/<group>/<name>` as retained messages and writes
+/// the values received on `<prefix>/<group>/<name>/set`. The values are the text of
+/// [`InterfaceInstance::value_to_string`], the blobs in base64.
+///
+/// The internal and the secret parameters are not bridged. The changes made by other processes are only
+/// published if the instance receives them, with the periodic update or the notifications
+pub struct MqttBridge {
+    interface: Arc<InterfaceInstance>,
+    client: Client,
+    callbacks: Vec<CallbackHandle>,
+    queue: Sender<Publication>,
+    stop_flag: Arc<AtomicBool>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+/******************************************************************************
+ * PRIVATE TYPES
+ ******************************************************************************/
+
+enum Publication {
+    Parameter(ParameterId),
+    /// All the parameters, after every connection since the broker may have lost the retained messages
+    All,
+    Stop,
+}
+
+/// Topics of the bridge
+struct Topics {
+    prefix: String,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn read_pem(path: &str) -> Result<Vec<u8>, EconfError> {
+    std::fs::read(path)
+        .map_err(|e| EconfError::Io(std::io::Error::new(e.kind(), format!("Failed to read {}: {}", path, e))))
+}
+
+fn is_bridged(parameter: &Parameter) -> bool {
+    !parameter.internal && !parameter.secret && parameter.value_type != ParameterValueType::TypeNone
+}
+
+fn bridged_parameters() -> impl Iterator<Item = ParameterId> {
+    (0..PARAMETERS_NUM)
+        .filter_map(ParameterId::from_index)
+        .filter(|id| is_bridged(&PARAMETER_DATA[id.index()]))
+}
+
+impl Topics {
+    fn value(&self, parameter: &Parameter) -> String {
+        let (group, name) = parameter.name_id.split_once('@').unwrap_or(("", parameter.name_id));
+        format!("{}/{}/{}", self.prefix, group, name)
+    }
+
+    fn status(&self) -> String {
+        format!("{}/{}", self.prefix, STATUS_TOPIC)
+    }
+
+    fn set_filter(&self) -> String {
+        format!("{}/+/+/{}", self.prefix, SET_SUFFIX)
+    }
+
+    /// The parameter of a "<prefix>/<group>/<name>/set" topic
+    fn parameter_of_set(&self, topic: &str) -> Option<ParameterId> {
+        let path = topic.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let (group, name) = path.strip_suffix(SET_SUFFIX)?.strip_suffix('/')?.split_once('/')?;
+        ParameterId::from_name(&format!("{}@{}", group, name)).filter(|id| is_bridged(&PARAMETER_DATA[id.index()]))
+    }
+}
+
+fn options(config: &MqttConfig, topics: &Topics) -> Result<MqttOptions, EconfError> {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(topics.status(), "offline", QoS::AtLeastOnce, true));
+    if let Some((username, password)) = &config.credentials {
+        options.set_credentials(username, password);
+    }
+    if let Some(tls) = &config.tls {
+        let client_auth = match &tls.client_auth {
+            Some((cert_file, key_file)) => Some((read_pem(cert_file)?, read_pem(key_file)?)),
+            None => None,
+        };
+        options.set_transport(Transport::tls_with_config(TlsConfiguration::Simple {
+            ca: read_pem(&tls.ca_file)?,
+            alpn: None,
+            client_auth,
+        }));
+    }
+    Ok(options)
+}
+
+/// Publish the values taken from the queue, the change callbacks only queue the IDs
+fn run_publisher(interface: Arc<InterfaceInstance>, client: Client, topics: Arc<Topics>, queue: Receiver<Publication>) {
+    let publish = |id: ParameterId| {
+        let parameter = &PARAMETER_DATA[id.index()];
+        let value = match interface.get(id, false) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("MQTT: failed to read {}: {}", parameter.name_id, e);
+                return;
+            }
+        };
+        debug!("MQTT: publish {}: {}", parameter.name_id, sensitive::log_value(id, &value));
+        let payload = InterfaceInstance::value_to_string(&value);
+        // Dropped if the client is not keeping up, all the values are published again on the reconnection
+        if let Err(e) = client.try_publish(topics.value(parameter), QoS::AtLeastOnce, true, payload) {
+            warn!("MQTT: failed to publish {}: {}", parameter.name_id, e);
+        }
+    };
+    while let Ok(publication) = queue.recv() {
+        match publication {
+            Publication::Parameter(id) => publish(id),
+            Publication::All => bridged_parameters().for_each(publish),
+            Publication::Stop => break,
+        }
+    }
+}
+
+/// Write a value received on a set topic with the role of the bridge
+fn write(interface: &InterfaceInstance, role: AccessLevel, id: ParameterId, payload: &[u8]) -> Result<(), String> {
+    let parameter = &PARAMETER_DATA[id.index()];
+    if role < parameter.access_level {
+        return Err(format!("requires the {} role, the bridge has {}", parameter.access_level.name(), role.name()));
+    }
+    let text = std::str::from_utf8(payload).map_err(|e| e.to_string())?;
+    let value = interface.set_from_string(id, text).map_err(|e| e.to_string())?;
+    info!("MQTT write of {}: {}", parameter.name_id, sensitive::log_value(id, &value));
+    interface.set_with_source(id, value, Some(WRITE_SOURCE)).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Handle the events of the connection, reconnecting after the failures until stopped
+fn run_connection(
+    interface: Arc<InterfaceInstance>,
+    mut connection: Connection,
+    client: Client,
+    topics: Arc<Topics>,
+    role: AccessLevel,
+    queue: Sender<Publication>,
+    stop_flag: Arc<AtomicBool>,
+) {
+    let mut delay = TCP_RECONNECT_MIN_DELAY;
+    for event in connection.iter() {
+        if stop_flag.load(Ordering::Relaxed) {
+            break;
+        }
+        match event {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("MQTT: connected");
+                delay = TCP_RECONNECT_MIN_DELAY;
+                let subscribed = client
+                    .subscribe(topics.set_filter(), QoS::AtLeastOnce)
+                    .and_then(|_| client.publish(topics.status(), QoS::AtLeastOnce, true, "online"));
+                if let Err(e) = subscribed {
+                    error!("MQTT: failed to subscribe: {}", e);
+                }
+                let _ = queue.send(Publication::All);
+            }
+            Ok(Event::Incoming(Packet::Publish(message))) => {
+                let Some(id) = topics.parameter_of_set(&message.topic) else {
+                    warn!("MQTT: no parameter for the topic {}", message.topic);
+                    continue;
+                };
+                if let Err(e) = write(&interface, role, id, &message.payload) {
+                    warn!("MQTT: write of {} refused: {}", PARAMETER_DATA[id.index()].name_id, e);
+                    // The retained value shows the writer that nothing changed
+                    let _ = queue.send(Publication::Parameter(id));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("MQTT: connection failed: {}, reconnecting in {:?}", e, delay);
+                thread::park_timeout(delay);
+                delay = (delay * 2).min(TCP_RECONNECT_MAX_DELAY);
+            }
+        }
+    }
+    debug!("MQTT: connection thread stopped");
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl MqttBridge {
+    /// Connect to the broker in the background and start bridging the parameters. The connection
+    /// is retried until the bridge is dropped
+    pub fn new(interface: Arc<InterfaceInstance>, config: MqttConfig) -> Result<Self, EconfError> {
+        let topics = Arc::new(Topics { prefix: config.topic_prefix.trim_end_matches('/').to_owned() });
+        if topics.prefix.is_empty() || topics.prefix.contains(['+', '#']) {
+            return Err(EconfError::Config(format!("Invalid MQTT topic prefix '{}'", config.topic_prefix)));
+        }
+        let (client, connection) = Client::new(options(&config, &topics)?, REQUESTS_CAPACITY);
+        let (queue, receiver) = channel();
+
+        let mut callbacks = Vec::new();
+        for id in bridged_parameters() {
+            let queue = queue.clone();
+            let callback = interface.add_callback_ex(
+                id,
+                Arc::new(move |event: &ParameterChangeEvent| {
+                    let _ = queue.send(Publication::Parameter(event.id));
+                }),
+            );
+            match callback {
+                Ok(handle) => callbacks.push(handle),
+                Err(e) => {
+                    for handle in callbacks {
+                        let _ = interface.delete_callback_handle(handle);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let threads = vec![
+            thread::spawn({
+                let (interface, client, topics) = (interface.clone(), client.clone(), topics.clone());
+                move || run_publisher(interface, client, topics, receiver)
+            }),
+            thread::spawn({
+                let (interface, client, queue, stop_flag) = (interface.clone(), client.clone(), queue.clone(), stop_flag.clone());
+                move || run_connection(interface, connection, client, topics, config.role, queue, stop_flag)
+            }),
+        ];
+        info!("MQTT bridge started for {}:{}", config.host, config.port);
+
+        Ok(Self { interface, client, callbacks, queue, stop_flag, threads })
+    }
+
+    pub fn interface(&self) -> &Arc<InterfaceInstance> {
+        &self.interface
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        for handle in self.callbacks.drain(..) {
+            if let Err(e) = self.interface.delete_callback_handle(handle) {
+                warn!("MQTT: failed to remove the callback {}: {}", handle, e);
+            }
+        }
+        self.stop_flag.store(true, Ordering::Relaxed);
+        let _ = self.queue.send(Publication::Stop);
+        if let Err(e) = self.client.try_disconnect() {
+            debug!("MQTT: disconnect failed: {}", e);
+        }
+        for handle in self.threads.drain(..) {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+        info!("MQTT bridge stopped");
+    }
+}
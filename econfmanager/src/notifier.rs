@@ -1,35 +1,260 @@
-use std::net::UdpSocket;
-use log::info;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use log::{error, info};
 use prost::Message;
 use crate::generated::ParameterId;
-use crate::service_events::ParameterNotification;
-use crate::constants::{MULTICAST_GROUP, MULTICAST_PORT};
+use crate::schema::ParameterValue;
+use crate::sensitive;
+use crate::service_events::{NotificationValue, ParameterNotification};
+use crate::service_events::notification_value::Value;
+use crate::config::Config;
+use crate::constants::{
+    EMBED_VALUES, MAX_BATCH_IDS, MAX_EMBEDDED_VALUE_SIZE, MAX_NOTIFICATION_SIZE, MULTICAST_GROUP, MULTICAST_PORT, MULTICAST_TTL,
+};
+use crate::database_utils::DatabaseManager;
+use crate::error::EconfError;
+use crate::transport::{MulticastTransport, NotificationTransport};
+
+struct PendingNotification {
+    id: ParameterId,
+    source: String,
+    timestamp: f64,
+    sequence: u64,
+    value: Option<NotificationValue>,
+}
+
+pub(crate) fn encode_value(value: &ParameterValue) -> Option<NotificationValue> {
+    let value = match value {
+        ParameterValue::ValBool(v) => Value::ValBool(*v),
+        ParameterValue::ValI32(v) => Value::ValInt32(*v),
+        ParameterValue::ValU32(v) => Value::ValUint32(*v),
+        ParameterValue::ValI64(v) => Value::ValInt64(*v),
+        ParameterValue::ValU64(v) => Value::ValUint64(*v),
+        ParameterValue::ValF32(v) => Value::ValFloat32(*v),
+        ParameterValue::ValF64(v) => Value::ValFloat64(*v),
+        ParameterValue::ValString(v) if v.len() <= MAX_EMBEDDED_VALUE_SIZE => Value::ValStr(v.to_string()),
+        ParameterValue::ValBlob(v) if v.len() <= MAX_EMBEDDED_VALUE_SIZE => Value::ValBlob(v.clone()),
+        ParameterValue::ValEnum(v) => Value::ValEnum(*v),
+        _ => return None,
+    };
+    Some(NotificationValue { value: Some(value) })
+}
+
+pub(crate) fn decode_value(value: NotificationValue) -> Option<ParameterValue> {
+    Some(match value.value? {
+        Value::ValBool(v) => ParameterValue::ValBool(v),
+        Value::ValInt32(v) => ParameterValue::ValI32(v),
+        Value::ValUint32(v) => ParameterValue::ValU32(v),
+        Value::ValInt64(v) => ParameterValue::ValI64(v),
+        Value::ValUint64(v) => ParameterValue::ValU64(v),
+        Value::ValFloat32(v) => ParameterValue::ValF32(v),
+        Value::ValFloat64(v) => ParameterValue::ValF64(v),
+        Value::ValStr(v) => ParameterValue::ValString(v.into()),
+        Value::ValBlob(v) => ParameterValue::ValBlob(v),
+        Value::ValEnum(v) => ParameterValue::ValEnum(v),
+    })
+}
+
+/// Encodes the notifications and sends them through the transport
+struct Transmitter {
+    transport: Arc<dyn NotificationTransport>,
+}
+
+impl Transmitter {
+    /// Send the notifications in as few messages as possible, a message carries the changes from one source
+    fn send(&self, pending: &[PendingNotification]) -> Result<(), EconfError> {
+        for run in pending.chunk_by(|a, b| a.source == b.source) {
+            let mut changes: Vec<&PendingNotification> = Vec::with_capacity(run.len());
+            let mut merged_sequences = Vec::new();
+            for notification in run {
+                // A parameter changed several times within the window is sent once with the last change
+                match changes.iter_mut().find(|change| change.id == notification.id) {
+                    Some(change) => {
+                        if change.sequence != notification.sequence {
+                            merged_sequences.push(change.sequence);
+                        }
+                        *change = notification;
+                    }
+                    None => changes.push(notification),
+                }
+            }
+            let last = run.last().unwrap();
+            for batch in changes.chunks(MAX_BATCH_IDS) {
+                self.send_batch(batch, std::mem::take(&mut merged_sequences), &last.source, last.timestamp)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_batch(
+        &self,
+        changes: &[&PendingNotification],
+        merged_sequences: Vec<u64>,
+        source: &str,
+        timestamp: f64,
+    ) -> Result<(), EconfError> {
+        let mut notification = if changes.len() == 1 {
+            ParameterNotification{
+                id: changes[0].id as i32,
+                source: source.to_owned(),
+                timestamp,
+                sequence: changes[0].sequence,
+                value: changes[0].value.clone(),
+                merged_sequences,
+                ..Default::default()
+            }
+        } else {
+            ParameterNotification{
+                // The receivers without the batch support get at least the first parameter
+                id: changes[0].id as i32,
+                source: source.to_owned(),
+                timestamp,
+                sequence: changes.iter().map(|change| change.sequence).max().unwrap_or(0),
+                ids: changes.iter().map(|change| change.id as i32).collect(),
+                sequences: changes.iter().map(|change| change.sequence).collect(),
+                values: changes.iter().map(|change| change.value.clone().unwrap_or_default()).collect(),
+                merged_sequences,
+                ..Default::default()
+            }
+        };
+        if notification.encoded_len() > MAX_NOTIFICATION_SIZE {
+            // Too many values for one message, the receivers will read them from the database
+            notification.value = None;
+            notification.values.clear();
+        }
+
+        let mut buf = Vec::with_capacity(notification.encoded_len());
+        notification.encode(&mut buf).map_err(std::io::Error::other)?;
+
+        self.transport.send(&buf)?;
+
+        info!(
+            "Notification for {:?} from {} (sequence {})",
+            changes.iter().map(|change| change.id as usize).collect::<Vec<_>>(),
+            if source.is_empty() { "unknown" } else { source },
+            notification.sequence
+        );
+        Ok(())
+    }
+}
 
-#[derive(Default)]
 pub(crate) struct Notifier {
+    transmitter: Arc<Transmitter>,
+    embed_values: bool,
+    /// Queue of the coalescing thread, None when the notifications are sent immediately
+    queue: Option<Sender<PendingNotification>>,
+}
 
+impl Default for Notifier {
+    fn default() -> Self {
+        Notifier {
+            transmitter: Arc::new(Transmitter {
+                transport: Arc::new(MulticastTransport::new(MULTICAST_GROUP, MULTICAST_PORT, MULTICAST_TTL)),
+            }),
+            embed_values: EMBED_VALUES,
+            queue: None,
+        }
+    }
 }
 
 impl Notifier {
-    pub(crate) fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Notifier{})
+    pub(crate) fn new(config: &Config, transport: Arc<dyn NotificationTransport>) -> Result<Self, EconfError> {
+        let transmitter = Arc::new(Transmitter { transport });
+
+        let queue = if config.coalesce_window.is_zero() {
+            None
+        } else {
+            let (sender, receiver) = mpsc::channel();
+            let thread_transmitter = transmitter.clone();
+            let window = config.coalesce_window;
+            std::thread::Builder::new()
+                .name("econf-notifier".to_owned())
+                .spawn(move || Self::coalesce(receiver, thread_transmitter, window))?;
+            info!("Coalescing the notifications within {:?}", window);
+            Some(sender)
+        };
+
+        Ok(Notifier{ transmitter, embed_values: config.embed_values, queue })
     }
 
-    pub(crate) fn notify_of_parameter_change(&self, id: ParameterId) -> Result<(), Box<dyn std::error::Error>> {
-        let socket = UdpSocket::bind("0.0.0.0:0")?;
-        
-        // Set Time-to-Live (TTL) for multicast
-        socket.set_ttl(1)?;  // Limit to local network
-        
-        let notification = ParameterNotification{id:id as i32};
+    /// Collect the notifications fired within the window after the first one and send them as batches.
+    /// The thread stops when the notifier is dropped, after sending the pending notifications
+    fn coalesce(receiver: Receiver<PendingNotification>, transmitter: Arc<Transmitter>, window: Duration) {
+        while let Ok(first) = receiver.recv() {
+            let deadline = Instant::now() + window;
+            let mut pending = vec![first];
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                match receiver.recv_timeout(deadline - now) {
+                    Ok(notification) => pending.push(notification),
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            if let Err(e) = transmitter.send(&pending) {
+                error!("Failed to send {} notifications: {}", pending.len(), e);
+            }
+        }
+    }
 
-        let mut buf = Vec::new();
-        buf.reserve(notification.encoded_len());
-        notification.encode(&mut buf)?;
+    pub(crate) fn notify_of_parameter_change(
+        &self,
+        id: ParameterId,
+        source: Option<&str>,
+        sequence: u64,
+        value: Option<&ParameterValue>,
+    ) -> Result<(), EconfError> {
+        let value = value
+            .filter(|_| self.embed_values && !sensitive::is_sensitive(id))
+            .and_then(encode_value);
+        self.send(vec![PendingNotification {
+            id,
+            source: source.unwrap_or_default().to_owned(),
+            timestamp: DatabaseManager::get_timestamp(),
+            sequence,
+            value,
+        }])
+    }
 
-        socket.send_to(&buf, (MULTICAST_GROUP, MULTICAST_PORT))?;
-        
-        info!("Notification for {}", id as usize);
-        Ok(())
+    /// Notify of several parameters changed by one write (load, factory reset)
+    pub(crate) fn notify_of_parameters_change(&self, ids: &[ParameterId], source: Option<&str>, sequence: u64) -> Result<(), EconfError> {
+        let timestamp = DatabaseManager::get_timestamp();
+        self.send(ids.iter().map(|id| PendingNotification {
+            id: *id,
+            source: source.unwrap_or_default().to_owned(),
+            timestamp,
+            sequence,
+            value: None,
+        }).collect())
+    }
+
+    /// Notify of several parameters written together, with the sequence number and the new value of each
+    pub(crate) fn notify_of_values_change(&self, changes: &[(ParameterId, u64, &ParameterValue)], source: Option<&str>) -> Result<(), EconfError> {
+        let timestamp = DatabaseManager::get_timestamp();
+        self.send(changes.iter().map(|(id, sequence, value)| PendingNotification {
+            id: *id,
+            source: source.unwrap_or_default().to_owned(),
+            timestamp,
+            sequence: *sequence,
+            value: Some(*value)
+                .filter(|_| self.embed_values && !sensitive::is_sensitive(*id))
+                .and_then(encode_value),
+        }).collect())
     }
-}
\ No newline at end of file
+
+    fn send(&self, pending: Vec<PendingNotification>) -> Result<(), EconfError> {
+        match &self.queue {
+            Some(queue) => {
+                for notification in pending {
+                    queue.send(notification)
+                        .map_err(|_| EconfError::Io(std::io::Error::other("Notification thread stopped")))?;
+                }
+                Ok(())
+            }
+            None => self.transmitter.send(&pending),
+        }
+    }
+}
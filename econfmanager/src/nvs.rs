@@ -0,0 +1,226 @@
+// Compact binary image of selected parameters for the companion microcontrollers that can't use SQLite.
+// The module is shared with build.rs, which writes the C header describing the layout.
+//
+// The image is little endian:
+//   header  magic "ECNV", u16 version, u16 number of entries, u32 size of the entries, u32 CRC-32 of the entries
+//   entry   u16 parameter ID, u8 type, u8 reserved (0), u32 length of the value, value padded with zeros to 4 bytes
+// The entries start 4-byte aligned, so the numeric values can be read in place.
+
+use std::error::Error;
+
+use crate::schema::{Parameter, ParameterValue, ParameterValueType};
+
+/// "ECNV" read as a little endian u32
+pub const NVS_MAGIC: u32 = u32::from_le_bytes(*b"ECNV");
+/// Increment when the layout of the image changes
+pub const NVS_FORMAT_VERSION: u16 = 1;
+pub const NVS_HEADER_SIZE: usize = 16;
+pub const NVS_ENTRY_HEADER_SIZE: usize = 8;
+
+/// Type codes of the entries
+pub const NVS_TYPE_BOOL: u8 = 1;
+pub const NVS_TYPE_I32: u8 = 2;
+pub const NVS_TYPE_U32: u8 = 3;
+pub const NVS_TYPE_I64: u8 = 4;
+pub const NVS_TYPE_U64: u8 = 5;
+pub const NVS_TYPE_F32: u8 = 6;
+pub const NVS_TYPE_F64: u8 = 7;
+pub const NVS_TYPE_STRING: u8 = 8;
+pub const NVS_TYPE_BLOB: u8 = 9;
+pub const NVS_TYPE_ENUM: u8 = 10;
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn nvs_type(value_type: &ParameterValueType) -> Option<(u8, &'static str)> {
+    Some(match value_type {
+        ParameterValueType::TypeBool => (NVS_TYPE_BOOL, "BOOL"),
+        ParameterValueType::TypeI32 => (NVS_TYPE_I32, "I32"),
+        ParameterValueType::TypeU32 => (NVS_TYPE_U32, "U32"),
+        ParameterValueType::TypeI64 => (NVS_TYPE_I64, "I64"),
+        ParameterValueType::TypeU64 => (NVS_TYPE_U64, "U64"),
+        ParameterValueType::TypeF32 => (NVS_TYPE_F32, "F32"),
+        ParameterValueType::TypeF64 => (NVS_TYPE_F64, "F64"),
+        ParameterValueType::TypeString => (NVS_TYPE_STRING, "STRING"),
+        ParameterValueType::TypeBlob => (NVS_TYPE_BLOB, "BLOB"),
+        ParameterValueType::TypeEnum(_) => (NVS_TYPE_ENUM, "ENUM"),
+        ParameterValueType::TypeNone => return None,
+    })
+}
+
+fn value_bytes(value: &ParameterValue) -> Option<Vec<u8>> {
+    Some(match value {
+        ParameterValue::ValBool(value) => vec![*value as u8],
+        ParameterValue::ValI32(value) | ParameterValue::ValEnum(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValU32(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValI64(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValU64(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValF32(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValF64(value) => value.to_le_bytes().to_vec(),
+        ParameterValue::ValString(value) => value.as_bytes().to_vec(),
+        ParameterValue::ValBlob(value) => value.clone(),
+        ParameterValue::ValNone | ParameterValue::ValPath(_) => return None,
+    })
+}
+
+/// CRC-32 (IEEE 802.3, the one of zlib), bitwise to keep the C version short
+pub fn nvs_crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+/// Image of the values, in the order given. The values must have the types of their parameters
+pub fn encode_nvs_image(values: &[(&Parameter, &ParameterValue)]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if values.len() > u16::MAX as usize {
+        return Err(format!("{} parameters don't fit in one image", values.len()).into());
+    }
+    let mut entries = Vec::new();
+    for (parameter, value) in values {
+        let key = u16::try_from(parameter.id)
+            .map_err(|_| format!("ID {} of {} doesn't fit in an image key", parameter.id, parameter.name_id))?;
+        let (value_type, _) = nvs_type(&parameter.value_type)
+            .ok_or_else(|| format!("Parameter {} has no type", parameter.name_id))?;
+        let bytes = value_bytes(value).ok_or_else(|| format!("Value of {} can't be exported", parameter.name_id))?;
+        let length = u32::try_from(bytes.len()).map_err(|_| format!("Value of {} is too large", parameter.name_id))?;
+        entries.extend_from_slice(&key.to_le_bytes());
+        entries.push(value_type);
+        entries.push(0);
+        entries.extend_from_slice(&length.to_le_bytes());
+        entries.extend_from_slice(&bytes);
+        entries.resize(entries.len().next_multiple_of(4), 0);
+    }
+    let size = u32::try_from(entries.len()).map_err(|_| "Image is too large")?;
+
+    let mut image = Vec::with_capacity(NVS_HEADER_SIZE + entries.len());
+    image.extend_from_slice(&NVS_MAGIC.to_le_bytes());
+    image.extend_from_slice(&NVS_FORMAT_VERSION.to_le_bytes());
+    image.extend_from_slice(&(values.len() as u16).to_le_bytes());
+    image.extend_from_slice(&size.to_le_bytes());
+    image.extend_from_slice(&nvs_crc32(&entries).to_le_bytes());
+    image.extend_from_slice(&entries);
+    Ok(image)
+}
+
+/// C header with the layout of the image, the keys and the types of the parameters,
+/// and the functions to check the image and find a value in it
+pub fn nvs_c_header(parameters: &[Parameter]) -> String {
+    let mut header = String::from(NVS_HEADER_HEAD);
+    header.push_str(&format!("#define ECONF_NVS_MAGIC 0x{:08X}u\n", NVS_MAGIC));
+    header.push_str(&format!("#define ECONF_NVS_VERSION {}u\n", NVS_FORMAT_VERSION));
+    header.push_str(&format!("#define ECONF_NVS_HEADER_SIZE {}u\n", NVS_HEADER_SIZE));
+    header.push_str(&format!("#define ECONF_NVS_ENTRY_HEADER_SIZE {}u\n\n", NVS_ENTRY_HEADER_SIZE));
+    for (name, code) in [
+        ("BOOL", NVS_TYPE_BOOL), ("I32", NVS_TYPE_I32), ("U32", NVS_TYPE_U32), ("I64", NVS_TYPE_I64),
+        ("U64", NVS_TYPE_U64), ("F32", NVS_TYPE_F32), ("F64", NVS_TYPE_F64), ("STRING", NVS_TYPE_STRING),
+        ("BLOB", NVS_TYPE_BLOB), ("ENUM", NVS_TYPE_ENUM),
+    ] {
+        header.push_str(&format!("#define ECONF_NVS_TYPE_{} {}u\n", name, code));
+    }
+    header.push_str("\n/* Keys and types of the parameters */\n\n");
+    for parameter in parameters {
+        let Some((_, type_name)) = nvs_type(&parameter.value_type) else {
+            continue;
+        };
+        let name = parameter.name_id.split('@').map(str::to_uppercase).collect::<Vec<_>>().join("_");
+        header.push_str(&format!("#define ECONF_NVS_KEY_{} {}u\n", name, parameter.id));
+        header.push_str(&format!("#define ECONF_NVS_TYPE_OF_{} ECONF_NVS_TYPE_{}\n", name, type_name));
+    }
+    header.push_str(NVS_HEADER_TAIL);
+    header
+}
+
+/// The part of the header that doesn't depend on the parameters
+const NVS_HEADER_HEAD: &str = r#"/*
+* Auto-generated - DO NOT EDIT
+* Layout of the parameter images exported by InterfaceInstance::export_nvs and econf_export_nvs.
+* Depends only on the C standard library, for the devices without econfmanager
+*
+* Little endian:
+*   header  u32 magic, u16 version, u16 number of entries, u32 size of the entries, u32 CRC-32 of the entries
+*   entry   u16 key, u8 type, u8 reserved, u32 length, value padded with zeros to 4 bytes
+*/
+
+#pragma once
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+"#;
+
+const NVS_HEADER_TAIL: &str = r#"
+static inline uint32_t econf_nvs_read_u32(const uint8_t *data) {
+    return (uint32_t)data[0] | ((uint32_t)data[1] << 8) | ((uint32_t)data[2] << 16) | ((uint32_t)data[3] << 24);
+}
+
+static inline uint16_t econf_nvs_read_u16(const uint8_t *data) {
+    return (uint16_t)(data[0] | (data[1] << 8));
+}
+
+static inline uint32_t econf_nvs_crc32(const uint8_t *data, size_t size) {
+    uint32_t crc = 0xFFFFFFFFu;
+    for (size_t i = 0; i < size; i++) {
+        crc ^= data[i];
+        for (int bit = 0; bit < 8; bit++) {
+            crc = (crc & 1u) ? (crc >> 1) ^ 0xEDB88320u : crc >> 1;
+        }
+    }
+    return ~crc;
+}
+
+/** Check the magic, the version, the size and the CRC of the image */
+static inline bool econf_nvs_check(const uint8_t *image, size_t size) {
+    if (size < ECONF_NVS_HEADER_SIZE || econf_nvs_read_u32(image) != ECONF_NVS_MAGIC
+        || econf_nvs_read_u16(image + 4) != ECONF_NVS_VERSION) {
+        return false;
+    }
+    uint32_t entries_size = econf_nvs_read_u32(image + 8);
+    return entries_size <= size - ECONF_NVS_HEADER_SIZE
+        && econf_nvs_crc32(image + ECONF_NVS_HEADER_SIZE, entries_size) == econf_nvs_read_u32(image + 12);
+}
+
+/**
+ * Find the value of the parameter in an image passed by econf_nvs_check. Returns false if the parameter
+ * is not in the image or has another type, otherwise `value` points into the image and `length` receives its size
+ */
+static inline bool econf_nvs_find(const uint8_t *image, uint16_t key, uint8_t type, const uint8_t **value, uint32_t *length) {
+    uint32_t entries_size = econf_nvs_read_u32(image + 8);
+    const uint8_t *entry = image + ECONF_NVS_HEADER_SIZE;
+    const uint8_t *end = entry + entries_size;
+    while ((size_t)(end - entry) >= ECONF_NVS_ENTRY_HEADER_SIZE) {
+        uint32_t entry_length = econf_nvs_read_u32(entry + 4);
+        if (entry_length > (size_t)(end - entry) - ECONF_NVS_ENTRY_HEADER_SIZE) {
+            return false;
+        }
+        if (econf_nvs_read_u16(entry) == key) {
+            if (entry[2] != type) {
+                return false;
+            }
+            *value = entry + ECONF_NVS_ENTRY_HEADER_SIZE;
+            *length = entry_length;
+            return true;
+        }
+        entry += ECONF_NVS_ENTRY_HEADER_SIZE + ((entry_length + 3u) & ~3u);
+    }
+    return false;
+}
+
+#ifdef __cplusplus
+} // extern "C"
+#endif
+"#;
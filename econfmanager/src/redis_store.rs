@@ -0,0 +1,512 @@
+use log::{debug, info, warn};
+use redis::{Client, Connection, RedisError, RedisResult, cmd, pipe};
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::constants::TCP_IO_TIMEOUT;
+use crate::database_utils::{validate_value, DatabaseManager, Status, WrittenValue};
+use crate::error::EconfError;
+use crate::generated::{PARAMETER_DATA, PARAMETERS_NUM, ParameterId};
+use crate::interface::{value_from_json, ParameterChangeEvent, WriteCondition};
+use crate::schema::{Parameter, ParameterValue};
+use crate::sensitive;
+use crate::store::{read_default, ParameterStore};
+use crate::transport::TransportConfig;
+
+/// Prefix of the keys if not set. The hash tag keeps all the keys in one slot of a Redis Cluster,
+/// the scripts change several of them at once
+pub const DEFAULT_KEY_PREFIX: &str = "{econf}:";
+
+/// Writes the values with one new sequence number each. KEYS: the sequence counter, then the parameters.
+/// ARGV: the timestamp, the source, the expected sequence of the only parameter ("" for no condition),
+/// then the values. Returns the sequences, or nil if the expected sequence doesn't match
+const WRITE_SCRIPT: &str = r"
+if ARGV[3] ~= '' and tonumber(redis.call('HGET', KEYS[2], 'sequence') or '0') ~= tonumber(ARGV[3]) then
+    return nil
+end
+local sequences = {}
+for i = 2, #KEYS do
+    local sequence = redis.call('INCR', KEYS[1])
+    redis.call('HSET', KEYS[i], 'value', ARGV[i + 2], 'timestamp', ARGV[1], 'source', ARGV[2], 'sequence', sequence)
+    sequences[#sequences + 1] = sequence
+end
+return sequences
+";
+
+/// Removes the values. KEYS: the sequence counter, then the parameters. Returns the sequence of the change
+const RESET_SCRIPT: &str = r"
+for i = 2, #KEYS do
+    redis.call('DEL', KEYS[i])
+end
+return redis.call('INCR', KEYS[1])
+";
+
+/// Copies the values between the working and the saved keys. KEYS: the sequence counter, then the pairs
+/// of the source and the destination. ARGV: the timestamp of the copied values, "" to keep them
+/// unchanged (save). A changed copy (load) gets a new sequence number, which is returned
+const COPY_SCRIPT: &str = r"
+local sequence = nil
+if ARGV[1] ~= '' then
+    sequence = redis.call('INCR', KEYS[1])
+end
+for i = 2, #KEYS, 2 do
+    local fields = redis.call('HGETALL', KEYS[i])
+    if #fields == 0 then
+        redis.call('DEL', KEYS[i + 1])
+    else
+        if sequence then
+            table.insert(fields, 'sequence')
+            table.insert(fields, sequence)
+            table.insert(fields, 'timestamp')
+            table.insert(fields, ARGV[1])
+        end
+        redis.call('HSET', KEYS[i + 1], unpack(fields))
+    end
+end
+return sequence
+";
+
+/// The Redis errors are reported as I/O errors, the variants of `EconfError` don't depend on the features
+pub(crate) fn redis_error(e: RedisError) -> EconfError {
+    EconfError::Io(std::io::Error::other(e))
+}
+
+fn encode_value(value: &ParameterValue) -> Result<Zeroizing<String>, EconfError> {
+    serde_json::to_string(value)
+        .map(Zeroizing::new)
+        .map_err(|e| EconfError::Schema(e.to_string()))
+}
+
+fn decode_value(id: ParameterId, encoded: &str) -> Result<ParameterValue, EconfError> {
+    let schema_error = |e: String| EconfError::Schema(format!("Value of {}: {}", PARAMETER_DATA[id.index()].name_id, e));
+    let value: Value = serde_json::from_str(encoded).map_err(|e| schema_error(e.to_string()))?;
+    value_from_json(id, &value).map_err(|e| schema_error(e.to_string()))
+}
+
+/// Parameter store keeping the values in Redis (or a compatible server like KeyDB), so the containers
+/// of a fleet can share the configuration without sharing a database file. Every parameter is a hash
+/// `<prefix>value:<name>` with the value encoded as JSON, the write time, the source and the sequence
+/// number from the counter `<prefix>sequence`. The saved copy is kept in the `<prefix>saved:<name>` keys.
+///
+/// The changes are written by scripts, so every write is atomic and gets its sequence number in the order
+/// of the writes. Use it with the transport returned by [`RedisStore::transport`], which delivers the
+/// keyspace notifications of the server instead of the UDP multicast. The blob streams, the profiles
+/// and the backups are not supported, and the secret parameters are refused as the values are not encrypted
+pub struct RedisStore {
+    client: Client,
+    /// Opened on the first use and after a connection error
+    connection: Option<Connection>,
+    url: String,
+    prefix: String,
+    default_data_folder: String,
+    /// Sequences of the parameters seen by the last update, 0 if not stored
+    seen: Vec<u64>,
+    saved_sequence: Option<u64>,
+}
+
+impl RedisStore {
+    /// Store in the server at the URL ("redis://host:6379/0") with the key prefix, [`DEFAULT_KEY_PREFIX`]
+    /// if empty. The instances sharing the configuration use the same URL and prefix
+    pub fn new(url: &str, prefix: &str, default_data_folder: &str) -> Result<Self, EconfError> {
+        if let Some(parameter) = PARAMETER_DATA.iter().find(|parameter| parameter.secret) {
+            return Err(EconfError::Config(format!(
+                "Parameter {} is secret, the Redis store can't encrypt it", parameter.name_id
+            )));
+        }
+        let client = Client::open(url).map_err(|e| EconfError::Config(format!("Redis URL {}: {}", url, e)))?;
+        let mut store = RedisStore {
+            client,
+            connection: None,
+            url: url.to_owned(),
+            prefix: if prefix.is_empty() { DEFAULT_KEY_PREFIX.to_owned() } else { prefix.to_owned() },
+            default_data_folder: default_data_folder.to_owned(),
+            seen: vec![0; PARAMETERS_NUM],
+            saved_sequence: None,
+        };
+        // Fail early if the server is not reachable
+        store.run(|connection| cmd("PING").query::<String>(connection))?;
+        info!("Redis store opened: {} {}", url, store.prefix);
+        Ok(store)
+    }
+
+    /// Transport delivering the keyspace notifications of the keys of this store
+    pub fn transport(&self) -> TransportConfig {
+        TransportConfig::RedisKeyspace { url: self.url.clone(), prefix: self.prefix.clone() }
+    }
+
+    fn value_key(&self, id: ParameterId) -> String {
+        format!("{}value:{}", self.prefix, PARAMETER_DATA[id.index()].name_id)
+    }
+
+    fn saved_key(&self, id: ParameterId) -> String {
+        format!("{}saved:{}", self.prefix, PARAMETER_DATA[id.index()].name_id)
+    }
+
+    fn sequence_key(&self) -> String {
+        format!("{}sequence", self.prefix)
+    }
+
+    /// Set by save, the saved copy doesn't exist without it
+    fn saved_sequence_key(&self) -> String {
+        format!("{}saved_sequence", self.prefix)
+    }
+
+    /// Run the commands on the connection, reconnecting next time if the connection failed
+    fn run<T>(&mut self, commands: impl FnOnce(&mut Connection) -> RedisResult<T>) -> Result<T, EconfError> {
+        if self.connection.is_none() {
+            let connection = self.client.get_connection_with_timeout(TCP_IO_TIMEOUT).map_err(redis_error)?;
+            connection.set_read_timeout(Some(TCP_IO_TIMEOUT)).map_err(redis_error)?;
+            connection.set_write_timeout(Some(TCP_IO_TIMEOUT)).map_err(redis_error)?;
+            self.connection = Some(connection);
+        }
+        let result = commands(self.connection.as_mut().expect("Connected above"));
+        if let Err(e) = &result {
+            if e.is_io_error() || e.is_connection_dropped() || e.is_timeout() {
+                warn!("Redis connection lost: {}", e);
+                self.connection = None;
+            }
+        }
+        result.map_err(redis_error)
+    }
+
+    /// Check the condition of a conditional write, returns the sequence number the parameter must
+    /// still have when the value is stored, None if the condition doesn't hold
+    fn check_condition(&mut self, id: ParameterId, condition: &WriteCondition) -> Result<Option<u64>, EconfError> {
+        // Taken before the value, a write in between changes the sequence and fails the write
+        let sequence = self.parameter_sequence(id)?;
+        let holds = match condition {
+            WriteCondition::Sequence(expected) => sequence == *expected,
+            WriteCondition::Value(expected) => {
+                let current = self.read(id)?;
+                let equal = current == *expected;
+                sensitive::discard(id, current);
+                equal
+            }
+        };
+        Ok(holds.then_some(sequence))
+    }
+
+    /// Write the values in one script, returns the sequences or None if the expected sequence doesn't match
+    fn store_values(
+        &mut self,
+        values: &[(ParameterId, ParameterValue)],
+        source: Option<&str>,
+        expected_sequence: Option<u64>,
+    ) -> Result<Option<Vec<u64>>, EconfError> {
+        let mut script = cmd("EVAL");
+        script.arg(WRITE_SCRIPT).arg(values.len() + 1).arg(self.sequence_key());
+        for (id, _) in values {
+            script.arg(self.value_key(*id));
+        }
+        script
+            .arg(DatabaseManager::get_timestamp())
+            .arg(source.unwrap_or(""))
+            .arg(expected_sequence.map(|sequence| sequence.to_string()).unwrap_or_default());
+        for (_, value) in values {
+            script.arg(encode_value(value)?.as_str());
+        }
+        self.run(|connection| script.query(connection))
+    }
+
+    fn copy_values(&mut self, pairs: Vec<(String, String)>, renumber: bool) -> Result<Option<u64>, EconfError> {
+        let mut script = cmd("EVAL");
+        script.arg(COPY_SCRIPT).arg(pairs.len() * 2 + 1).arg(self.sequence_key());
+        for (source, destination) in pairs {
+            script.arg(source).arg(destination);
+        }
+        if renumber {
+            script.arg(DatabaseManager::get_timestamp());
+        } else {
+            script.arg("");
+        }
+        self.run(|connection| script.query(connection))
+    }
+
+    fn all_ids() -> Vec<ParameterId> {
+        (0..PARAMETERS_NUM).filter_map(ParameterId::from_index).collect()
+    }
+
+    fn read_keys(&mut self, ids: &[ParameterId], keys: Vec<String>) -> Result<Vec<ParameterValue>, EconfError> {
+        // One transaction, the values come from the same state
+        let mut commands = pipe();
+        commands.atomic();
+        for key in &keys {
+            commands.cmd("HGET").arg(key).arg("value");
+        }
+        let encoded: Vec<Option<Zeroizing<String>>> = self.run(|connection| {
+            let values: Vec<Option<String>> = commands.query(connection)?;
+            Ok(values.into_iter().map(|value| value.map(Zeroizing::new)).collect())
+        })?;
+        ids.iter()
+            .zip(encoded)
+            .map(|(id, encoded)| match encoded {
+                Some(encoded) => decode_value(*id, &encoded),
+                None => self.default_value(&PARAMETER_DATA[id.index()]),
+            })
+            .collect()
+    }
+}
+
+impl ParameterStore for RedisStore {
+    fn read(&mut self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        let key = self.value_key(id);
+        let encoded: Option<String> = self.run(|connection| cmd("HGET").arg(key).arg("value").query(connection))?;
+        match encoded.map(Zeroizing::new) {
+            Some(encoded) => decode_value(id, &encoded),
+            None => self.default_value(&PARAMETER_DATA[id.index()]),
+        }
+    }
+
+    fn read_snapshot(&mut self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        let keys = ids.iter().map(|id| self.value_key(*id)).collect();
+        self.read_keys(ids, keys)
+    }
+
+    fn write(
+        &mut self,
+        id: ParameterId,
+        value: ParameterValue,
+        force: bool,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<(Status<ParameterValue>, Option<u64>), EconfError> {
+        let expected_sequence = match condition {
+            Some(condition) => match self.check_condition(id, condition)? {
+                Some(sequence) => Some(sequence),
+                None => {
+                    debug!("Write condition of {} doesn't hold", id as usize);
+                    return Ok((Status::StatusErrorConflict(value), None));
+                }
+            },
+            None => None,
+        };
+        if !force && condition.is_none() {
+            let current = self.read(id)?;
+            let equal = current == value;
+            sensitive::discard(id, current);
+            if equal {
+                debug!("Values are equal, skip writing");
+                return Ok((Status::StatusOkNotChanged(value), None));
+            }
+        }
+
+        let status = validate_value(id, Status::StatusOkChanged(value))?;
+        let value = match &status {
+            Status::StatusErrorNotAccepted(_) | Status::StatusErrorFailed => return Ok((status, None)),
+            status => status.clone().unwrap(),
+        };
+        debug!("Write to Redis: {} {}", id as usize, sensitive::log_value(id, &value));
+        let written = self.store_values(&[(id, value.clone())], source, expected_sequence);
+        sensitive::discard(id, value);
+        match written? {
+            Some(sequences) => Ok((status, sequences.first().copied())),
+            None => Ok((Status::StatusErrorConflict(status.unwrap()), None)),
+        }
+    }
+
+    fn write_all(
+        &mut self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<WrittenValue>, EconfError> {
+        let expected_sequence = match (condition, values.first()) {
+            (Some(condition), Some((id, _))) => match self.check_condition(*id, condition)? {
+                Some(sequence) => Some(sequence),
+                None => {
+                    let id = *id;
+                    debug!("Write condition of {} doesn't hold", id as usize);
+                    for (id, value) in values {
+                        sensitive::discard(id, value);
+                    }
+                    return Err(EconfError::Conflict(id));
+                }
+            },
+            _ => None,
+        };
+        let ids: Vec<ParameterId> = values.iter().map(|(id, _)| *id).collect();
+        let current = self.read_snapshot(&ids)?;
+        let mut statuses: Vec<(ParameterId, Status<ParameterValue>)> = Vec::with_capacity(values.len());
+        for (index, ((id, value), current)) in values.into_iter().zip(current).enumerate() {
+            // The value with the condition is written even if it is the same
+            let equal = current == value && !(index == 0 && expected_sequence.is_some());
+            sensitive::discard(id, current);
+            if equal {
+                statuses.push((id, Status::StatusOkNotChanged(value)));
+                continue;
+            }
+            let rejected = match validate_value(id, Status::StatusOkChanged(value))? {
+                Status::StatusErrorNotAccepted(value) => {
+                    let reason = format!("value {} not accepted", sensitive::log_value(id, &value));
+                    sensitive::discard(id, value);
+                    EconfError::Validation { id, reason }
+                }
+                Status::StatusErrorFailed => EconfError::Validation { id, reason: "validation failed".to_owned() },
+                status => {
+                    statuses.push((id, status));
+                    continue;
+                }
+            };
+            for (id, status) in statuses {
+                sensitive::discard(id, status.unwrap());
+            }
+            return Err(rejected);
+        }
+
+        let changed: Vec<(ParameterId, ParameterValue)> = statuses.iter()
+            .filter(|(_, status)| !matches!(status, Status::StatusOkNotChanged(_)))
+            .map(|(id, status)| (*id, status.clone().unwrap()))
+            .collect();
+        let written = if changed.is_empty() { Ok(Some(Vec::new())) } else { self.store_values(&changed, source, expected_sequence) };
+        for (id, value) in changed {
+            sensitive::discard(id, value);
+        }
+        let Some(sequences) = written? else {
+            return Err(EconfError::Conflict(ids[0]));
+        };
+        let mut sequences = sequences.into_iter();
+        Ok(statuses.into_iter()
+            .map(|(id, status)| match status {
+                Status::StatusOkNotChanged(_) => (id, status, None),
+                _ => (id, status, sequences.next()),
+            })
+            .collect())
+    }
+
+    fn reset(&mut self, ids: &[ParameterId]) -> Result<u64, EconfError> {
+        let mut script = cmd("EVAL");
+        script.arg(RESET_SCRIPT).arg(ids.len() + 1).arg(self.sequence_key());
+        for id in ids {
+            script.arg(self.value_key(*id));
+        }
+        self.run(|connection| script.query(connection))
+    }
+
+    fn update(&mut self) -> Result<Vec<(ParameterChangeEvent, Option<ParameterValue>)>, EconfError> {
+        let ids = Self::all_ids();
+        let mut commands = pipe();
+        commands.atomic();
+        for id in &ids {
+            commands.cmd("HMGET").arg(self.value_key(*id)).arg("value").arg("timestamp").arg("source").arg("sequence");
+        }
+        type Fields = (Option<String>, Option<f64>, Option<String>, Option<u64>);
+        let stored: Vec<Fields> = self.run(|connection| commands.query(connection))?;
+        let mut changes = Vec::new();
+        for (id, (encoded, timestamp, source, sequence)) in ids.into_iter().zip(stored) {
+            let encoded = encoded.map(Zeroizing::new);
+            let sequence = sequence.unwrap_or(0);
+            let seen = std::mem::replace(&mut self.seen[id.index()], sequence);
+            if sequence == seen {
+                continue;
+            }
+            let value = match &encoded {
+                Some(encoded) => Some(decode_value(id, encoded)?),
+                // Removed, the next read gives the default
+                None => None,
+            };
+            let event = ParameterChangeEvent {
+                id,
+                source: source.filter(|source| !source.is_empty()),
+                timestamp: timestamp.unwrap_or_else(DatabaseManager::get_timestamp),
+                sequence,
+            };
+            changes.push((event, value));
+        }
+        Ok(changes)
+    }
+
+    fn save(&mut self, filter: &dyn Fn(&String) -> bool, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        info!("Saving to {}saved", self.prefix);
+        let ids = Self::all_ids();
+        // The parameters not selected are removed from the saved copy, as the copy of a missing key
+        let pairs = ids.iter()
+            .map(|id| {
+                let selected = filter(&PARAMETER_DATA[id.index()].name_id.to_owned());
+                let source = if selected { self.value_key(*id) } else { format!("{}none", self.prefix) };
+                (source, self.saved_key(*id))
+            })
+            .collect();
+        let sequence = self.current_sequence()?;
+        self.copy_values(pairs, false)?;
+        let key = self.saved_sequence_key();
+        self.run(|connection| cmd("SET").arg(key).arg(sequence).query::<()>(connection))?;
+        progress(ids.len() as u64, ids.len() as u64);
+        self.saved_sequence = Some(sequence);
+        Ok(())
+    }
+
+    fn save_selected(&mut self, filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        info!("Saving the selected parameters to {}saved", self.prefix);
+        let pairs = Self::all_ids().into_iter()
+            .filter(|id| filter(&PARAMETER_DATA[id.index()].name_id.to_owned()))
+            .map(|id| (self.value_key(id), self.saved_key(id)))
+            .collect();
+        self.copy_values(pairs, false)?;
+        let key = self.saved_sequence_key();
+        self.run(|connection| cmd("SETNX").arg(key).arg(0).query::<()>(connection))
+    }
+
+    fn load(&mut self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        let key = self.saved_sequence_key();
+        let saved: bool = self.run(|connection| cmd("EXISTS").arg(key).query(connection))?;
+        if !saved {
+            return Err(EconfError::NotFound(format!("Saved values in {}saved", self.prefix)));
+        }
+        info!("Loading from {}saved", self.prefix);
+        let ids = Self::all_ids();
+        let pairs = ids.iter().map(|id| (self.saved_key(*id), self.value_key(*id))).collect();
+        self.copy_values(pairs, true)?;
+        progress(ids.len() as u64, ids.len() as u64);
+        Ok(())
+    }
+
+    fn drop_values(&mut self) -> Result<(), EconfError> {
+        info!("Deleting the stored values");
+        self.reset(&Self::all_ids())?;
+        Ok(())
+    }
+
+    fn read_saved(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        // A separate connection, the trait reads the saved values without changing the store
+        let mut connection = self.client.get_connection_with_timeout(TCP_IO_TIMEOUT).map_err(redis_error)?;
+        let mut commands = pipe();
+        commands.atomic();
+        for id in ids {
+            commands.cmd("HGET").arg(self.saved_key(*id)).arg("value");
+        }
+        let encoded: Vec<Option<String>> = commands.query(&mut connection).map_err(redis_error)?;
+        ids.iter()
+            .zip(encoded)
+            .map(|(id, encoded)| match encoded.map(Zeroizing::new) {
+                Some(encoded) => decode_value(*id, &encoded),
+                None => self.default_value(&PARAMETER_DATA[id.index()]),
+            })
+            .collect()
+    }
+
+    fn current_sequence(&mut self) -> Result<u64, EconfError> {
+        let key = self.sequence_key();
+        let sequence: Option<u64> = self.run(|connection| cmd("GET").arg(key).query(connection))?;
+        Ok(sequence.unwrap_or(0))
+    }
+
+    fn parameter_sequence(&mut self, id: ParameterId) -> Result<u64, EconfError> {
+        let key = self.value_key(id);
+        let sequence: Option<u64> = self.run(|connection| cmd("HGET").arg(key).arg("sequence").query(connection))?;
+        Ok(sequence.unwrap_or(0))
+    }
+
+    fn allocate_sequence(&mut self) -> Result<u64, EconfError> {
+        let key = self.sequence_key();
+        self.run(|connection| cmd("INCR").arg(key).query(connection))
+    }
+
+    fn default_value(&self, parameter: &Parameter) -> Result<ParameterValue, EconfError> {
+        Ok(read_default(&self.default_data_folder, parameter))
+    }
+
+    fn has_unsaved_changes(&mut self) -> Result<bool, EconfError> {
+        let sequence = self.current_sequence()?;
+        Ok(self.saved_sequence != Some(sequence))
+    }
+}
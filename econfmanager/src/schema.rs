@@ -1,6 +1,7 @@
 use std::{borrow::Cow, error::Error, fmt, mem};
 use base64::{prelude::BASE64_STANDARD, Engine};
 use prost_reflect::{DescriptorPool, DynamicMessage, FileDescriptor, MessageDescriptor, ReflectMessage, Value};
+use regex::Regex;
 use serde::ser::{Serialize, Serializer};
 
 pub(crate) struct SchemaManager {
@@ -76,6 +77,29 @@ impl ParameterValue {
             ParameterValue::ValPath(_) => ParameterValueType::TypeBlob,
         }
     }
+
+    /// False for NaN and infinities, the other types are always finite
+    pub fn is_finite(&self) -> bool {
+        match self {
+            ParameterValue::ValF32(v) => v.is_finite(),
+            ParameterValue::ValF64(v) => v.is_finite(),
+            _ => true,
+        }
+    }
+}
+
+/// JSON has no NaN and infinities, such floats are sent as "NaN", "Infinity" and "-Infinity".
+/// The strings parse back as floats
+pub fn non_finite_name(value: f64) -> Option<&'static str> {
+    if value.is_nan() {
+        Some("NaN")
+    } else if value == f64::INFINITY {
+        Some("Infinity")
+    } else if value == f64::NEG_INFINITY {
+        Some("-Infinity")
+    } else {
+        None
+    }
 }
 
 impl Default for ParameterValue {
@@ -95,8 +119,14 @@ impl Serialize for ParameterValue {
             ParameterValue::ValU32(v) => v.serialize(serializer),
             ParameterValue::ValI64(v) => v.serialize(serializer),
             ParameterValue::ValU64(v) => v.serialize(serializer),
-            ParameterValue::ValF32(v) => v.serialize(serializer),
-            ParameterValue::ValF64(v) => v.serialize(serializer),
+            ParameterValue::ValF32(v) => match non_finite_name(*v as f64) {
+                Some(name) => name.serialize(serializer),
+                None => v.serialize(serializer),
+            },
+            ParameterValue::ValF64(v) => match non_finite_name(*v) {
+                Some(name) => name.serialize(serializer),
+                None => v.serialize(serializer),
+            },
             ParameterValue::ValString(v) => v.serialize(serializer),
             ParameterValue::ValBlob(v) => {
                         let encoded = BASE64_STANDARD.encode(v);
@@ -235,14 +265,76 @@ pub enum ValidationMethod {
         values: Cow<'static, [ParameterValue]>,
         names: Cow<'static, [&'static str]>,
     },
+    Text {          // Strings: the length in characters and a pattern for the whole string
+        max_length: Option<u32>,
+        pattern: Option<&'static str>,
+    },
     CustomCallback, // Validate using a callback function
 }
 
+/// Lowest role allowed to change a parameter through the server, the levels are ordered
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AccessLevel {
+    #[default]
+    User,
+    Service,
+    Factory,
+}
+
+impl AccessLevel {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccessLevel::User => "user",
+            AccessLevel::Service => "service",
+            AccessLevel::Factory => "factory",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AccessLevel> {
+        match name.to_lowercase().as_str() {
+            "user" => Some(AccessLevel::User),
+            "service" => Some(AccessLevel::Service),
+            "factory" => Some(AccessLevel::Factory),
+            _ => None,
+        }
+    }
+}
+
+/// When the changes of a parameter are written to the database
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PersistPolicy {
+    #[default]
+    Immediate,
+    /// Written when the parameter is not changed for the time
+    Debounce { ms: u32 },
+    /// Kept in memory until save or the shutdown of the instance
+    OnSaveOnly,
+}
+
+impl PersistPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PersistPolicy::Immediate => "immediate",
+            PersistPolicy::Debounce { .. } => "debounce",
+            PersistPolicy::OnSaveOnly => "on_save_only",
+        }
+    }
+
+    pub fn debounce_ms(&self) -> Option<u32> {
+        match self {
+            PersistPolicy::Debounce { ms } => Some(*ms),
+            _ => None,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive (Debug)]
 pub struct Parameter {
     pub value_type: ParameterValueType,
     pub value_default: ParameterValue,
+    pub id: usize,
     pub name_id: &'static str,
     pub validation: ValidationMethod,
     pub comment: &'static str,
@@ -252,6 +344,20 @@ pub struct Parameter {
     pub runtime: bool,
     pub readonly: bool,
     pub internal: bool,
+    pub blob_schema: Option<&'static str>,
+    pub unit: Option<&'static str>,
+    pub step: Option<f64>,
+    pub precision: Option<u32>,
+    pub widget: Option<&'static str>,
+    pub deprecated: bool,
+    pub hidden: bool,
+    pub access_level: AccessLevel,
+    pub allow_non_finite: bool,
+    pub max_size: Option<u32>,
+    pub content_type: Option<&'static str>,
+    pub persist_policy: PersistPolicy,
+    pub secret: bool,
+    pub computed: bool,
 }
 
 #[repr(C)]
@@ -346,6 +452,7 @@ impl SchemaManager {
         let default_config = DynamicMessage::new(self.config_descriptor.clone());
         let mut groups = Vec::new();
         let mut parameters = Vec::new();
+        let mut next_id = 0;
         for field in default_config.descriptor().fields() {
             let value = &*default_config.get_field(&field);
             match value {
@@ -391,6 +498,7 @@ impl SchemaManager {
                                 _ => todo!("Unsupported paramter kind {:?}", field_type)
                             },
                             value_default: ParameterValue::ValNone,
+                            id: next_id,
                             // NOTE: Leak is okay since this function is only called at build time
                             name_id: Box::leak(Box::new(format!("{}@{}", field.name().to_string(), pm_field.name().to_string()))), 
                             validation: ValidationMethod::None, 
@@ -401,10 +509,30 @@ impl SchemaManager {
                             runtime: false,
                             readonly: false,
                             internal: false, 
+                            blob_schema: None,
+                            unit: None,
+                            step: None,
+                            precision: None,
+                            widget: None,
+                            deprecated: false,
+                            hidden: false,
+                            access_level: AccessLevel::User,
+                            allow_non_finite: false,
+                            max_size: None,
+                            content_type: None,
+                            persist_policy: PersistPolicy::Immediate,
+                            secret: false,
+                            computed: false,
                         };
 
                         let field_options = pm_field.options();
 
+                        if let Some((_, val)) = field_options.extensions().find(|(desc, _)| desc.name() == "id") {
+                            parameter.id = val.as_u32()
+                                .ok_or(format!("Field {} id option is not an unsigned integer", parameter.name_id))? as usize;
+                        }
+                        next_id = parameter.id + 1;
+
                         parameter.title = Box::leak(Box::new(field_options.extensions()
                             .find(|(desc, _)| desc.name() == "title")
                             .and_then(|(_, val)| val.as_str())
@@ -430,6 +558,114 @@ impl SchemaManager {
                             .and_then(|(_, val)| val.as_bool())
                             .unwrap_or(false);
 
+                        parameter.deprecated = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "deprecated")
+                            .and_then(|(_, val)| val.as_bool())
+                            .unwrap_or(false);
+
+                        parameter.hidden = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "hidden")
+                            .and_then(|(_, val)| val.as_bool())
+                            .unwrap_or(false);
+
+                        parameter.allow_non_finite = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "allow_non_finite")
+                            .and_then(|(_, val)| val.as_bool())
+                            .unwrap_or(false);
+
+                        parameter.access_level = match field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "access_level")
+                            .and_then(|(_, val)| val.as_enum_number())
+                            .unwrap_or(0)
+                        {
+                            0 => AccessLevel::User,
+                            1 => AccessLevel::Service,
+                            2 => AccessLevel::Factory,
+                            other => return Err(format!("Field {} has unknown access level {}", parameter.name_id, other).into()),
+                        };
+
+                        let debounce_ms = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "debounce_ms")
+                            .and_then(|(_, val)| val.as_u32());
+
+                        parameter.persist_policy = match field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "persist_policy")
+                            .and_then(|(_, val)| val.as_enum_number())
+                            .unwrap_or(0)
+                        {
+                            0 => PersistPolicy::Immediate,
+                            1 => match debounce_ms {
+                                Some(ms) if ms > 0 => PersistPolicy::Debounce { ms },
+                                _ => return Err(format!("Field {} persist_policy DEBOUNCE requires a non-zero debounce_ms", parameter.name_id).into()),
+                            },
+                            2 => PersistPolicy::OnSaveOnly,
+                            other => return Err(format!("Field {} has unknown persist policy {}", parameter.name_id, other).into()),
+                        };
+                        if debounce_ms.is_some() && parameter.persist_policy.debounce_ms().is_none() {
+                            return Err(format!("Field {} debounce_ms is only supported with persist_policy DEBOUNCE", parameter.name_id).into());
+                        }
+
+                        parameter.blob_schema = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "blob_schema")
+                            .and_then(|(_, val)| val.as_str())
+                            .map(|val| Box::leak(val.to_string().into_boxed_str()) as &'static str);
+
+                        parameter.unit = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "unit")
+                            .and_then(|(_, val)| val.as_str())
+                            .map(|val| Box::leak(val.to_string().into_boxed_str()) as &'static str);
+
+                        parameter.step = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "step")
+                            .and_then(|(_, val)| val.as_f64());
+
+                        parameter.precision = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "precision")
+                            .and_then(|(_, val)| val.as_u32());
+
+                        parameter.widget = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "widget")
+                            .and_then(|(_, val)| val.as_str())
+                            .map(|val| Box::leak(val.to_string().into_boxed_str()) as &'static str);
+
+                        parameter.max_size = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "max_size")
+                            .and_then(|(_, val)| val.as_u32());
+
+                        parameter.content_type = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "content_type")
+                            .and_then(|(_, val)| val.as_str())
+                            .map(|val| Box::leak(val.to_string().into_boxed_str()) as &'static str);
+
+                        parameter.secret = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "secret")
+                            .and_then(|(_, val)| val.as_bool())
+                            .unwrap_or(false);
+                        if parameter.secret && !matches!(parameter.value_type, ParameterValueType::TypeString | ParameterValueType::TypeBlob) {
+                            return Err(format!("Field {} secret option is only supported for string and bytes fields", parameter.name_id).into());
+                        }
+
+                        parameter.computed = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "computed")
+                            .and_then(|(_, val)| val.as_bool())
+                            .unwrap_or(false);
+                        if parameter.computed && (parameter.is_const || parameter.secret) {
+                            return Err(format!("Field {} computed option can't be used with is_const or secret", parameter.name_id).into());
+                        }
+
+                        if (parameter.max_size.is_some() || parameter.content_type.is_some()) && parameter.value_type != ParameterValueType::TypeBlob {
+                            return Err(format!("Field {} max_size and content_type options are only supported for bytes fields", parameter.name_id).into());
+                        }
+
+                        if let Some(blob_schema) = parameter.blob_schema {
+                            if parameter.value_type != ParameterValueType::TypeBlob {
+                                return Err(format!("Field {} has blob_schema {} but is not a bytes field", parameter.name_id, blob_schema).into());
+                            }
+                            if self.config_descriptor.parent_pool().get_message_by_name(blob_schema).is_none() {
+                                return Err(format!("Field {} blob_schema message {} not found", parameter.name_id, blob_schema).into());
+                            }
+                        }
+
                         parameter.tags = field_options.extensions()
                             .find(|(desc, _)| desc.name() == "tags")
                             .and_then(|(_, val)| {
@@ -500,6 +736,29 @@ impl SchemaManager {
                             }
                         }
 
+                        let max_length = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "max_length")
+                            .and_then(|(_, val)| val.as_u32());
+
+                        let pattern = field_options.extensions()
+                            .find(|(desc, _)| desc.name() == "pattern")
+                            .and_then(|(_, val)| val.as_str())
+                            .map(|val| Box::leak(val.to_string().into_boxed_str()) as &'static str);
+
+                        if max_length.is_some() || pattern.is_some() {
+                            if parameter.value_type != ParameterValueType::TypeString {
+                                return Err(format!("Field {} max_length and pattern options are only supported for strings", parameter.name_id).into());
+                            }
+                            if let Some(pattern) = pattern {
+                                Regex::new(pattern)
+                                    .map_err(|e| format!("Field {} pattern is not a valid regular expression: {}", parameter.name_id, e))?;
+                            }
+                            match parameter.validation {
+                                ValidationMethod::None => parameter.validation = ValidationMethod::Text { max_length, pattern },
+                                _ => return Err(format!("Field {} max_length and pattern options cannot be combined with the validation method", parameter.name_id).into()),
+                            }
+                        }
+
                         // Force allowed values for Enum fields
                         if let prost_reflect::Kind::Enum(enum_desc) = pm_field.kind()
                         {
@@ -580,7 +839,7 @@ impl SchemaManager {
                                 }
                             },
                             
-                            ValidationMethod::CustomCallback => {}
+                            ValidationMethod::Text { .. } | ValidationMethod::CustomCallback => {}
                         }
 
                         parameters.push(parameter);
@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::error::EconfError;
+use crate::generated::{PARAMETER_DATA, ParameterId};
+use crate::manifest::to_hex;
+use crate::schema::ParameterValue;
+
+/// Parameters with this tag hold credentials or keys: their copies in memory are wiped when dropped
+pub const SENSITIVE_TAG: &str = "sensitive";
+/// Values of the parameters with this tag are replaced in the logs with their length and a short hash,
+/// enough to see whether the value changed
+pub const NO_LOG_TAG: &str = "no_log";
+/// Shown by the servers and the logs instead of the values of the secret parameters
+pub const SECRET_MASK: &str = "***";
+
+/// First byte of the encrypted values, followed by the nonce and the ciphertext
+const SECRET_FORMAT_VERSION: u8 = 1;
+const NONCE_SIZE: usize = 12;
+
+/// The secret parameters are sensitive too
+pub(crate) fn is_sensitive_index(index: usize) -> bool {
+    PARAMETER_DATA[index].secret || PARAMETER_DATA[index].tags.contains(&SENSITIVE_TAG)
+}
+
+pub fn is_secret(id: ParameterId) -> bool {
+    PARAMETER_DATA[id.index()].secret
+}
+
+/// The values are not written to the logs, the secret parameters are not logged too
+pub fn is_no_log(id: ParameterId) -> bool {
+    is_secret(id) || PARAMETER_DATA[id.index()].tags.contains(&NO_LOG_TAG)
+}
+
+/// The secrets get no hash, a short secret could be found from it
+fn redacted(id: ParameterId, data: &[u8]) -> String {
+    if is_secret(id) {
+        return SECRET_MASK.to_owned();
+    }
+    format!("<{} bytes, sha256 {}>", data.len(), &to_hex(&Sha256::digest(data))[..8])
+}
+
+/// Value of a parameter for the log lines, see [`NO_LOG_TAG`]
+pub struct LogValue<'a> {
+    id: ParameterId,
+    value: &'a ParameterValue,
+}
+
+impl fmt::Display for LogValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !is_no_log(self.id) {
+            return write!(f, "{}", self.value);
+        }
+        match self.value {
+            ParameterValue::ValString(text) => f.write_str(&redacted(self.id, text.as_bytes())),
+            ParameterValue::ValBlob(blob) => f.write_str(&redacted(self.id, blob)),
+            other => f.write_str(&redacted(self.id, other.to_string().as_bytes())),
+        }
+    }
+}
+
+pub fn log_value(id: ParameterId, value: &ParameterValue) -> LogValue<'_> {
+    LogValue { id, value }
+}
+
+/// Same as [`log_value`] for a value received as text, before it is converted
+pub fn log_text(id: ParameterId, text: &str) -> Cow<'_, str> {
+    if is_no_log(id) {
+        return Cow::Owned(redacted(id, text.as_bytes()));
+    }
+    Cow::Borrowed(text)
+}
+
+/// Encryption of the secret parameters with the device key (ChaCha20-Poly1305). The name of
+/// the parameter is authenticated too, so a value copied to another parameter is not accepted
+pub(crate) struct SecretCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl SecretCipher {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        Self { cipher: ChaCha20Poly1305::new(Key::from_slice(key)) }
+    }
+
+    pub(crate) fn encrypt(&self, name: &str, plaintext: &[u8]) -> Result<Vec<u8>, EconfError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad: name.as_bytes() })
+            .map_err(|_| EconfError::Config(format!("Failed to encrypt {}", name)))?;
+        let mut data = Vec::with_capacity(1 + NONCE_SIZE + ciphertext.len());
+        data.push(SECRET_FORMAT_VERSION);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    pub(crate) fn decrypt(&self, name: &str, data: &[u8]) -> Result<Vec<u8>, EconfError> {
+        if data.len() < 1 + NONCE_SIZE || data[0] != SECRET_FORMAT_VERSION {
+            return Err(EconfError::Schema(format!("Stored value of {} is not encrypted", name)));
+        }
+        let (nonce, ciphertext) = data[1..].split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: name.as_bytes() })
+            .map_err(|_| EconfError::Schema(format!("Stored value of {} can't be decrypted, the device key is wrong", name)))
+    }
+}
+
+pub fn is_sensitive(id: ParameterId) -> bool {
+    is_sensitive_index(id.index())
+}
+
+/// Wipe the value if the parameter is sensitive, the value is dropped after that
+pub(crate) fn discard(id: ParameterId, mut value: ParameterValue) {
+    if is_sensitive(id) {
+        value.zeroize();
+    }
+}
+
+impl Zeroize for ParameterValue {
+    fn zeroize(&mut self) {
+        match self {
+            ParameterValue::ValBool(v) => v.zeroize(),
+            ParameterValue::ValI32(v) | ParameterValue::ValEnum(v) => v.zeroize(),
+            ParameterValue::ValU32(v) => v.zeroize(),
+            ParameterValue::ValI64(v) => v.zeroize(),
+            ParameterValue::ValU64(v) => v.zeroize(),
+            ParameterValue::ValF32(v) => v.zeroize(),
+            ParameterValue::ValF64(v) => v.zeroize(),
+            ParameterValue::ValString(Cow::Owned(v)) => v.zeroize(),
+            ParameterValue::ValBlob(v) => v.zeroize(),
+            // Borrowed strings and paths point to the static defaults
+            ParameterValue::ValString(Cow::Borrowed(_))
+            | ParameterValue::ValPath(_)
+            | ParameterValue::ValNone => {}
+        }
+    }
+}
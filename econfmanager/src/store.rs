@@ -0,0 +1,365 @@
+use std::path::Path;
+
+use log::error;
+
+use crate::database_utils::{DatabaseManager, Status, WrittenValue};
+use crate::error::EconfError;
+use crate::generated::ParameterId;
+use crate::interface::{ParameterChangeEvent, StorageHealth, WriteCondition};
+use crate::schema::{Parameter, ParameterValue};
+
+fn unsupported<T>(operation: &str) -> Result<T, EconfError> {
+    Err(EconfError::Unsupported(operation.to_owned()))
+}
+
+/// Default of the parameter for the stores other than the database, the file defaults are read
+/// from the folder and an unreadable file gives an empty blob like in the database
+pub(crate) fn read_default(default_data_folder: &str, parameter: &Parameter) -> ParameterValue {
+    match parameter.value_default {
+        ParameterValue::ValPath(path) => {
+            let full_path = Path::new(default_data_folder).join(path);
+            let bytes = std::fs::read(&full_path)
+                .map_err(|e| error!("Error reading file {} with default data: {}", full_path.display(), e))
+                .unwrap_or_default();
+            ParameterValue::ValBlob(bytes)
+        }
+        _ => parameter.value_default.clone(),
+    }
+}
+
+/// Storage of the parameter values behind [`crate::interface::InterfaceInstance`]. Only the values
+/// different from the defaults have to be stored. The SQLite [`DatabaseManager`] is the default store,
+/// [`crate::file_store::FileStore`] keeps the values in a flat file for the platforms without SQLite.
+///
+/// The required methods are the ones every store has to provide. The others have defaults for the stores
+/// without the feature: the blob streams, the profiles and the backups fail with `EconfError::Unsupported`,
+/// the maintenance and the integrity checks do nothing
+pub trait ParameterStore: Send + 'static {
+    /// Value of the parameter, the default if it is not stored
+    fn read(&mut self, id: ParameterId) -> Result<ParameterValue, EconfError>;
+
+    /// Values of the parameters from the same state of the store
+    fn read_snapshot(&mut self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        ids.iter().map(|id| self.read(*id)).collect()
+    }
+
+    /// Validate and write the value. Returns the status and the sequence number of the write, None if
+    /// the value was not written. A value equal to the stored one is not written unless forced.
+    /// With the condition the value is written only if the condition holds
+    fn write(
+        &mut self,
+        id: ParameterId,
+        value: ParameterValue,
+        force: bool,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<(Status<ParameterValue>, Option<u64>), EconfError>;
+
+    /// Validate and write several values at once, none of them if any is not accepted.
+    /// The condition is the one of the first value, it is written even if it is the same and none of
+    /// the values are written if the condition doesn't hold (`EconfError::Conflict`)
+    fn write_all(
+        &mut self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<WrittenValue>, EconfError>;
+
+    /// Remove the stored values of the parameters, so they have the defaults.
+    /// Returns the sequence number of the change
+    fn reset(&mut self, ids: &[ParameterId]) -> Result<u64, EconfError>;
+
+    /// Changes made by the other instances since the last update, with the new values if known
+    /// (None for the removed values and the values to be read)
+    fn update(&mut self) -> Result<Vec<(ParameterChangeEvent, Option<ParameterValue>)>, EconfError>;
+
+    /// Copy the values selected by the filter (by the parameter name) to the saved copy,
+    /// the progress is called with the parameters processed and the total number of them
+    fn save(&mut self, filter: &dyn Fn(&String) -> bool, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError>;
+
+    /// Replace the values with the saved copy
+    fn load(&mut self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError>;
+
+    /// Remove all the stored values, all the parameters get the defaults
+    fn drop_values(&mut self) -> Result<(), EconfError>;
+
+    /// Values of the parameters in the saved copy, the defaults for the ones not saved
+    fn read_saved(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError>;
+
+    /// Sequence number of the last write to the store
+    fn current_sequence(&mut self) -> Result<u64, EconfError>;
+
+    /// Sequence number of the last write of the parameter, 0 if it is not stored
+    fn parameter_sequence(&mut self, id: ParameterId) -> Result<u64, EconfError>;
+
+    /// New sequence number for a change not written to the store
+    fn allocate_sequence(&mut self) -> Result<u64, EconfError>;
+
+    /// Default value of the parameter, with the file defaults read from the default data folder
+    fn default_value(&self, parameter: &Parameter) -> Result<ParameterValue, EconfError>;
+
+    /// Write the deferred writes, only the due ones unless `all`. Returns the number of the values written
+    fn flush_pending(&mut self, _all: bool) -> Result<usize, EconfError> {
+        Ok(0)
+    }
+
+    /// The values were changed since the last save
+    fn has_unsaved_changes(&mut self) -> Result<bool, EconfError> {
+        Ok(true)
+    }
+
+    /// Save only the values selected by the filter, the others keep their saved values
+    fn save_selected(&mut self, _filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        unsupported("partial save")
+    }
+
+    fn storage_health(&self) -> StorageHealth {
+        StorageHealth::default()
+    }
+
+    fn check_integrity(&mut self, _quick: bool) -> Result<Vec<String>, EconfError> {
+        Ok(Vec::new())
+    }
+
+    /// Size of the write-ahead log, None if the store has none
+    fn wal_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Check that the saved copy can be read
+    fn check_saved(&self) -> Result<(), EconfError> {
+        Ok(())
+    }
+
+    fn maintain(&mut self) -> Result<(), EconfError> {
+        Ok(())
+    }
+
+    /// Parameters with the file defaults, their files and whether they can be read
+    fn default_files_status(&self) -> Vec<(ParameterId, &'static str, std::io::Result<()>)> {
+        Vec::new()
+    }
+
+    /// Reread the changed file defaults, returns the parameters with the new defaults
+    fn refresh_default_files(&mut self) -> Result<Vec<ParameterId>, EconfError> {
+        Ok(Vec::new())
+    }
+
+    /// Open the stored blob for reading in chunks, returns the stream handle and the size
+    fn blob_stream_open_read(&mut self, _id: ParameterId) -> Result<(i64, usize), EconfError> {
+        unsupported("blob streams")
+    }
+
+    fn blob_stream_open_write(&mut self, _size: usize) -> Result<i64, EconfError> {
+        unsupported("blob streams")
+    }
+
+    fn blob_stream_read(&mut self, _row: i64, _offset: usize, _buffer: &mut [u8]) -> Result<usize, EconfError> {
+        unsupported("blob streams")
+    }
+
+    fn blob_stream_write(&mut self, _row: i64, _offset: usize, _data: &[u8]) -> Result<(), EconfError> {
+        unsupported("blob streams")
+    }
+
+    /// Store the written blob as the value of the parameter, returns the sequence number of the write
+    fn blob_stream_commit(&mut self, _id: ParameterId, _row: i64, _source: Option<&str>) -> Result<u64, EconfError> {
+        unsupported("blob streams")
+    }
+
+    fn blob_stream_discard(&mut self, _row: i64) -> Result<(), EconfError> {
+        unsupported("blob streams")
+    }
+
+    fn save_profile(
+        &mut self,
+        _name: &str,
+        _filter: &dyn Fn(&String) -> bool,
+        _progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        unsupported("profiles")
+    }
+
+    fn load_profile(&mut self, _name: &str, _progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        unsupported("profiles")
+    }
+
+    fn list_profiles(&self) -> Result<Vec<String>, EconfError> {
+        unsupported("profiles")
+    }
+
+    fn delete_profile(&mut self, _name: &str) -> Result<(), EconfError> {
+        unsupported("profiles")
+    }
+
+    fn list_backups(&self) -> Result<Vec<String>, EconfError> {
+        unsupported("backups")
+    }
+
+    fn restore_backup(&mut self, _name: &str, _progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        unsupported("backups")
+    }
+}
+
+impl ParameterStore for DatabaseManager {
+    fn read(&mut self, id: ParameterId) -> Result<ParameterValue, EconfError> {
+        self.read_or_create(id)
+    }
+
+    fn read_snapshot(&mut self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        DatabaseManager::read_snapshot(self, ids)
+    }
+
+    fn write(
+        &mut self,
+        id: ParameterId,
+        value: ParameterValue,
+        force: bool,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<(Status<ParameterValue>, Option<u64>), EconfError> {
+        DatabaseManager::write(self, id, value, force, condition, source)
+    }
+
+    fn write_all(
+        &mut self,
+        values: Vec<(ParameterId, ParameterValue)>,
+        condition: Option<&WriteCondition>,
+        source: Option<&str>,
+    ) -> Result<Vec<WrittenValue>, EconfError> {
+        DatabaseManager::write_all(self, values, condition, source)
+    }
+
+    fn reset(&mut self, ids: &[ParameterId]) -> Result<u64, EconfError> {
+        self.reset_values(ids)
+    }
+
+    fn update(&mut self) -> Result<Vec<(ParameterChangeEvent, Option<ParameterValue>)>, EconfError> {
+        DatabaseManager::update(self)
+    }
+
+    fn save(&mut self, filter: &dyn Fn(&String) -> bool, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.save_database(filter, progress)
+    }
+
+    fn load(&mut self, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        self.load_database(progress)
+    }
+
+    fn drop_values(&mut self) -> Result<(), EconfError> {
+        self.drop_database()
+    }
+
+    fn read_saved(&self, ids: &[ParameterId]) -> Result<Vec<ParameterValue>, EconfError> {
+        self.read_saved_values(ids)
+    }
+
+    fn current_sequence(&mut self) -> Result<u64, EconfError> {
+        DatabaseManager::current_sequence(self)
+    }
+
+    fn parameter_sequence(&mut self, id: ParameterId) -> Result<u64, EconfError> {
+        DatabaseManager::parameter_sequence(self, id)
+    }
+
+    fn allocate_sequence(&mut self) -> Result<u64, EconfError> {
+        DatabaseManager::allocate_sequence(self)
+    }
+
+    fn default_value(&self, parameter: &Parameter) -> Result<ParameterValue, EconfError> {
+        Ok(self.get_default_value(parameter)?)
+    }
+
+    fn flush_pending(&mut self, all: bool) -> Result<usize, EconfError> {
+        DatabaseManager::flush_pending(self, all)
+    }
+
+    fn has_unsaved_changes(&mut self) -> Result<bool, EconfError> {
+        DatabaseManager::has_unsaved_changes(self)
+    }
+
+    fn save_selected(&mut self, filter: &dyn Fn(&String) -> bool) -> Result<(), EconfError> {
+        DatabaseManager::save_selected(self, filter)
+    }
+
+    fn storage_health(&self) -> StorageHealth {
+        DatabaseManager::storage_health(self)
+    }
+
+    fn check_integrity(&mut self, quick: bool) -> Result<Vec<String>, EconfError> {
+        DatabaseManager::check_integrity(self, quick)
+    }
+
+    fn wal_size(&self) -> Option<u64> {
+        DatabaseManager::wal_size(self)
+    }
+
+    fn check_saved(&self) -> Result<(), EconfError> {
+        DatabaseManager::check_saved_database(self)
+    }
+
+    fn maintain(&mut self) -> Result<(), EconfError> {
+        DatabaseManager::maintain(self)
+    }
+
+    fn default_files_status(&self) -> Vec<(ParameterId, &'static str, std::io::Result<()>)> {
+        DatabaseManager::default_files_status(self)
+    }
+
+    fn refresh_default_files(&mut self) -> Result<Vec<ParameterId>, EconfError> {
+        DatabaseManager::refresh_default_files(self)
+    }
+
+    fn blob_stream_open_read(&mut self, id: ParameterId) -> Result<(i64, usize), EconfError> {
+        DatabaseManager::blob_stream_open_read(self, id)
+    }
+
+    fn blob_stream_open_write(&mut self, size: usize) -> Result<i64, EconfError> {
+        DatabaseManager::blob_stream_open_write(self, size)
+    }
+
+    fn blob_stream_read(&mut self, row: i64, offset: usize, buffer: &mut [u8]) -> Result<usize, EconfError> {
+        DatabaseManager::blob_stream_read(self, row, offset, buffer)
+    }
+
+    fn blob_stream_write(&mut self, row: i64, offset: usize, data: &[u8]) -> Result<(), EconfError> {
+        DatabaseManager::blob_stream_write(self, row, offset, data)
+    }
+
+    fn blob_stream_commit(&mut self, id: ParameterId, row: i64, source: Option<&str>) -> Result<u64, EconfError> {
+        DatabaseManager::blob_stream_commit(self, id, row, source)
+    }
+
+    fn blob_stream_discard(&mut self, row: i64) -> Result<(), EconfError> {
+        DatabaseManager::blob_stream_discard(self, row)
+    }
+
+    fn save_profile(
+        &mut self,
+        name: &str,
+        filter: &dyn Fn(&String) -> bool,
+        progress: &dyn Fn(u64, u64),
+    ) -> Result<(), EconfError> {
+        DatabaseManager::save_profile(self, name, filter, progress)
+    }
+
+    fn load_profile(&mut self, name: &str, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        DatabaseManager::load_profile(self, name, progress)
+    }
+
+    fn list_profiles(&self) -> Result<Vec<String>, EconfError> {
+        DatabaseManager::list_profiles(self)
+    }
+
+    fn delete_profile(&mut self, name: &str) -> Result<(), EconfError> {
+        DatabaseManager::delete_profile(self, name)
+    }
+
+    fn list_backups(&self) -> Result<Vec<String>, EconfError> {
+        DatabaseManager::list_backups(self)
+    }
+
+    fn restore_backup(&mut self, name: &str, progress: &dyn Fn(u64, u64)) -> Result<(), EconfError> {
+        DatabaseManager::restore_backup(self, name, progress)
+    }
+}
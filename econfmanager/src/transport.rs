@@ -0,0 +1,739 @@
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::config::Config;
+use crate::constants::{
+    MAX_NOTIFICATION_SIZE, RECEIVER_POLL_INTERVAL, TCP_IO_TIMEOUT, TCP_RECONNECT_MAX_DELAY, TCP_RECONNECT_MIN_DELAY,
+};
+use crate::error::EconfError;
+
+/// How the change notifications are delivered between the instances sharing a database
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TransportConfig {
+    /// UDP multicast to the group and port of the configuration, works between processes and devices
+    #[default]
+    Multicast,
+    /// Unix datagram sockets in the folder, every receiver binds its own socket there.
+    /// For hosts where multicast is not available
+    UnixSocket(PathBuf),
+    /// Channel inside the process, for single binary deployments and tests.
+    /// The instances using the same channel name receive the notifications of each other
+    InProcess(String),
+    /// TCP connection to the notification broker at the address, for networks that drop multicast.
+    /// The connection is restored automatically when the broker restarts
+    Tcp(SocketAddr),
+    /// Act as the notification broker: listen at the address and relay the notifications
+    /// between the connected instances. Exactly one instance should be the broker
+    TcpBroker(SocketAddr),
+    /// Keyspace notifications of the Redis server of a `RedisStore`, see `RedisStore::transport`.
+    /// The writes to the store notify the instances themselves, nothing is sent
+    #[cfg(feature = "redis")]
+    RedisKeyspace { url: String, prefix: String },
+}
+
+/// Sends and receives encoded `ParameterNotification` messages.
+/// A notification sent by an instance is also delivered to its own receiver
+pub trait NotificationTransport: Send + Sync {
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError>;
+    /// Start receiving, the notifications sent after this call are delivered to the returned receiver
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError>;
+}
+
+pub trait NotificationReceiver: Send {
+    /// Wait up to the timeout for the next notification, returns the notification and the description
+    /// of the sender, or None when nothing arrived. The timeout lets the receiving thread stop
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError>;
+}
+
+/// The error of a socket read that timed out, the kind differs between the platforms
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+pub(crate) fn create_transport(config: &Config) -> Result<Arc<dyn NotificationTransport>, EconfError> {
+    info!("Notification transport: {:?}", config.transport);
+    Ok(match &config.transport {
+        TransportConfig::Multicast => Arc::new(MulticastTransport::new(
+            config.multicast_group,
+            config.multicast_port,
+            config.multicast_ttl,
+        )),
+        #[cfg(unix)]
+        TransportConfig::UnixSocket(folder) => Arc::new(UnixSocketTransport::new(folder.clone())?),
+        #[cfg(not(unix))]
+        TransportConfig::UnixSocket(_) => {
+            return Err(EconfError::Config("Unix socket transport is not supported on this platform".to_owned()))
+        }
+        TransportConfig::InProcess(name) => Arc::new(InProcessTransport::new(name)),
+        TransportConfig::Tcp(address) => Arc::new(TcpTransport::new(*address)),
+        TransportConfig::TcpBroker(address) => Arc::new(TcpBrokerTransport::new(*address)?),
+        #[cfg(feature = "redis")]
+        TransportConfig::RedisKeyspace { url, prefix } => Arc::new(RedisKeyspaceTransport::new(url, prefix)?),
+    })
+}
+
+/******************************************************************************
+ * UDP MULTICAST
+ ******************************************************************************/
+
+pub struct MulticastTransport {
+    multicast_group: Ipv4Addr,
+    port: u16,
+    ttl: u32,
+    /// Socket kept open between the sends
+    socket: Mutex<Option<UdpSocket>>,
+}
+
+impl MulticastTransport {
+    pub fn new(multicast_group: Ipv4Addr, port: u16, ttl: u32) -> Self {
+        Self { multicast_group, port, ttl, socket: Mutex::new(None) }
+    }
+}
+
+impl NotificationTransport for MulticastTransport {
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError> {
+        let mut socket = self.socket.lock().unwrap();
+        if socket.is_none() {
+            let new_socket = UdpSocket::bind("0.0.0.0:0")?;
+            // Set Time-to-Live (TTL) for multicast
+            new_socket.set_ttl(self.ttl)?;
+            *socket = Some(new_socket);
+        }
+        if let Err(e) = socket.as_ref().unwrap().send_to(notification, SocketAddrV4::new(self.multicast_group, self.port)) {
+            // Reopen the socket on the next send
+            *socket = None;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        let local_addr = Ipv4Addr::new(0, 0, 0, 0);
+
+        info!("Starting multicast receiver on {}:{}", self.multicast_group, self.port);
+
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))
+            .map_err(|e| {
+                error!("Socket creation failed: {}", e);
+                e
+            })?;
+
+        socket.set_reuse_address(true)
+            .map_err(|e| warn!("SO_REUSEADDR failed (non-fatal): {}", e)).ok();
+
+        #[cfg(target_os = "linux")]
+        socket.set_reuse_port(true)
+            .map_err(|e| warn!("SO_REUSEPORT failed (non-fatal): {}", e)).ok();
+
+        socket.bind(&SocketAddrV4::new(local_addr, self.port).into())
+            .map_err(|e| {
+                error!("Failed to bind to port {}: {}", self.port, e);
+                e
+            })?;
+        info!("Successfully bound to UDP port {}", self.port);
+
+        socket.join_multicast_v4(&self.multicast_group, &local_addr)
+            .map_err(|e| {
+                error!("Multicast join failed: {}", e);
+                e
+            })?;
+        socket.set_multicast_loop_v4(false)?;
+
+        Ok(Box::new(MulticastReceiver { socket: socket.into() }))
+    }
+}
+
+struct MulticastReceiver {
+    socket: UdpSocket,
+}
+
+impl NotificationReceiver for MulticastReceiver {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let mut buf = [0u8; MAX_NOTIFICATION_SIZE];
+        match self.socket.recv_from(&mut buf) {
+            Ok((num_bytes, src)) => Ok(Some((buf[..num_bytes].to_vec(), src.to_string()))),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/******************************************************************************
+ * UNIX DOMAIN SOCKETS
+ ******************************************************************************/
+
+#[cfg(unix)]
+const UNIX_SOCKET_EXTENSION: &str = "sock";
+
+#[cfg(unix)]
+pub struct UnixSocketTransport {
+    folder: PathBuf,
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl UnixSocketTransport {
+    pub fn new(folder: PathBuf) -> Result<Self, EconfError> {
+        std::fs::create_dir_all(&folder)?;
+        Ok(Self { folder, socket: std::os::unix::net::UnixDatagram::unbound()? })
+    }
+}
+
+#[cfg(unix)]
+impl NotificationTransport for UnixSocketTransport {
+    /// Send to every socket in the folder, the sockets left by the stopped processes are removed
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError> {
+        for entry in std::fs::read_dir(&self.folder)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != UNIX_SOCKET_EXTENSION) {
+                continue;
+            }
+            if let Err(e) = self.socket.send_to(notification, &path) {
+                match e.kind() {
+                    std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotFound => {
+                        info!("Removing stale notification socket {}", path.display());
+                        let _ = std::fs::remove_file(&path);
+                    }
+                    _ => warn!("Failed to notify {}: {}", path.display(), e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        static RECEIVER_COUNTER: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let number = RECEIVER_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = self.folder.join(format!("{}-{}.{}", std::process::id(), number, UNIX_SOCKET_EXTENSION));
+        // A socket left by a previous process with the same PID
+        let _ = std::fs::remove_file(&path);
+        let socket = std::os::unix::net::UnixDatagram::bind(&path)?;
+        info!("Listening for notifications on {}", path.display());
+        Ok(Box::new(UnixSocketReceiver { socket, path }))
+    }
+}
+
+#[cfg(unix)]
+struct UnixSocketReceiver {
+    socket: std::os::unix::net::UnixDatagram,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl NotificationReceiver for UnixSocketReceiver {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        let mut buf = [0u8; MAX_NOTIFICATION_SIZE];
+        match self.socket.recv(&mut buf) {
+            Ok(num_bytes) => Ok(Some((buf[..num_bytes].to_vec(), self.path.display().to_string()))),
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocketReceiver {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/******************************************************************************
+ * IN-PROCESS CHANNEL
+ ******************************************************************************/
+
+type InProcessSubscribers = Mutex<HashMap<String, Vec<Sender<Vec<u8>>>>>;
+
+fn in_process_subscribers() -> &'static InProcessSubscribers {
+    static SUBSCRIBERS: OnceLock<InProcessSubscribers> = OnceLock::new();
+    SUBSCRIBERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub struct InProcessTransport {
+    name: String,
+}
+
+impl InProcessTransport {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_owned() }
+    }
+}
+
+impl NotificationTransport for InProcessTransport {
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError> {
+        let mut subscribers = in_process_subscribers().lock().unwrap();
+        if let Some(senders) = subscribers.get_mut(&self.name) {
+            // The senders of the dropped receivers fail and are removed
+            senders.retain(|sender| sender.send(notification.to_vec()).is_ok());
+        }
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        let (sender, receiver) = mpsc::channel();
+        in_process_subscribers().lock().unwrap().entry(self.name.clone()).or_default().push(sender);
+        Ok(Box::new(InProcessReceiver { receiver, name: self.name.clone() }))
+    }
+}
+
+struct InProcessReceiver {
+    receiver: Receiver<Vec<u8>>,
+    name: String,
+}
+
+impl NotificationReceiver for InProcessReceiver {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(notification) => Ok(Some((notification, self.name.clone()))),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(EconfError::Io(std::io::Error::other("In-process channel closed"))),
+        }
+    }
+}
+
+/******************************************************************************
+ * TCP BROKER
+ ******************************************************************************/
+
+/// The notifications are sent over TCP as frames prefixed with the big endian u32 length
+fn write_frame(stream: &mut TcpStream, notification: &[u8]) -> std::io::Result<()> {
+    let mut frame = Vec::with_capacity(4 + notification.len());
+    frame.extend_from_slice(&(notification.len() as u32).to_be_bytes());
+    frame.extend_from_slice(notification);
+    stream.write_all(&frame)
+}
+
+/// Splits the received bytes into frames. The reads time out, so a partial frame is kept until the rest arrives
+#[derive(Default)]
+struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    /// The next frame, or None when no complete frame arrived within the timeout
+    fn read(&mut self, stream: &mut TcpStream, timeout: Duration) -> std::io::Result<Option<Vec<u8>>> {
+        if let Some(frame) = self.take_frame()? {
+            return Ok(Some(frame));
+        }
+        stream.set_read_timeout(Some(timeout))?;
+        let mut chunk = [0u8; MAX_NOTIFICATION_SIZE];
+        match stream.read(&mut chunk) {
+            Ok(0) => Err(std::io::Error::new(ErrorKind::UnexpectedEof, "Connection closed")),
+            Ok(num_bytes) => {
+                self.buffer.extend_from_slice(&chunk[..num_bytes]);
+                self.take_frame()
+            }
+            Err(e) if is_timeout(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn take_frame(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([self.buffer[0], self.buffer[1], self.buffer[2], self.buffer[3]]) as usize;
+        if length > MAX_NOTIFICATION_SIZE {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Notification of {} bytes is too large", length),
+            ));
+        }
+        if self.buffer.len() < 4 + length {
+            return Ok(None);
+        }
+        let notification = self.buffer[4..4 + length].to_vec();
+        self.buffer.drain(..4 + length);
+        Ok(Some(notification))
+    }
+}
+
+/// Connection to the broker, shared by the sender and the receiver of the instance
+pub struct TcpTransport {
+    address: SocketAddr,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl TcpTransport {
+    pub fn new(address: SocketAddr) -> Self {
+        Self { address, connection: Arc::new(Mutex::new(None)) }
+    }
+}
+
+impl NotificationTransport for TcpTransport {
+    /// The notifications sent while the broker is not connected are dropped,
+    /// the receivers pick up the changes by polling
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError> {
+        let mut connection = self.connection.lock().unwrap();
+        match connection.as_mut() {
+            Some(stream) => {
+                if let Err(e) = write_frame(stream, notification) {
+                    warn!("Failed to send the notification to the broker {}: {}", self.address, e);
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                    *connection = None;
+                }
+            }
+            None => debug!("Broker {} not connected, notification dropped", self.address),
+        }
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        Ok(Box::new(TcpReceiver {
+            address: self.address,
+            connection: self.connection.clone(),
+            stream: None,
+            reader: FrameReader::default(),
+            delay: TCP_RECONNECT_MIN_DELAY,
+            next_attempt: Instant::now(),
+        }))
+    }
+}
+
+/// Keeps the connection to the broker, reconnecting with an exponential backoff
+struct TcpReceiver {
+    address: SocketAddr,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    stream: Option<TcpStream>,
+    reader: FrameReader,
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+impl TcpReceiver {
+    /// Try to connect once, after a failure the next attempt is delayed
+    fn connect(&mut self) -> Option<TcpStream> {
+        match TcpStream::connect_timeout(&self.address, TCP_IO_TIMEOUT).and_then(|stream| {
+            stream.set_nodelay(true)?;
+            stream.set_write_timeout(Some(TCP_IO_TIMEOUT))?;
+            Ok((stream.try_clone()?, stream))
+        }) {
+            Ok((writer, stream)) => {
+                info!("Connected to the notification broker {}", self.address);
+                *self.connection.lock().unwrap() = Some(writer);
+                self.delay = TCP_RECONNECT_MIN_DELAY;
+                Some(stream)
+            }
+            Err(e) => {
+                if self.delay == TCP_RECONNECT_MIN_DELAY {
+                    warn!("Notification broker {} not available: {}, retrying", self.address, e);
+                } else {
+                    debug!("Notification broker {} not available: {}, retry in {:?}", self.address, e, self.delay);
+                }
+                self.next_attempt = Instant::now() + self.delay;
+                self.delay = (self.delay * 2).min(TCP_RECONNECT_MAX_DELAY);
+                None
+            }
+        }
+    }
+
+    fn disconnect(&mut self, e: std::io::Error) {
+        warn!("Disconnected from the notification broker {}: {}", self.address, e);
+        self.stream = None;
+        self.reader = FrameReader::default();
+        if let Some(writer) = self.connection.lock().unwrap().take() {
+            let _ = writer.shutdown(std::net::Shutdown::Both);
+        }
+    }
+}
+
+impl NotificationReceiver for TcpReceiver {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError> {
+        if self.stream.is_none() {
+            let wait = self.next_attempt.saturating_duration_since(Instant::now());
+            if !wait.is_zero() {
+                std::thread::sleep(wait.min(timeout));
+                return Ok(None);
+            }
+            self.stream = self.connect();
+        }
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(None);
+        };
+        match self.reader.read(stream, timeout) {
+            Ok(notification) => Ok(notification.map(|notification| (notification, self.address.to_string()))),
+            Err(e) => {
+                self.disconnect(e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct BrokerSubscribers {
+    clients: Vec<(SocketAddr, TcpStream)>,
+    local: Vec<Sender<Vec<u8>>>,
+}
+
+impl BrokerSubscribers {
+    /// Forward the notification to all the connected instances and the local receivers
+    fn relay(&mut self, notification: &[u8]) {
+        self.clients.retain_mut(|(address, stream)| match write_frame(stream, notification) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping the broker client {}: {}", address, e);
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+                false
+            }
+        });
+        self.local.retain(|sender| sender.send(notification.to_vec()).is_ok());
+    }
+}
+
+pub struct TcpBrokerTransport {
+    subscribers: Arc<Mutex<BrokerSubscribers>>,
+    shutdown: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl TcpBrokerTransport {
+    pub fn new(address: SocketAddr) -> Result<Self, EconfError> {
+        let listener = TcpListener::bind(address)?;
+        // Accept without blocking, so the thread can check for the shutdown
+        listener.set_nonblocking(true)?;
+        info!("Notification broker listening on {}", address);
+        let subscribers = Arc::new(Mutex::new(BrokerSubscribers::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_subscribers = subscribers.clone();
+        let thread_shutdown = shutdown.clone();
+        let accept_thread = std::thread::Builder::new()
+            .name("econf-broker".to_owned())
+            .spawn(move || Self::accept(listener, thread_subscribers, thread_shutdown))?;
+        Ok(Self { subscribers, shutdown, accept_thread: Some(accept_thread) })
+    }
+
+    fn accept(listener: TcpListener, subscribers: Arc<Mutex<BrokerSubscribers>>, shutdown: Arc<AtomicBool>) {
+        let mut client_threads = Vec::new();
+        while !shutdown.load(Ordering::Relaxed) {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    if !is_timeout(&e) {
+                        error!("Broker accept error: {}", e);
+                    }
+                    std::thread::sleep(RECEIVER_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let client = stream.peer_addr().and_then(|address| {
+                // Inherited from the listener on some platforms
+                stream.set_nonblocking(false)?;
+                stream.set_nodelay(true)?;
+                stream.set_write_timeout(Some(TCP_IO_TIMEOUT))?;
+                Ok((address, stream.try_clone()?))
+            });
+            let (address, writer) = match client {
+                Ok(client) => client,
+                Err(e) => {
+                    error!("Broker failed to set up a client connection: {}", e);
+                    continue;
+                }
+            };
+            info!("Broker client {} connected", address);
+            subscribers.lock().unwrap().clients.push((address, writer));
+            let thread_subscribers = subscribers.clone();
+            let thread_shutdown = shutdown.clone();
+            client_threads.retain(|thread: &JoinHandle<()>| !thread.is_finished());
+            client_threads.push(std::thread::spawn(move || {
+                let mut stream = stream;
+                let mut reader = FrameReader::default();
+                while !thread_shutdown.load(Ordering::Relaxed) {
+                    match reader.read(&mut stream, RECEIVER_POLL_INTERVAL) {
+                        Ok(Some(notification)) => thread_subscribers.lock().unwrap().relay(&notification),
+                        Ok(None) => {}
+                        Err(e) => {
+                            info!("Broker client {} disconnected: {}", address, e);
+                            break;
+                        }
+                    }
+                }
+                thread_subscribers.lock().unwrap().clients.retain(|(client, _)| *client != address);
+            }));
+        }
+        for thread in client_threads {
+            let _ = thread.join();
+        }
+        info!("Notification broker stopped");
+    }
+}
+
+impl Drop for TcpBrokerTransport {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        for (_, stream) in self.subscribers.lock().unwrap().clients.drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        if let Some(thread) = self.accept_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl NotificationTransport for TcpBrokerTransport {
+    fn send(&self, notification: &[u8]) -> Result<(), EconfError> {
+        self.subscribers.lock().unwrap().relay(notification);
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().local.push(sender);
+        Ok(Box::new(InProcessReceiver { receiver, name: "broker".to_owned() }))
+    }
+}
+
+/******************************************************************************
+ * REDIS KEYSPACE NOTIFICATIONS
+ ******************************************************************************/
+
+/// Delivers the changes of the parameters in a `RedisStore` from the keyspace notifications of the server.
+/// The notifications carry only the key, so the receivers read the value and the source is not known.
+/// The changes made while disconnected are picked up by the periodic update, and the
+/// `refresh_defaults` notifications are not propagated as they don't change the keys
+#[cfg(feature = "redis")]
+pub struct RedisKeyspaceTransport {
+    client: redis::Client,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisKeyspaceTransport {
+    pub fn new(url: &str, prefix: &str) -> Result<Self, EconfError> {
+        let client = redis::Client::open(url).map_err(|e| EconfError::Config(format!("Redis URL {}: {}", url, e)))?;
+        Ok(Self { client, prefix: prefix.to_owned() })
+    }
+}
+
+#[cfg(feature = "redis")]
+impl NotificationTransport for RedisKeyspaceTransport {
+    fn send(&self, _notification: &[u8]) -> Result<(), EconfError> {
+        Ok(())
+    }
+
+    fn receiver(&self) -> Result<Box<dyn NotificationReceiver>, EconfError> {
+        Ok(Box::new(RedisKeyspaceReceiver {
+            client: self.client.clone(),
+            prefix: self.prefix.clone(),
+            connection: None,
+            delay: TCP_RECONNECT_MIN_DELAY,
+            next_attempt: Instant::now(),
+        }))
+    }
+}
+
+/// Subscribed connection, restored with an exponential backoff like the connection to the TCP broker
+#[cfg(feature = "redis")]
+struct RedisKeyspaceReceiver {
+    client: redis::Client,
+    prefix: String,
+    connection: Option<redis::Connection>,
+    delay: Duration,
+    next_attempt: Instant,
+}
+
+#[cfg(feature = "redis")]
+impl RedisKeyspaceReceiver {
+    fn connect(&mut self) -> Option<redis::Connection> {
+        let pattern = format!("__keyspace@*__:{}value:*", self.prefix);
+        match self.client.get_connection_with_timeout(TCP_IO_TIMEOUT).and_then(|mut connection| {
+            Self::enable_notifications(&mut connection);
+            connection.send_packed_command(&redis::cmd("PSUBSCRIBE").arg(&pattern).get_packed_command())?;
+            Ok(connection)
+        }) {
+            Ok(connection) => {
+                info!("Subscribed to the Redis keyspace notifications {}", pattern);
+                self.delay = TCP_RECONNECT_MIN_DELAY;
+                Some(connection)
+            }
+            Err(e) => {
+                if self.delay == TCP_RECONNECT_MIN_DELAY {
+                    warn!("Redis server not available: {}, retrying", e);
+                } else {
+                    debug!("Redis server not available: {}, retry in {:?}", e, self.delay);
+                }
+                self.next_attempt = Instant::now() + self.delay;
+                self.delay = (self.delay * 2).min(TCP_RECONNECT_MAX_DELAY);
+                None
+            }
+        }
+    }
+
+    /// Add the keyspace events of the hashes and the generic commands (DEL) to the classes enabled on the server.
+    /// The managed servers can refuse CONFIG, there notify-keyspace-events has to be set by the administrator
+    fn enable_notifications(connection: &mut redis::Connection) {
+        let result: redis::RedisResult<()> = (|| {
+            let (_, mut classes): (String, String) =
+                redis::cmd("CONFIG").arg("GET").arg("notify-keyspace-events").query(connection)?;
+            // "A" includes all the classes of the events, not the keyspace channel "K"
+            let all_events = classes.contains('A');
+            for class in ['K', 'g', 'h'] {
+                if !classes.contains(class) && (class == 'K' || !all_events) {
+                    classes.push(class);
+                }
+            }
+            redis::cmd("CONFIG").arg("SET").arg("notify-keyspace-events").arg(classes).query(connection)
+        })();
+        if let Err(e) = result {
+            warn!("Could not enable the keyspace notifications, set notify-keyspace-events to Kgh on the server: {}", e);
+        }
+    }
+
+    /// The parameter of the changed key, "__keyspace@<db>__:<prefix>value:<name>"
+    fn parameter(&self, channel: &str) -> Option<crate::generated::ParameterId> {
+        let (_, key) = channel.split_once("__:")?;
+        crate::generated::ParameterId::from_name(key.strip_prefix(self.prefix.as_str())?.strip_prefix("value:")?)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl NotificationReceiver for RedisKeyspaceReceiver {
+    fn recv(&mut self, timeout: Duration) -> Result<Option<(Vec<u8>, String)>, EconfError> {
+        use prost::Message;
+
+        if self.connection.is_none() {
+            let wait = self.next_attempt.saturating_duration_since(Instant::now());
+            if !wait.is_zero() {
+                std::thread::sleep(wait.min(timeout));
+                return Ok(None);
+            }
+            self.connection = self.connect();
+        }
+        let Some(connection) = self.connection.as_mut() else {
+            return Ok(None);
+        };
+        let response = connection.set_read_timeout(Some(timeout)).and_then(|_| connection.recv_response());
+        let message = match response {
+            Ok(response) => redis::Msg::from_owned_value(response),
+            Err(e) if e.is_timeout() => return Ok(None),
+            Err(e) => {
+                warn!("Disconnected from the Redis server: {}", e);
+                self.connection = None;
+                return Ok(None);
+            }
+        };
+        // The subscription confirmations and the other keys are skipped
+        let Some(id) = message.and_then(|message| self.parameter(message.get_channel_name())) else {
+            return Ok(None);
+        };
+        let notification = crate::service_events::ParameterNotification {
+            id: id as i32,
+            timestamp: crate::database_utils::DatabaseManager::get_timestamp(),
+            ..Default::default()
+        };
+        Ok(Some((notification.encode_to_vec(), "redis".to_owned())))
+    }
+}
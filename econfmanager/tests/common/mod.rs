@@ -0,0 +1,39 @@
+//! Helpers of the tests, the instances use a temporary folder and the first writable i32 parameter of the schema
+
+use std::path::{Path, PathBuf};
+
+use econfmanager::generated::{PARAMETER_DATA, ParameterId};
+use econfmanager::interface::InterfaceInstance;
+use econfmanager::schema::{ParameterValue, ParameterValueType};
+
+pub fn test_folder(name: &str) -> PathBuf {
+    let folder = std::env::temp_dir().join(format!("econfmanager_test_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&folder);
+    std::fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+/// Instance of the database in the folder, every instance has its own connection like another process
+pub fn instance(folder: &Path) -> InterfaceInstance {
+    InterfaceInstance::new(
+        &folder.join("parameters.db").to_string_lossy().to_string(),
+        &folder.join("saved_parameters.db").to_string_lossy().to_string(),
+        &folder.to_string_lossy().to_string(),
+    )
+    .unwrap()
+}
+
+/// The first i32 parameter that can be written and its default value
+pub fn writable_parameter() -> (ParameterId, i32) {
+    let (index, parameter) = PARAMETER_DATA
+        .iter()
+        .enumerate()
+        .find(|(_, parameter)| {
+            parameter.value_type == ParameterValueType::TypeI32 && !parameter.is_const && !parameter.readonly && !parameter.runtime
+        })
+        .expect("The schema has no writable i32 parameter");
+    let ParameterValue::ValI32(default) = parameter.value_default else {
+        panic!("Default of {} is not an i32", parameter.name_id);
+    };
+    (ParameterId::try_from(index).unwrap(), default)
+}
@@ -0,0 +1,53 @@
+//! Load replacing the working database while another process reads it
+
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use econfmanager::error::EconfError;
+use econfmanager::schema::ParameterValue;
+
+use common::{instance, test_folder, writable_parameter};
+
+const LOADS: usize = 20;
+
+#[test]
+fn readers_see_whole_values_during_load() {
+    let folder = test_folder("concurrent_load");
+    let writer = instance(&folder);
+    let (id, default) = writable_parameter();
+    let (saved, changed) = (ParameterValue::ValI32(default + 1), ParameterValue::ValI32(default + 2));
+    writer.set(id, saved.clone()).unwrap();
+    writer.save().unwrap();
+
+    // Opened before the loads, the reader has its own connection like another process
+    let reader = instance(&folder);
+    let stop = Arc::new(AtomicBool::new(false));
+    let reader = {
+        let stop = stop.clone();
+        let expected = [saved.clone(), changed.clone()];
+        thread::spawn(move || {
+            let (mut reads, mut busy) = (0, 0);
+            while !stop.load(Ordering::Relaxed) {
+                match reader.get(id, true) {
+                    Ok(value) => assert!(expected.contains(&value), "Read {:?} during the load", value),
+                    Err(EconfError::DbBusy) => busy += 1,
+                    Err(e) => panic!("Read failed during the load: {}", e),
+                }
+                reads += 1;
+            }
+            (reads, busy)
+        })
+    };
+
+    for _ in 0..LOADS {
+        writer.set(id, changed.clone()).unwrap();
+        writer.load().unwrap();
+        assert_eq!(writer.get(id, true).unwrap(), saved);
+    }
+    stop.store(true, Ordering::Relaxed);
+    let (reads, busy) = reader.join().unwrap();
+    assert!(reads > busy, "Only {} of {} reads succeeded", reads - busy, reads);
+}
@@ -0,0 +1,81 @@
+//! Changes made by the other processes sharing the database
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use econfmanager::generated::PARAMETER_DATA;
+use econfmanager::interface::ParameterChangeEvent;
+use econfmanager::schema::ParameterValue;
+use rusqlite::{params, Connection};
+
+use common::{instance, test_folder, writable_parameter};
+
+const WRITES: usize = 6;
+const SOURCES: [&str; 2] = ["rest", "ws-client-1"];
+
+fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs_f64()
+}
+
+#[test]
+fn update_reports_direct_writes() {
+    let folder = test_folder("direct_write");
+    let mut interface = instance(&folder);
+    let (id, default) = writable_parameter();
+    assert_eq!(interface.get(id, false).unwrap(), ParameterValue::ValI32(default));
+    interface.update().unwrap();
+
+    // Another process writing the database directly, no notification is sent
+    let db = Connection::open(folder.join("parameters.db")).unwrap();
+    db.execute(
+        "INSERT OR REPLACE INTO parameters (key, value, timestamp) VALUES (?1, ?2, ?3)",
+        params![PARAMETER_DATA[id as usize].name_id, default + 1, now()],
+    )
+    .unwrap();
+
+    // The parameter has no callback, its cached value is dropped anyway
+    assert!(interface.update().unwrap().contains(&id));
+    assert_eq!(interface.get(id, false).unwrap(), ParameterValue::ValI32(default + 1));
+}
+
+/// One test, the notifications of the writers would reach the instances of the other tests
+#[test]
+fn change_events_are_ordered() {
+    let folder = test_folder("ordered_events");
+    let writers = [instance(&folder), instance(&folder)];
+    let mut reader = instance(&folder);
+    let (id, default) = writable_parameter();
+    let sequences = Arc::new(Mutex::new(Vec::new()));
+    let delivered = sequences.clone();
+    reader
+        .add_change_callback(id, Arc::new(move |event: &ParameterChangeEvent| {
+            if event.source.as_deref().is_some_and(|source| SOURCES.contains(&source)) {
+                delivered.lock().unwrap().push(event.sequence);
+            }
+        }))
+        .unwrap();
+    reader.update().unwrap();
+
+    // Each write is delivered once, by the notification or by the update poll
+    for i in 0..WRITES {
+        writers[i % 2]
+            .set_with_source(id, ParameterValue::ValI32(default + 1 + i as i32), Some(SOURCES[i % 2]))
+            .unwrap();
+        reader.update().unwrap();
+    }
+    let received = sequences.lock().unwrap().clone();
+    assert_eq!(received.len(), WRITES, "Delivered sequences {:?}", received);
+    assert!(received.windows(2).all(|pair| pair[0] < pair[1]), "Sequences {:?} are not increasing", received);
+
+    // A change older than the last delivered one is discarded
+    let db = Connection::open(folder.join("parameters.db")).unwrap();
+    db.execute(
+        "INSERT OR REPLACE INTO parameters (key, value, timestamp, source, sequence) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![PARAMETER_DATA[id as usize].name_id, default, now(), SOURCES[0], received[0] as i64],
+    )
+    .unwrap();
+    reader.update().unwrap();
+    assert_eq!(*sequences.lock().unwrap(), received);
+}
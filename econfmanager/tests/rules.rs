@@ -0,0 +1,89 @@
+//! Writes of the values adjusted by the rules
+
+mod common;
+
+use std::sync::Arc;
+
+use econfmanager::error::EconfError;
+use econfmanager::generated::{PARAMETER_DATA, ParameterId};
+use econfmanager::interface::{RuleAction, WriteCondition};
+use econfmanager::schema::{ParameterValue, ParameterValueType};
+
+use common::{instance, test_folder, writable_parameter};
+
+/// The first float parameter that can be written and doesn't accept the non-finite values, with its NaN value
+fn finite_parameter() -> (ParameterId, ParameterValue) {
+    let (index, parameter) = PARAMETER_DATA
+        .iter()
+        .enumerate()
+        .find(|(_, parameter)| {
+            matches!(parameter.value_type, ParameterValueType::TypeF32 | ParameterValueType::TypeF64)
+                && !parameter.allow_non_finite && !parameter.is_const && !parameter.readonly && !parameter.runtime && !parameter.computed
+        })
+        .expect("The schema has no writable float parameter");
+    let nan = match parameter.value_type {
+        ParameterValueType::TypeF32 => ParameterValue::ValF32(f32::NAN),
+        _ => ParameterValue::ValF64(f64::NAN),
+    };
+    (ParameterId::try_from(index).unwrap(), nan)
+}
+
+/// The float value with the offset, of the type of the parameter
+fn offset(value: &ParameterValue, offset: f64) -> ParameterValue {
+    match value {
+        ParameterValue::ValF32(value) => ParameterValue::ValF32(value + offset as f32),
+        ParameterValue::ValF64(value) => ParameterValue::ValF64(value + offset),
+        other => panic!("{:?} is not a float", other),
+    }
+}
+
+#[test]
+fn failing_adjustment_writes_nothing() {
+    let folder = test_folder("failing_adjustment");
+    let interface = instance(&folder);
+    let (id, default) = writable_parameter();
+    let (adjusted, nan) = finite_parameter();
+    let adjusted_before = interface.get(adjusted, true).unwrap();
+    interface.add_rule("nan", &[id, adjusted], Arc::new(move |_: &[ParameterValue]| {
+        RuleAction::Adjust(vec![(adjusted, nan.clone())])
+    })).unwrap();
+
+    let result = interface.set(id, ParameterValue::ValI32(default + 1));
+    assert!(matches!(result, Err(EconfError::Validation { id: rejected, .. }) if rejected == adjusted), "{:?}", result);
+    assert_eq!(interface.get(id, true).unwrap(), ParameterValue::ValI32(default));
+    assert_eq!(interface.get(adjusted, true).unwrap(), adjusted_before);
+}
+
+#[test]
+fn adjustment_is_written_with_the_value() {
+    let folder = test_folder("adjustment_written");
+    let interface = instance(&folder);
+    let (id, default) = writable_parameter();
+    let (adjusted, _) = finite_parameter();
+    let adjusted_default = interface.get_default(adjusted).unwrap();
+    let rule_default = adjusted_default.clone();
+    // A tenth of the change of the i32 parameter
+    interface.add_rule("follow", &[id, adjusted], Arc::new(move |values: &[ParameterValue]| {
+        let ParameterValue::ValI32(value) = values[0] else {
+            return RuleAction::Reject("not an i32".to_owned());
+        };
+        RuleAction::Adjust(vec![(adjusted, offset(&rule_default, (value - default) as f64 / 10.0))])
+    })).unwrap();
+    let never_written = interface.get_sequence(id).unwrap();
+
+    interface.set(id, ParameterValue::ValI32(default + 1)).unwrap();
+    assert_eq!(interface.get(adjusted, true).unwrap(), offset(&adjusted_default, 0.1));
+    // Written in the same transaction, one after the other
+    assert_eq!(interface.get_sequence(adjusted).unwrap(), interface.get_sequence(id).unwrap() + 1);
+
+    // A stale condition writes neither of them
+    let result = interface.set_if(id, ParameterValue::ValI32(default + 2), WriteCondition::Sequence(never_written), None);
+    assert!(matches!(result, Err(EconfError::Conflict(conflict)) if conflict == id), "{:?}", result);
+    assert_eq!(interface.get(id, true).unwrap(), ParameterValue::ValI32(default + 1));
+    assert_eq!(interface.get(adjusted, true).unwrap(), offset(&adjusted_default, 0.1));
+
+    let sequence = interface.get_sequence(id).unwrap();
+    interface.set_if(id, ParameterValue::ValI32(default + 2), WriteCondition::Sequence(sequence), None).unwrap();
+    assert_eq!(interface.get(id, true).unwrap(), ParameterValue::ValI32(default + 2));
+    assert_eq!(interface.get(adjusted, true).unwrap(), offset(&adjusted_default, 0.2));
+}
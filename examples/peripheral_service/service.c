@@ -52,7 +52,9 @@ int main(int argc, char *argv[]) {
     );
     
     if (status != StatusOk) {
-        fprintf(stderr, "Failed to initialize configuration manager\n");
+        char error[256] = {0};
+        econf_get_last_error(NULL, error, sizeof(error));
+        fprintf(stderr, "Failed to initialize configuration manager: %s\n", error);
         return EXIT_FAILURE;
     }
 
@@ -97,6 +99,8 @@ int main(int argc, char *argv[]) {
         sleep(1);
     }
 
+    econf_deinit(interface);
+
     printf("Exited.\n");
     return EXIT_SUCCESS;
 }
\ No newline at end of file
@@ -69,6 +69,14 @@ function process_notification(msg)
     if (msg.params) {
         Object.keys(msg.params).forEach(function(key) {
             try {
+                if (msg.seq) {
+                    // Discard the notifications older than the last one applied
+                    if (lastSequence[key] !== undefined && msg.seq <= lastSequence[key]) {
+                        console.info(`Stale notification for ${key}: ${msg.seq} <= ${lastSequence[key]}`);
+                        return;
+                    }
+                    lastSequence[key] = msg.seq;
+                }
                 updateParam(key, msg.params[key]);
             } catch (e) {
                 console.error(`Error processing parameter ${key}: ${e}`);
@@ -24,6 +24,8 @@ const pendingRequests = {};
 
 let parameters = {};
 
+let lastSequence = {};
+
 /******************************************************************************
  * EVENT LISTENERS
  ******************************************************************************/
@@ -54,6 +54,7 @@ async function writeParameter(param) {
 
 async function readAllParameters() {
     if (ws && ws.readyState === WebSocket.OPEN) {
+        await sendRpc("subscribe_all", {});
         console.log("Reading all parameters...");
         parameters.forEach(async param => {
             await readParameter(param);
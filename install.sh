@@ -29,6 +29,7 @@ fi
 # -----------------------------
 
 HEADER_FILE="${TARGET_DIR}/econfmanager.h"
+NVS_HEADER_FILE="${TARGET_DIR}/econf_nvs.h"
 STATIC_LIB="${TARGET_DIR}/libeconfmanager.a"
 DYN_LIB="${TARGET_DIR}/libeconfmanager.so"
 
@@ -72,7 +73,7 @@ if [[ ! -d "$TARGET_DIR" ]]; then
     exit 1
 fi
 
-for f in "$STATIC_LIB" "$DYN_LIB" "$HEADER_FILE"; do
+for f in "$STATIC_LIB" "$DYN_LIB" "$HEADER_FILE" "$NVS_HEADER_FILE"; do
     if [[ ! -f "$f" ]]; then
         echo "Error: missing build artifact: $f"
         exit 1
@@ -91,6 +92,7 @@ echo "  $TARGET_DIR"
 install -m 0644 "$STATIC_LIB" "$LIB_DIR/"
 install -m 0644 "$DYN_LIB" "$LIB_DIR/"
 install -m 0644 "$HEADER_FILE" "$HEADERS_DIR/"
+install -m 0644 "$NVS_HEADER_FILE" "$HEADERS_DIR/"
 install -m 0644 "econfmanager/cmake/CMakeLists.txt" "$HEADERS_DIR/"
 
 echo "Econfmanager installed successfully"
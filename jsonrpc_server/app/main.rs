@@ -18,6 +18,5 @@ async fn main() {
 
     build_server!(args.config, 
                   SERVE_STATIC_FILES, 
-                  warp::path("health").map(|| "OK"),
                   warp::path("version").map(|| VERSION.unwrap_or("unknown")));
 }
@@ -0,0 +1,346 @@
+use std::net::{IpAddr, SocketAddr};
+
+use base64::Engine;
+use econfmanager::generated::ParameterId;
+use econfmanager::schema::AccessLevel;
+use warp::Filter;
+
+use crate::configfile::{AuthAccount, AuthConfig};
+use crate::shared_state::AppState;
+
+/// Header with the role of the client: user, service or factory
+pub const ROLE_HEADER: &str = "x-econf-role";
+/// Header with the credentials: "Bearer <token>" or "Basic <base64 user:password>"
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// What a client can do through the server, the levels are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    /// Read the parameters and follow the changes
+    Read,
+    /// Write the parameters
+    Write,
+    /// Save, restore, factory reset and the profiles
+    Admin,
+}
+
+impl Permission {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::Read => "read",
+            Permission::Write => "write",
+            Permission::Admin => "admin",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Permission> {
+        match name.to_lowercase().as_str() {
+            "read" => Some(Permission::Read),
+            "write" => Some(Permission::Write),
+            "admin" => Some(Permission::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// Credentials sent with a request, checked by [`authorize`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Credentials {
+    pub authorization: Option<String>,
+    pub role: Option<String>,
+    pub address: Option<IpAddr>,
+}
+
+/// The client of an authorized request
+#[derive(Debug, Clone)]
+pub(crate) struct Client {
+    /// Name of the token or the user, None for the anonymous clients
+    pub name: Option<String>,
+    pub permission: Permission,
+    /// Role for the access levels of the parameters
+    pub role: AccessLevel,
+    pub address: Option<IpAddr>,
+}
+
+impl Client {
+    /// The clients with credentials are limited by their name, the anonymous ones by their address
+    pub(crate) fn rate_key(&self) -> String {
+        match (&self.name, self.address) {
+            (Some(name), _) => name.clone(),
+            (None, Some(address)) => address.to_string(),
+            (None, None) => "anonymous".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum AuthError {
+    /// No valid credentials, 401
+    Unauthenticated(String),
+    /// The client can't do the request, 403
+    Forbidden(String),
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Unauthenticated(msg) | AuthError::Forbidden(msg) => f.write_str(msg),
+        }
+    }
+}
+
+struct Account {
+    name: String,
+    secret: String,
+    permission: Permission,
+    role: AccessLevel,
+}
+
+/// Checks the credentials against the tokens and the users of the configuration. Without any of them
+/// the authentication is disabled and every client has the admin permission
+#[derive(Default)]
+pub(crate) struct Authenticator {
+    tokens: Vec<Account>,
+    users: Vec<Account>,
+    /// Permission of the requests without credentials, None refuses them
+    anonymous: Option<Permission>,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn permission_of(name: &str) -> Permission {
+    Permission::from_name(name).unwrap_or_else(|| panic!("Unknown permission {} in the auth configuration", name))
+}
+
+fn account(account: &AuthAccount, secret: &str, default_role: AccessLevel) -> Account {
+    let role = match &account.role {
+        Some(name) => AccessLevel::from_name(name)
+            .unwrap_or_else(|| panic!("Unknown role {} of {} in the auth configuration", name, account.name)),
+        None => default_role,
+    };
+    Account { name: account.name.clone(), secret: secret.to_owned(), permission: permission_of(&account.permission), role }
+}
+
+/// Compare the secrets in a time not depending on where they differ
+fn secrets_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn find_account<'a>(accounts: &'a [Account], name: Option<&str>, secret: &str) -> Option<&'a Account> {
+    // Every account is compared, so the time doesn't tell which one matched
+    accounts.iter().fold(None, |found, account| {
+        let matches = name.is_none_or(|name| name == account.name) & secrets_equal(&account.secret, secret);
+        found.or(matches.then_some(account))
+    })
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl Authenticator {
+    pub(crate) fn from_config(config: &AuthConfig, default_role: AccessLevel) -> Self {
+        let anonymous = match config.anonymous.as_str() {
+            "none" => None,
+            name => Some(permission_of(name)),
+        };
+        Self {
+            tokens: config.tokens.iter().map(|token| account(&token.account, &token.token, default_role)).collect(),
+            users: config.users.iter().map(|user| account(&user.account, &user.password, default_role)).collect(),
+            anonymous,
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || !self.users.is_empty()
+    }
+
+    /// The clients are asked for the HTTP Basic credentials only when there are users
+    pub(crate) fn has_users(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    fn authenticate(&self, authorization: Option<&str>) -> Result<(Option<String>, Permission, Option<AccessLevel>), AuthError> {
+        if !self.is_enabled() {
+            return Ok((None, Permission::Admin, None));
+        }
+        let Some(authorization) = authorization else {
+            return match self.anonymous {
+                Some(permission) => Ok((None, permission, None)),
+                None => Err(AuthError::Unauthenticated("Credentials required".to_owned())),
+            };
+        };
+        let account = match authorization.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => find_account(&self.tokens, None, token.trim()),
+            Some((scheme, encoded)) if scheme.eq_ignore_ascii_case("basic") => base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .and_then(|decoded| {
+                    let (user, password) = decoded.split_once(':')?;
+                    find_account(&self.users, Some(user), password)
+                }),
+            _ => None,
+        };
+        match account {
+            Some(account) => Ok((Some(account.name.clone()), account.permission, Some(account.role))),
+            None => Err(AuthError::Unauthenticated("Invalid credentials".to_owned())),
+        }
+    }
+}
+
+/// The authorization and the role headers of a request, and the address of the client
+pub(crate) fn credentials() -> impl Filter<Extract = (Credentials,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(AUTHORIZATION_HEADER)
+        .and(warp::header::optional::<String>(ROLE_HEADER))
+        .and(warp::addr::remote())
+        .map(|authorization, role, remote: Option<SocketAddr>| Credentials {
+            authorization,
+            role,
+            address: remote.map(|remote| remote.ip()),
+        })
+}
+
+/// Role of the request, the default role of the server when the header is not set
+pub(crate) fn request_role(header: Option<&str>, default_role: AccessLevel) -> Result<AccessLevel, String> {
+    match header {
+        Some(name) => AccessLevel::from_name(name).ok_or_else(|| format!("Unknown role |{}|", name)),
+        None => Ok(default_role),
+    }
+}
+
+/// Authenticate the client and check that it has the permission. With the authentication enabled the role
+/// header can only lower the role of the credentials
+pub(crate) fn authorize(app: &AppState, credentials: &Credentials, required: Permission) -> Result<Client, AuthError> {
+    let (name, permission, account_role) = app.auth.authenticate(credentials.authorization.as_deref())?;
+    // With the authentication enabled the anonymous clients have the default role at most
+    let limit = account_role.or(app.auth.is_enabled().then_some(app.default_role));
+    let role = request_role(credentials.role.as_deref(), limit.unwrap_or(app.default_role))
+        .map_err(AuthError::Forbidden)?;
+    if let Some(limit) = limit.filter(|limit| role > *limit) {
+        return Err(AuthError::Forbidden(format!(
+            "Role {} is above the {} role of the credentials", role.name(), limit.name()
+        )));
+    }
+    let client = Client { name, permission, role, address: credentials.address };
+    check_permission(&client, required)?;
+    Ok(client)
+}
+
+pub(crate) fn check_permission(client: &Client, required: Permission) -> Result<(), AuthError> {
+    if client.permission < required {
+        return Err(AuthError::Forbidden(format!(
+            "The request requires the {} permission, {} has {}",
+            required.name(), client.name.as_deref().unwrap_or("anonymous client"), client.permission.name()
+        )));
+    }
+    Ok(())
+}
+
+/// Writes need a role of the parameter access level or higher
+pub(crate) fn check_write_access(app: &AppState, id: ParameterId, role: AccessLevel) -> Result<(), String> {
+    let required = app.interface.get_access_level(id);
+    if role < required {
+        return Err(format!(
+            "Parameter |{}| requires the {} role, the request has {}",
+            app.interface.get_name(id), required.name(), role.name()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+anonymous: none
+tokens:
+  - { name: reader, permission: read, token: read-token }
+  - { name: writer, permission: write, role: service, token: write-token }
+users:
+  - { name: admin, permission: admin, password: secret }
+";
+
+    fn authenticator(config: &str) -> Authenticator {
+        Authenticator::from_config(&serde_yaml::from_str(config).unwrap(), AccessLevel::User)
+    }
+
+    /// The client of the request after the authentication, with the default role when the account has none
+    fn client(auth: &Authenticator, authorization: Option<&str>) -> Result<Client, AuthError> {
+        let (name, permission, role) = auth.authenticate(authorization)?;
+        Ok(Client { name, permission, role: role.unwrap_or_default(), address: None })
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, password)))
+    }
+
+    #[test]
+    fn missing_credentials() {
+        let auth = authenticator(CONFIG);
+        assert!(matches!(client(&auth, None), Err(AuthError::Unauthenticated(_))));
+
+        let auth = authenticator(&CONFIG.replace("anonymous: none", "anonymous: read"));
+        let anonymous = client(&auth, None).unwrap();
+        assert_eq!((anonymous.name, anonymous.permission), (None, Permission::Read));
+    }
+
+    #[test]
+    fn bad_credentials() {
+        let auth = authenticator(CONFIG);
+        for authorization in [
+            "Bearer wrong-token".to_owned(),
+            "Bearer read-token-2".to_owned(),
+            // A token is not a password and the other way round
+            "Bearer secret".to_owned(),
+            basic("reader", "read-token"),
+            basic("admin", "wrong"),
+            basic("nobody", "secret"),
+            "Basic not-base64".to_owned(),
+            "Digest read-token".to_owned(),
+            "read-token".to_owned(),
+        ] {
+            let result = client(&auth, Some(&authorization));
+            assert!(matches!(result, Err(AuthError::Unauthenticated(_))), "{} accepted", authorization);
+        }
+    }
+
+    #[test]
+    fn insufficient_permission() {
+        assert!(Permission::Read < Permission::Write && Permission::Write < Permission::Admin);
+        let auth = authenticator(CONFIG);
+
+        let reader = client(&auth, Some("Bearer read-token")).unwrap();
+        assert!(check_permission(&reader, Permission::Read).is_ok());
+        assert!(matches!(check_permission(&reader, Permission::Write), Err(AuthError::Forbidden(_))));
+        assert!(matches!(check_permission(&reader, Permission::Admin), Err(AuthError::Forbidden(_))));
+
+        let writer = client(&auth, Some("Bearer write-token")).unwrap();
+        assert!(check_permission(&writer, Permission::Write).is_ok());
+        assert!(matches!(check_permission(&writer, Permission::Admin), Err(AuthError::Forbidden(_))));
+    }
+
+    #[test]
+    fn allowed_credentials() {
+        let auth = authenticator(CONFIG);
+
+        let writer = client(&auth, Some("bearer  write-token ")).unwrap();
+        assert_eq!(writer.name.as_deref(), Some("writer"));
+        assert_eq!((writer.permission, writer.role), (Permission::Write, AccessLevel::Service));
+        assert_eq!(writer.rate_key(), "writer");
+
+        let admin = client(&auth, Some(&basic("admin", "secret"))).unwrap();
+        assert_eq!(admin.name.as_deref(), Some("admin"));
+        for required in [Permission::Read, Permission::Write, Permission::Admin] {
+            assert!(check_permission(&admin, required).is_ok());
+        }
+
+        // Without the tokens and the users every client is an admin
+        let open = authenticator("anonymous: none");
+        assert!(!open.is_enabled());
+        assert_eq!(client(&open, None).unwrap().permission, Permission::Admin);
+    }
+}
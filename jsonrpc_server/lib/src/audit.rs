@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use econfmanager::generated::{ParameterId, PARAMETER_DATA};
+use econfmanager::interface::InterfaceInstance;
+use econfmanager::schema::{ParameterValue, ParameterValueType};
+use econfmanager::sensitive;
+use log::error;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::access::Client;
+use crate::configfile::AuditConfig;
+
+/// Change made through the API
+#[derive(Clone, Serialize)]
+pub(crate) struct AuditEntry {
+    /// UNIX timestamp in seconds
+    pub timestamp: f64,
+    /// "rest" or "ws"
+    pub api: &'static str,
+    /// Name of the instance, not set for the default one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub address: Option<IpAddr>,
+    /// Name of the token or the user, null for the anonymous clients
+    pub client: Option<String>,
+    /// write, save, restore, factory_reset, save_profile, load_profile or delete_profile
+    pub action: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_value: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+/// The latest entries in memory and all of them in the optional file, one JSON object per line.
+/// Shared by the instances of the server
+#[derive(Default)]
+pub(crate) struct AuditLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<AuditEntry>>,
+    file: Option<Mutex<File>>,
+}
+
+/// Audit log of an instance, the entries are marked with its name
+#[derive(Clone, Default)]
+pub(crate) struct AuditTrail {
+    log: Arc<AuditLog>,
+    instance: Option<String>,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn blob_value(size: usize) -> serde_json::Value {
+    json!(format!("<{} bytes>", size))
+}
+
+/// The sensitive values are masked, the others are shown like in the log lines, the blobs only by their size
+fn audit_value(id: ParameterId, value: &ParameterValue) -> serde_json::Value {
+    if sensitive::is_sensitive(id) {
+        return json!(sensitive::SECRET_MASK);
+    }
+    if sensitive::is_no_log(id) {
+        return json!(sensitive::log_value(id, value).to_string());
+    }
+    match value {
+        ParameterValue::ValBlob(blob) => blob_value(blob.len()),
+        other => json!(other),
+    }
+}
+
+impl AuditLog {
+    fn record(&self, entry: AuditEntry) {
+        if let Some(file) = &self.file {
+            let mut line = json!(entry).to_string();
+            line.push('\n');
+            if let Err(e) = file.lock().unwrap().write_all(line.as_bytes()) {
+                error!("Failed to write the audit log: {}", e);
+            }
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+}
+
+impl AuditTrail {
+    fn entry(&self, api: &'static str, client: &Client, action: &'static str) -> AuditEntry {
+        AuditEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0.0, |time| time.as_secs_f64()),
+            api,
+            instance: self.instance.clone(),
+            address: client.address,
+            client: client.name.clone(),
+            action,
+            parameter: None,
+            old_value: None,
+            new_value: None,
+            profile: None,
+        }
+    }
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl AuditLog {
+    pub(crate) fn from_config(config: &AuditConfig) -> Self {
+        let file = config.file.as_ref().map(|path| {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap_or_else(|e| panic!("Failed to open the audit log {}: {}", path, e));
+            Mutex::new(file)
+        });
+        Self {
+            capacity: config.capacity,
+            entries: Mutex::new(VecDeque::with_capacity(config.capacity)),
+            file,
+        }
+    }
+
+    /// The latest `limit` entries, oldest first, of the parameter and the client when given
+    pub(crate) fn entries(&self, parameter: Option<&str>, client: Option<&str>, limit: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut selected: Vec<AuditEntry> = entries
+            .iter()
+            .rev()
+            .filter(|entry| parameter.is_none() || entry.parameter.as_deref() == parameter)
+            .filter(|entry| client.is_none() || entry.client.as_deref() == client)
+            .take(limit)
+            .cloned()
+            .collect();
+        selected.reverse();
+        selected
+    }
+}
+
+/// Value of the parameter before a write, for the audit entry. Not read for the sensitive parameters,
+/// so they are not left in memory, and for the blobs
+pub(crate) fn value_before_write(interface: &InterfaceInstance, id: ParameterId) -> Option<ParameterValue> {
+    if interface.is_sensitive(id) || PARAMETER_DATA[id.index()].value_type == ParameterValueType::TypeBlob {
+        return None;
+    }
+    interface.get(id, false).ok()
+}
+
+impl AuditTrail {
+    pub(crate) fn new(log: Arc<AuditLog>, instance: Option<String>) -> Self {
+        Self { log, instance }
+    }
+
+    pub(crate) fn log(&self) -> &AuditLog {
+        &self.log
+    }
+
+    /// Record a write of a parameter, `old` is the value read before it
+    pub(crate) fn write(
+        &self,
+        api: &'static str,
+        client: &Client,
+        interface: &InterfaceInstance,
+        id: ParameterId,
+        old: Option<&ParameterValue>,
+        new: &ParameterValue,
+    ) {
+        let mut entry = self.entry(api, client, "write");
+        entry.parameter = Some(interface.get_name(id));
+        entry.old_value = old.map(|old| audit_value(id, old));
+        entry.new_value = Some(audit_value(id, new));
+        self.log.record(entry);
+    }
+
+    /// Record a blob streamed or uploaded to the parameter, only its size is known
+    pub(crate) fn write_blob(&self, api: &'static str, client: &Client, interface: &InterfaceInstance, id: ParameterId, size: usize) {
+        let mut entry = self.entry(api, client, "write");
+        entry.parameter = Some(interface.get_name(id));
+        entry.new_value = Some(blob_value(size));
+        self.log.record(entry);
+    }
+
+    /// Record a change of the whole store: save, restore, factory_reset or a profile method
+    pub(crate) fn action(&self, api: &'static str, client: &Client, action: &'static str, profile: Option<&str>) {
+        let mut entry = self.entry(api, client, action);
+        entry.profile = profile.map(str::to_owned);
+        self.log.record(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use econfmanager::schema::AccessLevel;
+
+    use super::*;
+    use crate::access::Permission;
+
+    /// The first sensitive string parameter of the schema
+    fn sensitive_parameter() -> ParameterId {
+        (0..PARAMETER_DATA.len())
+            .map(|index| ParameterId::try_from(index).unwrap())
+            .find(|&id| sensitive::is_sensitive(id) && PARAMETER_DATA[id.index()].value_type == ParameterValueType::TypeString)
+            .expect("The schema has no sensitive string parameter")
+    }
+
+    #[test]
+    fn sensitive_value_is_masked() {
+        let path = std::env::temp_dir().join(format!("econfmanager_audit_{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = AuditConfig { capacity: 10, file: Some(path.to_string_lossy().into_owned()) };
+        let trail = AuditTrail::new(Arc::new(AuditLog::from_config(&config)), None);
+        let client = Client { name: Some("admin".to_owned()), permission: Permission::Admin, role: AccessLevel::default(), address: None };
+
+        let old = ParameterValue::ValString(Cow::Borrowed("old-token"));
+        let new = ParameterValue::ValString(Cow::Borrowed("new-token"));
+        trail.write("rest", &client, &InterfaceInstance::default(), sensitive_parameter(), Some(&old), &new);
+
+        let entries = json!(trail.log().entries(None, None, 10)).to_string();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        for recorded in [entries, lines] {
+            assert!(!recorded.contains("old-token") && !recorded.contains("new-token"), "{}", recorded);
+            assert!(recorded.contains(sensitive::SECRET_MASK), "{}", recorded);
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::fs;
+use std::net::Ipv4Addr;
 use serde::Deserialize;
 
 /******************************************************************************
@@ -17,6 +19,180 @@ pub struct Config {
     pub json_rpc_listen_address: String,
     #[serde(default = "default_json_rpc_port")]
     pub json_rpc_port: String,
+    /// File with the hex Ed25519 secret key used to sign /api/manifest
+    #[serde(default)]
+    pub manifest_signing_key_file: Option<String>,
+    /// File with the hex 32-byte device key of the secret parameters
+    #[serde(default)]
+    pub secret_key_file: Option<String>,
+    /// Role of the requests without the x-econf-role header: user, service or factory
+    #[serde(default = "default_role")]
+    pub default_role: String,
+    /// Largest blob accepted by /api/blob uploads, in bytes
+    #[serde(default = "default_blob_upload_limit")]
+    pub blob_upload_limit: u64,
+    /// API tokens and HTTP Basic users, every client has full access when none are set
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Cross-origin access to the API, for a web client hosted on another origin
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Headers added to every API response, an empty map disables them
+    #[serde(default = "default_security_headers")]
+    pub security_headers: BTreeMap<String, String>,
+    /// Limit of the writes of every client, by token or user name, or by IP address
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Further instances served at /api/<name>/, with the same parameters and their own databases
+    #[serde(default)]
+    pub instances: BTreeMap<String, InstanceConfig>,
+    /// Web client served next to the API
+    #[serde(default)]
+    pub static_files: StaticFilesConfig,
+    /// Save the parameters changed since the last save when the server stops on SIGTERM or SIGINT
+    #[serde(default)]
+    pub save_on_shutdown: bool,
+    /// Record of the writes and the store changes made through the API, at /api/audit
+    #[serde(default)]
+    pub audit: AuditConfig,
+}
+
+#[derive(Deserialize)]
+pub struct AuditConfig {
+    /// Latest entries kept in memory for /api/audit, 0 keeps none
+    #[serde(default = "default_audit_capacity")]
+    pub capacity: usize,
+    /// File the entries are appended to, one JSON object per line
+    #[serde(default)]
+    pub file: Option<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self { capacity: default_audit_capacity(), file: None }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StaticFilesConfig {
+    /// Directory of the files, nothing is served when neither the path nor embedded is set
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Serve the files built into the server with the embedded-static feature instead of a directory
+    #[serde(default)]
+    pub embedded: bool,
+    /// The unknown GET paths outside /api/ get index.html, for the routing of the single-page applications
+    #[serde(default = "default_spa_fallback")]
+    pub spa_fallback: bool,
+    /// Cache-Control of the files, index.html is always revalidated
+    #[serde(default = "default_static_cache_control")]
+    pub cache_control: String,
+}
+
+impl Default for StaticFilesConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            embedded: false,
+            spa_fallback: default_spa_fallback(),
+            cache_control: default_static_cache_control(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct InstanceConfig {
+    pub database_path: String,
+    pub saved_database_path: String,
+    #[serde(default = "default_default_data_folder")]
+    pub default_data_folder: String,
+    /// File with the hex 32-byte device key of the secret parameters of the instance
+    #[serde(default)]
+    pub secret_key_file: Option<String>,
+    /// The instances need their own notifications, the processes sharing the database of an instance use
+    /// the same group and port
+    #[serde(default = "default_multicast_group")]
+    pub multicast_group: Ipv4Addr,
+    pub multicast_port: u16,
+}
+
+#[derive(Deserialize, Default)]
+pub struct RateLimitConfig {
+    /// Sustained writes per second of a client, 0 disables the limit
+    #[serde(default)]
+    pub writes_per_second: f64,
+    /// Writes a client can make at once above the rate, at least one
+    #[serde(default)]
+    pub burst: u32,
+}
+
+#[derive(Deserialize)]
+pub struct CorsConfig {
+    /// Origins like "https://ui.example.com" or "*" for any, CORS is disabled when empty
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers the cross-origin scripts can send
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+    /// How long the browsers cache the preflight responses, in seconds
+    #[serde(default = "default_cors_max_age")]
+    pub max_age: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_cors_methods(),
+            allowed_headers: default_cors_headers(),
+            max_age: default_cors_max_age(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuthConfig {
+    /// Permission of the requests without credentials: none, read, write or admin
+    #[serde(default = "default_anonymous")]
+    pub anonymous: String,
+    /// Sent as "Authorization: Bearer <token>"
+    #[serde(default)]
+    pub tokens: Vec<AuthToken>,
+    /// Sent with HTTP Basic authentication
+    #[serde(default)]
+    pub users: Vec<AuthUser>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { anonymous: default_anonymous(), tokens: Vec::new(), users: Vec::new() }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuthAccount {
+    pub name: String,
+    /// read, write or admin
+    pub permission: String,
+    /// Role for the access levels of the parameters, default_role when not set
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuthToken {
+    #[serde(flatten)]
+    pub account: AuthAccount,
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+pub struct AuthUser {
+    #[serde(flatten)]
+    pub account: AuthAccount,
+    pub password: String,
 }
 
 #[derive(Deserialize)]
@@ -49,6 +225,59 @@ fn default_json_rpc_port() -> String {
     "3030".to_string()
 }
 
+/// The group of the library, the instances usually only change the port
+fn default_multicast_group() -> Ipv4Addr {
+    Ipv4Addr::new(224, 0, 0, 123)
+}
+
+fn default_role() -> String {
+    "user".to_string()
+}
+
+fn default_blob_upload_limit() -> u64 {
+    16 * 1024 * 1024
+}
+
+fn default_anonymous() -> String {
+    "none".to_string()
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "PUT"].map(String::from).to_vec()
+}
+
+fn default_cors_headers() -> Vec<String> {
+    ["authorization", "content-type", "if-match", "range", "x-econf-role"].map(String::from).to_vec()
+}
+
+fn default_cors_max_age() -> u64 {
+    600
+}
+
+fn default_spa_fallback() -> bool {
+    true
+}
+
+fn default_static_cache_control() -> String {
+    "no-cache".to_string()
+}
+
+fn default_audit_capacity() -> usize {
+    1000
+}
+
+fn default_security_headers() -> BTreeMap<String, String> {
+    [
+        ("x-content-type-options", "nosniff"),
+        ("x-frame-options", "DENY"),
+        ("referrer-policy", "no-referrer"),
+        ("content-security-policy", "default-src 'none'; frame-ancestors 'none'"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_owned(), value.to_owned()))
+    .collect()
+}
+
 /******************************************************************************
  * PUBLIC FUNCTIONS
  ******************************************************************************/
@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use warp::filters::BoxedFilter;
+use warp::http::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+    ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, VARY,
+};
+use warp::http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use warp::{Filter, Rejection, Reply};
+
+use crate::configfile::{Config, CorsConfig};
+
+/// Response headers the cross-origin scripts can read
+const EXPOSED_HEADERS: &str = "etag, content-range, accept-ranges, www-authenticate";
+
+struct Cors {
+    /// None allows any origin
+    origins: Option<Vec<String>>,
+    methods: HeaderValue,
+    headers: HeaderValue,
+    max_age: HeaderValue,
+}
+
+/// CORS and security headers of the API responses
+pub struct HttpHeaders {
+    cors: Option<Cors>,
+    security: HeaderMap,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| panic!("Invalid header value {} in the configuration", value))
+}
+
+impl Cors {
+    fn from_config(config: &CorsConfig) -> Option<Self> {
+        if config.allowed_origins.is_empty() {
+            return None;
+        }
+        let origins = if config.allowed_origins.iter().any(|origin| origin == "*") {
+            None
+        } else {
+            Some(config.allowed_origins.iter().map(|origin| origin.trim_end_matches('/').to_owned()).collect())
+        };
+        Some(Self {
+            origins,
+            methods: header_value(&config.allowed_methods.join(", ")),
+            headers: header_value(&config.allowed_headers.join(", ")),
+            max_age: HeaderValue::from(config.max_age),
+        })
+    }
+
+    fn allowed_origin(&self, origin: &str) -> Option<HeaderValue> {
+        let allowed = self.origins.as_ref().is_none_or(|origins| origins.iter().any(|allowed| allowed == origin));
+        allowed.then(|| HeaderValue::from_str(origin).ok()).flatten()
+    }
+
+    /// The other origins get no CORS headers and the browsers block the responses, the same origin
+    /// requests of a web client served by the server itself pass as before
+    fn add_headers(&self, origin: Option<&str>, headers: &mut HeaderMap) {
+        headers.append(VARY, HeaderValue::from_static("origin"));
+        if let Some(origin) = origin.and_then(|origin| self.allowed_origin(origin)) {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+        }
+    }
+
+    fn preflight(&self, origin: &str) -> warp::reply::Response {
+        let Some(origin) = self.allowed_origin(origin) else {
+            return StatusCode::FORBIDDEN.into_response();
+        };
+        let mut response = StatusCode::NO_CONTENT.into_response();
+        let headers = response.headers_mut();
+        headers.insert(VARY, HeaderValue::from_static("origin"));
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone());
+        headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, self.headers.clone());
+        headers.insert(ACCESS_CONTROL_MAX_AGE, self.max_age.clone());
+        response
+    }
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl HttpHeaders {
+    pub fn from_config(config: &Config) -> Self {
+        let security = config.security_headers.iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .unwrap_or_else(|_| panic!("Invalid header name {} in the configuration", name));
+                (name, header_value(value))
+            })
+            .collect();
+        Self { cors: Cors::from_config(&config.cors), security }
+    }
+
+    /// Add the headers to the responses of the routes and answer the CORS preflight requests
+    pub fn wrap<F, R>(self, routes: F) -> BoxedFilter<(warp::reply::Response,)>
+    where
+        F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        R: Reply,
+    {
+        let cors = self.cors.map(Arc::new);
+        let security = Arc::new(self.security);
+        let responses = {
+            let cors = cors.clone();
+            warp::header::optional::<String>("origin")
+                .and(routes)
+                .map(move |origin: Option<String>, reply: R| {
+                    let mut response = reply.into_response();
+                    for (name, value) in security.iter() {
+                        // The headers set by the handlers are kept
+                        response.headers_mut().entry(name).or_insert_with(|| value.clone());
+                    }
+                    if let Some(cors) = &cors {
+                        cors.add_headers(origin.as_deref(), response.headers_mut());
+                    }
+                    response
+                })
+        };
+        let Some(cors) = cors else {
+            return responses.boxed();
+        };
+
+        // Not found for the other requests, so the rejections of the routes are reported as before
+        let preflight = warp::method()
+            .and(warp::header::optional::<String>("origin"))
+            .and(warp::header::optional::<String>("access-control-request-method"))
+            .and_then(move |method: Method, origin: Option<String>, request_method: Option<String>| {
+                let cors = cors.clone();
+                async move {
+                    match (origin, request_method) {
+                        (Some(origin), Some(_)) if method == Method::OPTIONS => Ok(cors.preflight(&origin)),
+                        _ => Err(warp::reject::not_found()),
+                    }
+                }
+            });
+        preflight.or(responses).unify().boxed()
+    }
+}
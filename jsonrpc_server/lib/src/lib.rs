@@ -1,79 +1,370 @@
+pub mod access;
+pub mod audit;
 pub mod configfile;
+pub mod http_headers;
+pub mod openapi;
+pub mod rate_limit;
 pub mod rest_server;
 pub mod shared_state;
+pub mod shutdown;
+pub mod sse_server;
+pub mod static_files;
 pub mod utils;
 pub mod ws_server;
 
 use econfmanager::interface::InterfaceInstance;
-use warp::{Filter, ws};
+use econfmanager::schema::AccessLevel;
+use log::info;
+use tokio::sync::RwLock;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply, ws};
 
+use crate::access::{credentials, Authenticator};
+use crate::audit::{AuditLog, AuditTrail};
 use crate::configfile::Config;
-use crate::rest_server::{handle_info, handle_read_param, handle_write_param};
-use crate::shared_state::AppState;
-use crate::ws_server::handle_ws;
+use crate::http_headers::HttpHeaders;
+use crate::rate_limit::RateLimiter;
+use crate::rest_server::{handle_audit, handle_blob_read, handle_blob_upload, handle_blob_write, handle_bulk_read, handle_bulk_write, handle_info, handle_diff, handle_group, handle_healthz, handle_manifest, handle_metrics, handle_openapi, handle_read_param, handle_readyz, handle_write_param};
+use crate::shared_state::{AppState, InstanceStates, SharedState};
+use crate::sse_server::handle_events;
+use crate::shutdown::Shutdown;
+use crate::static_files::static_routes;
+use crate::ws_server::handle_ws_upgrade;
 use std::{
-    net::SocketAddr,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
     time::Duration,
 };
 
 const PERIODIC_UPDATE_INTERVAL: Duration = Duration::from_millis(5000);
 
+/// The first segments of the /api routes, not allowed as the instance names
+const RESERVED_INSTANCE_NAMES: &[&str] = &["read", "write", "info", "group", "manifest", "openapi.json", "diff", "events", "blob", "audit"];
 
-pub fn build_default_routes(
-    config_file: String,
-) -> (
-    impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone,
-    impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone,
-    impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone,
-    impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone,
-    SocketAddr,
-) {
-    let config = Config::from_file(config_file.to_owned());
 
-    let mut interface_instance = InterfaceInstance::new(
-        &config.database_path,
-        &config.saved_database_path,
-        &config.default_data_folder,
-    )
-    .unwrap();
+/// Read the hex 32-byte device key of the secret parameters, the server can't start without it
+fn read_secret_key(path: &str) -> [u8; 32] {
+    let hex = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed to read the secret key {}: {}", path, e));
+    let hex = hex.trim();
+    if hex.len() != 64 {
+        panic!("Secret key {} must have 64 hex characters, got {}", path, hex.len());
+    }
+    let mut key = [0u8; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .unwrap_or_else(|e| panic!("Invalid secret key {}: {}", path, e));
+    }
+    key
+}
+
+/// Open the interface of an instance and start the polling of its changes
+fn build_interface(
+    database_path: &str,
+    saved_database_path: &str,
+    default_data_folder: &str,
+    secret_key_file: Option<&String>,
+    multicast: Option<(Ipv4Addr, u16)>,
+) -> InterfaceInstance {
+    let mut builder = InterfaceInstance::builder()
+        .database_path(database_path)
+        .saved_database_path(saved_database_path)
+        .default_data_folder(default_data_folder);
+    if let Some(path) = secret_key_file {
+        builder = builder.secret_key(read_secret_key(path));
+    }
+    if let Some((group, port)) = multicast {
+        builder = builder.multicast(group, port);
+    }
+    let mut interface_instance = builder.build().unwrap();
     interface_instance.start_periodic_update(PERIODIC_UPDATE_INTERVAL);
+    interface_instance
+}
+
+fn build_state(config: &Config, interface_instance: InterfaceInstance, rate_limiter: Arc<RateLimiter>, audit: AuditTrail) -> SharedState {
     let parameter_names = interface_instance.get_parameter_names();
+    let manifest_signing_key = config.manifest_signing_key_file.as_ref().map(|path| {
+        std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read manifest signing key {}: {}", path, e))
+    });
+
+    let default_role = AccessLevel::from_name(&config.default_role)
+        .unwrap_or_else(|| panic!("Unknown default_role {} in the configuration", config.default_role));
 
-    let state = Arc::new(Mutex::new(AppState {
-        subscribers: (0..interface_instance.get_parameters_number())
-            .map(|_| Vec::new())
-            .collect(),
+    Arc::new(RwLock::new(AppState {
+        clients: HashMap::new(),
         interface: interface_instance,
         names: parameter_names,
-    }));
+        manifest_signing_key,
+        default_role,
+        blob_upload_limit: config.blob_upload_limit,
+        auth: Authenticator::from_config(&config.auth, default_role),
+        rate_limiter,
+        audit,
+        notify_gate: Arc::default(),
+    }))
+}
 
+pub type ResponseFilter = BoxedFilter<(warp::reply::Response,)>;
+
+/// The WebSocket, read, write, info, manifest, diff, events and blob routes of an instance
+type InstanceRoutes = (
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+);
+
+/// The routes of the default instance with the OpenAPI document, the health checks and the other instances, in
+/// the order of `build_server!`, then the static files, the headers to add to the replies, the shutdown of the
+/// instances and the address to listen
+pub type DefaultRoutes = (
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    ResponseFilter,
+    Option<ResponseFilter>,
+    HttpHeaders,
+    Shutdown,
+    SocketAddr,
+);
+
+/// The routes of the parameters of an instance: `api` is the prefix of the REST routes and `ws_path` the
+/// WebSocket endpoint
+fn instance_routes(
+    api: BoxedFilter<()>,
+    ws_path: BoxedFilter<()>,
+    state: SharedState,
+    blob_upload_limit: u64,
+) -> InstanceRoutes {
     let state_filter = warp::any().map(move || state.clone());
 
     // WebSocket route
-    let ws = warp::path("api_ws")
+    let ws = ws_path
         .and(ws())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(credentials())
         .and(state_filter.clone())
-        .map(|ws: ws::Ws, state| ws.on_upgrade(move |socket| handle_ws(socket, state)));
+        .and_then(handle_ws_upgrade);
 
     // REST API routes
-    let read_param = warp::path!("api" / "read" / String)
+    let read_param = api.clone().and(warp::path!("read" / String))
         .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(credentials())
         .and(state_filter.clone())
         .and_then(handle_read_param);
 
-    let info = warp::path!("api" / "info")
+    let bulk_read = api.clone().and(warp::path!("read"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<Vec<String>>())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_bulk_read);
+
+    let read_param = read_param.or(bulk_read).unify();
+
+    let info = api.clone().and(warp::path!("info"))
         .and(warp::get())
+        .and(credentials())
         .and(state_filter.clone())
         .and_then(handle_info);
 
-    let write_param = warp::path!("api" / "write" / String)
+    let group = api.clone().and(warp::path!("group" / String))
+        .and(warp::get())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_group);
+
+    let info = info.or(group).unify();
+
+    let manifest = api.clone().and(warp::path!("manifest"))
+        .and(warp::get())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_manifest);
+
+    let diff = api.clone().and(warp::path!("diff"))
+        .and(warp::get())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_diff);
+
+    let events = api.clone().and(warp::path!("events"))
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_events);
+
+    let write_param = api.clone().and(warp::path!("write" / String))
         .and(warp::post())
         .and(warp::body::content_length_limit(1024 * 1024))
         .and(warp::body::bytes())
+        .and(credentials())
+        .and(warp::header::optional::<String>("if-match"))
         .and(state_filter.clone())
         .and_then(handle_write_param);
 
+    let bulk_write = api.clone().and(warp::path!("write"))
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::json::<serde_json::Map<String, serde_json::Value>>())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_bulk_write);
+
+    let write_param = write_param.or(bulk_write).unify();
+
+    let blob_read = api.clone().and(warp::path!("blob" / String))
+        .and(warp::get())
+        .and(warp::header::optional::<String>("range"))
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_blob_read);
+
+    let blob_write = api.clone().and(warp::path!("blob" / String))
+        .and(warp::put())
+        .and(warp::header::optional::<u64>("content-length"))
+        .and(credentials())
+        .and(warp::body::stream())
+        .and(state_filter.clone())
+        .and_then(handle_blob_write);
+
+    let blob_upload = api.and(warp::path!("blob" / String))
+        .and(warp::post())
+        .and(credentials())
+        .and(warp::multipart::form().max_length(blob_upload_limit))
+        .and(state_filter)
+        .and_then(handle_blob_upload);
+
+    let blob = blob_read.or(blob_write).unify().or(blob_upload).unify();
+
+    (
+        ws.boxed(),
+        read_param.boxed(),
+        write_param.boxed(),
+        info.boxed(),
+        manifest.boxed(),
+        diff.boxed(),
+        events.boxed(),
+        blob.boxed(),
+    )
+}
+
+pub fn build_default_routes(
+    config_file: String,
+) -> DefaultRoutes {
+    let config = Config::from_file(config_file.to_owned());
+
+    let rate_limiter = Arc::new(RateLimiter::from_config(&config.rate_limit));
+    let interface_instance = build_interface(
+        &config.database_path,
+        &config.saved_database_path,
+        &config.default_data_folder,
+        config.secret_key_file.as_ref(),
+        None,
+    );
+    let audit_log = Arc::new(AuditLog::from_config(&config.audit));
+    let state = build_state(&config, interface_instance, rate_limiter.clone(), AuditTrail::new(audit_log.clone(), None));
+
+    let (ws, read_param, write_param, info, manifest, diff, events, blob) = instance_routes(
+        warp::path("api").boxed(),
+        warp::path("api_ws").boxed(),
+        state.clone(),
+        config.blob_upload_limit,
+    );
+
+    // The other instances, the routes of an unknown instance are rejected as not found
+    let mut instances = warp::any()
+        .and_then(|| async { Err::<warp::reply::Response, _>(warp::reject::not_found()) })
+        .boxed();
+    let mut multicast_ports = HashMap::new();
+    let mut states = vec![(None, state.clone())];
+    for (name, instance) in &config.instances {
+        if RESERVED_INSTANCE_NAMES.contains(&name.as_str()) || name.contains('/') {
+            panic!("Instance name {} is not allowed, it would hide the /api routes", name);
+        }
+        if let Some(other) = multicast_ports.insert(instance.multicast_port, name) {
+            panic!("Instances {} and {} have the same multicast_port {}", other, name, instance.multicast_port);
+        }
+        info!("Instance {} with the database {}", name, instance.database_path);
+        let interface_instance = build_interface(
+            &instance.database_path,
+            &instance.saved_database_path,
+            &instance.default_data_folder,
+            instance.secret_key_file.as_ref(),
+            Some((instance.multicast_group, instance.multicast_port)),
+        );
+        let audit = AuditTrail::new(audit_log.clone(), Some(name.clone()));
+        let instance_state = build_state(&config, interface_instance, rate_limiter.clone(), audit);
+        states.push((Some(name.clone()), instance_state.clone()));
+        let api = warp::path("api").and(warp::path(name.clone())).boxed();
+        let (ws, read_param, write_param, info, manifest, diff, events, blob) = instance_routes(
+            api.clone(),
+            api.and(warp::path("ws")).boxed(),
+            instance_state,
+            config.blob_upload_limit,
+        );
+        let routes = ws.or(read_param).unify()
+            .or(write_param).unify()
+            .or(info).unify()
+            .or(manifest).unify()
+            .or(diff).unify()
+            .or(events).unify()
+            .or(blob).unify();
+        instances = instances.or(routes).unify().boxed();
+    }
+
+    let state_filter = warp::any().map(move || state.clone());
+
+    let openapi = warp::path!("api" / "openapi.json")
+        .and(warp::get())
+        .and_then(handle_openapi)
+        .map(Reply::into_response);
+
+    let shutdown_states = states.iter().map(|(_, state)| state.clone()).collect();
+    let states: InstanceStates = Arc::new(states);
+    let states_filter = warp::any().map(move || states.clone());
+
+    let healthz = warp::path!("healthz")
+        .and(warp::get())
+        .and(states_filter.clone())
+        .and_then(handle_healthz);
+
+    let readyz = warp::path!("readyz")
+        .and(warp::get())
+        .and(states_filter)
+        .and_then(handle_readyz);
+
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_metrics);
+
+    let audit = warp::path!("api" / "audit")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(credentials())
+        .and(state_filter.clone())
+        .and_then(handle_audit);
+
+    let health = healthz.or(readyz).unify().or(metrics).unify().or(audit).unify();
+
     let addr_str = format!(
         "{}:{}",
         config.json_rpc_listen_address, config.json_rpc_port
@@ -82,7 +373,27 @@ pub fn build_default_routes(
         .parse()
         .expect("Failed to parse json_rpc_listen_address and json_rpc_port");
 
-    (ws, read_param, write_param, info, socket_addr)
+    let http_headers = HttpHeaders::from_config(&config);
+    let static_files = static_routes(&config.static_files);
+    let shutdown = Shutdown::new(shutdown_states, config.save_on_shutdown);
+
+    (
+        ws,
+        read_param,
+        write_param,
+        info,
+        manifest,
+        openapi.boxed(),
+        diff,
+        events,
+        blob,
+        health.boxed(),
+        instances,
+        static_files,
+        http_headers,
+        shutdown,
+        socket_addr,
+    )
 }
 
 #[macro_export]
@@ -95,16 +406,24 @@ macro_rules! build_server {
             use warp::Rejection;
             use warp::path::FullPath;
 
-            let (ws, read_param, write_param, info, socket_addr) =
+            let (ws, read_param, write_param, info, manifest, openapi, diff, events, blob, health, instances, static_files, http_headers, shutdown, socket_addr) =
                 build_default_routes(config_file);
             
             let api_routes = ws
                         .or(read_param)
                         .or(write_param)
-                        .or(info);
+                        .or(info)
+                        .or(manifest)
+                        .or(openapi)
+                        .or(diff)
+                        .or(events)
+                        .or(blob)
+                        .or(health)
+                        .or(instances);
             $(
                 let api_routes = api_routes.or($user_routes);
             )*
+            let api_routes = http_headers.wrap(api_routes);
 
             let log = warp::log::custom(|info| {
                 println!(
@@ -116,27 +435,21 @@ macro_rules! build_server {
                 );
             });
 
-            if $serve_static {
-                let static_files_path = std::env::var("STATIC_FILES_PATH").expect(
-                    "STATIC_FILES_PATH environment variable not set"
-                );
-                let static_files = warp::fs::dir(static_files_path.clone());
-                let fallback = warp::get()
-                    .and(warp::path::full())
-                    .map(move |_| {
-                        match std::fs::read_to_string(format!("{}/index.html", static_files_path)) {
-                            Ok(contents) => warp::reply::html(contents),
-                            Err(_) => warp::reply::html("Index file not found".to_owned())
-                        }
-                    });
-                warp::serve(api_routes.or(static_files).or(fallback).with(log))
-                    .run(socket_addr)
-                    .await;
-            } else {
-                warp::serve(api_routes.with(log))
-                    .run(socket_addr)
-                    .await;
-            }
+            let server = async move {
+                match static_files.filter(|_| $serve_static) {
+                    Some(static_files) => {
+                        warp::serve(api_routes.or(static_files).with(log))
+                            .run(socket_addr)
+                            .await;
+                    }
+                    None => {
+                        warp::serve(api_routes.with(log))
+                            .run(socket_addr)
+                            .await;
+                    }
+                }
+            };
+            shutdown.run(server).await;
         }
         __internal_run_server($config_file).await
     }};
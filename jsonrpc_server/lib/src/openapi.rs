@@ -0,0 +1,518 @@
+use econfmanager::generated::{GROUPS_DATA, PARAMETER_DATA};
+use econfmanager::schema::{Parameter, ParameterValue, ParameterValueType, ValidationMethod};
+use econfmanager::sensitive::SECRET_MASK;
+use serde_json::{json, Map, Value};
+
+use crate::access::ROLE_HEADER;
+
+const OPENAPI_VERSION: &str = "3.0.3";
+const API_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+lazy_static::lazy_static! {
+    /// The document only depends on the parameter definitions, built on the first request
+    pub(crate) static ref OPENAPI_DOCUMENT: Value = openapi_document();
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+/// Name of the component schema of the parameter, "@" is not allowed in the component names
+fn schema_name(parameter: &Parameter) -> String {
+    parameter.name_id.replace('@', ".")
+}
+
+fn schema_ref(parameter: &Parameter) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", schema_name(parameter)) })
+}
+
+fn is_writable(parameter: &Parameter) -> bool {
+    !parameter.is_const && !parameter.readonly && !parameter.computed
+}
+
+/// The value as a JSON number for the minimum and the maximum
+fn number(value: &ParameterValue) -> Option<Value> {
+    Some(match value {
+        ParameterValue::ValI32(v) | ParameterValue::ValEnum(v) => json!(v),
+        ParameterValue::ValU32(v) => json!(v),
+        ParameterValue::ValI64(v) => json!(v),
+        ParameterValue::ValU64(v) => json!(v),
+        // Through the text, so 0.1 is not widened to 0.10000000149011612
+        ParameterValue::ValF32(v) if v.is_finite() => json!(v.to_string().parse::<f64>().ok()?),
+        ParameterValue::ValF64(v) if v.is_finite() => json!(v),
+        _ => return None,
+    })
+}
+
+/// JSON schema of the value of the parameter, as returned by the reads
+fn value_schema(parameter: &Parameter) -> Value {
+    let mut schema = match parameter.value_type {
+        ParameterValueType::TypeBool => json!({ "type": "boolean" }),
+        ParameterValueType::TypeI32 | ParameterValueType::TypeEnum(_) => json!({ "type": "integer", "format": "int32" }),
+        ParameterValueType::TypeU32 => json!({ "type": "integer", "format": "int64", "minimum": 0, "maximum": u32::MAX }),
+        ParameterValueType::TypeI64 => json!({ "type": "integer", "format": "int64" }),
+        // No unsigned 64-bit format, the values over i64::MAX don't fit the generated clients
+        ParameterValueType::TypeU64 => json!({ "type": "integer", "format": "int64", "minimum": 0 }),
+        ParameterValueType::TypeF32 => json!({ "type": "number", "format": "float" }),
+        ParameterValueType::TypeF64 => json!({ "type": "number", "format": "double" }),
+        ParameterValueType::TypeString => json!({ "type": "string" }),
+        ParameterValueType::TypeBlob => json!({ "type": "string", "format": "byte" }),
+        ParameterValueType::TypeNone => json!({}),
+    };
+    let fields = schema.as_object_mut().expect("schema is an object");
+
+    match &parameter.validation {
+        ValidationMethod::Range { min, max } => {
+            if let Some(min) = number(min) {
+                fields.insert("minimum".to_owned(), min);
+            }
+            if let Some(max) = number(max) {
+                fields.insert("maximum".to_owned(), max);
+            }
+        }
+        ValidationMethod::AllowedValues { values, names } => {
+            fields.insert("enum".to_owned(), json!(values));
+            if matches!(parameter.value_type, ParameterValueType::TypeEnum(_)) {
+                // Names of the enum constants of openapi-generator
+                fields.insert("x-enum-varnames".to_owned(), json!(names));
+            }
+        }
+        ValidationMethod::Text { max_length, pattern } => {
+            if let Some(max_length) = max_length {
+                fields.insert("maxLength".to_owned(), json!(max_length));
+            }
+            if let Some(pattern) = pattern {
+                fields.insert("pattern".to_owned(), json!(format!("^(?:{})$", pattern)));
+            }
+        }
+        ValidationMethod::None | ValidationMethod::CustomCallback => {}
+    }
+    if let (ParameterValueType::TypeBlob, Some(max_size)) = (&parameter.value_type, parameter.max_size) {
+        // Base64 length of the largest blob
+        fields.insert("maxLength".to_owned(), json!((max_size as usize).div_ceil(3) * 4));
+    }
+
+    if !parameter.comment.is_empty() {
+        fields.insert("description".to_owned(), json!(parameter.comment));
+    }
+    if !parameter.title.is_empty() {
+        fields.insert("title".to_owned(), json!(parameter.title));
+    }
+    if !parameter.secret && !matches!(parameter.value_default, ParameterValue::ValNone | ParameterValue::ValPath(_)) {
+        fields.insert("default".to_owned(), json!(parameter.value_default));
+    }
+    if !is_writable(parameter) {
+        fields.insert("readOnly".to_owned(), json!(true));
+    }
+    if parameter.deprecated {
+        fields.insert("deprecated".to_owned(), json!(true));
+    }
+    if parameter.secret {
+        fields.insert("writeOnly".to_owned(), json!(true));
+    }
+    if let Some(unit) = parameter.unit {
+        fields.insert("x-unit".to_owned(), json!(unit));
+    }
+    if let Some(content_type) = parameter.content_type {
+        fields.insert("x-content-type".to_owned(), json!(content_type));
+    }
+    fields.insert("x-access-level".to_owned(), json!(parameter.access_level.name()));
+
+    let finite = matches!(parameter.value_type, ParameterValueType::TypeF32 | ParameterValueType::TypeF64);
+    if finite && parameter.allow_non_finite {
+        return json!({ "oneOf": [schema, { "type": "string", "enum": ["NaN", "Infinity", "-Infinity"] }] });
+    }
+    schema
+}
+
+fn error_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Error" } } },
+    })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+/// Result of the bulk reads and writes, by parameter name
+fn bulk_result_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "error": { "type": "string" },
+            "parameters": { "type": "object", "additionalProperties": {
+                "type": "object",
+                "properties": {
+                    "status": { "type": "integer" },
+                    "value": {},
+                    "name_label": { "type": "string", "description": "Name of the value of an enum" },
+                    "seq": { "type": "integer" },
+                    "changed": { "type": "boolean" },
+                    "clamped": { "type": "boolean" },
+                    "error": { "type": "string" },
+                },
+                "required": ["status"],
+            } },
+        },
+    })
+}
+
+fn role_header() -> Value {
+    json!({
+        "name": ROLE_HEADER,
+        "in": "header",
+        "required": false,
+        "description": "Role of the client, the default role of the server if not set. \
+                        With the authentication enabled it can only lower the role of the credentials",
+        "schema": { "type": "string", "enum": ["user", "service", "factory"] },
+    })
+}
+
+fn read_operation(parameter: &Parameter) -> Value {
+    let value = if parameter.secret {
+        json!({ "type": "string", "enum": [SECRET_MASK], "description": "The secret values are not returned" })
+    } else {
+        schema_ref(parameter)
+    };
+    let mut parameters = vec![json!({
+        "name": "verbose",
+        "in": "query",
+        "required": false,
+        "description": "Return the value with its type, the enum names and the blob encoding",
+        "schema": { "type": "boolean" },
+    })];
+    if parameter.blob_schema.is_some() {
+        parameters.push(json!({
+            "name": "decode",
+            "in": "query",
+            "required": false,
+            "description": "Return the blob decoded with its schema as JSON",
+            "schema": { "type": "boolean" },
+        }));
+    }
+    json!({
+        "operationId": format!("read_{}", parameter.name_id.replace('@', "_")),
+        "summary": format!("Read {}", parameter.name_id),
+        "tags": [parameter.name_id.split_once('@').map_or("", |(group, _)| group)],
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "The value, the ETag is the sequence of the last write for the conditional writes",
+                "headers": { "ETag": { "schema": { "type": "string" } } },
+                "content": { "application/json": { "schema": {
+                    "oneOf": [value, { "$ref": "#/components/schemas/TypedValue" }],
+                } } },
+            },
+            "404": error_response("Unknown parameter"),
+        },
+    })
+}
+
+fn write_operation(parameter: &Parameter) -> Value {
+    let response = if parameter.secret { json!({ "type": "string", "enum": [SECRET_MASK] }) } else { schema_ref(parameter) };
+    let request = match (&parameter.value_type, &parameter.validation) {
+        // The enums are also written by the names of the values
+        (ParameterValueType::TypeEnum(_), ValidationMethod::AllowedValues { names, .. }) => {
+            json!({ "oneOf": [schema_ref(parameter), { "type": "string", "enum": names }] })
+        }
+        _ => schema_ref(parameter),
+    };
+    json!({
+        "operationId": format!("write_{}", parameter.name_id.replace('@', "_")),
+        "summary": format!("Write {}", parameter.name_id),
+        "tags": [parameter.name_id.split_once('@').map_or("", |(group, _)| group)],
+        "parameters": [
+            role_header(),
+            {
+                "name": "If-Match",
+                "in": "header",
+                "required": false,
+                "description": "ETag of a read, the write fails with 412 if the parameter was changed since",
+                "schema": { "type": "string" },
+            },
+        ],
+        "requestBody": {
+            "required": true,
+            "description": "The value as text: true or false, the numbers, the enums as the numbers or the names, \
+                            the string as is, the blobs in base64",
+            "content": { "text/plain": { "schema": request } },
+        },
+        "responses": {
+            "200": json_response("The value written, after the pre-change hooks", response),
+            "400": error_response("Invalid value"),
+            "403": error_response("The parameter is read-only or needs a higher role"),
+            "404": error_response("Unknown parameter"),
+            "412": error_response("The parameter was changed since the read of the If-Match ETag"),
+            "413": error_response("The blob is larger than the maximum size"),
+            "429": error_response("Over the rate limit of the writes, Retry-After has the seconds to wait"),
+        },
+    })
+}
+
+fn blob_operations(parameter: &Parameter) -> Value {
+    let id = parameter.name_id.replace('@', "_");
+    let content_type = parameter.content_type.unwrap_or("application/octet-stream");
+    let binary = json!({ "type": "string", "format": "binary" });
+    let mut operations = json!({
+        "get": {
+            "operationId": format!("download_{}", id),
+            "summary": format!("Download {}", parameter.name_id),
+            "parameters": [{ "name": "Range", "in": "header", "required": false, "schema": { "type": "string" } }],
+            "responses": {
+                "200": { "description": "The blob", "content": { content_type: { "schema": binary } } },
+                "206": { "description": "The requested range of the blob", "content": { content_type: { "schema": binary } } },
+                "404": error_response("Unknown parameter"),
+                "416": error_response("Invalid range"),
+            },
+        },
+    });
+    if is_writable(parameter) {
+        let uploaded = json_response("The size of the blob written", json!({
+            "type": "object",
+            "properties": { "size": { "type": "integer", "format": "int64" } },
+        }));
+        operations["put"] = json!({
+            "operationId": format!("upload_{}", id),
+            "summary": format!("Upload {} as the request body", parameter.name_id),
+            "parameters": [role_header()],
+            "requestBody": { "required": true, "content": { "application/octet-stream": { "schema": binary } } },
+            "responses": {
+                "200": uploaded.clone(),
+                "403": error_response("The parameter is read-only or needs a higher role"),
+                "411": error_response("Content-Length is required"),
+                "413": error_response("The blob is larger than the maximum size"),
+                "429": error_response("Over the rate limit of the writes, Retry-After has the seconds to wait"),
+            },
+        });
+        operations["post"] = json!({
+            "operationId": format!("upload_form_{}", id),
+            "summary": format!("Upload {} as the file part of a form", parameter.name_id),
+            "parameters": [role_header()],
+            "requestBody": {
+                "required": true,
+                "content": { "multipart/form-data": { "schema": {
+                    "type": "object",
+                    "properties": { "file": binary },
+                    "required": ["file"],
+                } } },
+            },
+            "responses": {
+                "200": uploaded,
+                "403": error_response("The parameter is read-only or needs a higher role"),
+                "413": error_response("The blob is larger than the maximum size"),
+                "429": error_response("Over the rate limit of the writes, Retry-After has the seconds to wait"),
+            },
+        });
+    }
+    operations
+}
+
+fn health_operation(operation_id: &str, summary: &str) -> Value {
+    let report = json!({ "$ref": "#/components/schemas/HealthReport" });
+    json!({
+        "get": {
+            "operationId": operation_id,
+            "summary": summary,
+            "security": [],
+            "responses": {
+                "200": json_response("All the checks passed", report.clone()),
+                "503": json_response("Some of the checks failed", report),
+            },
+        },
+    })
+}
+
+fn openapi_document() -> Value {
+    let mut paths = Map::new();
+    let mut schemas = Map::new();
+    schemas.insert("Error".to_owned(), json!({
+        "type": "object",
+        "properties": { "error": { "type": "string" } },
+        "required": ["error"],
+    }));
+    schemas.insert("TypedValue".to_owned(), json!({
+        "type": "object",
+        "description": "The value of a verbose read",
+        "properties": {
+            "value": {},
+            "type": { "type": "string", "enum": ["Bool", "I32", "U32", "I64", "U64", "F32", "F64", "String", "Blob", "Enum"] },
+            "enum_name": { "type": "string", "description": "Enum type of the parameter" },
+            "name_label": { "type": "string", "nullable": true, "description": "Name of the enum value, null if it is not in the enum" },
+            "encoding": { "type": "string", "enum": ["base64"], "description": "Encoding of the blob values" },
+        },
+        "required": ["value", "type"],
+    }));
+    schemas.insert("HealthReport".to_owned(), json!({
+        "type": "object",
+        "properties": {
+            "status": { "type": "string", "enum": ["ok", "fail"] },
+            "checks": { "type": "array", "items": {
+                "type": "object",
+                "properties": {
+                    "name": { "type": "string" },
+                    "healthy": { "type": "boolean" },
+                    "detail": { "type": "string" },
+                },
+            } },
+        },
+    }));
+
+    for parameter in PARAMETER_DATA.iter().filter(|parameter| !parameter.internal) {
+        if parameter.value_type == ParameterValueType::TypeNone {
+            continue;
+        }
+        schemas.insert(schema_name(parameter), value_schema(parameter));
+        paths.insert(format!("/api/read/{}", parameter.name_id), json!({ "get": read_operation(parameter) }));
+        if is_writable(parameter) {
+            paths.insert(format!("/api/write/{}", parameter.name_id), json!({ "post": write_operation(parameter) }));
+        }
+        if parameter.value_type == ParameterValueType::TypeBlob {
+            paths.insert(format!("/api/blob/{}", parameter.name_id), blob_operations(parameter));
+        }
+    }
+
+    paths.insert("/api/info".to_owned(), json!({ "get": {
+        "operationId": "info",
+        "summary": "Definitions of the parameters and the groups, and the routes of the API",
+        "responses": { "200": json_response("The definitions", json!({ "type": "object" })) },
+    } }));
+    let group_names: Vec<&str> = GROUPS_DATA.iter().map(|group| group.name).collect();
+    paths.insert("/api/group/{group}".to_owned(), json!({ "get": {
+        "operationId": "readGroup",
+        "summary": "Definitions and values of the parameters of a group, the same parameters as listed by /api/info",
+        "parameters": [{ "name": "group", "in": "path", "required": true, "schema": { "type": "string", "enum": group_names } }],
+        "responses": {
+            "200": json_response("The group and its parameters, every one with its definition and `value`", json!({
+                "type": "object",
+                "properties": {
+                    "group": { "type": "object" },
+                    "parameters": { "type": "array", "items": { "type": "object", "properties": { "value": {}, "name_label": { "type": "string" } } } },
+                },
+            })),
+            "404": error_response("Unknown group"),
+        },
+    } }));
+    paths.insert("/api/manifest".to_owned(), json!({ "get": {
+        "operationId": "manifest",
+        "summary": "Report of all the parameters, defaults and validation rules with the schema hash",
+        "responses": {
+            "200": json_response("The manifest, signed if the server has a signing key", json!({ "type": "object" })),
+            "500": error_response("The manifest can't be signed"),
+        },
+    } }));
+    paths.insert("/api/diff".to_owned(), json!({ "get": {
+        "operationId": "diff",
+        "summary": "Parameters with different values in the working and the saved database",
+        "responses": {
+            "200": json_response("The changed parameters", json!({
+                "type": "object",
+                "properties": { "parameters": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": { "name": { "type": "string" }, "current": {}, "saved": {} },
+                } } },
+            })),
+        },
+    } }));
+    paths.insert("/api/read".to_owned(), json!({ "post": {
+        "operationId": "readMany",
+        "summary": "Read several parameters",
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": { "type": "array", "items": { "type": "string" } } } },
+        },
+        "responses": {
+            "200": json_response("The HTTP status and the value or the error of every parameter", bulk_result_schema()),
+        },
+    } }));
+    paths.insert("/api/write".to_owned(), json!({ "post": {
+        "operationId": "writeMany",
+        "summary": "Write several parameters in one transaction, none of them if any fails",
+        "parameters": [role_header()],
+        "requestBody": {
+            "required": true,
+            "content": { "application/json": { "schema": { "type": "object", "additionalProperties": {} } } },
+        },
+        "responses": {
+            "200": json_response("The written values, including the ones adjusted by the rules", bulk_result_schema()),
+            "429": error_response("Over the rate limit of the writes, Retry-After has the seconds to wait"),
+            "default": json_response("Nothing written, the failed parameters have their status, the others 424", bulk_result_schema()),
+        },
+    } }));
+    paths.insert("/api/events".to_owned(), json!({ "get": {
+        "operationId": "events",
+        "summary": "Server-Sent Events stream of the parameter changes",
+        "description": "Every change is a `change` event with the sequence as the event ID and \
+                        {\"name\", \"value\", \"seq\", \"timestamp\"} as the data, the enums also have \"name_label\"",
+        "parameters": [
+            { "name": "names", "in": "query", "required": false, "schema": { "type": "string" },
+              "description": "Comma-separated names of the parameters" },
+            { "name": "group", "in": "query", "required": false, "schema": { "type": "string" } },
+        ],
+        "responses": {
+            "200": { "description": "The event stream", "content": { "text/event-stream": { "schema": { "type": "string" } } } },
+            "404": error_response("Unknown parameter or group"),
+        },
+    } }));
+    paths.insert("/healthz".to_owned(), health_operation("healthz", "Liveness checks"));
+    paths.insert("/readyz".to_owned(), health_operation("readyz", "Readiness checks"));
+    paths.insert("/api/audit".to_owned(), json!({ "get": {
+        "operationId": "audit",
+        "summary": "Latest writes and store changes made through the API, oldest first. Needs the admin permission",
+        "parameters": [
+            { "name": "parameter", "in": "query", "schema": { "type": "string" }, "description": "Only the writes of the parameter" },
+            { "name": "client", "in": "query", "schema": { "type": "string" }, "description": "Only the changes of the token or the user" },
+            { "name": "limit", "in": "query", "schema": { "type": "integer", "minimum": 0 }, "description": "Number of the latest entries" },
+        ],
+        "responses": {
+            "200": json_response("The entries", json!({
+                "type": "object",
+                "properties": { "entries": { "type": "array", "items": {
+                    "type": "object",
+                    "properties": {
+                        "timestamp": { "type": "number" },
+                        "api": { "type": "string", "enum": ["rest", "ws"] },
+                        "instance": { "type": "string" },
+                        "address": { "type": "string", "nullable": true },
+                        "client": { "type": "string", "nullable": true },
+                        "action": { "type": "string" },
+                        "parameter": { "type": "string" },
+                        "old_value": {},
+                        "new_value": {},
+                        "profile": { "type": "string" },
+                    },
+                } } },
+            })),
+        },
+    } }));
+    paths.insert("/metrics".to_owned(), json!({ "get": {
+        "operationId": "metrics",
+        "summary": "Counters of the server in the Prometheus text format",
+        "responses": {
+            "200": { "description": "The metrics", "content": { "text/plain": { "schema": { "type": "string" } } } },
+        },
+    } }));
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": "econfmanager REST API",
+            "version": API_VERSION,
+            "description": "Parameters of the device, one read and one write path per parameter. \
+                            The WebSocket JSON-RPC API at /api_ws is not described. \
+                            With the authentication configured the requests without valid credentials get 401, \
+                            the ones the client has no permission for get 403",
+        },
+        // The empty requirement is the anonymous access, when the server allows it
+        "security": [{}, { "bearerAuth": [] }, { "basicAuth": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "bearerAuth": { "type": "http", "scheme": "bearer" },
+                "basicAuth": { "type": "http", "scheme": "basic" },
+            },
+        },
+    })
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::configfile::RateLimitConfig;
+
+/// Buckets kept before the full ones are dropped
+const MAX_IDLE_BUCKETS: usize = 1024;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    /// Writes refused since the start, for the metrics
+    rejected: u64,
+}
+
+/// Token buckets of the writes of the clients, refilled at the configured rate up to the burst.
+/// The buckets have their own lock, the requests only hold the read lock of the state
+#[derive(Default)]
+pub(crate) struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub(crate) fn from_config(config: &RateLimitConfig) -> Self {
+        if !config.writes_per_second.is_finite() || config.writes_per_second < 0.0 {
+            panic!("Invalid rate_limit.writes_per_second {} in the configuration", config.writes_per_second);
+        }
+        Self {
+            rate: config.writes_per_second,
+            burst: config.burst.max(1) as f64,
+            buckets: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn rejected(&self) -> u64 {
+        self.buckets.lock().unwrap().rejected
+    }
+
+    /// Take `writes` from the bucket of the client, the time to wait when it has not enough.
+    /// A request with more writes than the burst waits for the bucket to be full
+    pub(crate) fn check(&self, key: &str, writes: usize) -> Result<(), Duration> {
+        if self.rate == 0.0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.buckets.len() > MAX_IDLE_BUCKETS {
+            let (rate, burst) = (self.rate, self.burst);
+            buckets.buckets.retain(|_, bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst);
+        }
+        let bucket = buckets.buckets.entry(key.to_owned()).or_insert(Bucket { tokens: self.burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        let needed = (writes as f64).min(self.burst);
+        if bucket.tokens >= needed {
+            bucket.tokens -= needed;
+            return Ok(());
+        }
+        let wait = Duration::from_secs_f64((needed - bucket.tokens) / self.rate);
+        buckets.rejected += 1;
+        Err(wait)
+    }
+}
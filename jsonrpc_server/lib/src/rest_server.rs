@@ -1,10 +1,31 @@
-use econfmanager::generated::ParameterId;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use econfmanager::error::EconfError;
+use econfmanager::generated::{ParameterId, PARAMETER_DATA};
+use econfmanager::interface::{HealthCheck, InterfaceInstance, ParameterFilter, WriteCondition};
+use econfmanager::manifest::sign_manifest;
+use econfmanager::schema::{AccessLevel, ParameterValue, ParameterValueType};
+use econfmanager::sensitive::SECRET_MASK;
+use futures_util::{Stream, StreamExt};
 use serde::Serialize;
-use warp::Rejection;
+use warp::http::header::{HeaderValue, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, RETRY_AFTER, WWW_AUTHENTICATE};
+use warp::hyper::body::Buf;
+use warp::multipart::FormData;
+use warp::{Rejection, Reply};
 use warp::{http::StatusCode, reply::json};
 use serde_json::json;
+use log::warn;
+
+use crate::access::{authorize, check_write_access, AuthError, Client, Credentials, Permission};
+use crate::audit::value_before_write;
+use crate::openapi::OPENAPI_DOCUMENT;
+use crate::shared_state::{run_blocking, with_state, AppState, InstanceStates, SharedState};
+use crate::utils::{client_value, enum_label, typed_value};
 
-use crate::shared_state::SharedState;
+/// Size of the chunks read from the blob streams
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+const BASIC_CHALLENGE: &str = "Basic realm=\"econfmanager\"";
 
 // use crate::SharedState;
 
@@ -24,10 +45,32 @@ struct ParameterInfo {
     is_const: bool,
     runtime: bool,
     readonly: bool,
+    computed: bool,
     group: String,
     tags: Vec<String>,
     validation: serde_json::Value,
     parameter_type: String,
+    blob_schema: Option<String>,
+    unit: Option<String>,
+    step: Option<f64>,
+    precision: Option<u32>,
+    widget: Option<String>,
+    max_size: Option<u32>,
+    content_type: Option<String>,
+    deprecated: bool,
+    access_level: String,
+    secret: bool,
+}
+
+/// Parameter of a group page: the definition with the current value
+#[derive(Debug, Serialize)]
+struct GroupParameter {
+    #[serde(flatten)]
+    info: ParameterInfo,
+    value: serde_json::Value,
+    /// Name of the value of an enum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name_label: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -47,13 +90,83 @@ lazy_static::lazy_static! {
         RouteInfo {
             path: "/api/read/:parameter".to_string(),
             method: "GET".to_string(),
-            description: "Read a parameter value".to_string(),
+            description: "Read a parameter value, ?decode=true returns blobs with a schema as JSON, ?verbose=1 the value with its type".to_string(),
         },
         RouteInfo {
             path: "/api/write/:parameter".to_string(),
             method: "POST".to_string(),
             description: "Write a parameter value".to_string(),
         },
+        RouteInfo {
+            path: "/api/read".to_string(),
+            method: "POST".to_string(),
+            description: "Read several parameters, the body is a JSON list of names".to_string(),
+        },
+        RouteInfo {
+            path: "/api/write".to_string(),
+            method: "POST".to_string(),
+            description: "Write several parameters in one transaction, the body is a JSON map of the names to the values".to_string(),
+        },
+        RouteInfo {
+            path: "/api/blob/:parameter".to_string(),
+            method: "GET".to_string(),
+            description: "Download a blob parameter with its content type, supports Range requests".to_string(),
+        },
+        RouteInfo {
+            path: "/api/blob/:parameter".to_string(),
+            method: "PUT".to_string(),
+            description: "Upload a blob parameter as the raw request body, Content-Length is required".to_string(),
+        },
+        RouteInfo {
+            path: "/api/blob/:parameter".to_string(),
+            method: "POST".to_string(),
+            description: "Upload a blob parameter as the \"file\" part of a multipart form".to_string(),
+        },
+        RouteInfo {
+            path: "/api/group/:group".to_string(),
+            method: "GET".to_string(),
+            description: "Definitions and values of the parameters of a group".to_string(),
+        },
+        RouteInfo {
+            path: "/api/manifest".to_string(),
+            method: "GET".to_string(),
+            description: "Report of all the parameters, defaults and validation rules with the schema hash".to_string(),
+        },
+        RouteInfo {
+            path: "/api/openapi.json".to_string(),
+            method: "GET".to_string(),
+            description: "OpenAPI 3 description of the REST API with the schemas of the parameters".to_string(),
+        },
+        RouteInfo {
+            path: "/api/diff".to_string(),
+            method: "GET".to_string(),
+            description: "Parameters with different values in the working and the saved database".to_string(),
+        },
+        RouteInfo {
+            path: "/api/events".to_string(),
+            method: "GET".to_string(),
+            description: "Server-Sent Events stream of the parameter changes, filtered by ?names=a,b and ?group=".to_string(),
+        },
+        RouteInfo {
+            path: "/api/audit".to_string(),
+            method: "GET".to_string(),
+            description: "Audit trail of the writes and the store changes, filtered by ?parameter=, ?client= and ?limit=, admin only".to_string(),
+        },
+        RouteInfo {
+            path: "/healthz".to_string(),
+            method: "GET".to_string(),
+            description: "Liveness: the database can be read and the notification receiver runs, 503 if not".to_string(),
+        },
+        RouteInfo {
+            path: "/readyz".to_string(),
+            method: "GET".to_string(),
+            description: "Readiness: the liveness checks, the WAL size and the saved database, 503 if any fails".to_string(),
+        },
+        RouteInfo {
+            path: "/metrics".to_string(),
+            method: "GET".to_string(),
+            description: "Counters of the server in the Prometheus text format".to_string(),
+        },
         RouteInfo {
             path: "/info".to_string(),
             method: "GET".to_string(),
@@ -62,8 +175,56 @@ lazy_static::lazy_static! {
     ];
 }
 
-pub(crate) async fn handle_info(state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let app = state.lock().unwrap();
+fn error_status(err: &EconfError) -> StatusCode {
+    match err {
+        EconfError::NotFound(_) => StatusCode::NOT_FOUND,
+        EconfError::Validation { .. } | EconfError::Vetoed { .. } | EconfError::TypeMismatch { .. } => {
+            StatusCode::BAD_REQUEST
+        }
+        EconfError::Const(_) | EconfError::ReadOnly(_) | EconfError::ReadOnlyInstance => StatusCode::FORBIDDEN,
+        EconfError::Conflict(_) => StatusCode::PRECONDITION_FAILED,
+        EconfError::LockTimeout | EconfError::DbBusy => StatusCode::SERVICE_UNAVAILABLE,
+        EconfError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        EconfError::Database(_)
+        | EconfError::Corrupt(_)
+        | EconfError::Io(_)
+        | EconfError::Schema(_)
+        | EconfError::Config(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Definition of the parameter as listed by /api/info
+fn parameter_info(app: &AppState, id: ParameterId) -> ParameterInfo {
+    ParameterInfo {
+        id: id as usize,
+        name: app.interface.get_name(id),
+        comment: app.interface.get_comment(id),
+        title: app.interface.get_title(id),
+        parameter_type: app.interface.get_type_string(id),
+        is_const: app.interface.is_const(id),
+        runtime: app.interface.is_runtime(id),
+        validation: app.interface.get_validation_json(id),
+        group: app.interface.get_group(id),
+        readonly: app.interface.is_readonly(id),
+        computed: app.interface.is_computed(id),
+        tags: app.interface.get_tags(id),
+        blob_schema: app.interface.get_blob_schema(id),
+        unit: app.interface.get_unit(id),
+        step: app.interface.get_step(id),
+        precision: app.interface.get_precision(id),
+        widget: app.interface.get_widget(id),
+        max_size: app.interface.get_max_size(id),
+        content_type: app.interface.get_content_type(id),
+        deprecated: app.interface.is_deprecated(id),
+        access_level: app.interface.get_access_level(id).name().to_owned(),
+        secret: app.interface.is_secret(id),
+    }
+}
+
+/// The parameters, the groups and the routes, returned by /api/info and the WebSocket info method
+pub(crate) fn info_document(app: &AppState) -> serde_json::Value {
     let routes_json = ROUTES.iter().map(|r| {
         json!({
             "path": r.path,
@@ -72,28 +233,9 @@ pub(crate) async fn handle_info(state: SharedState) -> Result<impl warp::Reply,
         })
     }).collect::<Vec<_>>();
 
-    let parameters: Vec<ParameterInfo> = app.names.iter()
-        .enumerate()
-        .filter(|(idx, _)| {
-            let id = ParameterId::try_from(*idx).unwrap();
-            !app.interface.is_internal(id)
-        })
-        .map(|(idx, _)| {
-            let id = ParameterId::try_from(idx).unwrap();
-            ParameterInfo {
-                id: id as usize,
-                name: app.interface.get_name(id),
-                comment: app.interface.get_comment(id),
-                title: app.interface.get_title(id),
-                parameter_type: app.interface.get_type_string(id),
-                is_const: app.interface.is_const(id),
-                runtime: app.interface.is_runtime(id),
-                validation: app.interface.get_validation_json(id),
-                group: app.interface.get_group(id),
-                readonly: app.interface.is_readonly(id),
-                tags: app.interface.get_tags(id),
-            }
-        })
+    let visible = ParameterFilter { internal: Some(false), hidden: Some(false), ..Default::default() };
+    let parameters: Vec<ParameterInfo> = app.interface.iter_parameters(visible)
+        .map(|(id, _)| parameter_info(app, id))
         .collect();
 
     let group_parameters = app.interface.get_groups();
@@ -107,71 +249,305 @@ pub(crate) async fn handle_info(state: SharedState) -> Result<impl warp::Reply,
         })
         .collect();
 
-    Ok(warp::reply::with_status(
-        json(&json!({"parameters": parameters, "group": groups, "routes": routes_json})),
-        StatusCode::OK,
-    ))
+    json!({"parameters": parameters, "group": groups, "routes": routes_json})
+}
+
+pub(crate) async fn handle_info(credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        Ok(warp::reply::with_status(
+            json(&info_document(app)),
+            StatusCode::OK,
+        ).into_response())
+    })
+    .await
+}
+
+/// The group with the definitions and the values of its parameters, the same parameters as listed by /api/info
+pub(crate) async fn handle_group(group: String, credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let Some((name, title, comment)) = app.interface.get_groups().into_iter().find(|(name, _, _)| *name == group) else {
+            return Ok(error_reply(StatusCode::NOT_FOUND, format!("Group |{}| does not exist", group)));
+        };
+        let values = match app.interface.get_group_values(&group) {
+            Ok(values) => values,
+            Err(err) => {
+                return Ok(error_reply(error_status(&err), format!("Failed to read group |{}|: {}", group, err)));
+            }
+        };
+        let parameters: Vec<GroupParameter> = values
+            .iter()
+            .filter(|(id, _)| !app.interface.is_internal(*id) && !app.interface.is_hidden(*id))
+            .map(|(id, value)| GroupParameter {
+                info: parameter_info(app, *id),
+                value: client_value(&app.interface, *id, value),
+                name_label: enum_label(&app.interface, *id, value),
+            })
+            .collect();
+        Ok(warp::reply::with_status(
+            json(&json!({ "group": GroupInfo { name, title, comment }, "parameters": parameters })),
+            StatusCode::OK,
+        ).into_response())
+    })
+    .await
+}
+
+pub(crate) async fn handle_manifest(credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let mut manifest = app.interface.get_manifest();
+        if let Some(key) = &app.manifest_signing_key {
+            if let Err(err) = sign_manifest(&mut manifest, key) {
+                let error_response = json(&json!({
+                    "error": format!("Failed to sign the manifest: {}", err)
+                }));
+                return Ok(warp::reply::with_status(
+                    error_response,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                ).into_response());
+            }
+        }
+        Ok(warp::reply::with_status(
+            json(&manifest),
+            StatusCode::OK,
+        ).into_response())
+    })
+    .await
+}
+
+pub(crate) async fn handle_openapi() -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(json(&*OPENAPI_DOCUMENT))
+}
+
+pub(crate) async fn handle_diff(credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let diffs = match app.interface.diff_saved() {
+            Ok(diffs) => diffs,
+            Err(err) => {
+                return Ok(error_reply(error_status(&err), format!("Failed to compare with the saved database: {}", err)));
+            }
+        };
+        let parameters: Vec<serde_json::Value> = diffs
+            .iter()
+            .filter(|diff| !app.interface.is_internal(diff.id) && !app.interface.is_hidden(diff.id))
+            .map(|diff| {
+                json!({
+                    "name": app.interface.get_name(diff.id),
+                    "current": client_value(&app.interface, diff.id, &diff.current),
+                    "saved": client_value(&app.interface, diff.id, &diff.saved),
+                })
+            })
+            .collect();
+        Ok(warp::reply::with_status(json(&json!({ "parameters": parameters })), StatusCode::OK).into_response())
+    })
+    .await
+}
+
+/// 200 if all the checks passed, 503 with the failed ones otherwise. The checks of the named instances
+/// have the name of the instance
+fn health_reply(checks: Vec<(Option<String>, HealthCheck)>) -> warp::reply::Response {
+    let healthy = checks.iter().all(|(_, check)| check.healthy);
+    let checks: Vec<serde_json::Value> = checks
+        .iter()
+        .map(|(instance, check)| {
+            let mut value = json!({ "name": check.name, "healthy": check.healthy, "detail": check.detail });
+            if let Some(instance) = instance {
+                value["instance"] = json!(instance);
+            }
+            value
+        })
+        .collect();
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    warp::reply::with_status(
+        json(&json!({ "status": if healthy { "ok" } else { "fail" }, "checks": checks })),
+        status,
+    )
+    .into_response()
+}
+
+/// Run the checks on every instance, the server is healthy only if all of them are
+async fn instances_health(states: InstanceStates, check: fn(&InterfaceInstance) -> Vec<HealthCheck>) -> warp::reply::Response {
+    run_blocking(move || {
+        let checks = states
+            .iter()
+            .flat_map(|(name, state)| {
+                check(&state.blocking_read().interface)
+                    .into_iter()
+                    .map(move |check| (name.clone(), check))
+            })
+            .collect();
+        health_reply(checks)
+    })
+    .await
+}
+
+pub(crate) async fn handle_healthz(states: InstanceStates) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(instances_health(states, InterfaceInstance::check_liveness).await)
+}
+
+pub(crate) async fn handle_readyz(states: InstanceStates) -> Result<warp::reply::Response, warp::Rejection> {
+    Ok(instances_health(states, InterfaceInstance::check_readiness).await)
+}
+
+/// Counters of the server in the Prometheus text format
+pub(crate) async fn handle_metrics(credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let metrics = format!(
+            "# HELP econf_rate_limited_total Write requests refused by the rate limit\n\
+             # TYPE econf_rate_limited_total counter\n\
+             econf_rate_limited_total {}\n\
+             # HELP econf_ws_clients Connected WebSocket clients\n\
+             # TYPE econf_ws_clients gauge\n\
+             econf_ws_clients {}\n",
+            app.rate_limiter.rejected(),
+            app.clients.len(),
+        );
+        Ok(warp::reply::with_header(metrics, CONTENT_TYPE, "text/plain; version=0.0.4").into_response())
+    })
+    .await
+}
+
+/// The audit trail of the API changes, the latest `limit` entries of the `parameter` or the `client` when given
+pub(crate) async fn handle_audit(query: HashMap<String, String>, credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Admin) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let limit = match query.get("limit").map(|limit| limit.parse::<usize>()) {
+            None => usize::MAX,
+            Some(Ok(limit)) => limit,
+            Some(Err(_)) => return Ok(error_reply(StatusCode::BAD_REQUEST, "Could not decode limit".to_owned())),
+        };
+        let entries = app.audit.log().entries(
+            query.get("parameter").map(String::as_str),
+            query.get("client").map(String::as_str),
+            limit,
+        );
+        Ok(json(&json!({ "entries": entries })).into_response())
+    })
+    .await
 }
 
-pub(crate) async fn handle_read_param(name: String, state: SharedState) -> Result<impl warp::Reply, warp::Rejection> {
-    let app = state.lock().unwrap();
+pub(crate) async fn handle_read_param(name: String, query: HashMap<String, String>, credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
     
-    if !app.names.contains(&name) {
-        let error_response = json(&json!({
-            "error": format!("Parameter |{}| does not exist", name)
-        }));
-        return Ok(warp::reply::with_status(
-            error_response,
-            StatusCode::NOT_FOUND,
-        ));
-    }
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        if !app.names.contains(&name) {
+            return Ok(error_reply(StatusCode::NOT_FOUND, format!("Parameter |{}| does not exist", name)));
+        }
 
-    let parameter_id = match app.interface.get_parameter_id_from_name(name.clone()) {
-        Some(id) => id,
-        None => {
-            let error_response = json(&json!({
-                "error": format!("Could not find ID for parameter |{}|", name)
-            }));
+        let parameter_id = match app.interface.get_parameter_id_from_name(name.clone()) {
+            Some(id) => id,
+            None => {
+                return Ok(error_reply(StatusCode::NOT_FOUND, format!("Could not find ID for parameter |{}|", name)));
+            }
+        };
+
+        if app.interface.is_internal(parameter_id)
+        {
+            return Ok(error_reply(StatusCode::FORBIDDEN, format!("Access internal parameter |{}| forbidden", name)));
+        }
+
+        let verbose = query.get("verbose").map(|v| v == "true" || v == "1").unwrap_or(false);
+        if app.interface.is_secret(parameter_id) {
+            let masked = if verbose {
+                json!({ "value": SECRET_MASK, "type": app.interface.get_type_string(parameter_id) })
+            } else {
+                json!(SECRET_MASK)
+            };
             return Ok(warp::reply::with_status(
-                error_response,
-                StatusCode::NOT_FOUND,
-            ));
+                json(&masked),
+                StatusCode::OK,
+            ).into_response());
         }
-    };
 
-    if app.interface.is_internal(parameter_id)
-    {
-        let error_response = json(&json!({
-            "error": format!("Access internal parameter |{}| forbidden", name)
-        }));
-        return Ok(warp::reply::with_status(
-            error_response,
-            StatusCode::FORBIDDEN,
-        ));
-    }
+        let decode = query.get("decode").map(|v| v == "true" || v == "1").unwrap_or(false);
+        if decode {
+            return match app.interface.get_decoded_json(parameter_id) {
+                Ok(value) => Ok(warp::reply::with_status(
+                    json(&value),
+                    StatusCode::OK,
+                ).into_response()),
+                Err(err) => {
+                    Ok(error_reply(StatusCode::BAD_REQUEST, format!("Failed to decode parameter |{}|: {}", name, err)))
+                }
+            };
+        }
 
-    match app.interface.get(parameter_id, false) {
-        Ok(value) => Ok(warp::reply::with_status(
-            json(&json!(value)),
-            StatusCode::OK,
-        )),
-        Err(err) => {
-            let error_response = json(&json!({
-                "error": format!("Failed to read parameter |{}|: {:?}", name, err)
-            }));
-            Ok(warp::reply::with_status(
-                error_response,
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ))
+        // Sequence first, a write in between makes a conditional write with the ETag fail instead of lost
+        let sequence = match app.interface.get_sequence(parameter_id) {
+            Ok(sequence) => sequence,
+            Err(err) => {
+                return Ok(error_reply(error_status(&err), format!("Failed to read parameter |{}|: {}", name, err)));
+            }
+        };
+
+        match app.interface.get(parameter_id, false) {
+            Ok(value) => {
+                let body = if verbose { typed_value(&app.interface, parameter_id, &value) } else { json!(value) };
+                let mut response = warp::reply::with_status(
+                    json(&body),
+                    StatusCode::OK,
+                ).into_response();
+                if let Ok(etag) = HeaderValue::from_str(&format!("\"{}\"", sequence)) {
+                    response.headers_mut().insert(ETAG, etag);
+                }
+                Ok(response)
+            },
+            Err(err) => {
+                Ok(error_reply(error_status(&err), format!("Failed to read parameter |{}|: {}", name, err)))
+            }
         }
+    })
+    .await
+}
+
+/// Expected sequence number from an `If-Match` header with the ETag of a read, None for `*`
+fn parse_if_match(if_match: &str) -> Result<Option<u64>, String> {
+    let tag = if_match.trim();
+    if tag == "*" {
+        return Ok(None);
     }
+    tag.trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|_| format!("Invalid If-Match header |{}|", tag))
 }
 
 pub(crate) async fn handle_write_param(
     name: String,
     value_bytes: warp::hyper::body::Bytes,
+    credentials: Credentials,
+    if_match: Option<String>,
     state: SharedState,
-) -> Result<impl warp::Reply, Rejection> {
+) -> Result<warp::reply::Response, Rejection> {
+    let expected_sequence = match if_match.as_deref().map(parse_if_match).transpose() {
+        Ok(sequence) => sequence.flatten(),
+        Err(msg) => {
+            let error_response = json(&json!({ "error": msg }));
+            return Ok(warp::reply::with_status(
+                error_response,
+                StatusCode::BAD_REQUEST,
+            ).into_response());
+        }
+    };
+
     let value_str = match String::from_utf8(value_bytes.to_vec()) {
         Ok(s) => s,
         Err(e) => {
@@ -181,88 +557,544 @@ pub(crate) async fn handle_write_param(
             return Ok(warp::reply::with_status(
                 error_response,
                 StatusCode::BAD_REQUEST,
-            ));
+            ).into_response());
         }
     };
 
-    let app = state.lock().unwrap();
+    with_state(state, move |app| {
     
-    if !app.names.contains(&name) {
-        let error_response = json(&json!({
-            "error": format!("Parameter |{}| does not exist", name)
-        }));
-        return Ok(warp::reply::with_status(
-            error_response,
-            StatusCode::NOT_FOUND,
-        ));
-    }
+        let client = match authorize(app, &credentials, Permission::Write) {
+            Ok(client) => client,
+            Err(err) => return Ok(auth_error_reply(app, err)),
+        };
+        if let Some(reply) = rate_limit_reply(app, &client, 1) {
+            return Ok(reply);
+        }
 
-    let parameter_id = match app.interface.get_parameter_id_from_name(name.clone()) {
-        Some(id) => id,
-        None => {
+        if !app.names.contains(&name) {
             let error_response = json(&json!({
-                "error": format!("No ID found for parameter |{}|", name)
+                "error": format!("Parameter |{}| does not exist", name)
             }));
             return Ok(warp::reply::with_status(
                 error_response,
                 StatusCode::NOT_FOUND,
-            ));
+            ).into_response());
         }
-    };
 
-    if app.interface.is_internal(parameter_id)
-    {
-        let error_response = json(&json!({
-            "error": format!("Access internal parameter |{}| forbidden", name)
-        }));
-        return Ok(warp::reply::with_status(
-            error_response,
-            StatusCode::FORBIDDEN,
-        ));
-    }
+        let parameter_id = match app.interface.get_parameter_id_from_name(name.clone()) {
+            Some(id) => id,
+            None => {
+                let error_response = json(&json!({
+                    "error": format!("No ID found for parameter |{}|", name)
+                }));
+                return Ok(warp::reply::with_status(
+                    error_response,
+                    StatusCode::NOT_FOUND,
+                ).into_response());
+            }
+        };
 
-    if app.interface.is_readonly(parameter_id)
-    {
-        let error_response = json(&json!({
-            "error": format!("Readonly parameter cannnot be changed |{}|", name)
-        }));
-        return Ok(warp::reply::with_status(
-            error_response,
-            StatusCode::FORBIDDEN,
-        ));
-    }
+        if app.interface.is_internal(parameter_id)
+        {
+            let error_response = json(&json!({
+                "error": format!("Access internal parameter |{}| forbidden", name)
+            }));
+            return Ok(warp::reply::with_status(
+                error_response,
+                StatusCode::FORBIDDEN,
+            ).into_response());
+        }
 
-    let converted = match app.interface.set_from_string(parameter_id, &value_str) {
-        Ok(v) => v,
-        Err(e) => {
+        if app.interface.is_readonly(parameter_id)
+        {
             let error_response = json(&json!({
-                "error": format!("Invalid parameter |{}| value |{}|: {}", name, value_str, e)
+                "error": format!("Readonly parameter cannnot be changed |{}|", name)
             }));
             return Ok(warp::reply::with_status(
                 error_response,
-                StatusCode::BAD_REQUEST,
-            ));
+                StatusCode::FORBIDDEN,
+            ).into_response());
         }
-    };
 
-    match app.interface.set(parameter_id, converted) {
-        Ok(applied) => {
-            let success_response = json(&json!(
-                applied
-            ));
-            Ok(warp::reply::with_status(
-                success_response,
-                StatusCode::OK,
-            ))
-        },
-        Err(e) => {
+        if let Err(msg) = check_write_access(app, parameter_id, client.role)
+        {
             let error_response = json(&json!({
-                "error": format!("Failed to set parameter |{}|: {}", name, e)
+                "error": msg
             }));
-            Ok(warp::reply::with_status(
+            return Ok(warp::reply::with_status(
                 error_response,
-                StatusCode::INTERNAL_SERVER_ERROR,
-            ))
+                StatusCode::FORBIDDEN,
+            ).into_response());
+        }
+
+        if let Some(max_size) = app.interface.get_max_size(parameter_id) {
+            // The blobs are sent base64 encoded, reject the oversized ones before decoding
+            if value_str.len() > (max_size as usize).div_ceil(3) * 4 {
+                let error_response = json(&json!({
+                    "error": format!("Value of |{}| is larger than {} bytes", name, max_size)
+                }));
+                return Ok(warp::reply::with_status(
+                    error_response,
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                ).into_response());
+            }
+        }
+
+        let converted = match app.interface.set_from_string(parameter_id, &value_str) {
+            Ok(v) => v,
+            Err(e) => {
+                let shown_value = if app.interface.is_secret(parameter_id) { SECRET_MASK } else { value_str.as_str() };
+                let error_response = json(&json!({
+                    "error": format!("Invalid parameter |{}| value |{}|: {}", name, shown_value, e)
+                }));
+                return Ok(warp::reply::with_status(
+                    error_response,
+                    StatusCode::BAD_REQUEST,
+                ).into_response());
+            }
+        };
+
+        let old_value = value_before_write(&app.interface, parameter_id);
+        let result = match expected_sequence {
+            Some(sequence) => app.interface.set_if(parameter_id, converted, WriteCondition::Sequence(sequence), Some("rest")),
+            None => app.interface.set_with_source(parameter_id, converted, Some("rest")),
+        };
+        match result {
+            Ok(applied) => {
+                app.audit.write("rest", &client, &app.interface, parameter_id, old_value.as_ref(), &applied.value);
+                let success_response = json(&client_value(&app.interface, parameter_id, &applied.value));
+                Ok(warp::reply::with_status(
+                    success_response,
+                    StatusCode::OK,
+                ).into_response())
+            },
+            Err(e) => {
+                let error_response = json(&json!({
+                    "error": format!("Failed to set parameter |{}|: {}", name, e)
+                }));
+                Ok(warp::reply::with_status(
+                    error_response,
+                    error_status(&e),
+                ).into_response())
+            }
+        }
+    })
+    .await
+}
+
+/// Read several parameters, the body is a JSON list of names. Every parameter has the HTTP status of its read
+pub(crate) async fn handle_bulk_read(names: Vec<String>, credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let mut parameters = serde_json::Map::new();
+        for name in names {
+            let result = match app.interface.get_parameter_id_from_name(name.clone()) {
+                None => json!({ "status": StatusCode::NOT_FOUND.as_u16(), "error": format!("Parameter |{}| does not exist", name) }),
+                Some(id) if app.interface.is_internal(id) => {
+                    json!({ "status": StatusCode::FORBIDDEN.as_u16(), "error": format!("Access internal parameter |{}| forbidden", name) })
+                }
+                Some(id) => {
+                    // Sequence first, as in the single reads
+                    match app.interface.get_sequence(id).and_then(|sequence| Ok((sequence, app.interface.get(id, false)?))) {
+                        Ok((sequence, value)) => {
+                            let mut result = json!({
+                                "status": StatusCode::OK.as_u16(),
+                                "value": client_value(&app.interface, id, &value),
+                                "seq": sequence,
+                            });
+                            if let Some(label) = enum_label(&app.interface, id, &value) {
+                                result["name_label"] = json!(label);
+                            }
+                            result
+                        }
+                        Err(err) => json!({
+                            "status": error_status(&err).as_u16(),
+                            "error": format!("Failed to read parameter |{}|: {}", name, err),
+                        }),
+                    }
+                }
+            };
+            parameters.insert(name, result);
+        }
+        Ok(warp::reply::with_status(json(&json!({ "parameters": parameters })), StatusCode::OK).into_response())
+    })
+    .await
+}
+
+/// Write several parameters together, the body is a JSON map of the names to the values. Either all the
+/// values are written or none: the parameters that failed have their HTTP status and the error, the
+/// others 424 (Failed Dependency), and the response has the status of the first failure
+pub(crate) async fn handle_bulk_write(
+    values: serde_json::Map<String, serde_json::Value>,
+    credentials: Credentials,
+    state: SharedState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    with_state(state, move |app| {
+        let client = match authorize(app, &credentials, Permission::Write) {
+            Ok(client) => client,
+            Err(err) => return Ok(auth_error_reply(app, err)),
+        };
+        if let Some(reply) = rate_limit_reply(app, &client, values.len()) {
+            return Ok(reply);
+        }
+        let role = client.role;
+
+        let mut converted = Vec::with_capacity(values.len());
+        let mut failures: Vec<(String, StatusCode, String)> = Vec::new();
+        for (name, value) in &values {
+            let parameter_id = match app.interface.get_parameter_id_from_name(name.clone()) {
+                Some(id) if !app.interface.is_internal(id) => id,
+                Some(_) => {
+                    failures.push((name.clone(), StatusCode::FORBIDDEN, format!("Access internal parameter |{}| forbidden", name)));
+                    continue;
+                }
+                None => {
+                    failures.push((name.clone(), StatusCode::NOT_FOUND, format!("Parameter |{}| does not exist", name)));
+                    continue;
+                }
+            };
+            if app.interface.is_readonly(parameter_id) {
+                failures.push((name.clone(), StatusCode::FORBIDDEN, format!("Readonly parameter cannnot be changed |{}|", name)));
+                continue;
+            }
+            if let Err(msg) = check_write_access(app, parameter_id, role) {
+                failures.push((name.clone(), StatusCode::FORBIDDEN, msg));
+                continue;
+            }
+            match app.interface.set_from_json(parameter_id, value) {
+                Ok(value) => converted.push((parameter_id, value)),
+                Err(e) => failures.push((name.clone(), StatusCode::BAD_REQUEST, format!("Invalid parameter |{}| value: {}", name, e))),
+            }
+        }
+
+        if failures.is_empty() {
+            let old_values: HashMap<ParameterId, ParameterValue> = converted.iter()
+                .filter_map(|(id, _)| value_before_write(&app.interface, *id).map(|value| (*id, value)))
+                .collect();
+            match app.interface.set_all(converted, Some("rest")) {
+                Ok(written) => {
+                    for (id, outcome) in &written {
+                        app.audit.write("rest", &client, &app.interface, *id, old_values.get(id), &outcome.value);
+                    }
+                    let parameters: serde_json::Map<String, serde_json::Value> = written
+                        .iter()
+                        .map(|(id, outcome)| {
+                            (app.interface.get_name(*id), json!({
+                                "status": StatusCode::OK.as_u16(),
+                                "value": client_value(&app.interface, *id, &outcome.value),
+                                "changed": outcome.changed,
+                                "clamped": outcome.clamped,
+                            }))
+                        })
+                        .collect();
+                    return Ok(warp::reply::with_status(json(&json!({ "parameters": parameters })), StatusCode::OK).into_response());
+                }
+                Err(e) => {
+                    let name = e.parameter().map(|id| app.interface.get_name(id)).unwrap_or_default();
+                    failures.push((name, error_status(&e), format!("Failed to set the parameters: {}", e)));
+                }
+            }
+        }
+
+        let mut parameters = serde_json::Map::new();
+        for name in values.keys() {
+            parameters.insert(name.clone(), json!({
+                "status": StatusCode::FAILED_DEPENDENCY.as_u16(),
+                "error": "Not written, another parameter failed",
+            }));
+        }
+        for (name, status, msg) in &failures {
+            if !name.is_empty() {
+                parameters.insert(name.clone(), json!({ "status": status.as_u16(), "error": msg }));
+            }
+        }
+        let (_, status, msg) = &failures[0];
+        Ok(warp::reply::with_status(json(&json!({ "error": msg, "parameters": parameters })), *status).into_response())
+    })
+    .await
+}
+
+pub(crate) fn error_reply(status: StatusCode, message: String) -> warp::reply::Response {
+    warp::reply::with_status(json(&json!({ "error": message })), status).into_response()
+}
+
+/// 401 for the missing or invalid credentials, with the HTTP Basic challenge when the server has users,
+/// 403 for the requests the client can't do
+pub(crate) fn auth_error_reply(app: &AppState, err: AuthError) -> warp::reply::Response {
+    match err {
+        AuthError::Unauthenticated(msg) => {
+            let mut response = error_reply(StatusCode::UNAUTHORIZED, msg);
+            if app.auth.has_users() {
+                response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static(BASIC_CHALLENGE));
+            }
+            response
+        }
+        AuthError::Forbidden(msg) => error_reply(StatusCode::FORBIDDEN, msg),
+    }
+}
+
+/// 429 with the seconds to wait in Retry-After when the client writes faster than the rate limit
+fn rate_limit_reply(app: &AppState, client: &Client, writes: usize) -> Option<warp::reply::Response> {
+    let key = client.rate_key();
+    app.rate_limiter.check(&key, writes).err().map(|wait| {
+        warn!("Writes of {} refused by the rate limit", key);
+        let mut response = error_reply(
+            StatusCode::TOO_MANY_REQUESTS,
+            format!("Rate limit exceeded, retry in {} ms", wait.as_millis()),
+        );
+        response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(wait.as_secs_f64().ceil() as u64));
+        response
+    })
+}
+
+/// Find a blob parameter accessible through the API, the error is the reply to send
+#[allow(clippy::result_large_err)]
+fn blob_parameter(app: &AppState, name: &str) -> Result<ParameterId, warp::reply::Response> {
+    let Some(parameter_id) = app.interface.get_parameter_id_from_name(name.to_owned()) else {
+        return Err(error_reply(StatusCode::NOT_FOUND, format!("Parameter |{}| does not exist", name)));
+    };
+    if app.interface.is_internal(parameter_id) {
+        return Err(error_reply(StatusCode::FORBIDDEN, format!("Access internal parameter |{}| forbidden", name)));
+    }
+    if PARAMETER_DATA[parameter_id.index()].value_type != ParameterValueType::TypeBlob {
+        return Err(error_reply(StatusCode::BAD_REQUEST, format!("Parameter |{}| is not a blob", name)));
+    }
+    Ok(parameter_id)
+}
+
+/// The same checks as for /api/write, and the size limits of the upload
+#[allow(clippy::result_large_err)]
+fn check_blob_write(app: &AppState, parameter_id: ParameterId, name: &str, role: AccessLevel, size: u64) -> Result<(), warp::reply::Response> {
+    if app.interface.is_readonly(parameter_id) {
+        return Err(error_reply(StatusCode::FORBIDDEN, format!("Readonly parameter cannnot be changed |{}|", name)));
+    }
+    check_write_access(app, parameter_id, role)
+        .map_err(|msg| error_reply(StatusCode::FORBIDDEN, msg))?;
+    let limit = app.interface.get_max_size(parameter_id).map_or(app.blob_upload_limit, |max_size| max_size as u64);
+    if size > limit {
+        return Err(error_reply(StatusCode::PAYLOAD_TOO_LARGE, format!("Value of |{}| is larger than {} bytes", name, limit)));
+    }
+    Ok(())
+}
+
+/// Single range of a `Range: bytes=...` header as the first and the last byte, None if it can't be served
+fn parse_range(header: &str, size: usize) -> Option<(usize, usize)> {
+    let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix = suffix.parse::<usize>().ok()?.min(size);
+            (size.checked_sub(suffix)?, size.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, size.checked_sub(1)?),
+        (start, end) => (start.parse().ok()?, end.parse::<usize>().ok()?.min(size.checked_sub(1)?)),
+    };
+    (start <= end).then_some((start, end))
+}
+
+pub(crate) async fn handle_blob_read(name: String, range: Option<String>, credentials: Credentials, state: SharedState) -> Result<warp::reply::Response, Rejection> {
+    with_state(state, move |app| {
+        if let Err(err) = authorize(app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(app, err));
+        }
+        let parameter_id = match blob_parameter(app, &name) {
+            Ok(id) => id,
+            Err(reply) => return Ok(reply),
+        };
+
+        let mut reader = match app.interface.blob_open_read(parameter_id) {
+            Ok(reader) => reader,
+            Err(e) => return Ok(error_reply(error_status(&e), format!("Failed to read parameter |{}|: {}", name, e))),
+        };
+        let size = reader.size();
+        let (start, end) = match range.as_deref().map(|range| parse_range(range, size)) {
+            None => (0, size),
+            Some(Some((first, last))) => (first, last + 1),
+            Some(None) => {
+                let _ = app.interface.blob_close_read(reader);
+                let mut response = error_reply(StatusCode::RANGE_NOT_SATISFIABLE, format!("Range is outside the {} bytes of |{}|", size, name));
+                response.headers_mut().insert(CONTENT_RANGE, HeaderValue::from_str(&format!("bytes */{}", size)).unwrap());
+                return Ok(response);
+            }
+        };
+
+        reader.seek(start);
+        let mut body = vec![0u8; end - start];
+        let mut filled = 0;
+        while filled < body.len() {
+            let chunk_end = (filled + BLOB_CHUNK_SIZE).min(body.len());
+            match app.interface.blob_read_chunk(&mut reader, &mut body[filled..chunk_end]) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) => {
+                    let _ = app.interface.blob_close_read(reader);
+                    return Ok(error_reply(error_status(&e), format!("Failed to read parameter |{}|: {}", name, e)));
+                }
+            }
+        }
+        body.truncate(filled);
+        let _ = app.interface.blob_close_read(reader);
+
+        let partial = range.is_some();
+        let content_type = app.interface.get_content_type(parameter_id).unwrap_or_else(|| "application/octet-stream".to_owned());
+        let status = if partial { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
+        let mut response = warp::reply::with_status(body, status).into_response();
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&content_type).unwrap_or(HeaderValue::from_static("application/octet-stream")));
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        if partial {
+            headers.insert(CONTENT_RANGE, HeaderValue::from_str(&format!("bytes {}-{}/{}", start, start + filled.max(1) - 1, size)).unwrap());
+        }
+        Ok(response)
+    })
+    .await
+}
+
+/// Upload the raw request body in chunks, the blob is not collected in memory
+pub(crate) async fn handle_blob_write<S, B>(
+    name: String,
+    content_length: Option<u64>,
+    credentials: Credentials,
+    mut body: S,
+    state: SharedState,
+) -> Result<warp::reply::Response, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin,
+    B: Buf,
+{
+    let Some(size) = content_length else {
+        return Ok(error_reply(StatusCode::LENGTH_REQUIRED, format!("Content-Length is required to upload |{}|", name)));
+    };
+
+    // Break with the reply when the upload is refused
+    let opened = {
+        let name = name.clone();
+        with_state(state.clone(), move |app| {
+            let client = match authorize(app, &credentials, Permission::Write) {
+                Ok(client) => client,
+                Err(err) => return ControlFlow::Break(auth_error_reply(app, err)),
+            };
+            if let Some(reply) = rate_limit_reply(app, &client, 1) {
+                return ControlFlow::Break(reply);
+            }
+            let parameter_id = match blob_parameter(app, &name) {
+                Ok(id) => id,
+                Err(reply) => return ControlFlow::Break(reply),
+            };
+            if let Err(reply) = check_blob_write(app, parameter_id, &name, client.role, size) {
+                return ControlFlow::Break(reply);
+            }
+            match app.interface.blob_open_write(parameter_id, size as usize) {
+                Ok(writer) => ControlFlow::Continue((writer, client, parameter_id)),
+                Err(e) => ControlFlow::Break(error_reply(error_status(&e), format!("Failed to set parameter |{}|: {}", name, e))),
+            }
+        })
+        .await
+    };
+    let (mut writer, client, parameter_id) = match opened {
+        ControlFlow::Continue(opened) => opened,
+        ControlFlow::Break(reply) => return Ok(reply),
+    };
+
+    while let Some(chunk) = body.next().await {
+        let mut chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Closing the incomplete blob discards it
+                let _ = with_state(state.clone(), move |app| app.interface.blob_close_write(writer, None)).await;
+                return Ok(error_reply(StatusCode::BAD_REQUEST, format!("Failed to receive |{}|: {}", name, e)));
+            }
+        };
+        let data = chunk.copy_to_bytes(chunk.remaining());
+        let written = with_state(state.clone(), move |app| match app.interface.blob_write_chunk(&mut writer, &data) {
+            Ok(()) => Ok(writer),
+            Err(e) => {
+                let _ = app.interface.blob_close_write(writer, None);
+                Err(e)
+            }
+        })
+        .await;
+        writer = match written {
+            Ok(writer) => writer,
+            Err(e) => return Ok(error_reply(error_status(&e), format!("Failed to set parameter |{}|: {}", name, e))),
+        };
+    }
+
+    with_state(state, move |app| match app.interface.blob_close_write(writer, Some("rest")) {
+        Ok(()) => {
+            app.audit.write_blob("rest", &client, &app.interface, parameter_id, size as usize);
+            Ok(warp::reply::with_status(json(&json!({ "size": size })), StatusCode::OK).into_response())
+        }
+        Err(e) => Ok(error_reply(error_status(&e), format!("Failed to set parameter |{}|: {}", name, e))),
+    })
+    .await
+}
+
+/// Upload the "file" part of a multipart form, the size is limited by the form options of the route
+pub(crate) async fn handle_blob_upload(name: String, credentials: Credentials, mut form: FormData, state: SharedState) -> Result<warp::reply::Response, Rejection> {
+    // Refuse the clients without the write permission or over the rate limit before receiving the form
+    {
+        let app = state.read().await;
+        let client = match authorize(&app, &credentials, Permission::Write) {
+            Ok(client) => client,
+            Err(err) => return Ok(auth_error_reply(&app, err)),
+        };
+        if let Some(reply) = rate_limit_reply(&app, &client, 1) {
+            return Ok(reply);
         }
     }
+
+    let mut data = Vec::new();
+    let mut found = false;
+    while let Some(part) = form.next().await {
+        let part = match part {
+            Ok(part) => part,
+            Err(e) => return Ok(error_reply(StatusCode::BAD_REQUEST, format!("Invalid multipart form: {}", e))),
+        };
+        if part.name() != "file" {
+            continue;
+        }
+        let mut stream = part.stream();
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(mut chunk) => {
+                    while chunk.has_remaining() {
+                        let length = chunk.chunk().len();
+                        data.extend_from_slice(chunk.chunk());
+                        chunk.advance(length);
+                    }
+                }
+                Err(e) => return Ok(error_reply(StatusCode::BAD_REQUEST, format!("Failed to receive |{}|: {}", name, e))),
+            }
+        }
+        found = true;
+        break;
+    }
+    if !found {
+        return Ok(error_reply(StatusCode::BAD_REQUEST, "The form has no \"file\" part".to_owned()));
+    }
+
+    with_state(state, move |app| {
+        let client = match authorize(app, &credentials, Permission::Write) {
+            Ok(client) => client,
+            Err(err) => return Ok(auth_error_reply(app, err)),
+        };
+        let parameter_id = match blob_parameter(app, &name) {
+            Ok(id) => id,
+            Err(reply) => return Ok(reply),
+        };
+        if let Err(reply) = check_blob_write(app, parameter_id, &name, client.role, data.len() as u64) {
+            return Ok(reply);
+        }
+        let size = data.len();
+        match app.interface.set_with_source(parameter_id, ParameterValue::ValBlob(data), Some("rest")) {
+            Ok(_) => {
+                app.audit.write_blob("rest", &client, &app.interface, parameter_id, size);
+                Ok(warp::reply::with_status(json(&json!({ "size": size })), StatusCode::OK).into_response())
+            }
+            Err(e) => Ok(error_reply(error_status(&e), format!("Failed to set parameter |{}|: {}", name, e))),
+        }
+    })
+    .await
 }
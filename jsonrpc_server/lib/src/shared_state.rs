@@ -1,13 +1,119 @@
+use crate::access::Authenticator;
+use crate::audit::AuditTrail;
+use crate::rate_limit::RateLimiter;
+use econfmanager::generated::ParameterId;
 use econfmanager::interface::InterfaceInstance;
+use econfmanager::schema::AccessLevel;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Notify, RwLock};
 use warp::ws::Message;
 
+/// Options of a subscription, given with the subscribe methods
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct SubscriptionOptions {
+    /// Shortest time between two notifications, the changes in between are coalesced to the latest value
+    pub min_interval: Option<Duration>,
+    /// Skip the notifications with the same value as the last one sent
+    pub on_change_only: bool,
+}
+
+/// Notification held back until the minimum interval of the subscription passes
+pub(crate) struct PendingNotification {
+    pub due: Instant,
+    pub message: Message,
+    pub value: String,
+}
+
+/// Notifications sent for a subscription with options
+#[derive(Default)]
+pub(crate) struct Delivery {
+    pub last_sent: Option<Instant>,
+    pub last_value: Option<String>,
+    pub pending: Option<PendingNotification>,
+}
+
+pub(crate) struct WsClient {
+    /// Bounded queue of the messages to the client
+    pub tx: mpsc::Sender<Message>,
+    /// Parameters the client gets the notifications of
+    pub subscriptions: HashMap<ParameterId, SubscriptionOptions>,
+    /// State of the subscriptions with options, updated by the notifications with the state locked for reading
+    pub deliveries: Mutex<HashMap<ParameterId, Delivery>>,
+    /// Wakes the connection task to send the held back notifications
+    pub flush: Arc<Notify>,
+    /// Set when the queue overflowed, the connection is closed and nothing more is queued
+    pub slow: AtomicBool,
+    /// Wakes the connection task to close a slow client
+    pub disconnect: Arc<Notify>,
+}
+
+/// Lets the change callbacks of the WebSocket clients lock the state until the server stops
+#[derive(Default)]
+pub(crate) struct NotifyGate {
+    closed: std::sync::RwLock<bool>,
+}
+
+impl NotifyGate {
+    /// Run `f` unless the gate is closed, closing waits for it to return
+    pub fn run(&self, f: impl FnOnce()) {
+        if !*self.closed.read().unwrap() {
+            f();
+        }
+    }
+
+    /// Wait for the running callbacks and skip the later ones, so the threads calling them can be
+    /// joined with the state locked
+    pub fn close(&self) {
+        *self.closed.write().unwrap() = true;
+    }
+}
+
 #[derive(Default)]
 pub(crate) struct AppState {
-    pub subscribers: Vec<Vec<mpsc::UnboundedSender<Message>>>,
+    /// Connected WebSocket clients by client ID
+    pub clients: HashMap<usize, WsClient>,
     pub interface: InterfaceInstance,
     pub names: Vec<String>,
+    /// Hex Ed25519 secret key, the manifest is signed when set
+    pub manifest_signing_key: Option<String>,
+    /// Role of the requests without the role header
+    pub default_role: AccessLevel,
+    /// Largest blob accepted by /api/blob uploads, in bytes
+    pub blob_upload_limit: u64,
+    pub auth: Authenticator,
+    /// Shared by the instances of the server, so a client has one limit for all of them
+    pub rate_limiter: Arc<RateLimiter>,
+    pub audit: AuditTrail,
+    pub notify_gate: Arc<NotifyGate>,
 }
 
-pub(crate) type SharedState = Arc<Mutex<AppState>>;
+/// Most of the requests only read the state, the WebSocket clients and their subscriptions need the write lock
+pub(crate) type SharedState = Arc<RwLock<AppState>>;
+
+/// The states of all the instances of the server with their names, the default instance first without a name
+pub(crate) type InstanceStates = Arc<Vec<(Option<String>, SharedState)>>;
+
+/// Run the blocking work on the blocking thread pool, so the SQLite access doesn't stall the executor
+/// with the WebSocket pings and the other requests
+pub(crate) async fn run_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// Run `f` on the blocking thread pool with the state locked for reading
+pub(crate) async fn with_state<T, F>(state: SharedState, f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce(&AppState) -> T + Send + 'static,
+{
+    run_blocking(move || f(&state.blocking_read())).await
+}
@@ -0,0 +1,96 @@
+use std::future::Future;
+use std::time::Duration;
+
+use log::{error, info, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+
+use crate::shared_state::{run_blocking, SharedState};
+use crate::ws_server::close_clients;
+
+/// How long the WebSocket clients get to receive the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stops the server on SIGTERM or SIGINT: closes the WebSocket connections and stops the instances
+pub struct Shutdown {
+    states: Vec<SharedState>,
+    /// Save the parameters changed since the last save
+    save: bool,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+async fn wait_for_signal() -> &'static str {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to install the SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+impl Shutdown {
+    pub(crate) fn new(states: Vec<SharedState>, save: bool) -> Self {
+        Self { states, save }
+    }
+
+    async fn stop(self) {
+        for state in &self.states {
+            close_clients(&*state.read().await, "Server shutting down");
+        }
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        for state in &self.states {
+            while !state.read().await.clients.is_empty() {
+                if Instant::now() >= deadline {
+                    warn!("{} WebSocket clients not closed in time", state.read().await.clients.len());
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        let save = self.save;
+        for state in self.states {
+            // The callbacks wait for the state on the threads joined below, the write lock is taken
+            // once none of them is running
+            let gate = state.read().await.notify_gate.clone();
+            run_blocking(move || gate.close()).await;
+            run_blocking(move || {
+                let mut app = state.blocking_write();
+                // Stops the periodic update and writes the deferred values
+                app.interface.shutdown();
+                if !save {
+                    return;
+                }
+                let unsaved = app.interface.has_unsaved_changes().unwrap_or_else(|e| {
+                    error!("Failed to check for unsaved changes on shutdown: {}", e);
+                    true
+                });
+                if unsaved {
+                    match app.interface.save() {
+                        Ok(()) => info!("Parameters saved on shutdown"),
+                        Err(e) => error!("Failed to save the parameters on shutdown: {}", e),
+                    }
+                }
+            })
+            .await;
+        }
+        info!("Server stopped");
+    }
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+impl Shutdown {
+    /// Run the server until SIGTERM or SIGINT, then stop it. New connections are no longer accepted
+    pub async fn run<F: Future<Output = ()>>(self, server: F) {
+        tokio::select! {
+            _ = server => warn!("Server exited"),
+            signal = wait_for_signal() => info!("{} received, shutting down", signal),
+        }
+        self.stop().await;
+    }
+}
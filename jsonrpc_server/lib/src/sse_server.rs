@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use econfmanager::generated::ParameterId;
+use econfmanager::interface::{CallbackHandle, ParameterChangeCallback, ParameterChangeEvent};
+use futures_util::stream;
+use log::{error, info, warn};
+use serde_json::json;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use warp::http::StatusCode;
+use warp::sse::Event;
+use warp::Reply;
+
+use crate::access::{authorize, Credentials, Permission};
+use crate::rest_server::{auth_error_reply, error_reply};
+use crate::shared_state::{with_state, AppState, SharedState};
+use crate::utils::{client_value, enum_label};
+
+/// Events queued for a stream, a client that doesn't read them in time is disconnected
+const EVENT_QUEUE_SIZE: usize = 256;
+
+/// Callbacks of an event stream, removed when the client disconnects and the stream is dropped
+struct EventSubscription {
+    state: SharedState,
+    handles: Vec<CallbackHandle>,
+}
+
+impl Drop for EventSubscription {
+    fn drop(&mut self) {
+        // Dropped by the executor, the state can't be locked here
+        let state = self.state.clone();
+        let handles = std::mem::take(&mut self.handles);
+        tokio::spawn(async move {
+            let app = state.read().await;
+            for handle in &handles {
+                let _ = app.interface.delete_callback_handle(*handle);
+            }
+            info!("Event stream of {} parameters closed", handles.len());
+        });
+    }
+}
+
+/// The parameters selected by the names= and group= filters, both have to match when given.
+/// All the parameters except the internal ones without the filters
+fn selected_parameters(app: &AppState, query: &HashMap<String, String>) -> Result<Vec<ParameterId>, String> {
+    let mut ids: Vec<ParameterId> = (0..app.interface.get_parameters_number())
+        .filter_map(ParameterId::from_index)
+        .filter(|id| !app.interface.is_internal(*id))
+        .collect();
+
+    if let Some(group) = query.get("group") {
+        if !app.interface.get_groups().iter().any(|(name, _, _)| name == group) {
+            return Err(format!("Group |{}| does not exist", group));
+        }
+        ids.retain(|id| app.interface.get_group(*id) == *group);
+    }
+
+    if let Some(names) = query.get("names") {
+        let mut named = Vec::new();
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match app.interface.get_parameter_id_from_name(name.to_owned()) {
+                Some(id) if !app.interface.is_internal(id) => named.push(id),
+                _ => return Err(format!("Parameter |{}| does not exist", name)),
+            }
+        }
+        ids.retain(|id| named.contains(id));
+    }
+    Ok(ids)
+}
+
+fn change_event(app: &AppState, event: &ParameterChangeEvent) -> Option<Event> {
+    let value = match app.interface.get(event.id, false) {
+        Ok(value) => value,
+        Err(err) => {
+            error!("Could not read parameter {} in event: {}", event.id as usize, err);
+            return None;
+        }
+    };
+    let mut data = json!({
+        "name": app.interface.get_name(event.id),
+        "value": client_value(&app.interface, event.id, &value),
+        "seq": event.sequence,
+        "timestamp": event.timestamp,
+    });
+    if let Some(label) = enum_label(&app.interface, event.id, &value) {
+        data["name_label"] = json!(label);
+    }
+    Some(Event::default().event("change").id(event.sequence.to_string()).data(data.to_string()))
+}
+
+/// Stream the changes of the parameters as `change` events until the client disconnects
+pub(crate) async fn handle_events(
+    query: HashMap<String, String>,
+    credentials: Credentials,
+    state: SharedState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let (tx, rx) = mpsc::channel::<ParameterChangeEvent>(EVENT_QUEUE_SIZE);
+    let overflow = Arc::new(AtomicBool::new(false));
+    let subscription = {
+        let app = state.read().await;
+        if let Err(err) = authorize(&app, &credentials, Permission::Read) {
+            return Ok(auth_error_reply(&app, err));
+        }
+        let ids = match selected_parameters(&app, &query) {
+            Ok(ids) => ids,
+            Err(msg) => return Ok(error_reply(StatusCode::NOT_FOUND, msg)),
+        };
+        let mut subscription = EventSubscription { state: state.clone(), handles: Vec::new() };
+        for id in ids {
+            let tx = tx.clone();
+            let overflow = overflow.clone();
+            let callback = Arc::new(move |event: &ParameterChangeEvent| {
+                // The queue of a client that stopped reading would grow without a limit
+                if let Err(TrySendError::Full(_)) = tx.try_send(event.clone()) {
+                    if !overflow.swap(true, Ordering::Relaxed) {
+                        warn!("Event stream client does not read the events, disconnecting");
+                    }
+                }
+            }) as ParameterChangeCallback;
+            match app.interface.add_callback_ex(id, callback) {
+                Ok(handle) => subscription.handles.push(handle),
+                Err(err) => {
+                    drop(app);
+                    return Ok(error_reply(StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to subscribe: {}", err)));
+                }
+            }
+        }
+        info!("Event stream of {} parameters opened", subscription.handles.len());
+        subscription
+    };
+
+    let events = stream::unfold((rx, subscription), move |(mut rx, subscription)| {
+        let overflow = overflow.clone();
+        async move {
+            loop {
+                // The stream ends instead of skipping the dropped events, the client reconnects
+                if overflow.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let event = rx.recv().await?;
+                let state = subscription.state.clone();
+                let sse_event = with_state(state, move |app| change_event(app, &event)).await;
+                if let Some(sse_event) = sse_event {
+                    return Some((Ok::<_, Infallible>(sse_event), (rx, subscription)));
+                }
+            }
+        }
+    });
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)).into_response())
+}
@@ -0,0 +1,146 @@
+use log::info;
+use warp::filters::BoxedFilter;
+use warp::http::header::CACHE_CONTROL;
+use warp::http::{HeaderValue, StatusCode};
+use warp::path::FullPath;
+use warp::{Filter, Reply};
+
+use crate::configfile::StaticFilesConfig;
+
+const INDEX_FILE: &str = "index.html";
+
+/// The files built into the server, the folder is given by ECONF_STATIC_FILES_DIR when building
+#[cfg(feature = "embedded-static")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "$ECONF_STATIC_FILES_DIR"]
+struct EmbeddedFiles;
+
+#[derive(Clone)]
+enum Source {
+    Directory(String),
+    #[cfg(feature = "embedded-static")]
+    Embedded,
+}
+
+/******************************************************************************
+ * PRIVATE FUNCTIONS
+ ******************************************************************************/
+
+#[cfg(feature = "embedded-static")]
+fn embedded_file(path: &str, if_none_match: Option<&str>) -> Option<warp::reply::Response> {
+    use warp::http::header::{CONTENT_TYPE, ETAG};
+
+    let path = if path.is_empty() || path.ends_with('/') {
+        format!("{}{}", path, INDEX_FILE)
+    } else {
+        path.to_owned()
+    };
+    let file = EmbeddedFiles::get(&path)?;
+    let hash: String = file.metadata.sha256_hash()[..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    let etag = format!("\"{}\"", hash);
+    let mut response = if if_none_match == Some(etag.as_str()) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut response = file.data.into_owned().into_response();
+        if let Ok(mime) = HeaderValue::from_str(file.metadata.mimetype()) {
+            response.headers_mut().insert(CONTENT_TYPE, mime);
+        }
+        response
+    };
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response.headers_mut().insert(ETAG, etag);
+    }
+    Some(response)
+}
+
+impl Source {
+    fn from_config(config: &StaticFilesConfig) -> Option<Self> {
+        match (&config.path, config.embedded) {
+            (Some(_), true) => panic!("static_files can have either a path or embedded, not both"),
+            (Some(path), false) => {
+                info!("Serving the static files from {}", path);
+                Some(Source::Directory(path.clone()))
+            }
+            #[cfg(feature = "embedded-static")]
+            (None, true) => {
+                info!("Serving the embedded static files");
+                Some(Source::Embedded)
+            }
+            #[cfg(not(feature = "embedded-static"))]
+            (None, true) => panic!("static_files.embedded needs the server built with the embedded-static feature"),
+            (None, false) => {
+                info!("No static_files in the configuration, only the API is served");
+                None
+            }
+        }
+    }
+
+    fn files(&self) -> BoxedFilter<(warp::reply::Response,)> {
+        match self {
+            Source::Directory(path) => warp::fs::dir(path.clone()).map(Reply::into_response).boxed(),
+            #[cfg(feature = "embedded-static")]
+            Source::Embedded => warp::get()
+                .and(warp::path::tail())
+                .and(warp::header::optional::<String>("if-none-match"))
+                .and_then(|tail: warp::path::Tail, if_none_match: Option<String>| async move {
+                    embedded_file(tail.as_str(), if_none_match.as_deref()).ok_or_else(warp::reject::not_found)
+                })
+                .boxed(),
+        }
+    }
+
+    fn index(&self) -> Option<Vec<u8>> {
+        match self {
+            Source::Directory(path) => std::fs::read(std::path::Path::new(path).join(INDEX_FILE)).ok(),
+            #[cfg(feature = "embedded-static")]
+            Source::Embedded => EmbeddedFiles::get(INDEX_FILE).map(|file| file.data.into_owned()),
+        }
+    }
+}
+
+/// index.html is revalidated on every load, so a new version of the web client is picked up with its files
+fn with_cache_control(mut response: warp::reply::Response, path: &str, cache_control: &HeaderValue) -> warp::reply::Response {
+    let value = if path.ends_with('/') || path.ends_with(INDEX_FILE) {
+        HeaderValue::from_static("no-cache")
+    } else {
+        cache_control.clone()
+    };
+    response.headers_mut().entry(CACHE_CONTROL).or_insert(value);
+    response
+}
+
+/******************************************************************************
+ * PUBLIC FUNCTIONS
+ ******************************************************************************/
+
+/// Routes of the web client, None when the configuration has no static files
+pub fn static_routes(config: &StaticFilesConfig) -> Option<BoxedFilter<(warp::reply::Response,)>> {
+    let source = Source::from_config(config)?;
+    let cache_control = HeaderValue::from_str(&config.cache_control)
+        .unwrap_or_else(|_| panic!("Invalid static_files.cache_control {} in the configuration", config.cache_control));
+
+    let files = warp::path::full()
+        .and(source.files())
+        .map(move |path: FullPath, response| with_cache_control(response, path.as_str(), &cache_control));
+    if !config.spa_fallback {
+        return Some(files.boxed());
+    }
+
+    // The unknown API routes stay not found instead of getting the page
+    let fallback = warp::get()
+        .and(warp::path::full())
+        .and_then(move |path: FullPath| {
+            let source = source.clone();
+            async move {
+                if path.as_str().starts_with("/api/") {
+                    return Err(warp::reject::not_found());
+                }
+                let response = match source.index() {
+                    Some(contents) => warp::reply::html(contents).into_response(),
+                    None => warp::reply::with_status("Index file not found", StatusCode::NOT_FOUND).into_response(),
+                };
+                Ok(with_cache_control(response, INDEX_FILE, &HeaderValue::from_static("no-cache")))
+            }
+        });
+    Some(files.or(fallback).unify().boxed())
+}
@@ -1,5 +1,10 @@
+use econfmanager::generated::ParameterId;
+use econfmanager::interface::InterfaceInstance;
+use econfmanager::schema::ParameterValue;
+use econfmanager::sensitive::SECRET_MASK;
 use env_logger::Env;
 use log::debug;
+use serde_json::json;
 use std::io::Write;
 use ansi_term::Colour;
 
@@ -12,6 +17,44 @@ pub(crate) fn debug_limited(msg: &String, max_len: usize) {
     debug!("{}", truncated);
 }
 
+/// The value as sent to the clients, the secret parameters are masked
+pub(crate) fn client_value(interface: &InterfaceInstance, id: ParameterId, value: &ParameterValue) -> serde_json::Value {
+    if interface.is_secret(id) {
+        return json!(SECRET_MASK);
+    }
+    json!(value)
+}
+
+/// Name of the value of an enum parameter, None for the other parameters and the values not in the enum
+pub(crate) fn enum_label(interface: &InterfaceInstance, id: ParameterId, value: &ParameterValue) -> Option<String> {
+    interface.get_enum_name(id)?;
+    match value {
+        // The values read from the database are I32
+        ParameterValue::ValEnum(number) | ParameterValue::ValI32(number) => interface.get_enum_label(id, *number),
+        _ => None,
+    }
+}
+
+/// The value with its type for the verbose reads: the enums have the type and the value names,
+/// the blobs the encoding of the value
+pub(crate) fn typed_value(interface: &InterfaceInstance, id: ParameterId, value: &ParameterValue) -> serde_json::Value {
+    let mut typed = json!({
+        "value": client_value(interface, id, value),
+        "type": interface.get_type_string(id),
+    });
+    if interface.is_secret(id) {
+        return typed;
+    }
+    if let Some(enum_name) = interface.get_enum_name(id) {
+        typed["type"] = json!("Enum");
+        typed["enum_name"] = json!(enum_name);
+        typed["name_label"] = json!(enum_label(interface, id, value));
+    } else if let ParameterValue::ValBlob(_) = value {
+        typed["encoding"] = json!("base64");
+    }
+    typed
+}
+
 pub fn setup_logging() {
     let start_time = std::time::Instant::now();
     let _ = env_logger::Builder::from_env(Env::default().default_filter_or("info"))
@@ -1,183 +1,617 @@
-use crate::shared_state::{AppState, SharedState};
-use econfmanager::interface::{InterfaceInstance, ParameterUpdateCallback};
+use crate::access::{authorize, check_permission, check_write_access, Client, Credentials, Permission};
+use crate::audit::value_before_write;
+use crate::rest_server::{auth_error_reply, info_document};
+use crate::shared_state::{run_blocking, AppState, PendingNotification, SharedState, SubscriptionOptions, WsClient};
+use econfmanager::error::EconfError;
+use econfmanager::interface::{InterfaceInstance, ParameterChangeCallback, ParameterChangeEvent, WriteCondition};
 use econfmanager::generated::ParameterId;
-use serde::{Deserialize, Serialize};
-use std::{sync::{Arc, Mutex}};
-use warp::{ws::{Message, WebSocket}};
+use econfmanager::schema::{AccessLevel, ParameterValue};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::time::{Duration, Instant};
+use warp::{Reply, ws::{Message, WebSocket, Ws}};
 use futures::{SinkExt, StreamExt};
-use tokio::sync::mpsc;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::Notify;
 use log::{debug, error, info, warn};
-use crate::utils::debug_limited;
+use crate::utils::{client_value, debug_limited, enum_label, typed_value};
+use econfmanager::sensitive::{self, SECRET_MASK};
+
+static NEXT_CLIENT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Messages queued for a client, a client that doesn't read them in time is disconnected
+const CLIENT_QUEUE_SIZE: usize = 256;
+
+const JSONRPC_VERSION: &str = "2.0";
+
+/// Error codes of the JSON-RPC 2.0 specification
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Server errors, from the range reserved for the implementations
+pub const SERVER_ERROR: i64 = -32000;
+/// The parameter is internal, read-only or needs a higher role, or the client lacks the permission
+pub const ACCESS_DENIED: i64 = -32001;
+/// The parameter was changed since the expected sequence
+pub const CONFLICT: i64 = -32002;
+/// The client writes faster than the rate limit
+pub const RATE_LIMITED: i64 = -32003;
 
-#[derive(Deserialize)]
 pub(crate) struct RpcRequest {
-    id: serde_json::Value,
+    /// None for the notifications, which get no response
+    id: Option<serde_json::Value>,
     method: String,
     params: Option<serde_json::Value>,
 }
 
+#[derive(Serialize, Debug)]
+pub struct RpcError {
+    code: i64,
+    message: String,
+}
+
 #[derive(Serialize)]
 pub struct RpcResponse {
+    jsonrpc: &'static str,
     id: serde_json::Value,
-    result: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
 }
 
-pub(crate) fn handle_rpc_logic_ws(
-    state: SharedState,
-    req: &RpcRequest,
-    client_tx: tokio::sync::mpsc::UnboundedSender<Message>,
-) -> Result<serde_json::Value, String> {
-    let mut app = state.lock().unwrap();
-
-    match req.method.as_str() {
-        "read" => {
-            debug!("Got read request {:?}", req.params);
-            let name = req.params
-                .as_ref()
-                .and_then(|p| p.get("name"))
-                .and_then(|v| v.as_str())
-                .ok_or("Could not decode parameter name")?;
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        let message = message.into();
+        error!("{}", message);
+        Self { code, message }
+    }
 
-            if !app.names.contains(&name.to_string()) {
-                return Err(format!("Unknown parameter {}", name));
+    /// The code of the library error, the message is prefixed with what failed
+    fn econf(context: String, e: EconfError) -> Self {
+        let code = match e {
+            EconfError::NotFound(_) | EconfError::Validation { .. } | EconfError::Vetoed { .. } | EconfError::TypeMismatch { .. } => {
+                INVALID_PARAMS
             }
+            EconfError::Const(_) | EconfError::ReadOnly(_) | EconfError::ReadOnlyInstance => ACCESS_DENIED,
+            EconfError::Conflict(_) => CONFLICT,
+            _ => SERVER_ERROR,
+        };
+        Self::new(code, format!("{}: {}", context, e))
+    }
+}
 
-            let parameter_id = app.interface
-                .get_parameter_id_from_name(name.to_string())
-                .ok_or(format!("Could not find parameter ID for {}", name))?;
+impl RpcResponse {
+    fn new(id: serde_json::Value, result: Result<serde_json::Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self { jsonrpc: JSONRPC_VERSION, id, result, error }
+    }
+}
 
-            if app.interface.is_internal(parameter_id)
-            {
-                let msg = format!("Access internal parameter |{}| forbidden", name);
-                error!("{}", msg);
-                return Err(msg);
+/// Check a request object against the specification. The error response has the ID of the request
+/// if it could be read, null otherwise
+fn parse_request(value: serde_json::Value) -> Result<RpcRequest, RpcResponse> {
+    let serde_json::Value::Object(mut fields) = value else {
+        return Err(RpcResponse::new(serde_json::Value::Null, Err(RpcError::new(INVALID_REQUEST, "Request is not an object"))));
+    };
+    let id = fields.remove("id");
+    let valid_id = matches!(
+        id,
+        None | Some(serde_json::Value::Null) | Some(serde_json::Value::String(_)) | Some(serde_json::Value::Number(_))
+    );
+    let invalid = |message: &str| {
+        let id = id.clone().filter(|_| valid_id).unwrap_or_default();
+        Err(RpcResponse::new(id, Err(RpcError::new(INVALID_REQUEST, message))))
+    };
+    if !valid_id {
+        return invalid("Request id must be a string, a number or null");
+    }
+    if fields.get("jsonrpc").and_then(|v| v.as_str()) != Some(JSONRPC_VERSION) {
+        return invalid("Request must have \"jsonrpc\": \"2.0\"");
+    }
+    let Some(serde_json::Value::String(method)) = fields.remove("method") else {
+        return invalid("Request method must be a string");
+    };
+    let params = fields.remove("params");
+    if !matches!(params, None | Some(serde_json::Value::Object(_)) | Some(serde_json::Value::Array(_))) {
+        return invalid("Request params must be an object or an array");
+    }
+    Ok(RpcRequest { id, method, params })
+}
+
+/// The params of the methods are named, the positional ones are refused
+fn named_params(req: &RpcRequest) -> Result<&serde_json::Value, RpcError> {
+    match &req.params {
+        Some(params @ serde_json::Value::Object(_)) => Ok(params),
+        Some(_) => Err(RpcError::new(INVALID_PARAMS, format!("Method {} takes named params", req.method))),
+        None => Err(RpcError::new(INVALID_PARAMS, "Missing parameters")),
+    }
+}
+
+fn profile_name(req: &RpcRequest) -> Result<&str, RpcError> {
+    named_params(req)?
+        .get("name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode profile name"))
+}
+
+/// The name and the ID of the parameter in the params, internal parameters are refused
+fn parameter_of(app: &AppState, params: &serde_json::Value) -> Result<(String, ParameterId), RpcError> {
+    let name = params.get("name")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode parameter name"))?;
+
+    if !app.names.contains(&name.to_string()) {
+        return Err(RpcError::new(INVALID_PARAMS, format!("Unknown parameter {}", name)));
+    }
+
+    let parameter_id = app.interface.get_parameter_id_from_name(name.to_string())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Could not find parameter ID for {}", name)))?;
+
+    if app.interface.is_internal(parameter_id)
+    {
+        return Err(RpcError::new(ACCESS_DENIED, format!("Access internal parameter |{}| forbidden", name)));
+    }
+    Ok((name.to_owned(), parameter_id))
+}
+
+/// The parameters of the group, without the internal ones
+fn group_parameters(app: &AppState, params: &serde_json::Value) -> Result<Vec<ParameterId>, RpcError> {
+    let group = params.get("group")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode group name"))?;
+
+    if !app.interface.get_groups().iter().any(|(name, _, _)| name == group) {
+        return Err(RpcError::new(INVALID_PARAMS, format!("Unknown group {}", group)));
+    }
+    Ok(exposed_parameters(app)
+        .filter(|id| app.interface.get_group(*id) == group)
+        .collect())
+}
+
+fn exposed_parameters(app: &AppState) -> impl Iterator<Item = ParameterId> + '_ {
+    (0..app.interface.get_parameters_number())
+        .filter_map(ParameterId::from_index)
+        .filter(|id| !app.interface.is_internal(*id))
+}
+
+fn is_watched(app: &AppState, id: ParameterId) -> bool {
+    app.clients.values().any(|client| client.subscriptions.contains_key(&id))
+}
+
+/// The options of the subscribe methods, `"options": {"min_interval_ms": 200, "on_change_only": true}`
+fn subscription_options(params: Option<&serde_json::Value>) -> Result<SubscriptionOptions, RpcError> {
+    let options = match params.and_then(|p| p.get("options")) {
+        None | Some(serde_json::Value::Null) => return Ok(SubscriptionOptions::default()),
+        Some(serde_json::Value::Object(options)) => options,
+        Some(_) => return Err(RpcError::new(INVALID_PARAMS, "Subscription options must be an object")),
+    };
+    let min_interval = match options.get("min_interval_ms") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(v) => {
+            let millis = v.as_u64().ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode min_interval_ms"))?;
+            Some(Duration::from_millis(millis)).filter(|interval| !interval.is_zero())
+        }
+    };
+    let on_change_only = match options.get("on_change_only") {
+        None | Some(serde_json::Value::Null) => false,
+        Some(v) => v.as_bool().ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode on_change_only"))?,
+    };
+    Ok(SubscriptionOptions { min_interval, on_change_only })
+}
+
+/// Add the parameters to the subscriptions of the client, or change the options of the subscriptions.
+/// The change callback of a parameter is registered with its first subscriber
+fn subscribe(
+    state: &SharedState,
+    app: &mut AppState,
+    client_id: usize,
+    ids: &[ParameterId],
+    options: SubscriptionOptions,
+) -> Result<(), RpcError> {
+    for &id in ids {
+        if !is_watched(app, id) {
+            let state = Arc::clone(state);
+            let gate = Arc::clone(&app.notify_gate);
+            let callback = Arc::new(move |event: &ParameterChangeEvent| {
+                gate.run(|| notify_client(&state.blocking_read(), event));
+            }) as ParameterChangeCallback;
+
+            app.interface.add_change_callback(id, callback)
+                .map_err(|e| RpcError::new(INTERNAL_ERROR, format!("Internal error: {}", e)))?;
+        }
+        if let Some(client) = app.clients.get_mut(&client_id) {
+            client.subscriptions.insert(id, options);
+            if options == SubscriptionOptions::default() {
+                client.deliveries.lock().unwrap().remove(&id);
             }
+        }
+    }
+    Ok(())
+}
+
+/// Remove the parameters from the subscriptions of the client, and the change callbacks of the
+/// parameters nobody is subscribed to anymore
+fn unsubscribe(app: &mut AppState, client_id: usize, ids: &[ParameterId]) {
+    if let Some(client) = app.clients.get_mut(&client_id) {
+        let mut deliveries = client.deliveries.lock().unwrap();
+        for id in ids {
+            client.subscriptions.remove(id);
+            deliveries.remove(id);
+        }
+    }
+    for &id in ids {
+        if !is_watched(app, id) {
+            let _ = app.interface.delete_callback(id);
+        }
+    }
+}
+
+fn subscription_names(app: &AppState, ids: &[ParameterId]) -> serde_json::Value {
+    serde_json::json!(ids.iter().map(|id| app.interface.get_name(*id)).collect::<Vec<_>>())
+}
+
+/// Name, ID and value of a write or a staged change, checked for the access of the client
+fn parse_write(app: &AppState, params: &serde_json::Value, role: AccessLevel) -> Result<(String, ParameterId, ParameterValue), RpcError> {
+    let (name, parameter_id) = parameter_of(app, params)?;
+
+    if app.interface.is_readonly(parameter_id)
+    {
+        return Err(RpcError::new(ACCESS_DENIED, format!("Readonly parameter cannnot be changed |{}|", name)));
+    }
+
+    check_write_access(app, parameter_id, role).map_err(|msg| RpcError::new(ACCESS_DENIED, msg))?;
+    
+    let value = params.get("value")
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing value field"))?;
+
+    let value_string = match value {
+        serde_json::Value::Null => value.to_string(),
+        serde_json::Value::Bool(_) => value.to_string(),
+        serde_json::Value::Number(_) => value.to_string(),
+        serde_json::Value::String(_) => value.as_str().unwrap().to_owned(),
+        serde_json::Value::Array(_) => value.to_string(),
+        serde_json::Value::Object(_) => value.to_string(),
+    };
+    let converted = app.interface.set_from_string(parameter_id, &value_string)
+        .map_err(|e| {
+            let max_len = 32;
+            let truncated_value: String = if app.interface.is_no_log(parameter_id) {
+                sensitive::log_text(parameter_id, &value_string).into_owned()
+            } else {
+                value_string.chars().take(max_len).collect()
+            };
+            RpcError::new(INVALID_PARAMS, format!("Unsupported type of |{}| id {} {}: {}", truncated_value, parameter_id as usize, name, e))
+        })?;
+
+    Ok((name, parameter_id, converted))
+}
+
+/// Result of the reads and the subscriptions, the enums also have the name of the value unless verbose
+fn read_result(app: &AppState, name: String, id: ParameterId, value: &ParameterValue, sequence: u64, verbose: bool) -> serde_json::Value {
+    if verbose {
+        return serde_json::json!({ "pm": { name: typed_value(&app.interface, id, value) }, "seq": sequence });
+    }
+    let mut result = serde_json::json!({ "pm": { name: client_value(&app.interface, id, value) }, "seq": sequence });
+    if let Some(label) = enum_label(&app.interface, id, value) {
+        result["name_label"] = serde_json::json!(label);
+    }
+    result
+}
 
+/// Permission the client needs for the method
+fn method_permission(method: &str) -> Permission {
+    match method {
+        "write" | "stage" | "apply_staged" | "discard_staged" => Permission::Write,
+        "save" | "restore" | "factory_reset" | "save_profile" | "load_profile" | "delete_profile" => Permission::Admin,
+        _ => Permission::Read,
+    }
+}
+
+/// Methods changing the store, limited by the rate limit
+fn is_store_write(method: &str) -> bool {
+    matches!(
+        method,
+        "write" | "apply_staged" | "save" | "restore" | "factory_reset" | "save_profile" | "load_profile" | "delete_profile"
+    )
+}
+
+/// The methods changing the subscriptions of the client, with the state locked for writing
+fn handle_subscription_ws(
+    state: &SharedState,
+    app: &mut AppState,
+    req: &RpcRequest,
+    client_id: usize,
+) -> Result<serde_json::Value, RpcError> {
+    match req.method.as_str() {
+        "subscribe" => {
+            let params = named_params(req)?;
+            let (name, parameter_id) = parameter_of(app, params)?;
+            let verbose = params.get("verbose").and_then(|v| v.as_bool()).unwrap_or(false);
+            let options = subscription_options(Some(params))?;
+            subscribe(state, app, client_id, &[parameter_id], options)?;
+
+            // Read after subscribing, so no change is missed between the value and the notifications
+            let sequence = app.interface.get_sequence(parameter_id)
+                .map_err(|e| RpcError::econf(format!("Could not read {}", name), e))?;
             let value = app.interface.get(parameter_id, false)
-                .map_err(|e| format!("Internal error: {}", e))?;
-
-            if app.subscribers[parameter_id as usize].is_empty() {
-                let state: Arc<Mutex<_>> = Arc::clone(&state);
-                let callback = Arc::new(move |id: ParameterId| {
-                    let state = Arc::clone(&state);
-                    let mut app = state.lock().unwrap();
-                    notify_client(&mut app, id);
-                }) as ParameterUpdateCallback;
-
-                app.interface.add_callback(parameter_id, callback)
-                    .map_err(|e| format!("Internal error: {}", e))?;
+                .map_err(|e| RpcError::econf(format!("Could not read {}", name), e))?;
+            if options.on_change_only {
+                // The client has the value, the notifications start with the next change
+                if let Some(client) = app.clients.get(&client_id) {
+                    client.deliveries.lock().unwrap().entry(parameter_id).or_default().last_value =
+                        Some(notification_text(app, parameter_id, &value));
+                }
             }
+            Ok(read_result(app, name, parameter_id, &value, sequence, verbose))
+        }
 
-            // Subscribe this client if not already subscribed
-            if !app.subscribers[parameter_id as usize]
-                .iter()
-                .any(|sub| sub.same_channel(&client_tx))
-            {
-                app.subscribers[parameter_id as usize].push(client_tx.clone());
-            }
+        "subscribe_group" => {
+            let params = named_params(req)?;
+            let ids = group_parameters(app, params)?;
+            subscribe(state, app, client_id, &ids, subscription_options(Some(params))?)?;
+            Ok(serde_json::json!({ "subscribed": subscription_names(app, &ids) }))
+        }
+
+        "subscribe_all" => {
+            let ids: Vec<ParameterId> = exposed_parameters(app).collect();
+            subscribe(state, app, client_id, &ids, subscription_options(req.params.as_ref())?)?;
+            Ok(serde_json::json!({ "subscribed": subscription_names(app, &ids) }))
+        }
+
+        "unsubscribe" => {
+            // A parameter, a group, or all the subscriptions without the params
+            let ids: Vec<ParameterId> = match &req.params {
+                Some(params) if params.get("name").is_some() => vec![parameter_of(app, named_params(req)?)?.1],
+                Some(params) if params.get("group").is_some() => group_parameters(app, named_params(req)?)?,
+                _ => app.clients.get(&client_id)
+                    .map(|client| client.subscriptions.keys().copied().collect())
+                    .unwrap_or_default(),
+            };
+            let ids: Vec<ParameterId> = ids.into_iter()
+                .filter(|id| app.clients.get(&client_id).is_some_and(|client| client.subscriptions.contains_key(id)))
+                .collect();
+            unsubscribe(app, client_id, &ids);
+            Ok(serde_json::json!({ "unsubscribed": subscription_names(app, &ids) }))
+        }
+
+        method => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+    }
+}
+
+pub(crate) fn handle_rpc_logic_ws(
+    state: SharedState,
+    req: &RpcRequest,
+    client_id: usize,
+    client: &Client,
+) -> Result<serde_json::Value, RpcError> {
+    check_permission(client, method_permission(&req.method)).map_err(|err| RpcError::new(ACCESS_DENIED, err.to_string()))?;
+    if matches!(req.method.as_str(), "subscribe" | "subscribe_group" | "subscribe_all" | "unsubscribe") {
+        return handle_subscription_ws(&state, &mut state.blocking_write(), req, client_id);
+    }
+    let role = client.role;
+    let app = state.blocking_read();
+    if is_store_write(&req.method) {
+        let key = client.rate_key();
+        app.rate_limiter.check(&key, 1).map_err(|wait| {
+            RpcError::new(RATE_LIMITED, format!("Rate limit of {} exceeded, retry in {} ms", key, wait.as_millis()))
+        })?;
+    }
+
+    match req.method.as_str() {
+        "read" => {
+            debug!("Got read request {:?}", req.params);
+            let params = named_params(req)?;
+            let (name, parameter_id) = parameter_of(&app, params)?;
+            let verbose = params.get("verbose").and_then(|v| v.as_bool()).unwrap_or(false);
 
-            Ok(serde_json::json!({ "pm": { name: value } }))
+            // Sequence first, a write in between makes a conditional write with it fail instead of lost
+            let sequence = app.interface.get_sequence(parameter_id)
+                .map_err(|e| RpcError::econf(format!("Could not read {}", name), e))?;
+            let value = app.interface.get(parameter_id, false)
+                .map_err(|e| RpcError::econf(format!("Could not read {}", name), e))?;
+
+            Ok(read_result(&app, name, parameter_id, &value, sequence, verbose))
         }
 
         "write" => {
-            debug_limited(&format!("Got write request {:?}", req.params), 100);
-            let params = req.params.as_ref().ok_or_else(|| {
-                let msg = "Missing parameters";
-                error!("{}", msg);
-                msg
-            })?;
-            
-            let name = params.get("name")
+            let no_log_id = req.params.as_ref()
+                .and_then(|p| p.get("name"))
                 .and_then(|v| v.as_str())
-                .ok_or_else(|| {
-                    let msg = "Could not decode parameter name";
-                    error!("{}", msg);
-                    msg
-                })?;
-
-            if !app.names.contains(&name.to_string()) {
-                let msg = format!("Unknown parameter {}", name);
-                error!("{}", msg);
-                return Err(msg);
+                .and_then(|name| app.interface.get_parameter_id_from_name(name.to_string()))
+                .filter(|id| app.interface.is_no_log(*id));
+            if let Some(id) = no_log_id {
+                let value = req.params.as_ref().and_then(|p| p.get("value")).map(|v| v.to_string()).unwrap_or_default();
+                debug!("Got write request of {} {}", app.interface.get_name(id), sensitive::log_text(id, &value));
+            } else {
+                debug_limited(&format!("Got write request {:?}", req.params), 100);
             }
+            let params = named_params(req)?;
+            
+            let (name, parameter_id, converted) = parse_write(&app, params, role)?;
+
+            let expected_sequence = match params.get("expected_seq") {
+                None | Some(serde_json::Value::Null) => None,
+                Some(v) => Some(v.as_u64().ok_or_else(|| RpcError::new(INVALID_PARAMS, "Could not decode expected_seq"))?),
+            };
 
-            let parameter_id = app.interface.get_parameter_id_from_name(name.to_string())
-                .ok_or_else(|| {
-                    let msg = format!("Could not find parameter ID for {}", name);
-                    error!("{}", msg);
-                    msg
-                })?;
-
-            if app.interface.is_internal(parameter_id)
-            {
-                let msg = format!("Access internal parameter |{}| forbidden", name);
-                error!("{}", msg);
-                return Err(msg);
+            let source = format!("ws-client-{}", client_id);
+            let old_value = value_before_write(&app.interface, parameter_id);
+            let applied = match expected_sequence {
+                Some(sequence) => app.interface.set_if(parameter_id, converted, WriteCondition::Sequence(sequence), Some(&source)),
+                None => app.interface.set_with_source(parameter_id, converted, Some(&source)),
             }
+            .map_err(|e| RpcError::econf(format!("Failed to set the parameter {} id {}", name, parameter_id as usize), e))?;
+            app.audit.write("ws", client, &app.interface, parameter_id, old_value.as_ref(), &applied.value);
+
+            Ok(serde_json::json!({ "pm": { name: client_value(&app.interface, parameter_id, &applied.value) } }))
+        },
+
+        "stage" => {
+            debug_limited(&format!("Got stage request {:?}", req.params), 100);
+            let (name, parameter_id, converted) = parse_write(&app, named_params(req)?, role)?;
+            app.interface.set_staged(parameter_id, converted)
+                .map_err(|e| RpcError::econf(format!("Failed to stage the parameter {} id {}", name, parameter_id as usize), e))?;
+            let staged: Vec<String> = app.interface.get_staged_ids()
+                .into_iter()
+                .map(|id| app.interface.get_name(id))
+                .collect();
+            Ok(serde_json::json!({ "staged": staged }))
+        },
+
+        "read_staged" => {
+            debug!("Got read staged request {:?}", req.params);
+            let (name, parameter_id) = parameter_of(&app, named_params(req)?)?;
+            let value = app.interface.get_staged(parameter_id)
+                .map_err(|e| RpcError::econf(format!("Could not read the staged {}", name), e))?;
+            Ok(serde_json::json!({ "pm": { name: client_value(&app.interface, parameter_id, &value) } }))
+        },
 
-            if app.interface.is_readonly(parameter_id)
-            {
-                let msg = format!("Readonly parameter cannnot be changed |{}|", name);
-                error!("{}", msg);
-                return Err(msg);
+        "apply_staged" => {
+            debug!("Got apply staged request");
+            let source = format!("ws-client-{}", client_id);
+            let old_values: HashMap<ParameterId, ParameterValue> = app.interface.get_staged_ids().into_iter()
+                .filter_map(|id| value_before_write(&app.interface, id).map(|value| (id, value)))
+                .collect();
+            let written = app.interface.apply_staged(Some(&source))
+                .map_err(|e| RpcError::econf("Could not apply the staged changes".to_owned(), e))?;
+            for (id, outcome) in &written {
+                app.audit.write("ws", client, &app.interface, *id, old_values.get(id), &outcome.value);
             }
-            
-            let value = params.get("value")
-                .ok_or_else(|| {
-                    let msg = "Missing value field";
-                    error!("{}", msg);
-                    msg
-                })?;
-
-            let value_string = match value {
-                serde_json::Value::Null => value.to_string(),
-                serde_json::Value::Bool(_) => value.to_string(),
-                serde_json::Value::Number(_) => value.to_string(),
-                serde_json::Value::String(_) => value.as_str().unwrap().to_owned(),
-                serde_json::Value::Array(_) => value.to_string(),
-                serde_json::Value::Object(_) => value.to_string(),
-            };
-            let converted = app.interface.set_from_string(parameter_id, &value_string)
-                .map_err(|e| {
-                    let max_len = 32;
-                    let truncated_value: String = value_string.chars().take(max_len).collect();
-                    let msg = format!("Unsupported type of |{}| id {} {}: {}", truncated_value, parameter_id as usize, name, e);
-                    error!("{}", msg);
-                    msg
-                })?;
-
-            let applied = app.interface.set(parameter_id, converted)
-                .map_err(|e| format!("Failed to set the parameter {} id {} {}", e, parameter_id as usize, name))?;
-
-            Ok(serde_json::json!({ "pm": { name: applied } }))
+            let values: serde_json::Map<String, serde_json::Value> = written.iter()
+                .filter(|(id, _)| !app.interface.is_internal(*id))
+                .map(|(id, outcome)| (app.interface.get_name(*id), client_value(&app.interface, *id, &outcome.value)))
+                .collect();
+            Ok(serde_json::json!({ "pm": values }))
+        },
+
+        "discard_staged" => {
+            debug!("Got discard staged request");
+            let discarded = app.interface.discard_staged();
+            Ok(serde_json::json!({ "discarded": discarded }))
         },
 
         "save" => {
             debug!("Got save request");
             app.interface.save()
-                .map_err(|e| format!("Could not save: {}", e))?;
+                .map_err(|e| RpcError::econf("Could not save".to_owned(), e))?;
+            app.audit.action("ws", client, "save", None);
             Ok(serde_json::json!({ "status": "saved" }))
         },
 
         "restore" => {
             debug!("Got restore request");
             app.interface.load()
-                .map_err(|e| format!("Could not restore: {}", e))?;
+                .map_err(|e| RpcError::econf("Could not restore".to_owned(), e))?;
+            app.audit.action("ws", client, "restore", None);
             Ok(serde_json::json!({ "status": "restored" }))
         }
 
         "factory_reset" => {
             debug!("Got factory reset request");
             app.interface.factory_reset()
-                .map_err(|e| format!("Could not do a factory reset: {}", e))?;
+                .map_err(|e| RpcError::econf("Could not do a factory reset".to_owned(), e))?;
+            app.audit.action("ws", client, "factory_reset", None);
             Ok(serde_json::json!({ "status": "reset done" }))
         },
 
-        _ => Err("Unknown method".into()),
+        "info" => {
+            debug!("Got info request");
+            Ok(info_document(&app))
+        },
+
+        "list_profiles" => {
+            debug!("Got list profiles request");
+            let profiles = app.interface.list_profiles()
+                .map_err(|e| RpcError::econf("Could not list the profiles".to_owned(), e))?;
+            Ok(serde_json::json!({ "profiles": profiles }))
+        },
+
+        "save_profile" => {
+            let name = profile_name(req)?;
+            debug!("Got save profile request {}", name);
+            app.interface.save_profile(name)
+                .map_err(|e| RpcError::econf(format!("Could not save profile {}", name), e))?;
+            app.audit.action("ws", client, "save_profile", Some(name));
+            Ok(serde_json::json!({ "status": "saved", "profile": name }))
+        },
+
+        "load_profile" => {
+            let name = profile_name(req)?;
+            debug!("Got load profile request {}", name);
+            app.interface.load_profile(name)
+                .map_err(|e| RpcError::econf(format!("Could not load profile {}", name), e))?;
+            app.audit.action("ws", client, "load_profile", Some(name));
+            Ok(serde_json::json!({ "status": "restored", "profile": name }))
+        },
+
+        "delete_profile" => {
+            let name = profile_name(req)?;
+            debug!("Got delete profile request {}", name);
+            app.interface.delete_profile(name)
+                .map_err(|e| RpcError::econf(format!("Could not delete profile {}", name), e))?;
+            app.audit.action("ws", client, "delete_profile", Some(name));
+            Ok(serde_json::json!({ "status": "deleted", "profile": name }))
+        },
+
+        method => Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method {}", method))),
     }
 }
 
-pub(crate) fn notify_client(app: &mut AppState, id: ParameterId) {
+/// Handle a request or a batch of requests, returns the response unless all of them were notifications
+fn handle_rpc_call(
+    state: &SharedState,
+    value: serde_json::Value,
+    client_id: usize,
+    client: &Client,
+) -> Option<RpcResponse> {
+    let req = match parse_request(value) {
+        Ok(req) => req,
+        Err(response) => return Some(response),
+    };
+    let result = handle_rpc_logic_ws(state.clone(), &req, client_id, client);
+    req.id.map(|id| RpcResponse::new(id, result))
+}
+
+pub(crate) fn handle_rpc_message(
+    state: &SharedState,
+    text: &str,
+    client_id: usize,
+    client: &Client,
+) -> Option<String> {
+    let reply = match serde_json::from_str::<serde_json::Value>(text) {
+        Err(e) => serde_json::to_value(RpcResponse::new(
+            serde_json::Value::Null,
+            Err(RpcError::new(PARSE_ERROR, format!("Invalid JSON: {}", e))),
+        )),
+        Ok(serde_json::Value::Array(batch)) if batch.is_empty() => serde_json::to_value(RpcResponse::new(
+            serde_json::Value::Null,
+            Err(RpcError::new(INVALID_REQUEST, "Empty batch")),
+        )),
+        Ok(serde_json::Value::Array(batch)) => {
+            // The requests of a batch are handled in order, one response for each request with an ID
+            let responses: Vec<RpcResponse> = batch
+                .into_iter()
+                .filter_map(|value| handle_rpc_call(state, value, client_id, client))
+                .collect();
+            if responses.is_empty() {
+                return None;
+            }
+            serde_json::to_value(responses)
+        }
+        Ok(value) => serde_json::to_value(handle_rpc_call(state, value, client_id, client)?),
+    };
+    reply.map(|reply| reply.to_string()).ok()
+}
+
+pub(crate) fn notify_client(app: &AppState, event: &ParameterChangeEvent) {
+    let id = event.id;
     if app.interface.is_internal(id)
     {
         return;
@@ -191,39 +625,186 @@ pub(crate) fn notify_client(app: &mut AppState, id: ParameterId) {
         return;
     };
 
-    let notification = serde_json::json!({
+    let text = notification_text(app, id, &value);
+    let mut notification = serde_json::json!({
         "jsonrpc": "2.0",
         "method": "notify",
         "params": {
-            parameter_name.clone(): InterfaceInstance::value_to_string(&value),
+            parameter_name.clone(): text,
+        },
+        "seq": event.sequence,
+    });
+    // Next to the params, which only have the parameter names as the keys
+    if let Some(label) = enum_label(&app.interface, id, &value) {
+        notification["name_label"] = serde_json::json!(label);
+    }
+    let notification = notification.to_string();
+
+    if app.interface.is_no_log(id) {
+        debug!("Notify subscribers for ID {} {}: {}", id as usize, parameter_name, sensitive::log_value(id, &value));
+    } else {
+        debug_limited(&format!("Notify subscribers for ID {} {}: {}", id as usize, parameter_name, notification), 100);
+    }
+    let message = Message::text(notification);
+    let now = Instant::now();
+    let subscribers = app.clients.iter()
+        .filter_map(|(client_id, client)| Some((*client_id, client, client.subscriptions.get(&id)?)));
+    for (client_id, client, options) in subscribers {
+        if *options != SubscriptionOptions::default() && !admit_notification(client, id, options, &message, &text, now) {
+            continue;
         }
-    })
-    .to_string();
+        send_notification(client_id, client, message.clone());
+    }
+}
 
-    debug_limited(&format!("Notify subscribers for ID {} {}: {}", id as usize, parameter_name, notification), 100);
-    for tx in app.subscribers[id as usize].clone() {
-        match tx.send(Message::text(notification.clone())) {
-            Ok(_) => {},
-            Err(err) => {
-                error!("Failed notification: {}", err);
-            },
+/// The value as sent in the notifications
+fn notification_text(app: &AppState, id: ParameterId, value: &ParameterValue) -> String {
+    if app.interface.is_secret(id) {
+        SECRET_MASK.to_owned()
+    } else {
+        InterfaceInstance::value_to_string(value)
+    }
+}
+
+fn send_notification(client_id: usize, client: &WsClient, message: Message) {
+    if client.slow.load(Ordering::Relaxed) {
+        return;
+    }
+    match client.tx.try_send(message) {
+        Ok(_) => {},
+        Err(TrySendError::Full(_)) => {
+            // The queue of a client that stopped reading would grow without a limit
+            if !client.slow.swap(true, Ordering::Relaxed) {
+                warn!("Client {} does not read the notifications, disconnecting", client_id);
+                client.disconnect.notify_one();
+            }
+        },
+        Err(TrySendError::Closed(_)) => {
+            debug!("Client {} is disconnecting, notification dropped", client_id);
+        },
+    }
+}
+
+/// Whether the notification of a subscription with options is sent now. Within the minimum interval
+/// it replaces the held back notification of the parameter, which the connection task sends when due
+fn admit_notification(
+    client: &WsClient,
+    id: ParameterId,
+    options: &SubscriptionOptions,
+    message: &Message,
+    text: &str,
+    now: Instant,
+) -> bool {
+    let mut deliveries = client.deliveries.lock().unwrap();
+    let delivery = deliveries.entry(id).or_default();
+    if options.on_change_only && delivery.last_value.as_deref() == Some(text) {
+        // Back to the value the client has, the held back change is not needed
+        delivery.pending = None;
+        return false;
+    }
+    if let (Some(min_interval), Some(last_sent)) = (options.min_interval, delivery.last_sent) {
+        let due = last_sent + min_interval;
+        if now < due {
+            if delivery.pending.is_none() {
+                client.flush.notify_one();
+            }
+            delivery.pending = Some(PendingNotification { due, message: message.clone(), value: text.to_owned() });
+            return false;
         }
     }
+    delivery.last_sent = Some(now);
+    delivery.last_value = Some(text.to_owned());
+    delivery.pending = None;
+    true
+}
+
+/// Send the held back notifications of the client that are due, returns when the next one is due
+fn flush_notifications(app: &AppState, client_id: usize) -> Option<Instant> {
+    let client = app.clients.get(&client_id)?;
+    let now = Instant::now();
+    let mut next_due: Option<Instant> = None;
+    let mut due = Vec::new();
+    for delivery in client.deliveries.lock().unwrap().values_mut() {
+        if let Some(pending) = delivery.pending.take_if(|pending| pending.due <= now) {
+            delivery.last_sent = Some(now);
+            delivery.last_value = Some(pending.value);
+            due.push(pending.message);
+        } else if let Some(pending) = &delivery.pending {
+            next_due = Some(next_due.map_or(pending.due, |next| next.min(pending.due)));
+        }
+    }
+    for message in due {
+        send_notification(client_id, client, message);
+    }
+    next_due
+}
+
+/// Queue a close frame to every client, their connections end once it is sent. The clients that
+/// don't read their messages are disconnected without it
+pub(crate) fn close_clients(app: &AppState, reason: &'static str) {
+    for client in app.clients.values() {
+        if client.tx.try_send(Message::close_with(1001u16, reason)).is_err() {
+            client.disconnect.notify_one();
+        }
+    }
+}
+
+/// Authorize the client before the upgrade. Browsers can't set the headers of a WebSocket, the token can be
+/// passed in the token= query parameter instead
+pub(crate) async fn handle_ws_upgrade(
+    ws: Ws,
+    query: HashMap<String, String>,
+    mut credentials: Credentials,
+    state: SharedState,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if credentials.authorization.is_none() {
+        credentials.authorization = query.get("token").map(|token| format!("Bearer {}", token));
+    }
+    let client = {
+        let app = state.read().await;
+        match authorize(&app, &credentials, Permission::Read) {
+            Ok(client) => client,
+            Err(err) => {
+                warn!("WebSocket connection rejected: {}", err);
+                return Ok(auth_error_reply(&app, err));
+            }
+        }
+    };
+    Ok(ws.on_upgrade(move |socket| handle_ws(socket, state, client)).into_response())
 }
 
-pub(crate) async fn handle_ws(ws: WebSocket, state: SharedState) {
+async fn handle_ws(ws: WebSocket, state: SharedState, client: Client) {
     let (mut client_ws_tx, mut client_ws_rx) = ws.split();
-    let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
+    let (tx, mut rx) = mpsc::channel::<Message>(CLIENT_QUEUE_SIZE);
+    let disconnect = Arc::new(Notify::new());
+    let flush = Arc::new(Notify::new());
+    let mut next_flush: Option<Instant> = None;
     let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
-
-    info!("Client connected");
+    let client_id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+
+    info!("Client {} ({}) connected with the {} permission and the {} role", client_id,
+        client.name.as_deref().unwrap_or("anonymous"), client.permission.name(), client.role.name());
+    state.write().await.clients.insert(client_id, WsClient {
+        tx: tx.clone(),
+        subscriptions: HashMap::new(),
+        deliveries: Mutex::new(HashMap::new()),
+        flush: flush.clone(),
+        slow: AtomicBool::new(false),
+        disconnect: disconnect.clone(),
+    });
 
     let mut forward_task = tokio::task::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            debug_limited(&format!("Send message {:?}", msg), 100);
+            // The frames carry the values, see the no_log tag
+            debug!("Send message of {} bytes", msg.as_bytes().len());
+            let close = msg.is_close();
             if client_ws_tx.send(msg).await.is_err() {
                 break; // Exit if send fails (connection closed)
             }
+            if close {
+                let _ = client_ws_tx.close().await;
+                break;
+            }
         }
     });
 
@@ -232,20 +813,19 @@ pub(crate) async fn handle_ws(ws: WebSocket, state: SharedState) {
     while connection_active {
         tokio::select! {
             msg = client_ws_rx.next() => {
-                debug_limited(&format!("Received message {:?}", msg), 100);
+                if let Some(Ok(msg)) = &msg {
+                    debug!("Received message of {} bytes", msg.as_bytes().len());
+                }
                 match msg {
                     Some(Ok(msg)) => {
-                        if msg.is_text() {
-                            if let Ok(req) = serde_json::from_str::<RpcRequest>(msg.to_str().unwrap()) {
-                                let result = match handle_rpc_logic_ws(state.clone(), &req, tx.clone()) {
-                                    Ok(value) => value,
-                                    Err(error) => serde_json::json!({ "error": error }),
-                                };
-                                let response = RpcResponse {
-                                    id: req.id,
-                                    result,
-                                };
-                                let _ = tx.send(Message::text(serde_json::to_string(&response).unwrap()));
+                        if let Ok(text) = msg.to_str() {
+                            let (state, text, client) = (state.clone(), text.to_owned(), client.clone());
+                            let reply = run_blocking(move || handle_rpc_message(&state, &text, client_id, &client)).await;
+                            if let Some(reply) = reply {
+                                if tx.try_send(Message::text(reply)).is_err() {
+                                    warn!("Client {} does not read the responses, disconnecting", client_id);
+                                    connection_active = false;
+                                }
                             }
                         }
                     },
@@ -261,37 +841,38 @@ pub(crate) async fn handle_ws(ws: WebSocket, state: SharedState) {
             },
 
             _ = interval.tick() => {
-                if tx.send(Message::ping(vec![])).is_err() {
+                if tx.try_send(Message::ping(vec![])).is_err() {
                     connection_active = false;
                 }
             },
 
+            _ = disconnect.notified() => {
+                connection_active = false;
+            },
+
+            _ = flush.notified() => {
+                next_flush = flush_notifications(&*state.read().await, client_id);
+            },
+
+            _ = tokio::time::sleep_until(next_flush.map_or_else(tokio::time::Instant::now, tokio::time::Instant::from_std)),
+                if next_flush.is_some() => {
+                next_flush = flush_notifications(&*state.read().await, client_id);
+            },
+
             _ = &mut forward_task => {
                 info!("Forwarding task terminated");
                 connection_active = false;
             }
         }
     }
+    // A send blocked on a client that stopped reading would keep the connection open
+    forward_task.abort();
 
-    let mut app = match state.lock() {
-        Ok(guard) => guard,
-        Err(poisoned) => {
-            warn!("Mutex poisoned, attempting recovery");
-            poisoned.into_inner()
-        }
-    };
-
-    let mut indices_to_delete = Vec::new();
-    for (idx, param_subscribers) in app.subscribers.iter_mut().enumerate() {
-        param_subscribers.retain(|sub| !sub.same_channel(&tx));
-        if param_subscribers.is_empty() {
-            indices_to_delete.push(idx);
-        }
-    }
+    let mut app = state.write().await;
 
-    for idx in indices_to_delete {
-        if let Ok(id) = ParameterId::try_from(idx) {
-            let _ = app.interface.delete_callback(id);
-        }
-    }
-}
\ No newline at end of file
+    let subscriptions: Vec<ParameterId> = app.clients.get(&client_id)
+        .map(|client| client.subscriptions.keys().copied().collect())
+        .unwrap_or_default();
+    unsubscribe(&mut app, client_id, &subscriptions);
+    app.clients.remove(&client_id);
+}